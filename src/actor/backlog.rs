@@ -0,0 +1,69 @@
+use std::collections::VecDeque;
+
+/// Most recent bytes of the replication stream, from which a replica reconnecting after a brief
+/// disconnection is sent what it missed instead of a whole snapshot.
+#[derive(Debug)]
+pub struct ReplicationBacklog {
+    buffer: VecDeque<u8>,
+    capacity: usize,
+    /// Replication offset right after the last byte of the buffer.
+    end_offset: usize,
+}
+
+impl ReplicationBacklog {
+    pub fn new(capacity: usize) -> ReplicationBacklog {
+        ReplicationBacklog {
+            buffer: VecDeque::new(),
+            capacity,
+            end_offset: 0,
+        }
+    }
+
+    /// Append bytes sent to the replicas, dropping the oldest ones beyond the capacity.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.end_offset += bytes.len();
+        let bytes = &bytes[bytes.len().saturating_sub(self.capacity)..];
+        let overflow = (self.buffer.len() + bytes.len()).saturating_sub(self.capacity);
+        self.buffer.drain(..overflow);
+        self.buffer.extend(bytes);
+    }
+
+    /// Bytes of the replication stream from `offset` on, or None when some of them are no longer
+    /// (or not yet) in the backlog.
+    pub fn since(&self, offset: usize) -> Option<Vec<u8>> {
+        let start_offset = self.end_offset - self.buffer.len();
+        if offset < start_offset || offset > self.end_offset {
+            return None;
+        }
+        Some(
+            self.buffer
+                .range(offset - start_offset..)
+                .copied()
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReplicationBacklog;
+
+    #[test]
+    fn only_recent_bytes_are_kept() {
+        let mut backlog = ReplicationBacklog::new(8);
+
+        backlog.feed(b"hello");
+        assert_eq!(backlog.since(0), Some(b"hello".to_vec()));
+        assert_eq!(backlog.since(3), Some(b"lo".to_vec()));
+        assert_eq!(backlog.since(5), Some(Vec::new()));
+        assert_eq!(backlog.since(6), None);
+
+        backlog.feed(b"world");
+        assert_eq!(backlog.since(1), None);
+        assert_eq!(backlog.since(2), Some(b"lloworld".to_vec()));
+
+        backlog.feed(b"0123456789");
+        assert_eq!(backlog.since(11), None);
+        assert_eq!(backlog.since(12), Some(b"23456789".to_vec()));
+    }
+}
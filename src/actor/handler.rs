@@ -0,0 +1,3405 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    iter::zip,
+    net::SocketAddr,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use indexmap::IndexMap;
+use itertools::Itertools;
+
+use crate::{
+    config::{Config, ReplicationRole},
+    connection::{
+        fmt::{help_reply, stream_reply},
+        parser::{Command, CommandMeta, CommandVerb},
+        reply::{Protocol, Reply},
+    },
+    glob::glob_match,
+    lcs::lcs,
+    store::{
+        bitmap::{BitFieldOperation, BitFieldOverflow, BitFieldType, BitUnit},
+        stream::{
+            ClaimRequest, ClaimedEntries, ConsumerGroupError, RequestedStreamEntryId, StreamEntry,
+            StreamEntryId, StreamTrim,
+        },
+        ItemType, Store,
+    },
+    REDIS_VERSION,
+};
+
+/// Result of applying a command to the store: the reply for the client, plus the side effects the
+/// calling actor is responsible for.
+#[derive(Debug, PartialEq)]
+pub struct CommandOutcome {
+    pub response: Reply,
+    /// Command to forward to replicas, set for writes only.
+    pub propagate: Option<Vec<Vec<u8>>>,
+    /// Keys whose value has been modified by the command.
+    pub modified_keys: Vec<String>,
+    /// Keys modified in another database than the selected one, by `COPY ... DB` and `MOVE`.
+    pub other_db_keys: Vec<(usize, String)>,
+}
+
+impl CommandOutcome {
+    fn reply(response: Reply) -> CommandOutcome {
+        CommandOutcome {
+            response,
+            propagate: None,
+            modified_keys: Vec::new(),
+            other_db_keys: Vec::new(),
+        }
+    }
+
+    fn write(response: Reply, propagate: Vec<Vec<u8>>, key: &str) -> CommandOutcome {
+        CommandOutcome {
+            response,
+            propagate: Some(propagate),
+            modified_keys: vec![key.to_owned()],
+            other_db_keys: Vec::new(),
+        }
+    }
+}
+
+/// Command-to-store logic shared by the master and replica actors.
+pub struct CommandHandler<'a> {
+    store: &'a mut Store,
+    config: &'a Config,
+    replication: Option<ReplicationInfo>,
+    stats: ServerStats,
+}
+
+/// Counters kept by the actor, reported by `INFO stats` and `INFO commandstats`.
+#[derive(Debug, Default, Clone)]
+pub struct ServerStats {
+    /// Number of keys removed because they expired.
+    pub expired_keys: usize,
+    pub commands: HashMap<CommandVerb, CommandStat>,
+}
+
+/// Calls to a single command and the total time spent processing them.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CommandStat {
+    pub calls: u64,
+    pub usec: u64,
+}
+
+/// Live replication state of the instance, reported by `INFO replication`.
+#[derive(Debug)]
+pub enum ReplicationInfo {
+    Master {
+        offset: usize,
+        replicas: Vec<ReplicaInfo>,
+    },
+    Replica {
+        /// Offset processed from the replication stream.
+        offset: usize,
+        link_up: bool,
+    },
+}
+
+#[derive(Debug)]
+pub struct ReplicaInfo {
+    pub addr: Option<SocketAddr>,
+    /// Port announced by the replica, reported instead of the one of its connection.
+    pub listening_port: Option<u16>,
+    /// Last offset acknowledged by the replica.
+    pub offset: usize,
+    /// Seconds since the last acknowledgement.
+    pub lag: u64,
+}
+
+impl<'a> CommandHandler<'a> {
+    pub fn new(store: &'a mut Store, config: &'a Config) -> CommandHandler<'a> {
+        CommandHandler {
+            store,
+            config,
+            replication: None,
+            stats: ServerStats::default(),
+        }
+    }
+
+    pub fn with_replication(mut self, replication: ReplicationInfo) -> CommandHandler<'a> {
+        self.replication = Some(replication);
+        self
+    }
+
+    pub fn with_stats(mut self, stats: ServerStats) -> CommandHandler<'a> {
+        self.stats = stats;
+        self
+    }
+
+    /// Apply `command` to the store. Returns `None` when the verb is not a store command and must be
+    /// handled by the actor itself (transactions, replication, connection management).
+    pub fn handle(&mut self, command: &Command) -> Option<CommandOutcome> {
+        let Command { verb, cmd, raw, .. } = command;
+        if self.is_out_of_memory(verb) {
+            return Some(CommandOutcome::reply(Reply::error(
+                "OOM command not allowed when used memory > 'maxmemory'.",
+            )));
+        }
+        let outcome = match verb {
+            CommandVerb::PING => self.process_ping(),
+            CommandVerb::ECHO => self.process_echo(cmd, raw),
+            CommandVerb::SET => self.process_set(cmd, raw),
+            CommandVerb::GET => self.process_get(cmd),
+            CommandVerb::GETDEL => self.process_getdel(cmd),
+            CommandVerb::GETEX => self.process_getex(cmd),
+            CommandVerb::DEL | CommandVerb::UNLINK => self.process_del(cmd, raw),
+            CommandVerb::EXISTS => self.process_exists(cmd),
+            CommandVerb::EXPIRE => self.process_expire(cmd, "EX"),
+            CommandVerb::PEXPIRE => self.process_expire(cmd, "PX"),
+            CommandVerb::EXPIREAT => self.process_expire(cmd, "EXAT"),
+            CommandVerb::PEXPIREAT => self.process_expire(cmd, "PXAT"),
+            CommandVerb::PERSIST => self.process_persist(cmd, raw),
+            CommandVerb::PTTL => self.process_pttl(cmd),
+            CommandVerb::RENAME => self.process_rename(cmd, raw),
+            CommandVerb::COPY => self.process_copy(cmd, raw),
+            CommandVerb::MOVE => self.process_move(cmd, raw),
+            CommandVerb::INCR => self.process_incr(cmd, raw, 1),
+            CommandVerb::DECR => self.process_incr(cmd, raw, -1),
+            CommandVerb::INCRBY => self.process_incrby(cmd, raw, false),
+            CommandVerb::DECRBY => self.process_incrby(cmd, raw, true),
+            CommandVerb::APPEND => self.process_append(cmd, raw),
+            CommandVerb::SETRANGE => self.process_setrange(cmd, raw),
+            CommandVerb::GETRANGE => self.process_getrange(cmd),
+            CommandVerb::LCS => self.process_lcs(cmd),
+            CommandVerb::BITPOS => self.process_bitpos(cmd),
+            CommandVerb::SETBIT => self.process_setbit(cmd, raw),
+            CommandVerb::GETBIT => self.process_getbit(cmd),
+            CommandVerb::BITFIELD => self.process_bitfield(cmd, raw),
+            CommandVerb::TYPE => self.process_type(cmd),
+            CommandVerb::OBJECT => self.process_object(cmd),
+            CommandVerb::XADD => self.process_xadd(cmd, raw),
+            CommandVerb::XRANGE => self.process_xrange(cmd),
+            CommandVerb::XREAD => self.process_xread(cmd),
+            CommandVerb::XDEL => self.process_xdel(cmd, raw),
+            CommandVerb::XLEN => self.process_xlen(cmd),
+            CommandVerb::XTRIM => self.process_xtrim(cmd, raw),
+            CommandVerb::XGROUP => self.process_xgroup(cmd, raw),
+            CommandVerb::XREADGROUP => self.process_xreadgroup(cmd, raw),
+            CommandVerb::XACK => self.process_xack(cmd, raw),
+            CommandVerb::XPENDING => self.process_xpending(cmd),
+            CommandVerb::XCLAIM => self.process_xclaim(cmd, raw),
+            CommandVerb::XAUTOCLAIM => self.process_xautoclaim(cmd, raw),
+            CommandVerb::CONFIG => self.process_config(cmd),
+            CommandVerb::KEYS => self.process_keys(cmd, raw),
+            CommandVerb::INFO => self.process_info(cmd),
+            CommandVerb::COMMAND => self.process_command_info(cmd),
+            _ => return None,
+        };
+        Some(outcome)
+    }
+
+    /// Whether `verb` must be refused because the dataset is over `maxmemory`. Without eviction,
+    /// commands that may grow the dataset are rejected, while reads and deletions remain allowed.
+    /// Writes from the master are always applied by replicas.
+    fn is_out_of_memory(&self, verb: &CommandVerb) -> bool {
+        self.config.maxmemory > 0
+            && self
+                .config
+                .get_arg("maxmemory-policy")
+                .is_none_or(|policy| policy == "noeviction")
+            && self.config.replication.role == ReplicationRole::Master
+            && verb.metadata().flags.contains(&"denyoom")
+            && self.store.used_memory() > self.config.maxmemory
+    }
+
+    fn process_ping(&mut self) -> CommandOutcome {
+        CommandOutcome::reply(Reply::Simple(String::from("PONG")))
+    }
+
+    fn process_echo(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let Some(message) = raw.get(1) else {
+            return wrong_number_of_arguments(command);
+        };
+        CommandOutcome::reply(Reply::Bulk(Some(message.clone())))
+    }
+
+    fn process_set(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let (Some(key), Some(value)) = (command.get(1), raw.get(2)) else {
+            return wrong_number_of_arguments(command);
+        };
+
+        let option = command.get(3).map(|option| option.to_uppercase());
+        let expiry = match (option.as_deref(), command.get(4)) {
+            (Some(unit @ ("EX" | "PX" | "EXAT" | "PXAT")), Some(time)) => {
+                let Some(expiry) = time
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|time| *time > 0)
+                    .and_then(|time| absolute_expiry(unit, time))
+                else {
+                    return CommandOutcome::reply(Reply::error(
+                        "ERR invalid expire time in 'set' command",
+                    ));
+                };
+                Some(expiry)
+            }
+            _ => None,
+        };
+
+        println!("Setting {}: {}", key, String::from_utf8_lossy(value));
+        self.store.set_string(key, value, None);
+        let Some(expiry) = expiry else {
+            return CommandOutcome::write(Reply::ok(), raw.to_vec(), key);
+        };
+        self.store.set_expiry(key, Some(expiry));
+        // Replicas receive the absolute expiry so that they converge with the master
+        let mut propagate = raw[..3].to_vec();
+        propagate.push(b"PXAT".to_vec());
+        propagate.push(expiry.timestamp_millis().to_string().into_bytes());
+        CommandOutcome::write(Reply::ok(), propagate, key)
+    }
+
+    fn process_get(&mut self, command: &[String]) -> CommandOutcome {
+        let Some(key) = command.get(1) else {
+            return wrong_number_of_arguments(command);
+        };
+        let response = match self.store.get_string(key) {
+            Ok(value) => Reply::Bulk(value),
+            Err(err) => Reply::Error(err.to_string()),
+        };
+        CommandOutcome::reply(response)
+    }
+
+    fn process_getdel(&mut self, command: &[String]) -> CommandOutcome {
+        let Some(key) = command.get(1) else {
+            return wrong_number_of_arguments(command);
+        };
+        match self.store.get_string(key) {
+            Ok(Some(value)) => {
+                self.store.delete(key);
+                CommandOutcome::write(Reply::Bulk(Some(value)), to_args(&["DEL", key]), key)
+            }
+            Ok(None) => CommandOutcome::reply(Reply::Null),
+            Err(err) => CommandOutcome::reply(Reply::Error(err.to_string())),
+        }
+    }
+
+    fn process_getex(&mut self, command: &[String]) -> CommandOutcome {
+        let Some(key) = command.get(1) else {
+            return wrong_number_of_arguments(command);
+        };
+
+        // `None` leaves the TTL untouched, `Some(None)` removes it
+        let option = command.get(2).map(|option| option.to_uppercase());
+        let expiry: Option<Option<DateTime<Utc>>> = match (option.as_deref(), command.get(3)) {
+            (None, _) => None,
+            (Some("PERSIST"), None) => Some(None),
+            (Some(unit @ ("EX" | "PX" | "EXAT" | "PXAT")), Some(time)) if command.len() == 4 => {
+                let Some(expiry) = time
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|time| *time > 0)
+                    .and_then(|time| absolute_expiry(unit, time))
+                else {
+                    return CommandOutcome::reply(Reply::error(
+                        "ERR invalid expire time in 'getex' command",
+                    ));
+                };
+                Some(Some(expiry))
+            }
+            _ => return CommandOutcome::reply(Reply::error("ERR syntax error")),
+        };
+
+        let value = match self.store.get_string(key) {
+            Ok(Some(value)) => value,
+            Ok(None) => return CommandOutcome::reply(Reply::Null),
+            Err(err) => return CommandOutcome::reply(Reply::Error(err.to_string())),
+        };
+        let response = Reply::Bulk(Some(value));
+        match expiry {
+            None => CommandOutcome::reply(response),
+            Some(expiry) => {
+                self.store.set_expiry(key, expiry);
+                // Replicas receive the absolute expiry so that they converge with the master
+                let propagate = match expiry {
+                    Some(expiry) => {
+                        to_args(&["PEXPIREAT", key, &expiry.timestamp_millis().to_string()])
+                    }
+                    None => to_args(&["PERSIST", key]),
+                };
+                CommandOutcome::write(response, propagate, key)
+            }
+        }
+    }
+
+    /// DEL and UNLINK. Only the keys that actually existed are propagated, in a single command.
+    fn process_del(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        if command.len() < 2 {
+            return wrong_number_of_arguments(command);
+        }
+        let mut propagate = vec![raw[0].clone()];
+        let mut deleted_keys = Vec::new();
+        for (key, raw_key) in zip(&command[1..], &raw[1..]) {
+            if self.store.delete(key) {
+                propagate.push(raw_key.clone());
+                deleted_keys.push(key.clone());
+            }
+        }
+        if deleted_keys.is_empty() {
+            return CommandOutcome::reply(Reply::Integer(0));
+        }
+        CommandOutcome {
+            response: Reply::Integer(deleted_keys.len() as i64),
+            propagate: Some(propagate),
+            modified_keys: deleted_keys,
+            other_db_keys: Vec::new(),
+        }
+    }
+
+    /// Number of existing keys among the arguments, a key given twice being counted twice.
+    fn process_exists(&mut self, command: &[String]) -> CommandOutcome {
+        let count = command[1..]
+            .iter()
+            .filter(|key| self.store.get_item_type(key).is_some())
+            .count();
+        CommandOutcome::reply(Reply::Integer(count as i64))
+    }
+
+    /// EXPIRE, PEXPIRE, EXPIREAT and PEXPIREAT, `unit` being the matching `SET` option. Replicas
+    /// always receive the absolute expiry, as `PEXPIREAT`.
+    ///
+    /// NX and XX only set an expiry on keys without or with one, GT and LT only replace a later
+    /// or an earlier expiry. Keys without expiry are treated as expiring last.
+    fn process_expire(&mut self, command: &[String], unit: &str) -> CommandOutcome {
+        let (Some(key), Some(time)) = (command.get(1), command.get(2)) else {
+            return wrong_number_of_arguments(command);
+        };
+        let Some(expiry) = time
+            .parse::<i64>()
+            .ok()
+            .and_then(|time| absolute_expiry(unit, time))
+        else {
+            return CommandOutcome::reply(Reply::error(
+                "ERR value is not an integer or out of range",
+            ));
+        };
+        let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+        for option in &command[3..] {
+            match option.to_uppercase().as_str() {
+                "NX" => nx = true,
+                "XX" => xx = true,
+                "GT" => gt = true,
+                "LT" => lt = true,
+                _ => {
+                    return CommandOutcome::reply(Reply::error(&format!(
+                        "ERR Unsupported option {option}"
+                    )))
+                }
+            }
+        }
+        if nx && (xx || gt || lt) {
+            return CommandOutcome::reply(Reply::error(
+                "ERR NX and XX, GT or LT options at the same time are not compatible",
+            ));
+        }
+        if gt && lt {
+            return CommandOutcome::reply(Reply::error(
+                "ERR GT and LT options at the same time are not compatible",
+            ));
+        }
+        if self.store.get_item_type(key).is_none() {
+            return CommandOutcome::reply(Reply::Integer(0));
+        }
+        let allowed = match self.store.get_expiry(key) {
+            None => !xx && !gt,
+            Some(current) => !nx && (!gt || expiry > current) && (!lt || expiry < current),
+        };
+        if !allowed || !self.store.set_expiry(key, Some(expiry)) {
+            return CommandOutcome::reply(Reply::Integer(0));
+        }
+        let propagate = to_args(&["PEXPIREAT", key, &expiry.timestamp_millis().to_string()]);
+        CommandOutcome::write(Reply::Integer(1), propagate, key)
+    }
+
+    fn process_persist(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let Some(key) = command.get(1) else {
+            return wrong_number_of_arguments(command);
+        };
+        if self.store.get_expiry(key).is_none() {
+            return CommandOutcome::reply(Reply::Integer(0));
+        }
+        self.store.set_expiry(key, None);
+        CommandOutcome::write(Reply::Integer(1), raw.to_vec(), key)
+    }
+
+    fn process_pttl(&mut self, command: &[String]) -> CommandOutcome {
+        let Some(key) = command.get(1) else {
+            return wrong_number_of_arguments(command);
+        };
+        let ttl = match (self.store.get_item_type(key), self.store.get_expiry(key)) {
+            (None, _) => -2,
+            (Some(_), None) => -1,
+            (Some(_), Some(expiry)) => (expiry - Utc::now()).num_milliseconds().max(0),
+        };
+        CommandOutcome::reply(Reply::Integer(ttl))
+    }
+
+    fn process_rename(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let (Some(source), Some(destination)) = (command.get(1), command.get(2)) else {
+            return wrong_number_of_arguments(command);
+        };
+        if !self.store.rename(source, destination) {
+            return CommandOutcome::reply(Reply::error("ERR no such key"));
+        }
+        CommandOutcome {
+            response: Reply::ok(),
+            propagate: Some(raw.to_vec()),
+            modified_keys: vec![source.clone(), destination.clone()],
+            other_db_keys: Vec::new(),
+        }
+    }
+
+    fn process_move(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let (Some(key), Some(db)) = (command.get(1), command.get(2)) else {
+            return wrong_number_of_arguments(command);
+        };
+        let destination_db = match db.parse::<i64>() {
+            Ok(db) if (0..self.store.databases() as i64).contains(&db) => db as usize,
+            Ok(_) => return CommandOutcome::reply(Reply::error("ERR DB index is out of range")),
+            Err(_) => {
+                return CommandOutcome::reply(Reply::error(
+                    "ERR value is not an integer or out of range",
+                ))
+            }
+        };
+        if destination_db == self.store.selected() {
+            return CommandOutcome::reply(Reply::error(
+                "ERR source and destination objects are the same",
+            ));
+        }
+        if !self
+            .store
+            .move_key(key, self.store.selected(), destination_db)
+        {
+            return CommandOutcome::reply(Reply::Integer(0));
+        }
+        CommandOutcome {
+            response: Reply::Integer(1),
+            propagate: Some(raw.to_vec()),
+            modified_keys: vec![key.clone()],
+            other_db_keys: vec![(destination_db, key.clone())],
+        }
+    }
+
+    fn process_copy(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let (Some(source), Some(destination)) = (command.get(1), command.get(2)) else {
+            return wrong_number_of_arguments(command);
+        };
+        let mut replace = false;
+        let mut destination_db = self.store.selected();
+        let mut options = command[3..].iter();
+        while let Some(option) = options.next() {
+            match option.to_uppercase().as_str() {
+                "REPLACE" => replace = true,
+                "DB" => match options.next().map(|db| db.parse::<i64>()) {
+                    Some(Ok(db)) if (0..self.store.databases() as i64).contains(&db) => {
+                        destination_db = db as usize
+                    }
+                    Some(Ok(_)) => {
+                        return CommandOutcome::reply(Reply::error("ERR DB index is out of range"))
+                    }
+                    Some(Err(_)) => {
+                        return CommandOutcome::reply(Reply::error(
+                            "ERR value is not an integer or out of range",
+                        ))
+                    }
+                    None => return CommandOutcome::reply(Reply::error("ERR syntax error")),
+                },
+                _ => return CommandOutcome::reply(Reply::error("ERR syntax error")),
+            }
+        }
+        if source == destination && destination_db == self.store.selected() {
+            return CommandOutcome::reply(Reply::error(
+                "ERR source and destination objects are the same",
+            ));
+        }
+        if !self
+            .store
+            .copy(source, destination, destination_db, replace)
+        {
+            return CommandOutcome::reply(Reply::Integer(0));
+        }
+        if destination_db == self.store.selected() {
+            return CommandOutcome::write(Reply::Integer(1), raw.to_vec(), destination);
+        }
+        CommandOutcome {
+            response: Reply::Integer(1),
+            propagate: Some(raw.to_vec()),
+            modified_keys: Vec::new(),
+            other_db_keys: vec![(destination_db, destination.clone())],
+        }
+    }
+
+    fn process_incr(
+        &mut self,
+        command: &[String],
+        raw: &[Vec<u8>],
+        increment: i64,
+    ) -> CommandOutcome {
+        let Some(key) = command.get(1) else {
+            return wrong_number_of_arguments(command);
+        };
+        self.apply_increment(key, increment, raw)
+    }
+
+    fn process_incrby(
+        &mut self,
+        command: &[String],
+        raw: &[Vec<u8>],
+        decrement: bool,
+    ) -> CommandOutcome {
+        let (Some(key), Some(increment)) = (command.get(1), command.get(2)) else {
+            return wrong_number_of_arguments(command);
+        };
+        let Ok(increment) = increment.parse::<i64>() else {
+            return CommandOutcome::reply(Reply::error(
+                "ERR value is not an integer or out of range",
+            ));
+        };
+        let increment = if decrement {
+            let Some(increment) = increment.checked_neg() else {
+                return CommandOutcome::reply(Reply::error("ERR decrement would overflow"));
+            };
+            increment
+        } else {
+            increment
+        };
+        self.apply_increment(key, increment, raw)
+    }
+
+    fn apply_increment(&mut self, key: &str, increment: i64, raw: &[Vec<u8>]) -> CommandOutcome {
+        match self.store.incr_by(key, increment) {
+            Ok(new_value) => CommandOutcome::write(Reply::Integer(new_value), raw.to_vec(), key),
+            Err(err) => CommandOutcome::reply(Reply::Error(err.to_string())),
+        }
+    }
+
+    fn process_append(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let (Some(key), Some(value)) = (command.get(1), raw.get(2)) else {
+            return wrong_number_of_arguments(command);
+        };
+        match self.store.append(key, value) {
+            Ok(len) => CommandOutcome::write(Reply::Integer(len as i64), raw.to_vec(), key),
+            Err(err) => CommandOutcome::reply(Reply::Error(err.to_string())),
+        }
+    }
+
+    fn process_setrange(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let (Some(key), Some(offset), Some(value)) = (command.get(1), command.get(2), raw.get(3))
+        else {
+            return wrong_number_of_arguments(command);
+        };
+        let Ok(offset) = offset.parse::<i64>() else {
+            return CommandOutcome::reply(Reply::error(
+                "ERR value is not an integer or out of range",
+            ));
+        };
+        let Ok(offset) = usize::try_from(offset) else {
+            return CommandOutcome::reply(Reply::error("ERR offset is out of range"));
+        };
+        if !value.is_empty() && !self.is_valid_string_size(offset.saturating_add(value.len())) {
+            return CommandOutcome::reply(Reply::error(
+                "ERR string exceeds maximum allowed size (proto-max-bulk-len)",
+            ));
+        }
+        match self.store.set_range(key, offset, value) {
+            Ok(len) => CommandOutcome::write(Reply::Integer(len as i64), raw.to_vec(), key),
+            Err(err) => CommandOutcome::reply(Reply::Error(err.to_string())),
+        }
+    }
+
+    fn process_getrange(&mut self, command: &[String]) -> CommandOutcome {
+        let (Some(key), Some(start), Some(end)) = (command.get(1), command.get(2), command.get(3))
+        else {
+            return wrong_number_of_arguments(command);
+        };
+        let (Ok(start), Ok(end)) = (start.parse::<i64>(), end.parse::<i64>()) else {
+            return CommandOutcome::reply(Reply::error(
+                "ERR value is not an integer or out of range",
+            ));
+        };
+        let response = match self.store.get_range(key, start, end) {
+            Ok(value) => Reply::Bulk(Some(value)),
+            Err(err) => Reply::Error(err.to_string()),
+        };
+        CommandOutcome::reply(response)
+    }
+
+    fn process_setbit(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let (Some(key), Some(offset), Some(bit)) = (command.get(1), command.get(2), command.get(3))
+        else {
+            return wrong_number_of_arguments(command);
+        };
+        let Some(offset) = self.parse_bit_offset(offset) else {
+            return CommandOutcome::reply(Reply::error(
+                "ERR bit offset is not an integer or out of range",
+            ));
+        };
+        let bit = match bit.as_str() {
+            "0" => false,
+            "1" => true,
+            _ => {
+                return CommandOutcome::reply(Reply::error(
+                    "ERR bit is not an integer or out of range",
+                ))
+            }
+        };
+        match self.store.set_bit(key, offset, bit) {
+            Ok(previous) => {
+                CommandOutcome::write(Reply::Integer(previous.into()), raw.to_vec(), key)
+            }
+            Err(err) => CommandOutcome::reply(Reply::Error(err.to_string())),
+        }
+    }
+
+    fn process_getbit(&mut self, command: &[String]) -> CommandOutcome {
+        let (Some(key), Some(offset)) = (command.get(1), command.get(2)) else {
+            return wrong_number_of_arguments(command);
+        };
+        let Some(offset) = self.parse_bit_offset(offset) else {
+            return CommandOutcome::reply(Reply::error(
+                "ERR bit offset is not an integer or out of range",
+            ));
+        };
+        let response = match self.store.get_bit(key, offset) {
+            Ok(bit) => Reply::Integer(bit.into()),
+            Err(err) => Reply::Error(err.to_string()),
+        };
+        CommandOutcome::reply(response)
+    }
+
+    /// Whether a string of `len` bytes fits in `proto-max-bulk-len`, the size limit of values.
+    fn is_valid_string_size(&self, len: usize) -> bool {
+        len <= self.config.proto_max_bulk_len
+    }
+
+    /// Parse a SETBIT or GETBIT bit offset, which must address a bit of a valid string.
+    fn parse_bit_offset(&self, arg: &str) -> Option<usize> {
+        let offset = arg.parse::<usize>().ok()?;
+        self.is_valid_string_size(offset / 8 + 1).then_some(offset)
+    }
+
+    /// Parse a BITFIELD bit offset, `#N` meaning the N-th field of the given type. The whole
+    /// field must fit in a valid string.
+    fn parse_bitfield_offset(&self, arg: &str, field_type: BitFieldType) -> Option<usize> {
+        let offset = match arg.strip_prefix('#') {
+            Some(index) => index.parse::<usize>().ok()?.checked_mul(field_type.bits)?,
+            None => arg.parse::<usize>().ok()?,
+        };
+        let len = offset.checked_add(field_type.bits)?.div_ceil(8);
+        self.is_valid_string_size(len).then_some(offset)
+    }
+
+    fn process_lcs(&mut self, command: &[String]) -> CommandOutcome {
+        let (Some(key_a), Some(key_b)) = (command.get(1), command.get(2)) else {
+            return wrong_number_of_arguments(command);
+        };
+
+        let (mut len, mut idx, mut with_match_len, mut min_match_len) = (false, false, false, 0);
+        let mut options = command[3..].iter();
+        while let Some(option) = options.next() {
+            match option.to_uppercase().as_str() {
+                "LEN" => len = true,
+                "IDX" => idx = true,
+                "WITHMATCHLEN" => with_match_len = true,
+                "MINMATCHLEN" => {
+                    let Some(value) = options.next().and_then(|value| value.parse::<i64>().ok())
+                    else {
+                        return CommandOutcome::reply(Reply::error(
+                            "ERR value is not an integer or out of range",
+                        ));
+                    };
+                    min_match_len = value.max(0) as usize;
+                }
+                _ => return CommandOutcome::reply(Reply::error("ERR syntax error")),
+            }
+        }
+        if len && idx {
+            return CommandOutcome::reply(Reply::error(
+                "ERR If you want both the length and indexes, please just use IDX.",
+            ));
+        }
+
+        let (a, b) = match (self.store.get_string(key_a), self.store.get_string(key_b)) {
+            (Ok(a), Ok(b)) => (a.unwrap_or_default(), b.unwrap_or_default()),
+            (Err(err), _) | (_, Err(err)) => {
+                return CommandOutcome::reply(Reply::Error(err.to_string()))
+            }
+        };
+        let result = lcs(&a, &b);
+
+        let response = if idx {
+            let matches = result
+                .matches
+                .iter()
+                .filter(|range| range.match_len() >= min_match_len)
+                .map(|range| {
+                    let mut reply = vec![
+                        Reply::Array(vec![
+                            Reply::Integer(range.a.0 as i64),
+                            Reply::Integer(range.a.1 as i64),
+                        ]),
+                        Reply::Array(vec![
+                            Reply::Integer(range.b.0 as i64),
+                            Reply::Integer(range.b.1 as i64),
+                        ]),
+                    ];
+                    if with_match_len {
+                        reply.push(Reply::Integer(range.match_len() as i64));
+                    }
+                    Reply::Array(reply)
+                })
+                .collect();
+            Reply::Map(vec![
+                (Reply::bulk("matches"), Reply::Array(matches)),
+                (
+                    Reply::bulk("len"),
+                    Reply::Integer(result.subsequence.len() as i64),
+                ),
+            ])
+        } else if len {
+            Reply::Integer(result.subsequence.len() as i64)
+        } else {
+            Reply::Bulk(Some(result.subsequence))
+        };
+        CommandOutcome::reply(response)
+    }
+
+    fn process_bitpos(&mut self, command: &[String]) -> CommandOutcome {
+        let (Some(key), Some(bit)) = (command.get(1), command.get(2)) else {
+            return wrong_number_of_arguments(command);
+        };
+        let bit = match bit.as_str() {
+            "0" => false,
+            "1" => true,
+            _ => {
+                return CommandOutcome::reply(Reply::error("ERR The bit argument must be 1 or 0."))
+            }
+        };
+        let unit = match command.get(5).map(|unit| unit.to_uppercase()).as_deref() {
+            None | Some("BYTE") => BitUnit::Byte,
+            Some("BIT") => BitUnit::Bit,
+            Some(_) => return CommandOutcome::reply(Reply::error("ERR syntax error")),
+        };
+        if command.len() > 6 {
+            return CommandOutcome::reply(Reply::error("ERR syntax error"));
+        }
+        let Ok(range) = command[3..command.len().min(5)]
+            .iter()
+            .map(|index| index.parse::<i64>())
+            .collect::<Result<Vec<i64>, _>>()
+        else {
+            return CommandOutcome::reply(Reply::error(
+                "ERR value is not an integer or out of range",
+            ));
+        };
+
+        let response = match self.store.bitpos(
+            key,
+            bit,
+            range.first().copied(),
+            range.get(1).copied(),
+            unit,
+        ) {
+            Ok(position) => Reply::Integer(position),
+            Err(err) => Reply::Error(err.to_string()),
+        };
+        CommandOutcome::reply(response)
+    }
+
+    fn process_bitfield(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let Some(key) = command.get(1) else {
+            return wrong_number_of_arguments(command);
+        };
+
+        let mut operations = Vec::new();
+        let mut overflow = BitFieldOverflow::Wrap;
+        let mut args = command[2..].iter();
+        while let Some(subcommand) = args.next() {
+            let subcommand = subcommand.to_uppercase();
+            let arity = match subcommand.as_str() {
+                "OVERFLOW" => 1,
+                "GET" => 2,
+                "SET" | "INCRBY" => 3,
+                _ => return CommandOutcome::reply(Reply::error("ERR syntax error")),
+            };
+            let arguments: Vec<&String> = args.by_ref().take(arity).collect();
+            if arguments.len() < arity {
+                return CommandOutcome::reply(Reply::error("ERR syntax error"));
+            }
+
+            if subcommand == "OVERFLOW" {
+                overflow = match arguments[0].to_uppercase().as_str() {
+                    "WRAP" => BitFieldOverflow::Wrap,
+                    "SAT" => BitFieldOverflow::Sat,
+                    "FAIL" => BitFieldOverflow::Fail,
+                    _ => {
+                        return CommandOutcome::reply(Reply::error(
+                            "ERR Invalid OVERFLOW type specified",
+                        ))
+                    }
+                };
+                continue;
+            }
+            let field_type = match BitFieldType::try_from(arguments[0].as_str()) {
+                Ok(field_type) => field_type,
+                Err(err) => return CommandOutcome::reply(Reply::error(err)),
+            };
+            let Some(offset) = self.parse_bitfield_offset(arguments[1], field_type) else {
+                return CommandOutcome::reply(Reply::error(
+                    "ERR bit offset is not an integer or out of range",
+                ));
+            };
+            if subcommand == "GET" {
+                operations.push(BitFieldOperation::Get { field_type, offset });
+                continue;
+            }
+            let Ok(value) = arguments[2].parse::<i64>() else {
+                return CommandOutcome::reply(Reply::error(
+                    "ERR value is not an integer or out of range",
+                ));
+            };
+            operations.push(if subcommand == "SET" {
+                BitFieldOperation::Set {
+                    field_type,
+                    offset,
+                    value,
+                    overflow,
+                }
+            } else {
+                BitFieldOperation::IncrBy {
+                    field_type,
+                    offset,
+                    increment: value,
+                    overflow,
+                }
+            });
+        }
+
+        let results = match self.store.bitfield(key, &operations) {
+            Ok(results) => results,
+            Err(err) => return CommandOutcome::reply(Reply::Error(err.to_string())),
+        };
+        let written = zip(&operations, &results)
+            .any(|(operation, result)| operation.is_write() && result.is_some());
+        let response = Reply::Array(
+            results
+                .into_iter()
+                .map(|result| result.map_or(Reply::Null, Reply::Integer))
+                .collect(),
+        );
+        if written {
+            CommandOutcome::write(response, raw.to_vec(), key)
+        } else {
+            CommandOutcome::reply(response)
+        }
+    }
+
+    fn process_type(&mut self, command: &[String]) -> CommandOutcome {
+        let Some(key) = command.get(1) else {
+            return wrong_number_of_arguments(command);
+        };
+        let response = match self.store.get_item_type(key) {
+            None => "none",
+            Some(ItemType::String) => "string",
+            Some(ItemType::Stream) => "stream",
+        };
+        CommandOutcome::reply(Reply::Simple(response.to_owned()))
+    }
+
+    fn process_object(&mut self, command: &[String]) -> CommandOutcome {
+        if command[1].eq_ignore_ascii_case("help") {
+            return CommandOutcome::reply(help_reply(
+                "OBJECT",
+                &[
+                    "ENCODING <key>",
+                    "    Return the kind of internal representation used in order to store the value",
+                    "    associated with a <key>.",
+                    "FREQ <key>",
+                    "    Return the access frequency index of the <key>. The returned integer is",
+                    "    proportional to the logarithm of the recent access frequency of the key.",
+                    "IDLETIME <key>",
+                    "    Return the idle time of the <key>, that is the approximated number of",
+                    "    seconds elapsed since the last access to the key.",
+                ],
+            ));
+        }
+        let (Some(subcommand), Some(key)) = (command.get(1), command.get(2)) else {
+            return wrong_number_of_arguments(command);
+        };
+
+        let lfu_policy = self
+            .config
+            .get_arg("maxmemory-policy")
+            .is_some_and(|policy| policy.ends_with("-lfu"));
+        let response = match subcommand.to_uppercase().as_str() {
+            "IDLETIME" if lfu_policy => Reply::error(
+                "ERR An LFU maxmemory policy is selected, idle time not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.",
+            ),
+            "IDLETIME" => match self.store.get_idle_time(key) {
+                Some(idle_time) => Reply::Integer(idle_time),
+                None => Reply::Null,
+            },
+            "FREQ" if !lfu_policy => Reply::error(
+                "ERR An LFU maxmemory policy is not selected, access frequency not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.",
+            ),
+            "FREQ" => match self.store.get_access_frequency(key) {
+                Some(frequency) => Reply::Integer(frequency.into()),
+                None => Reply::Null,
+            },
+            "ENCODING" => match self.store.get_encoding(key) {
+                Some(encoding) => Reply::bulk(&encoding.to_string()),
+                None => Reply::Null,
+            },
+            _ => Reply::Error(format!(
+                "ERR unknown subcommand '{subcommand}'. Try OBJECT HELP."
+            )),
+        };
+        CommandOutcome::reply(response)
+    }
+
+    fn process_xadd(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let Some(stream_key) = command.get(1) else {
+            return wrong_number_of_arguments(command);
+        };
+        let Some(entry_id) = command.get(2).and_then(parse_requested_stream_entry_id) else {
+            return CommandOutcome::reply(Reply::error(
+                "ERR Invalid stream ID specified as stream command argument",
+            ));
+        };
+
+        let entries: IndexMap<String, String> = command[3..]
+            .iter()
+            .tuple_windows::<(_, _)>()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        match self
+            .store
+            .add_stream_entry(stream_key, &entry_id, &entries, None)
+        {
+            Ok(entry_id) => {
+                // Replicas must store the entry under the ID generated by the master
+                let mut propagate = raw.to_vec();
+                propagate[2] = entry_id.to_string().into_bytes();
+                CommandOutcome::write(Reply::bulk(&entry_id.to_string()), propagate, stream_key)
+            }
+            Err(err) => CommandOutcome::reply(Reply::Error(err.to_string())),
+        }
+    }
+
+    fn process_xrange(&mut self, command: &[String]) -> CommandOutcome {
+        let Some(stream_key) = command.get(1) else {
+            return wrong_number_of_arguments(command);
+        };
+        let start_id = command.get(2).and_then(|s| parse_stream_entry_id(s));
+        let end_id = command.get(3).and_then(|s| parse_stream_entry_id(s));
+
+        let stream = self
+            .store
+            .get_stream_range(stream_key, start_id.as_ref(), end_id.as_ref());
+        CommandOutcome::reply(stream_reply(stream))
+    }
+
+    fn process_xread(&mut self, command: &[String]) -> CommandOutcome {
+        let Some(XREADArguments { streams, .. }) = parse_xread_arguments(command) else {
+            return CommandOutcome::reply(Reply::error("ERR syntax error"));
+        };
+        // `$` only matches entries added while blocking, so it never yields entries here
+        let response: Vec<Reply> = streams
+            .iter()
+            .filter_map(|(stream, id)| {
+                let stream_values = self
+                    .store
+                    .get_stream_range(stream, Some(id.as_ref()?), None);
+                (!stream_values.is_empty())
+                    .then(|| Reply::Array(vec![Reply::bulk(stream), stream_reply(stream_values)]))
+            })
+            .collect();
+        if response.is_empty() {
+            return CommandOutcome::reply(Reply::Null);
+        }
+        CommandOutcome::reply(Reply::Array(response))
+    }
+
+    fn process_xgroup(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let subcommand = command[1].to_lowercase();
+        if subcommand == "help" {
+            return CommandOutcome::reply(help_reply(
+                "XGROUP",
+                &[
+                    "CREATE <key> <groupname> <id|$> [MKSTREAM]",
+                    "    Create a new consumer group. Options are:",
+                    "    * MKSTREAM",
+                    "      Create the empty stream if it does not exist.",
+                ],
+            ));
+        }
+        if subcommand != "create" {
+            return CommandOutcome::reply(Reply::Error(format!(
+                "ERR unknown subcommand '{}'. Try XGROUP HELP.",
+                command[1]
+            )));
+        }
+        let [_, _, key, group, id, options @ ..] = command else {
+            return CommandOutcome::reply(Reply::error(
+                "ERR wrong number of arguments for 'xgroup|create' command",
+            ));
+        };
+        let mkstream = match options {
+            [] => false,
+            [option] if option.eq_ignore_ascii_case("mkstream") => true,
+            _ => return CommandOutcome::reply(Reply::error("ERR syntax error")),
+        };
+        // `$` starts the group after the current last entry of the stream
+        let id = if id == "$" {
+            None
+        } else {
+            match parse_stream_id_or_timestamp(id) {
+                Some(id) => Some(id),
+                None => {
+                    return CommandOutcome::reply(Reply::error(
+                        "ERR Invalid stream ID specified as stream command argument",
+                    ))
+                }
+            }
+        };
+
+        match self.store.create_consumer_group(key, group, id, mkstream) {
+            Ok(()) => CommandOutcome::write(Reply::ok(), raw.to_vec(), key),
+            Err(err) => CommandOutcome::reply(Reply::Error(err.to_string())),
+        }
+    }
+
+    /// XREADGROUP, without blocking. Replies with a null array when `>` found no new entries.
+    fn process_xreadgroup(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let arguments = match parse_xreadgroup_arguments(command) {
+            Ok(arguments) => arguments,
+            Err(message) => return CommandOutcome::reply(Reply::error(message)),
+        };
+        let XREADGROUPArguments {
+            group,
+            consumer,
+            count,
+            noack,
+            streams,
+            ..
+        } = arguments;
+
+        // Nothing is read unless every stream has the group
+        for (key, _) in &streams {
+            match self.store.get_consumer_group(key, &group) {
+                Ok(_) => {}
+                Err(ConsumerGroupError::NoGroup) => {
+                    return CommandOutcome::reply(Reply::Error(format!(
+                        "NOGROUP No such key '{key}' or consumer group '{group}' in XREADGROUP \
+                         with GROUP option"
+                    )))
+                }
+                Err(err) => return CommandOutcome::reply(Reply::Error(err.to_string())),
+            }
+        }
+
+        let mut response = Vec::new();
+        let mut modified_keys = Vec::new();
+        for (key, start) in &streams {
+            let Ok(entries) = self
+                .store
+                .read_group(key, &group, &consumer, *start, count, noack)
+            else {
+                continue;
+            };
+            if !entries.is_empty() {
+                modified_keys.push(key.clone());
+            } else if start.is_none() {
+                continue;
+            }
+            response.push(Reply::Array(vec![Reply::bulk(key), stream_reply(&entries)]));
+        }
+
+        let response = if response.is_empty() {
+            Reply::NullArray
+        } else {
+            Reply::Array(response)
+        };
+        if modified_keys.is_empty() {
+            return CommandOutcome::reply(response);
+        }
+        // Replicas replay the read to keep the same pending entries
+        CommandOutcome {
+            response,
+            propagate: Some(raw.to_vec()),
+            modified_keys,
+            other_db_keys: Vec::new(),
+        }
+    }
+
+    fn process_xdel(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let [_, key, ids @ ..] = command else {
+            return wrong_number_of_arguments(command);
+        };
+        let Some(ids) = ids
+            .iter()
+            .map(|id| parse_stream_id_or_timestamp(id))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return CommandOutcome::reply(Reply::error(
+                "ERR Invalid stream ID specified as stream command argument",
+            ));
+        };
+        match self.store.delete_stream_entries(key, &ids) {
+            Ok(0) => CommandOutcome::reply(Reply::Integer(0)),
+            Ok(deleted) => CommandOutcome::write(Reply::Integer(deleted as i64), raw.to_vec(), key),
+            Err(err) => CommandOutcome::reply(Reply::Error(err.to_string())),
+        }
+    }
+
+    /// `XTRIM key MAXLEN|MINID [=|~] threshold [LIMIT count]`. Approximate trimming (`~`) trims
+    /// exactly like `=`, only allowing a LIMIT on the number of removed entries.
+    fn process_xtrim(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let [_, key, strategy, arguments @ ..] = command else {
+            return wrong_number_of_arguments(command);
+        };
+        let (approximate, arguments) = match arguments {
+            [modifier, arguments @ ..] if modifier == "~" => (true, arguments),
+            [modifier, arguments @ ..] if modifier == "=" => (false, arguments),
+            arguments => (false, arguments),
+        };
+        let (threshold, limit) = match arguments {
+            [threshold] => (threshold, None),
+            [threshold, option, limit] if option.eq_ignore_ascii_case("LIMIT") => {
+                if !approximate {
+                    return CommandOutcome::reply(Reply::error(
+                        "ERR syntax error, LIMIT cannot be used without the special ~ option",
+                    ));
+                }
+                match limit.parse::<usize>() {
+                    // Like in Redis, a limit of 0 means no limit
+                    Ok(0) => (threshold, None),
+                    Ok(limit) => (threshold, Some(limit)),
+                    Err(_) => {
+                        return CommandOutcome::reply(Reply::error(
+                            "ERR value is not an integer or out of range",
+                        ))
+                    }
+                }
+            }
+            _ => return CommandOutcome::reply(Reply::error("ERR syntax error")),
+        };
+
+        let trim = match strategy.to_uppercase().as_str() {
+            "MAXLEN" => match threshold.parse::<i64>() {
+                Ok(max_len) if max_len >= 0 => StreamTrim::MaxLen(max_len as usize),
+                Ok(_) => {
+                    return CommandOutcome::reply(Reply::error(
+                        "ERR The MAXLEN argument must be >= 0.",
+                    ))
+                }
+                Err(_) => {
+                    return CommandOutcome::reply(Reply::error(
+                        "ERR value is not an integer or out of range",
+                    ))
+                }
+            },
+            "MINID" => match parse_stream_id_or_timestamp(threshold) {
+                Some(min_id) => StreamTrim::MinId(min_id),
+                None => {
+                    return CommandOutcome::reply(Reply::error(
+                        "ERR Invalid stream ID specified as stream command argument",
+                    ))
+                }
+            },
+            _ => return CommandOutcome::reply(Reply::error("ERR syntax error")),
+        };
+
+        match self.store.trim_stream(key, trim, limit) {
+            Ok(0) => CommandOutcome::reply(Reply::Integer(0)),
+            Ok(removed) => CommandOutcome::write(Reply::Integer(removed as i64), raw.to_vec(), key),
+            Err(err) => CommandOutcome::reply(Reply::Error(err.to_string())),
+        }
+    }
+
+    fn process_xlen(&mut self, command: &[String]) -> CommandOutcome {
+        let Some(key) = command.get(1) else {
+            return wrong_number_of_arguments(command);
+        };
+        let response = match self.store.stream_len(key) {
+            Ok(len) => Reply::Integer(len as i64),
+            Err(err) => Reply::Error(err.to_string()),
+        };
+        CommandOutcome::reply(response)
+    }
+
+    fn process_xack(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let [_, key, group, ids @ ..] = command else {
+            return wrong_number_of_arguments(command);
+        };
+        let Some(ids) = ids
+            .iter()
+            .map(|id| parse_stream_id_or_timestamp(id))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return CommandOutcome::reply(Reply::error(
+                "ERR Invalid stream ID specified as stream command argument",
+            ));
+        };
+
+        match self.store.get_consumer_group_mut(key, group) {
+            Ok(consumer_group) => match consumer_group.ack(&ids) {
+                0 => CommandOutcome::reply(Reply::Integer(0)),
+                acked => CommandOutcome::write(Reply::Integer(acked as i64), raw.to_vec(), key),
+            },
+            Err(ConsumerGroupError::WrongType) => {
+                CommandOutcome::reply(Reply::Error(ConsumerGroupError::WrongType.to_string()))
+            }
+            // A missing key or group has nothing pending
+            Err(_) => CommandOutcome::reply(Reply::Integer(0)),
+        }
+    }
+
+    /// XPENDING summary form, or the extended form listing pending entries when a range is given.
+    fn process_xpending(&mut self, command: &[String]) -> CommandOutcome {
+        let [_, key, group, options @ ..] = command else {
+            return wrong_number_of_arguments(command);
+        };
+        let consumer_group = match self.store.get_consumer_group(key, group) {
+            Ok(consumer_group) => consumer_group,
+            Err(ConsumerGroupError::NoGroup) => {
+                return CommandOutcome::reply(Reply::Error(format!(
+                    "NOGROUP No such key '{key}' or consumer group '{group}'"
+                )))
+            }
+            Err(err) => return CommandOutcome::reply(Reply::Error(err.to_string())),
+        };
+
+        if options.is_empty() {
+            let pending = &consumer_group.pending;
+            let (Some((first, _)), Some((last, _))) =
+                (pending.first_key_value(), pending.last_key_value())
+            else {
+                return CommandOutcome::reply(Reply::Array(vec![
+                    Reply::Integer(0),
+                    Reply::Bulk(None),
+                    Reply::Bulk(None),
+                    Reply::NullArray,
+                ]));
+            };
+            let mut consumers: BTreeMap<&str, usize> = BTreeMap::new();
+            for entry in pending.values() {
+                *consumers.entry(&entry.consumer).or_default() += 1;
+            }
+            return CommandOutcome::reply(Reply::Array(vec![
+                Reply::Integer(pending.len() as i64),
+                Reply::bulk(&first.to_string()),
+                Reply::bulk(&last.to_string()),
+                Reply::Array(
+                    consumers
+                        .into_iter()
+                        .map(|(consumer, count)| {
+                            Reply::Array(vec![
+                                Reply::bulk(consumer),
+                                Reply::bulk(&count.to_string()),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ]));
+        }
+
+        let (min_idle, options) = match options {
+            [idle, min_idle, options @ ..] if idle.eq_ignore_ascii_case("idle") => {
+                let Ok(min_idle) = min_idle.parse::<i64>() else {
+                    return CommandOutcome::reply(Reply::error(
+                        "ERR value is not an integer or out of range",
+                    ));
+                };
+                (min_idle, options)
+            }
+            _ => (0, options),
+        };
+        let (start, end, count, consumer) = match options {
+            [start, end, count] => (start, end, count, None),
+            [start, end, count, consumer] => (start, end, count, Some(consumer)),
+            _ => return CommandOutcome::reply(Reply::error("ERR syntax error")),
+        };
+        let (Some(start), Some(end)) = (parse_range_id(start, 0), parse_range_id(end, usize::MAX))
+        else {
+            return CommandOutcome::reply(Reply::error(
+                "ERR Invalid stream ID specified as stream command argument",
+            ));
+        };
+        let Ok(count) = count.parse::<i64>() else {
+            return CommandOutcome::reply(Reply::error(
+                "ERR value is not an integer or out of range",
+            ));
+        };
+        if start > end {
+            return CommandOutcome::reply(Reply::Array(Vec::new()));
+        }
+
+        let now = Utc::now();
+        let entries = consumer_group
+            .pending
+            .range(start..=end)
+            .map(|(id, entry)| (id, entry, (now - entry.delivery_time).num_milliseconds()))
+            .filter(|(_, entry, idle)| {
+                *idle >= min_idle && consumer.is_none_or(|consumer| entry.consumer == *consumer)
+            })
+            .take(usize::try_from(count).unwrap_or(0))
+            .map(|(id, entry, idle)| {
+                Reply::Array(vec![
+                    Reply::bulk(&id.to_string()),
+                    Reply::bulk(&entry.consumer),
+                    Reply::Integer(idle),
+                    Reply::Integer(entry.delivery_count as i64),
+                ])
+            })
+            .collect();
+        CommandOutcome::reply(Reply::Array(entries))
+    }
+
+    fn process_xclaim(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let [_, key, group, consumer, min_idle, arguments @ ..] = command else {
+            return wrong_number_of_arguments(command);
+        };
+        let Ok(min_idle) = min_idle.parse::<i64>() else {
+            return CommandOutcome::reply(Reply::error(
+                "ERR Invalid min-idle-time argument for XCLAIM",
+            ));
+        };
+        // IDs come first, options start at the first argument that is not an ID
+        let ids: Vec<StreamEntryId> = arguments
+            .iter()
+            .map_while(|id| parse_stream_id_or_timestamp(id))
+            .collect();
+        let mut justid = false;
+        for option in &arguments[ids.len()..] {
+            if option.eq_ignore_ascii_case("justid") {
+                justid = true;
+            } else {
+                return CommandOutcome::reply(Reply::Error(format!(
+                    "ERR Unrecognized XCLAIM option '{option}'"
+                )));
+            }
+        }
+        if ids.is_empty() {
+            return CommandOutcome::reply(Reply::error(
+                "ERR Invalid stream ID specified as stream command argument",
+            ));
+        }
+
+        let request = ClaimRequest {
+            consumer,
+            min_idle,
+            justid,
+        };
+        match self.store.claim(key, group, request, &ids) {
+            Ok(claimed) => {
+                let response = claimed_entries_reply(&claimed.claimed, justid);
+                claim_outcome(response, &claimed, raw, justid)
+            }
+            Err(err) => CommandOutcome::reply(claim_error(err, key, group)),
+        }
+    }
+
+    fn process_xautoclaim(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let [_, key, group, consumer, min_idle, start, options @ ..] = command else {
+            return wrong_number_of_arguments(command);
+        };
+        let Ok(min_idle) = min_idle.parse::<i64>() else {
+            return CommandOutcome::reply(Reply::error(
+                "ERR Invalid min-idle-time argument for XAUTOCLAIM",
+            ));
+        };
+        let Some(start) = parse_range_id(start, 0) else {
+            return CommandOutcome::reply(Reply::error(
+                "ERR Invalid stream ID specified as stream command argument",
+            ));
+        };
+        let mut count = 100;
+        let mut justid = false;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            match option.to_lowercase().as_str() {
+                "count" => {
+                    let Some(value) = options.next().and_then(|count| count.parse::<i64>().ok())
+                    else {
+                        return CommandOutcome::reply(Reply::error(
+                            "ERR value is not an integer or out of range",
+                        ));
+                    };
+                    let Some(value) = usize::try_from(value).ok().filter(|count| *count > 0) else {
+                        return CommandOutcome::reply(Reply::error("ERR COUNT must be > 0"));
+                    };
+                    count = value;
+                }
+                "justid" => justid = true,
+                _ => return CommandOutcome::reply(Reply::error("ERR syntax error")),
+            }
+        }
+
+        let request = ClaimRequest {
+            consumer,
+            min_idle,
+            justid,
+        };
+        match self.store.auto_claim(key, group, request, start, count) {
+            Ok((next, claimed)) => {
+                let response = Reply::Array(vec![
+                    Reply::bulk(&next.to_string()),
+                    claimed_entries_reply(&claimed.claimed, justid),
+                    Reply::Array(
+                        claimed
+                            .deleted
+                            .iter()
+                            .map(|id| Reply::bulk(&id.to_string()))
+                            .collect(),
+                    ),
+                ]);
+                claim_outcome(response, &claimed, raw, justid)
+            }
+            Err(err) => CommandOutcome::reply(claim_error(err, key, group)),
+        }
+    }
+
+    fn process_config(&mut self, command: &[String]) -> CommandOutcome {
+        if command
+            .get(1)
+            .is_some_and(|action| action.eq_ignore_ascii_case("help"))
+        {
+            return CommandOutcome::reply(help_reply(
+                "CONFIG",
+                &[
+                    "GET <parameter>",
+                    "    Return the value of the configuration <parameter>.",
+                    "SET <directive> <value> [<directive> <value> ...]",
+                    "    Set the configuration <directive> to <value>.",
+                    "REWRITE",
+                    "    Rewrite the configuration file.",
+                ],
+            ));
+        }
+        if command
+            .get(1)
+            .is_some_and(|action| action.eq_ignore_ascii_case("rewrite"))
+        {
+            return match self.config.rewrite() {
+                Ok(()) => CommandOutcome::reply(Reply::ok()),
+                Err(err) => CommandOutcome::reply(Reply::Error(err.to_string())),
+            };
+        }
+        let (Some(action), Some(key)) = (command.get(1), command.get(2)) else {
+            return wrong_number_of_arguments(command);
+        };
+        if !action.eq_ignore_ascii_case("get") {
+            return CommandOutcome::reply(Reply::Error(format!(
+                "ERR unknown subcommand '{action}'. Try CONFIG HELP."
+            )));
+        }
+        let key = key.to_lowercase();
+        let Some(value) = self.config.get_arg(&key) else {
+            return CommandOutcome::reply(Reply::Array(Vec::new()));
+        };
+        CommandOutcome::reply(Reply::Array(vec![Reply::bulk(&key), Reply::bulk(&value)]))
+    }
+
+    fn process_command_info(&mut self, command: &[String]) -> CommandOutcome {
+        let subcommand = command.get(1).map(|subcommand| subcommand.to_uppercase());
+        if subcommand.as_deref() == Some("HELP") {
+            return CommandOutcome::reply(help_reply(
+                "COMMAND",
+                &[
+                    "INFO [<command-name> ...]",
+                    "    Return details about multiple Redis commands.",
+                ],
+            ));
+        }
+        if subcommand.as_deref() != Some("INFO") {
+            return CommandOutcome::reply(Reply::Error(format!(
+                "ERR unknown subcommand '{}'. Try COMMAND HELP.",
+                command.get(1).map_or("", String::as_str)
+            )));
+        }
+        let response = command[2..]
+            .iter()
+            .map(|name| match CommandVerb::try_from(name.clone()) {
+                Ok(verb) => command_meta_reply(&verb.metadata()),
+                Err(_) => Reply::Null,
+            })
+            .collect();
+        CommandOutcome::reply(Reply::Array(response))
+    }
+
+    fn process_keys(&mut self, command: &[String], raw: &[Vec<u8>]) -> CommandOutcome {
+        let Some(pattern) = raw.get(1) else {
+            return wrong_number_of_arguments(command);
+        };
+        let keys = self.store.get_keys();
+        CommandOutcome::reply(Reply::Array(
+            keys.iter()
+                .filter(|key| glob_match(pattern, key.as_bytes()))
+                .map(|key| Reply::bulk(key))
+                .collect(),
+        ))
+    }
+
+    /// INFO with the stats, replication, commandstats and keyspace sections. Without section, or
+    /// with `default`, every section but commandstats is reported, `all` or `everything` adding
+    /// it. Unknown sections are empty.
+    fn process_info(&mut self, command: &[String]) -> CommandOutcome {
+        let sections: Vec<String> = command[1..].iter().map(|s| s.to_lowercase()).collect();
+        let all_sections = sections.is_empty()
+            || sections
+                .iter()
+                .any(|section| matches!(section.as_str(), "default" | "all" | "everything"));
+        let requested = |name: &str| all_sections || sections.iter().any(|section| section == name);
+
+        let mut reported = Vec::new();
+        if requested("stats") {
+            reported.push(format!(
+                "# Stats\r\nexpired_keys:{}\r\n",
+                self.stats.expired_keys
+            ));
+        }
+        if requested("replication") {
+            reported.push(self.info_replication());
+        }
+        // Like in Redis, command statistics are not part of the default sections
+        let all_or_everything = sections
+            .iter()
+            .any(|section| matches!(section.as_str(), "all" | "everything"));
+        if all_or_everything || sections.iter().any(|section| section == "commandstats") {
+            reported.push(self.info_commandstats());
+        }
+        if requested("keyspace") {
+            reported.push(self.info_keyspace());
+        }
+        CommandOutcome::reply(Reply::bulk(&reported.join("\r\n")))
+    }
+
+    fn info_commandstats(&self) -> String {
+        let mut response = String::from("# Commandstats\r\n");
+        let stats = self
+            .stats
+            .commands
+            .iter()
+            .map(|(verb, stat)| (verb.metadata().name, stat))
+            .sorted_by_key(|(name, _)| *name);
+        for (name, stat) in stats {
+            let usec_per_call = stat.usec as f64 / stat.calls.max(1) as f64;
+            response.push_str(&format!(
+                "cmdstat_{name}:calls={},usec={},usec_per_call={usec_per_call:.2}\r\n",
+                stat.calls, stat.usec
+            ));
+        }
+        response
+    }
+
+    fn info_keyspace(&self) -> String {
+        let mut response = String::from("# Keyspace\r\n");
+        for (index, keys, expires) in self.store.keyspace() {
+            response.push_str(&format!("db{index}:keys={keys},expires={expires}\r\n"));
+        }
+        response
+    }
+
+    fn info_replication(&self) -> String {
+        let mut response = String::from("# Replication\r\n");
+        let role = match self.config.replication.role {
+            ReplicationRole::Master => String::from("master"),
+            ReplicationRole::Replica(_) => String::from("slave"),
+        };
+        response.push_str(&format!("role:{role}\r\n"));
+        let mut offset = self.config.replication.repl_offset;
+        match &self.replication {
+            Some(ReplicationInfo::Master {
+                offset: master_offset,
+                replicas,
+            }) => {
+                offset = *master_offset;
+                response.push_str(&format!("connected_slaves:{}\r\n", replicas.len()));
+                for (index, replica) in replicas.iter().enumerate() {
+                    let (ip, port) = replica.addr.map_or((String::from("?"), 0), |addr| {
+                        (addr.ip().to_string(), addr.port())
+                    });
+                    let port = replica.listening_port.unwrap_or(port);
+                    response.push_str(&format!(
+                        "slave{index}:ip={ip},port={port},state=online,offset={},lag={}\r\n",
+                        replica.offset, replica.lag
+                    ));
+                }
+            }
+            Some(ReplicationInfo::Replica {
+                offset: replica_offset,
+                link_up,
+            }) => {
+                offset = *replica_offset;
+                if let ReplicationRole::Replica((host, port)) = &self.config.replication.role {
+                    response.push_str(&format!("master_host:{host}\r\n"));
+                    response.push_str(&format!("master_port:{port}\r\n"));
+                }
+                let status = if *link_up { "up" } else { "down" };
+                response.push_str(&format!("master_link_status:{status}\r\n"));
+                response.push_str(&format!("slave_repl_offset:{replica_offset}\r\n"));
+            }
+            None => {}
+        }
+        response.push_str(&format!(
+            "master_replid:{}\r\n",
+            self.config.replication.replid
+        ));
+        response.push_str(&format!(
+            "master_replid2:{}\r\n",
+            self.config.replication.replid2
+        ));
+        response.push_str(&format!("master_repl_offset:{offset}\r\n"));
+        let second_offset = self
+            .config
+            .replication
+            .second_repl_offset
+            .map_or(-1, |offset| offset as i64);
+        response.push_str(&format!("second_repl_offset:{second_offset}\r\n"));
+        response
+    }
+}
+
+/// Convert a relative (`EX`, `PX`) or absolute (`EXAT`, `PXAT`) expire time to a date, truncated
+/// to the millisecond so that it matches what is propagated to replicas.
+fn absolute_expiry(unit: &str, time: i64) -> Option<DateTime<Utc>> {
+    let expiry = match unit {
+        "EX" => Utc::now().checked_add_signed(TimeDelta::try_seconds(time)?),
+        "PX" => Utc::now().checked_add_signed(TimeDelta::try_milliseconds(time)?),
+        "EXAT" => DateTime::from_timestamp(time, 0),
+        "PXAT" => DateTime::from_timestamp_millis(time),
+        _ => None,
+    }?;
+    DateTime::from_timestamp_millis(expiry.timestamp_millis())
+}
+
+fn to_args(args: &[&str]) -> Vec<Vec<u8>> {
+    args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+}
+
+fn command_meta_reply(meta: &CommandMeta) -> Reply {
+    let flags = meta
+        .flags
+        .iter()
+        .map(|flag| Reply::Simple(flag.to_string()))
+        .collect();
+    Reply::Array(vec![
+        Reply::bulk(meta.name),
+        Reply::Integer(meta.arity),
+        Reply::Array(flags),
+        Reply::Integer(meta.first_key),
+        Reply::Integer(meta.last_key),
+        Reply::Integer(meta.step),
+        // ACL categories, tips, key specifications and subcommands are not tracked
+        Reply::Array(Vec::new()),
+        Reply::Array(Vec::new()),
+        Reply::Array(Vec::new()),
+        Reply::Array(Vec::new()),
+    ])
+}
+
+fn claimed_entries_reply(entries: &[StreamEntry], justid: bool) -> Reply {
+    if justid {
+        Reply::Array(
+            entries
+                .iter()
+                .map(|entry| Reply::bulk(&entry.id.to_string()))
+                .collect(),
+        )
+    } else {
+        stream_reply(entries)
+    }
+}
+
+/// XCLAIM and XAUTOCLAIM are propagated as an XCLAIM of the affected IDs without idle time
+/// condition, so that replicas update the same entries whatever their own idle times.
+fn claim_outcome(
+    response: Reply,
+    claimed: &ClaimedEntries,
+    raw: &[Vec<u8>],
+    justid: bool,
+) -> CommandOutcome {
+    if claimed.claimed.is_empty() && claimed.deleted.is_empty() {
+        return CommandOutcome::reply(response);
+    }
+    let mut propagate = vec![
+        b"XCLAIM".to_vec(),
+        raw[1].clone(),
+        raw[2].clone(),
+        raw[3].clone(),
+        b"0".to_vec(),
+    ];
+    let ids = claimed
+        .claimed
+        .iter()
+        .map(|entry| entry.id)
+        .chain(claimed.deleted.iter().copied());
+    propagate.extend(ids.map(|id| id.to_string().into_bytes()));
+    if justid {
+        propagate.push(b"JUSTID".to_vec());
+    }
+    let key = String::from_utf8_lossy(&raw[1]);
+    CommandOutcome::write(response, propagate, &key)
+}
+
+fn claim_error(err: ConsumerGroupError, key: &str, group: &str) -> Reply {
+    match err {
+        ConsumerGroupError::NoGroup => Reply::Error(format!(
+            "NOGROUP No such key '{key}' or consumer group '{group}'"
+        )),
+        err => Reply::Error(err.to_string()),
+    }
+}
+
+/// Protocol negotiated by `HELLO [protover [AUTH username password] [SETNAME clientname]]`, the
+/// `current` one being kept when no version is given, along with whether the client is
+/// `authenticated` once done. Client names are accepted but ignored.
+pub fn parse_hello(
+    command: &[String],
+    current: Protocol,
+    config: &Config,
+    authenticated: bool,
+) -> Result<(Protocol, bool), Reply> {
+    let protocol = match command.get(1).map(|version| version.parse::<i64>()) {
+        None => current,
+        Some(Ok(2)) => Protocol::RESP2,
+        Some(Ok(3)) => Protocol::RESP3,
+        Some(Ok(_)) => return Err(Reply::error("NOPROTO unsupported protocol version")),
+        Some(Err(_)) => {
+            return Err(Reply::error(
+                "ERR Protocol version is not an integer or out of range",
+            ))
+        }
+    };
+
+    let mut authenticated = authenticated;
+    let mut options = command.iter().skip(2);
+    while let Some(option) = options.next() {
+        match option.to_uppercase().as_str() {
+            "AUTH" => {
+                let (Some(username), Some(password)) = (options.next(), options.next()) else {
+                    return Err(Reply::Error(format!(
+                        "ERR Syntax error in HELLO option '{option}'"
+                    )));
+                };
+                if !valid_credentials(username, password, config) {
+                    return Err(wrong_password());
+                }
+                authenticated = true;
+            }
+            "SETNAME" if options.next().is_some() => {}
+            _ => {
+                return Err(Reply::Error(format!(
+                    "ERR Syntax error in HELLO option '{option}'"
+                )))
+            }
+        }
+    }
+    if !is_authenticated(config, authenticated) {
+        return Err(Reply::error(
+            "NOAUTH HELLO must be called with the client already authenticated, otherwise the \
+             HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and \
+             select the RESP protocol version at the same time",
+        ));
+    }
+    Ok((protocol, authenticated))
+}
+
+/// Check the credentials of `AUTH [username] password`. `default` is the only user, whose
+/// password is `requirepass`.
+pub fn check_auth(command: &[String], config: &Config) -> Result<(), Reply> {
+    let (username, password) = match command {
+        [_, password] => {
+            if config.requirepass.is_none() {
+                return Err(Reply::error(
+                    "ERR AUTH <password> called without any password configured for the default \
+                     user. Are you sure your configuration is correct?",
+                ));
+            }
+            ("default", password)
+        }
+        [_, username, password] => (username.as_str(), password),
+        _ => return Err(Reply::error("ERR syntax error")),
+    };
+    if !valid_credentials(username, password, config) {
+        return Err(wrong_password());
+    }
+    Ok(())
+}
+
+fn valid_credentials(username: &str, password: &str, config: &Config) -> bool {
+    username == "default"
+        && config
+            .requirepass
+            .as_ref()
+            .is_none_or(|requirepass| requirepass == password)
+}
+
+fn wrong_password() -> Reply {
+    Reply::error("WRONGPASS invalid username-password pair or user is disabled.")
+}
+
+/// Whether a connection may run commands: always, unless a password is required and it did not
+/// authenticate yet.
+pub fn is_authenticated(config: &Config, authenticated: bool) -> bool {
+    authenticated || config.requirepass.is_none()
+}
+
+/// Error to reply when `command` needs an authentication the connection did not go through.
+pub fn check_authenticated(
+    command: &Command,
+    config: &Config,
+    authenticated: bool,
+) -> Option<Reply> {
+    if is_authenticated(config, authenticated) || command.verb.metadata().flags.contains(&"no_auth")
+    {
+        return None;
+    }
+    Some(Reply::error("NOAUTH Authentication required."))
+}
+
+/// Whether the command is `CONFIG SET`, which the actors handle as it changes their config.
+pub fn is_config_set(command: &[String]) -> bool {
+    command
+        .get(1)
+        .is_some_and(|action| action.eq_ignore_ascii_case("set"))
+}
+
+/// Configuration after `CONFIG SET <parameter> <value> [<parameter> <value> ...]`. Either all
+/// parameters are set, or none when one of them is rejected.
+pub fn config_set(command: &[String], config: &Config) -> Result<Config, Reply> {
+    let pairs = command.get(2..).unwrap_or_default();
+    if pairs.is_empty() || pairs.len() % 2 != 0 {
+        return Err(Reply::error(
+            "ERR wrong number of arguments for 'config|set' command",
+        ));
+    }
+    let mut config = config.clone();
+    for (key, value) in pairs.iter().tuples() {
+        config
+            .set(&key.to_lowercase(), value)
+            .map_err(|err| Reply::Error(err.to_string()))?;
+    }
+    Ok(config)
+}
+
+/// Server information replied to `HELLO`.
+pub fn hello_reply(config: &Config, protocol: Protocol, client_id: u64) -> Reply {
+    let role = match config.replication.role {
+        ReplicationRole::Master => "master",
+        ReplicationRole::Replica(_) => "replica",
+    };
+    let proto = match protocol {
+        Protocol::RESP2 => 2,
+        Protocol::RESP3 => 3,
+    };
+    Reply::Map(vec![
+        (Reply::bulk("server"), Reply::bulk("redis")),
+        (Reply::bulk("version"), Reply::bulk(REDIS_VERSION)),
+        (Reply::bulk("proto"), Reply::Integer(proto)),
+        (Reply::bulk("id"), Reply::Integer(client_id as i64)),
+        (Reply::bulk("mode"), Reply::bulk("standalone")),
+        (Reply::bulk("role"), Reply::bulk(role)),
+        (Reply::bulk("modules"), Reply::Array(Vec::new())),
+    ])
+}
+
+/// Error replied to commands whose name is not supported, quoting their first arguments.
+pub fn unknown_command_error(args: &[Vec<u8>]) -> Reply {
+    let name = args.first().map_or(Cow::Borrowed(""), |name| {
+        String::from_utf8_lossy(&name[..name.len().min(128)])
+    });
+    let mut message = format!("ERR unknown command '{name}', with args beginning with: ");
+    for arg in args.iter().skip(1) {
+        message.push_str(&format!(
+            "'{}' ",
+            String::from_utf8_lossy(&arg[..arg.len().min(128)])
+        ));
+    }
+    Reply::Error(message)
+}
+
+/// Error to reply when `command` does not have the number of arguments its verb expects.
+pub fn check_arity(command: &Command) -> Option<Reply> {
+    if command.verb.metadata().accepts(command.cmd.len()) {
+        return None;
+    }
+    Some(wrong_number_of_arguments(&command.cmd).response)
+}
+
+fn wrong_number_of_arguments(command: &[String]) -> CommandOutcome {
+    let name = command
+        .first()
+        .map(|s| s.to_lowercase())
+        .unwrap_or_default();
+    CommandOutcome::reply(Reply::Error(format!(
+        "ERR wrong number of arguments for '{name}' command"
+    )))
+}
+
+fn parse_requested_stream_entry_id(arg: &String) -> Option<RequestedStreamEntryId> {
+    if arg == "*" {
+        return Some(RequestedStreamEntryId::AutoGenerate);
+    }
+
+    // A bare timestamp lets the sequence number be generated
+    if !arg.is_empty() && arg.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Some(RequestedStreamEntryId::AutoGenerateSequence(
+            arg.parse::<usize>().ok()?,
+        ));
+    }
+
+    let (first, second) = arg.split_at_checked(arg.find("-")?)?;
+    let timestamp = first.parse::<usize>().ok()?;
+    let second = second.strip_prefix("-")?;
+
+    if second == "*" {
+        return Some(RequestedStreamEntryId::AutoGenerateSequence(timestamp));
+    }
+
+    let sequence_number = second.parse::<usize>().ok()?;
+    Some(RequestedStreamEntryId::Explicit(StreamEntryId {
+        timestamp,
+        sequence_number,
+    }))
+}
+
+fn parse_stream_entry_id(arg: &str) -> Option<StreamEntryId> {
+    if arg == "+" || arg == "-" {
+        return None;
+    }
+
+    let (first, second) = arg.split_at_checked(arg.find("-")?)?;
+    let timestamp = first.parse::<usize>().ok()?;
+
+    let sequence_number = second.strip_prefix("-")?.parse::<usize>().ok()?;
+    Some(StreamEntryId {
+        timestamp,
+        sequence_number,
+    })
+}
+
+/// Parse a stream ID, a bare timestamp meaning its first sequence number.
+fn parse_stream_id_or_timestamp(arg: &str) -> Option<StreamEntryId> {
+    if !arg.is_empty() && arg.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Some(StreamEntryId {
+            timestamp: arg.parse::<usize>().ok()?,
+            sequence_number: 0,
+        });
+    }
+    parse_stream_entry_id(arg)
+}
+
+/// Parse a range boundary: `-` and `+` stand for the smallest and greatest IDs, and a bare
+/// timestamp uses `sequence_number`.
+fn parse_range_id(arg: &str, sequence_number: usize) -> Option<StreamEntryId> {
+    match arg {
+        "-" => Some(StreamEntryId::default()),
+        "+" => Some(StreamEntryId {
+            timestamp: usize::MAX,
+            sequence_number: usize::MAX,
+        }),
+        _ if !arg.is_empty() && arg.bytes().all(|byte| byte.is_ascii_digit()) => {
+            Some(StreamEntryId {
+                timestamp: arg.parse::<usize>().ok()?,
+                sequence_number,
+            })
+        }
+        _ => parse_stream_entry_id(arg),
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct XREADGROUPArguments {
+    pub group: String,
+    pub consumer: String,
+    pub count: Option<usize>,
+    pub block_for: Option<usize>,
+    pub noack: bool,
+    /// Streams to read, with `None` standing for the special `>` ID.
+    pub streams: Vec<(String, Option<StreamEntryId>)>,
+}
+
+pub fn parse_xreadgroup_arguments(cmd: &[String]) -> Result<XREADGROUPArguments, &'static str> {
+    const SYNTAX_ERROR: &str = "ERR syntax error";
+    let mut iter = cmd[1..].iter();
+
+    if !iter
+        .next()
+        .is_some_and(|arg| arg.eq_ignore_ascii_case("group"))
+    {
+        return Err(SYNTAX_ERROR);
+    }
+    let (Some(group), Some(consumer)) = (iter.next(), iter.next()) else {
+        return Err(SYNTAX_ERROR);
+    };
+    let mut count = None;
+    let mut block_for = None;
+    let mut noack = false;
+    loop {
+        let option = iter.next().ok_or(SYNTAX_ERROR)?.to_lowercase();
+        match option.as_str() {
+            "count" => {
+                // A count of 0 means no limit, like in Redis
+                count = Some(
+                    iter.next()
+                        .and_then(|count| count.parse::<usize>().ok())
+                        .ok_or("ERR value is not an integer or out of range")?,
+                )
+                .filter(|count| *count > 0);
+            }
+            "block" => {
+                block_for = Some(
+                    iter.next()
+                        .and_then(|timeout| timeout.parse::<usize>().ok())
+                        .ok_or("ERR timeout is not an integer or out of range")?,
+                );
+            }
+            "noack" => noack = true,
+            "streams" => break,
+            _ => return Err(SYNTAX_ERROR),
+        }
+    }
+
+    let cmd = iter.as_slice();
+    if cmd.is_empty() || cmd.len() % 2 != 0 {
+        return Err(
+            "ERR Unbalanced 'xreadgroup' list of streams: for each stream key an ID or \
+                    '>' must be specified.",
+        );
+    }
+    let (names, ids) = cmd.split_at(cmd.len() / 2);
+    let streams = zip(names, ids)
+        .map(|(name, id)| {
+            let id = if id == ">" {
+                None
+            } else {
+                Some(
+                    parse_stream_id_or_timestamp(id)
+                        .ok_or("ERR Invalid stream ID specified as stream command argument")?,
+                )
+            };
+            Ok((name.clone(), id))
+        })
+        .collect::<Result<_, &'static str>>()?;
+
+    Ok(XREADGROUPArguments {
+        group: group.clone(),
+        consumer: consumer.clone(),
+        count,
+        block_for,
+        noack,
+        streams,
+    })
+}
+
+#[derive(PartialEq, Debug)]
+pub struct XREADArguments {
+    pub streams: Vec<(String, Option<StreamEntryId>)>,
+    pub block_for: Option<usize>,
+}
+
+pub fn parse_xread_arguments(cmd: &[String]) -> Option<XREADArguments> {
+    let mut iter = cmd[1..].iter();
+
+    let mut option = iter.next()?;
+    let timeout = if option.eq_ignore_ascii_case("block") {
+        let timeout = iter.next().and_then(|t| t.as_str().parse::<usize>().ok());
+        option = iter.next()?;
+        timeout
+    } else {
+        None
+    };
+    if !option.eq_ignore_ascii_case("streams") {
+        return None;
+    }
+    let cmd = iter.as_slice();
+    let midpoint = cmd.len() / 2;
+    let names = cmd[..midpoint].iter();
+    let ids = cmd[midpoint..].iter();
+
+    let streams: Vec<(String, Option<StreamEntryId>)> = zip(names, ids)
+        .map(|(name, id)| (name.clone(), parse_stream_entry_id(id)))
+        .collect();
+
+    Some(XREADArguments {
+        streams,
+        block_for: timeout,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::{Args, Config},
+        connection::{
+            parser::Command,
+            reply::{Protocol, Reply},
+        },
+        store::{
+            stream::{RequestedStreamEntryId, StreamEntryId},
+            Store,
+        },
+    };
+
+    use super::{
+        check_auth, check_authenticated, parse_requested_stream_entry_id, parse_xread_arguments,
+        to_args, CommandHandler, CommandOutcome, XREADArguments,
+    };
+
+    fn handle(store: &mut Store, config: &Config, cmd: &[&str]) -> Option<CommandOutcome> {
+        let command = Command::new(cmd.iter().map(|s| s.as_bytes().to_vec()).collect()).unwrap();
+        CommandHandler::new(store, config).handle(&command)
+    }
+
+    #[test]
+    fn set_then_get() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+
+        let outcome = handle(&mut store, &config, &["SET", "foo", "bar"]).unwrap();
+        assert_eq!(
+            outcome,
+            CommandOutcome {
+                response: Reply::ok(),
+                propagate: Some(vec![b"SET".to_vec(), b"foo".to_vec(), b"bar".to_vec()]),
+                modified_keys: vec!["foo".to_owned()],
+                other_db_keys: Vec::new(),
+            }
+        );
+
+        let outcome = handle(&mut store, &config, &["GET", "foo"]).unwrap();
+        assert_eq!(outcome.response, Reply::bulk("bar"));
+        assert_eq!(outcome.propagate, None);
+    }
+
+    #[test]
+    fn requirepass_is_enforced() {
+        let command = |cmd: &[&str]| {
+            Command::new(cmd.iter().map(|s| s.as_bytes().to_vec()).collect()).unwrap()
+        };
+        let config = Config::from_args(Args::from([(
+            String::from("requirepass"),
+            String::from("secret"),
+        )]));
+        let auth = |cmd: &[&str]| check_auth(&to_args_strings(cmd), &config);
+
+        assert_eq!(
+            check_authenticated(&command(&["GET", "foo"]), &config, false),
+            Some(Reply::error("NOAUTH Authentication required."))
+        );
+        assert_eq!(
+            check_authenticated(&command(&["GET", "foo"]), &config, true),
+            None
+        );
+        assert_eq!(
+            check_authenticated(&command(&["AUTH", "secret"]), &config, false),
+            None
+        );
+        assert_eq!(auth(&["AUTH", "secret"]), Ok(()));
+        assert_eq!(auth(&["AUTH", "default", "secret"]), Ok(()));
+        assert!(auth(&["AUTH", "nope"]).is_err());
+        assert!(auth(&["AUTH", "admin", "secret"]).is_err());
+
+        let config = Config::from_args(Args::new());
+        assert_eq!(
+            check_authenticated(&command(&["GET", "foo"]), &config, false),
+            None
+        );
+        assert!(check_auth(&to_args_strings(&["AUTH", "secret"]), &config).is_err());
+    }
+
+    #[test]
+    fn set_then_get_binary_value() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+        let value = vec![0xff, 0x00, 0xfe];
+
+        let command = Command::new(vec![b"SET".to_vec(), b"foo".to_vec(), value.clone()]).unwrap();
+        let outcome = CommandHandler::new(&mut store, &config)
+            .handle(&command)
+            .unwrap();
+        assert_eq!(outcome.propagate, Some(command.raw));
+
+        let outcome = handle(&mut store, &config, &["GET", "foo"]).unwrap();
+        assert_eq!(outcome.response, Reply::Bulk(Some(value)));
+    }
+
+    #[test]
+    fn xadd_against_string_key() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+        handle(&mut store, &config, &["SET", "k", "v"]).unwrap();
+
+        let outcome = handle(&mut store, &config, &["XADD", "k", "*", "f", "v"]).unwrap();
+        assert_eq!(
+            outcome,
+            CommandOutcome::reply(Reply::error(
+                "WRONGTYPE Operation against a key holding the wrong kind of value"
+            ))
+        );
+        let outcome = handle(&mut store, &config, &["GET", "k"]).unwrap();
+        assert_eq!(outcome.response, Reply::bulk("v"));
+    }
+
+    #[test]
+    fn keys_filters_on_pattern() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+        for key in ["foo", "far", "bar"] {
+            handle(&mut store, &config, &["SET", key, "1"]).unwrap();
+        }
+
+        let outcome = handle(&mut store, &config, &["KEYS", "f*"]).unwrap();
+        let Reply::Array(keys) = outcome.response else {
+            panic!("KEYS should reply with an array");
+        };
+        assert_eq!(keys.len(), 2);
+        assert!(keys.contains(&Reply::bulk("foo")) && keys.contains(&Reply::bulk("far")));
+
+        let outcome = handle(&mut store, &config, &["KEYS", "b?[rz]"]).unwrap();
+        assert_eq!(outcome.response, Reply::Array(vec![Reply::bulk("bar")]));
+    }
+
+    /// Apply the command propagated by `outcome`, as a replica would.
+    fn replicate(replica: &mut Store, config: &Config, outcome: &CommandOutcome) {
+        let propagate = outcome.propagate.clone().unwrap();
+        CommandHandler::new(replica, config)
+            .handle(&Command::new(propagate).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn set_expiry_is_propagated_as_absolute_time() {
+        let mut master = Store::new();
+        let mut replica = Store::new();
+        let config = Config::from_args(Args::new());
+
+        let in_a_minute = (chrono::Utc::now().timestamp_millis() + 60_000).to_string();
+        for (unit, time) in [
+            ("EX", "10"),
+            ("PX", "10000"),
+            ("PXAT", in_a_minute.as_str()),
+        ] {
+            let outcome = handle(&mut master, &config, &["SET", "foo", "bar", unit, time]);
+            let outcome = outcome.unwrap();
+            let propagate = outcome.propagate.as_ref().unwrap();
+            assert_eq!(propagate[3], b"PXAT".to_vec());
+            let expiry = master.get_expiry("foo").unwrap();
+            assert_eq!(
+                propagate[4],
+                expiry.timestamp_millis().to_string().into_bytes()
+            );
+            replicate(&mut replica, &config, &outcome);
+            assert_eq!(replica.get_expiry("foo"), Some(expiry));
+        }
+
+        let outcome = handle(&mut master, &config, &["SET", "foo", "bar", "PX", "0"]).unwrap();
+        assert_eq!(
+            outcome.response,
+            Reply::error("ERR invalid expire time in 'set' command")
+        );
+    }
+
+    #[test]
+    fn expire_options_are_applied() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+        let mut expire = |args: &[&str]| handle(&mut store, &config, args).unwrap().response;
+        let _ = expire(&["SET", "foo", "bar"]);
+
+        assert_eq!(expire(&["EXPIRE", "foo", "100", "XX"]), Reply::Integer(0));
+        assert_eq!(expire(&["EXPIRE", "foo", "100", "GT"]), Reply::Integer(0));
+        assert_eq!(expire(&["EXPIRE", "foo", "100", "NX"]), Reply::Integer(1));
+        assert_eq!(expire(&["EXPIRE", "foo", "200", "NX"]), Reply::Integer(0));
+        assert_eq!(expire(&["EXPIRE", "foo", "200", "LT"]), Reply::Integer(0));
+        assert_eq!(expire(&["EXPIRE", "foo", "50", "lt"]), Reply::Integer(1));
+        assert_eq!(expire(&["EXPIRE", "foo", "10", "GT"]), Reply::Integer(0));
+        assert_eq!(
+            expire(&["EXPIRE", "foo", "300", "XX", "GT"]),
+            Reply::Integer(1)
+        );
+        assert_eq!(expire(&["PERSIST", "foo"]), Reply::Integer(1));
+        assert_eq!(expire(&["EXPIRE", "foo", "300", "LT"]), Reply::Integer(1));
+        assert_eq!(
+            expire(&["EXPIRE", "missing", "10", "LT"]),
+            Reply::Integer(0)
+        );
+
+        assert_eq!(
+            expire(&["EXPIRE", "foo", "10", "NX", "GT"]),
+            Reply::error("ERR NX and XX, GT or LT options at the same time are not compatible")
+        );
+        assert_eq!(
+            expire(&["EXPIRE", "foo", "10", "GT", "LT"]),
+            Reply::error("ERR GT and LT options at the same time are not compatible")
+        );
+        assert_eq!(
+            expire(&["EXPIRE", "foo", "10", "KEEPTTL"]),
+            Reply::error("ERR Unsupported option KEEPTTL")
+        );
+    }
+
+    #[test]
+    fn getdel_and_getex_are_replicated() {
+        let mut master = Store::new();
+        let mut replica = Store::new();
+        let config = Config::from_args(Args::new());
+
+        for key in ["foo", "bar"] {
+            let outcome = handle(&mut master, &config, &["SET", key, "1"]).unwrap();
+            replicate(&mut replica, &config, &outcome);
+        }
+
+        let outcome = handle(&mut master, &config, &["GETDEL", "foo"]).unwrap();
+        assert_eq!(outcome.response, Reply::bulk("1"));
+        replicate(&mut replica, &config, &outcome);
+        assert_eq!(replica.get_string("foo"), Ok(None));
+
+        let outcome = handle(&mut master, &config, &["GETEX", "bar", "PX", "10000"]).unwrap();
+        assert_eq!(
+            outcome.propagate.as_ref().unwrap()[0],
+            b"PEXPIREAT".to_vec()
+        );
+        replicate(&mut replica, &config, &outcome);
+        assert_eq!(replica.get_expiry("bar"), master.get_expiry("bar"));
+
+        let outcome = handle(&mut master, &config, &["GETEX", "bar", "PERSIST"]).unwrap();
+        replicate(&mut replica, &config, &outcome);
+        assert_eq!(replica.get_expiry("bar"), None);
+
+        let outcome = handle(&mut master, &config, &["GETEX", "bar"]).unwrap();
+        assert_eq!(outcome.propagate, None);
+    }
+
+    #[test]
+    fn xadd_propagates_generated_id() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+
+        let outcome = handle(&mut store, &config, &["XADD", "s", "1-*", "f", "v"]).unwrap();
+        assert_eq!(outcome.response, Reply::bulk("1-0"));
+        assert_eq!(
+            outcome.propagate,
+            Some(vec![
+                b"XADD".to_vec(),
+                b"s".to_vec(),
+                b"1-0".to_vec(),
+                b"f".to_vec(),
+                b"v".to_vec()
+            ])
+        );
+    }
+
+    #[test]
+    fn incr_on_non_integer_is_not_propagated() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+        store.set_string("foo", b"bar", None);
+
+        let outcome = handle(&mut store, &config, &["INCR", "foo"]).unwrap();
+        assert_eq!(
+            outcome,
+            CommandOutcome {
+                response: Reply::error("ERR value is not an integer or out of range"),
+                propagate: None,
+                modified_keys: Vec::new(),
+                other_db_keys: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn append_yields_raw_encoding() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+
+        handle(&mut store, &config, &["SET", "k", "123"]);
+        let outcome = handle(&mut store, &config, &["OBJECT", "ENCODING", "k"]).unwrap();
+        assert_eq!(outcome.response, Reply::bulk("int"));
+
+        let outcome = handle(&mut store, &config, &["APPEND", "k", "4"]).unwrap();
+        assert_eq!(outcome.response, Reply::Integer(4));
+        assert!(outcome.propagate.is_some());
+        let outcome = handle(&mut store, &config, &["OBJECT", "ENCODING", "k"]).unwrap();
+        assert_eq!(outcome.response, Reply::bulk("raw"));
+
+        let outcome = handle(&mut store, &config, &["SETRANGE", "k", "1", "xy"]).unwrap();
+        assert_eq!(outcome.response, Reply::Integer(4));
+        let outcome = handle(&mut store, &config, &["GETRANGE", "k", "0", "-2"]).unwrap();
+        assert_eq!(outcome.response, Reply::bulk("1xy"));
+        let outcome = handle(&mut store, &config, &["SETRANGE", "k", "-1", "a"]).unwrap();
+        assert_eq!(outcome.response, Reply::error("ERR offset is out of range"));
+    }
+
+    #[test]
+    fn offsets_are_limited_to_max_string_size() {
+        let mut store = Store::new();
+        let mut args = Args::new();
+        args.insert(String::from("proto-max-bulk-len"), String::from("1kb"));
+        let config = Config::from_args(args);
+
+        let bit_offset_error = Reply::error("ERR bit offset is not an integer or out of range");
+        let string_size_error =
+            Reply::error("ERR string exceeds maximum allowed size (proto-max-bulk-len)");
+        for (command, response) in [
+            (&["SETBIT", "k", "8191", "1"][..], Reply::Integer(0)),
+            (&["GETBIT", "k", "8191"], Reply::Integer(1)),
+            (&["SETBIT", "k", "8192", "1"], bit_offset_error.clone()),
+            (&["SETBIT", "k", "-1", "1"], bit_offset_error.clone()),
+            (&["GETBIT", "k", "4294967296"], bit_offset_error.clone()),
+            (
+                &["SETBIT", "k", "0", "2"],
+                Reply::error("ERR bit is not an integer or out of range"),
+            ),
+            (
+                &["BITFIELD", "k", "SET", "u8", "8184", "1"],
+                Reply::Array(vec![Reply::Integer(1)]),
+            ),
+            (&["BITFIELD", "k", "GET", "u8", "8185"], bit_offset_error),
+            (&["SETRANGE", "k", "1020", "abcd"], Reply::Integer(1024)),
+            (&["SETRANGE", "k", "1020", "abcde"], string_size_error),
+        ] {
+            let outcome = handle(&mut store, &config, command).unwrap();
+            assert_eq!(outcome.response, response, "{command:?}");
+        }
+    }
+
+    #[test]
+    fn writes_are_rejected_over_maxmemory() {
+        let mut store = Store::new();
+        let mut args = Args::new();
+        args.insert(String::from("maxmemory"), String::from("100"));
+        let config = Config::from_args(args);
+        let value = "v".repeat(200);
+        let oom = Reply::error("OOM command not allowed when used memory > 'maxmemory'.");
+
+        let outcome = handle(&mut store, &config, &["SET", "foo", &value]).unwrap();
+        assert_eq!(outcome.response, Reply::ok());
+        let outcome = handle(&mut store, &config, &["SET", "bar", "v"]).unwrap();
+        assert_eq!(outcome.response, oom);
+        assert_eq!(outcome.propagate, None);
+        let outcome = handle(&mut store, &config, &["APPEND", "foo", "v"]).unwrap();
+        assert_eq!(outcome.response, oom);
+
+        let outcome = handle(&mut store, &config, &["GET", "foo"]).unwrap();
+        assert_eq!(outcome.response, Reply::bulk(&value));
+        let outcome = handle(&mut store, &config, &["DEL", "foo"]).unwrap();
+        assert_eq!(outcome.response, Reply::Integer(1));
+        let outcome = handle(&mut store, &config, &["SET", "bar", "v"]).unwrap();
+        assert_eq!(outcome.response, Reply::ok());
+    }
+
+    #[test]
+    fn stream_is_kept_once_emptied() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+
+        handle(&mut store, &config, &["XADD", "s", "1-1", "foo", "bar"]);
+        let outcome = handle(&mut store, &config, &["XDEL", "s", "1-1", "2"]).unwrap();
+        assert_eq!(outcome.response, Reply::Integer(1));
+        assert!(outcome.propagate.is_some());
+        let outcome = handle(&mut store, &config, &["XDEL", "s", "1-1"]).unwrap();
+        assert_eq!(outcome.response, Reply::Integer(0));
+        assert_eq!(outcome.propagate, None);
+
+        let outcome = handle(&mut store, &config, &["TYPE", "s"]).unwrap();
+        assert_eq!(outcome.response, Reply::Simple(String::from("stream")));
+        let outcome = handle(&mut store, &config, &["XLEN", "s"]).unwrap();
+        assert_eq!(outcome.response, Reply::Integer(0));
+        let outcome = handle(&mut store, &config, &["XLEN", "missing"]).unwrap();
+        assert_eq!(outcome.response, Reply::Integer(0));
+    }
+
+    #[test]
+    fn counts_are_integer_replies() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+        let mut resp = |command: &[&str]| {
+            let outcome = handle(&mut store, &config, command).unwrap();
+            String::from_utf8(outcome.response.encode(Protocol::RESP2)).unwrap()
+        };
+
+        resp(&["SET", "a", "1"]);
+        resp(&["SET", "b", "1"]);
+        assert_eq!(resp(&["EXISTS", "a", "b", "a", "c"]), ":3\r\n");
+        assert_eq!(resp(&["APPEND", "a", "23"]), ":3\r\n");
+        assert_eq!(resp(&["PERSIST", "a"]), ":0\r\n");
+        assert_eq!(resp(&["SETBIT", "c", "1", "1"]), ":0\r\n");
+        assert_eq!(resp(&["DEL", "a", "b", "d"]), ":2\r\n");
+        assert_eq!(resp(&["EXISTS", "a"]), ":0\r\n");
+    }
+
+    #[test]
+    fn xtrim_by_length_or_minimum_id() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+        for id in ["1-1", "1-2", "2-1", "3-1", "4-1"] {
+            handle(&mut store, &config, &["XADD", "s", id, "f", "v"]);
+        }
+        let mut xtrim = |command: &[&str]| handle(&mut store, &config, command).unwrap();
+
+        let outcome = xtrim(&["XTRIM", "s", "MAXLEN", "4"]);
+        assert_eq!(outcome.response, Reply::Integer(1));
+        assert!(outcome.propagate.is_some());
+        let outcome = xtrim(&["XTRIM", "s", "MINID", "=", "3"]);
+        assert_eq!(outcome.response, Reply::Integer(2));
+        let outcome = xtrim(&["XTRIM", "s", "MAXLEN", "~", "0", "LIMIT", "1"]);
+        assert_eq!(outcome.response, Reply::Integer(1));
+        let outcome = xtrim(&["XTRIM", "s", "MINID", "2-0"]);
+        assert_eq!(outcome.response, Reply::Integer(0));
+        assert_eq!(outcome.propagate, None);
+        assert_eq!(
+            xtrim(&["XTRIM", "s", "MAXLEN", "0", "LIMIT", "1"]).response,
+            Reply::error("ERR syntax error, LIMIT cannot be used without the special ~ option")
+        );
+        assert_eq!(
+            xtrim(&["XTRIM", "s", "MAXLEN", "-1"]).response,
+            Reply::error("ERR The MAXLEN argument must be >= 0.")
+        );
+        assert_eq!(
+            xtrim(&["XRANGE", "s", "-", "+"]).response,
+            Reply::Array(vec![Reply::Array(vec![
+                Reply::bulk("4-1"),
+                Reply::Array(vec![Reply::bulk("f"), Reply::bulk("v")])
+            ])])
+        );
+    }
+
+    #[test]
+    fn rename_and_copy_keep_expiry() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+        let pttl = |store: &mut Store, key: &str| match handle(store, &config, &["PTTL", key])
+            .unwrap()
+            .response
+        {
+            Reply::Integer(ttl) => ttl,
+            reply => panic!("unexpected PTTL reply {reply:?}"),
+        };
+
+        handle(&mut store, &config, &["SET", "a", "v", "PX", "10000"]);
+        handle(&mut store, &config, &["SET", "b", "old"]);
+        let outcome = handle(&mut store, &config, &["RENAME", "a", "b"]).unwrap();
+        assert_eq!(outcome.response, Reply::ok());
+        assert_eq!(outcome.modified_keys, vec!["a", "b"]);
+        assert!((9000..=10000).contains(&pttl(&mut store, "b")));
+        assert_eq!(pttl(&mut store, "a"), -2);
+
+        let outcome = handle(&mut store, &config, &["COPY", "b", "c"]).unwrap();
+        assert_eq!(outcome.response, Reply::Integer(1));
+        assert!((9000..=10000).contains(&pttl(&mut store, "c")));
+        let outcome = handle(&mut store, &config, &["GET", "c"]).unwrap();
+        assert_eq!(outcome.response, Reply::bulk("v"));
+
+        handle(&mut store, &config, &["SET", "d", "persistent"]);
+        let outcome = handle(&mut store, &config, &["COPY", "d", "c"]).unwrap();
+        assert_eq!(outcome.response, Reply::Integer(0));
+        let outcome = handle(&mut store, &config, &["COPY", "d", "c", "REPLACE"]).unwrap();
+        assert_eq!(outcome.response, Reply::Integer(1));
+        assert_eq!(pttl(&mut store, "c"), -1);
+
+        let outcome = handle(&mut store, &config, &["RENAME", "missing", "e"]).unwrap();
+        assert_eq!(outcome.response, Reply::error("ERR no such key"));
+    }
+
+    #[test]
+    fn move_keeps_expiry_and_does_not_overwrite() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+
+        handle(&mut store, &config, &["SET", "a", "v", "PX", "10000"]);
+        let outcome = handle(&mut store, &config, &["MOVE", "a", "1"]).unwrap();
+        assert_eq!(outcome.response, Reply::Integer(1));
+        assert_eq!(outcome.modified_keys, vec!["a"]);
+        assert_eq!(outcome.other_db_keys, vec![(1, String::from("a"))]);
+        assert_eq!(store.get_string("a"), Ok(None));
+        store.select(1);
+        assert_eq!(store.get_string("a"), Ok(Some(b"v".to_vec())));
+        let outcome = handle(&mut store, &config, &["PTTL", "a"]).unwrap();
+        assert!(matches!(outcome.response, Reply::Integer(ttl) if (9000..=10000).contains(&ttl)));
+
+        // Missing in the source, or already in the destination
+        let outcome = handle(&mut store, &config, &["MOVE", "missing", "0"]).unwrap();
+        assert_eq!(outcome.response, Reply::Integer(0));
+        assert_eq!(outcome.propagate, None);
+        store.select(0);
+        handle(&mut store, &config, &["SET", "a", "other"]);
+        let outcome = handle(&mut store, &config, &["MOVE", "a", "1"]).unwrap();
+        assert_eq!(outcome.response, Reply::Integer(0));
+        assert_eq!(store.get_string("a"), Ok(Some(b"other".to_vec())));
+
+        for (db, error) in [
+            ("0", "ERR source and destination objects are the same"),
+            ("16", "ERR DB index is out of range"),
+            ("x", "ERR value is not an integer or out of range"),
+        ] {
+            let outcome = handle(&mut store, &config, &["MOVE", "a", db]).unwrap();
+            assert_eq!(outcome.response, Reply::error(error));
+        }
+    }
+
+    #[test]
+    fn incr_and_decr_overflow() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+        store.set_string("max", i64::MAX.to_string().as_bytes(), None);
+        store.set_string("min", i64::MIN.to_string().as_bytes(), None);
+
+        let overflow = Reply::error("ERR increment or decrement would overflow");
+        for command in [
+            &["INCR", "max"][..],
+            &["INCRBY", "max", "1"],
+            &["DECR", "min"],
+            &["DECRBY", "min", "1"],
+        ] {
+            let outcome = handle(&mut store, &config, command).unwrap();
+            assert_eq!(outcome.response, overflow);
+            assert_eq!(outcome.propagate, None);
+        }
+
+        let outcome = handle(
+            &mut store,
+            &config,
+            &["DECRBY", "min", &i64::MIN.to_string()],
+        );
+        assert_eq!(
+            outcome.unwrap().response,
+            Reply::error("ERR decrement would overflow")
+        );
+        let outcome = handle(&mut store, &config, &["DECRBY", "max", "10"]).unwrap();
+        assert_eq!(outcome.response, Reply::Integer(i64::MAX - 10));
+    }
+
+    #[test]
+    fn xgroup_and_xreadgroup() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+
+        let mut run = |command: &[&str]| {
+            let outcome = handle(&mut store, &config, command).unwrap();
+            let response = String::from_utf8(outcome.response.encode(Protocol::RESP2)).unwrap();
+            (response, outcome.propagate.is_some())
+        };
+        assert!(run(&["XGROUP", "CREATE", "s", "g", "$"])
+            .0
+            .starts_with("-ERR The XGROUP subcommand requires the key to exist."));
+        assert_eq!(
+            run(&["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"]),
+            ("+OK\r\n".to_owned(), true)
+        );
+        assert_eq!(
+            run(&["XGROUP", "CREATE", "s", "g", "0"]).0,
+            "-BUSYGROUP Consumer Group name already exists\r\n"
+        );
+        assert_eq!(
+            run(&["XGROUP", "DESTROYALL", "s", "g"]).0,
+            "-ERR unknown subcommand 'DESTROYALL'. Try XGROUP HELP.\r\n"
+        );
+        run(&["XADD", "s", "1-0", "f", "v"]);
+
+        assert_eq!(
+            run(&["XREADGROUP", "GROUP", "other", "c", "STREAMS", "s", ">"]).0,
+            "-NOGROUP No such key 's' or consumer group 'other' in XREADGROUP with GROUP \
+             option\r\n"
+        );
+        assert_eq!(
+            run(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s"]).0,
+            "-ERR Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' \
+             must be specified.\r\n"
+        );
+        assert_eq!(
+            run(&[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "c",
+                "count",
+                "1",
+                "STREAMS",
+                "s",
+                ">"
+            ]),
+            (
+                "*1\r\n*2\r\n$1\r\ns\r\n*1\r\n*2\r\n$3\r\n1-0\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n"
+                    .to_owned(),
+                true
+            )
+        );
+        // Nothing new for the group
+        assert_eq!(
+            run(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s", ">"]),
+            ("*-1\r\n".to_owned(), false)
+        );
+        // The pending entry is delivered again when reading the consumer's history
+        assert_eq!(
+            run(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s", "0"]).0,
+            "*1\r\n*2\r\n$1\r\ns\r\n*1\r\n*2\r\n$3\r\n1-0\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n"
+        );
+        assert_eq!(
+            run(&["XREADGROUP", "GROUP", "g", "other", "STREAMS", "s", "0"]).0,
+            "*1\r\n*2\r\n$1\r\ns\r\n*0\r\n"
+        );
+
+        assert_eq!(
+            run(&["XPENDING", "s", "g"]).0,
+            "*4\r\n:1\r\n$3\r\n1-0\r\n$3\r\n1-0\r\n*1\r\n*2\r\n$1\r\nc\r\n$1\r\n1\r\n"
+        );
+        assert!(run(&["XPENDING", "s", "g", "-", "+", "10", "c"])
+            .0
+            .starts_with("*1\r\n*4\r\n$3\r\n1-0\r\n$1\r\nc\r\n:"));
+        assert!(run(&["XPENDING", "s", "g", "-", "+", "10", "c"])
+            .0
+            .ends_with(":2\r\n"));
+        assert_eq!(
+            run(&["XPENDING", "s", "g", "-", "+", "10", "other"]).0,
+            "*0\r\n"
+        );
+        assert_eq!(
+            run(&["XPENDING", "s", "g", "IDLE", "60000", "-", "+", "10"]).0,
+            "*0\r\n"
+        );
+        assert_eq!(
+            run(&["XPENDING", "s", "missing"]).0,
+            "-NOGROUP No such key 's' or consumer group 'missing'\r\n"
+        );
+
+        assert_eq!(
+            run(&["XACK", "s", "g", "1-0", "2-0"]),
+            (":1\r\n".to_owned(), true)
+        );
+        assert_eq!(
+            run(&["XACK", "s", "g", "1-0"]),
+            (":0\r\n".to_owned(), false)
+        );
+        assert_eq!(run(&["XACK", "missing", "g", "1-0"]).0, ":0\r\n");
+        assert_eq!(
+            run(&["XCLAIM", "s", "g", "c", "0", "1-0"]),
+            ("*0\r\n".to_owned(), false)
+        );
+        assert_eq!(
+            run(&["XPENDING", "s", "g"]).0,
+            "*4\r\n:0\r\n$-1\r\n$-1\r\n*-1\r\n"
+        );
+        assert_eq!(
+            run(&["XACK", "s", "g", "nope"]).0,
+            "-ERR Invalid stream ID specified as stream command argument\r\n"
+        );
+        assert_eq!(
+            run(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s", "0"]).0,
+            "*1\r\n*2\r\n$1\r\ns\r\n*0\r\n"
+        );
+    }
+
+    #[test]
+    fn xpending_summary() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+
+        let mut run = |command: &[&str]| handle(&mut store, &config, command).unwrap().response;
+        run(&["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"]);
+        assert_eq!(
+            run(&["XPENDING", "s", "g"]),
+            Reply::Array(vec![
+                Reply::Integer(0),
+                Reply::Bulk(None),
+                Reply::Bulk(None),
+                Reply::NullArray
+            ])
+        );
+
+        for id in ["1-0", "2-0", "3-0"] {
+            run(&["XADD", "s", id, "f", "v"]);
+        }
+        run(&[
+            "XREADGROUP",
+            "GROUP",
+            "g",
+            "bob",
+            "COUNT",
+            "2",
+            "STREAMS",
+            "s",
+            ">",
+        ]);
+        run(&["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"]);
+        assert_eq!(
+            run(&["XPENDING", "s", "g"]),
+            Reply::Array(vec![
+                Reply::Integer(3),
+                Reply::bulk("1-0"),
+                Reply::bulk("3-0"),
+                Reply::Array(vec![
+                    Reply::Array(vec![Reply::bulk("alice"), Reply::bulk("1")]),
+                    Reply::Array(vec![Reply::bulk("bob"), Reply::bulk("2")]),
+                ]),
+            ])
+        );
+        assert_eq!(
+            run(&["XPENDING", "missing", "g"]),
+            Reply::error("NOGROUP No such key 'missing' or consumer group 'g'")
+        );
+        assert_eq!(
+            run(&["XPENDING", "s"]),
+            Reply::error("ERR wrong number of arguments for 'xpending' command")
+        );
+    }
+
+    #[test]
+    fn xpending_extended_form() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+
+        let mut run = |command: &[&str]| handle(&mut store, &config, command).unwrap().response;
+        run(&["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"]);
+        for id in ["1-0", "2-0", "3-0"] {
+            run(&["XADD", "s", id, "f", "v"]);
+        }
+        run(&[
+            "XREADGROUP",
+            "GROUP",
+            "g",
+            "bob",
+            "COUNT",
+            "2",
+            "STREAMS",
+            "s",
+            ">",
+        ]);
+        run(&["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"]);
+        // Delivered again to bob, counting a second delivery
+        run(&["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", "0"]);
+
+        // Idle times depend on the clock, only the other fields are compared
+        let pending = |reply: Reply| -> Vec<Reply> {
+            let Reply::Array(entries) = reply else {
+                panic!("XPENDING should reply with an array, got {reply:?}");
+            };
+            entries
+                .into_iter()
+                .map(|entry| match entry {
+                    Reply::Array(mut fields) if fields.len() == 4 => {
+                        fields[2] = Reply::Integer(0);
+                        Reply::Array(fields)
+                    }
+                    entry => entry,
+                })
+                .collect()
+        };
+        let entry = |id: &str, consumer: &str, deliveries| {
+            Reply::Array(vec![
+                Reply::bulk(id),
+                Reply::bulk(consumer),
+                Reply::Integer(0),
+                Reply::Integer(deliveries),
+            ])
+        };
+
+        assert_eq!(
+            pending(run(&["XPENDING", "s", "g", "-", "+", "10"])),
+            vec![
+                entry("1-0", "bob", 2),
+                entry("2-0", "bob", 2),
+                entry("3-0", "alice", 1)
+            ]
+        );
+        assert_eq!(
+            pending(run(&["XPENDING", "s", "g", "2-0", "+", "1"])),
+            vec![entry("2-0", "bob", 2)]
+        );
+        assert_eq!(
+            pending(run(&["XPENDING", "s", "g", "-", "+", "10", "alice"])),
+            vec![entry("3-0", "alice", 1)]
+        );
+        assert_eq!(
+            pending(run(&[
+                "XPENDING", "s", "g", "IDLE", "0", "2-0", "3-0", "10", "bob"
+            ])),
+            vec![entry("2-0", "bob", 2)]
+        );
+        assert_eq!(
+            pending(run(&[
+                "XPENDING", "s", "g", "IDLE", "60000", "-", "+", "10"
+            ])),
+            vec![]
+        );
+        assert_eq!(
+            pending(run(&["XPENDING", "s", "g", "3-0", "1-0", "10"])),
+            vec![]
+        );
+        assert_eq!(
+            run(&["XPENDING", "s", "g", "-", "+"]),
+            Reply::error("ERR syntax error")
+        );
+        assert_eq!(
+            run(&["XPENDING", "s", "g", "-", "+", "many"]),
+            Reply::error("ERR value is not an integer or out of range")
+        );
+        assert_eq!(
+            run(&["XPENDING", "s", "g", "nope", "+", "10"]),
+            Reply::error("ERR Invalid stream ID specified as stream command argument")
+        );
+    }
+
+    #[test]
+    fn xclaim_and_xautoclaim() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+        handle(
+            &mut store,
+            &config,
+            &["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"],
+        );
+        handle(&mut store, &config, &["XADD", "s", "1-0", "f", "v"]);
+        handle(&mut store, &config, &["XADD", "s", "2-0", "f", "v"]);
+        handle(
+            &mut store,
+            &config,
+            &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"],
+        );
+
+        let outcome = handle(
+            &mut store,
+            &config,
+            &["XCLAIM", "s", "g", "bob", "60000", "1-0"],
+        )
+        .unwrap();
+        assert_eq!(outcome.response, Reply::Array(Vec::new()));
+        assert_eq!(outcome.propagate, None);
+
+        let outcome = handle(
+            &mut store,
+            &config,
+            &["XCLAIM", "s", "g", "bob", "0", "1-0", "JUSTID"],
+        )
+        .unwrap();
+        assert_eq!(outcome.response, Reply::Array(vec![Reply::bulk("1-0")]));
+        assert_eq!(
+            outcome.propagate,
+            Some(to_args(&["XCLAIM", "s", "g", "bob", "0", "1-0", "JUSTID"]))
+        );
+
+        let outcome = handle(
+            &mut store,
+            &config,
+            &[
+                "XAUTOCLAIM",
+                "s",
+                "g",
+                "carol",
+                "0",
+                "0",
+                "COUNT",
+                "1",
+                "JUSTID",
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            outcome.response,
+            Reply::Array(vec![
+                Reply::bulk("2-0"),
+                Reply::Array(vec![Reply::bulk("1-0")]),
+                Reply::Array(Vec::new()),
+            ])
+        );
+        assert_eq!(
+            outcome.propagate,
+            Some(to_args(&[
+                "XCLAIM", "s", "g", "carol", "0", "1-0", "JUSTID"
+            ]))
+        );
+
+        let outcome = handle(
+            &mut store,
+            &config,
+            &["XCLAIM", "s", "g", "bob", "0", "1-0", "FORCE"],
+        )
+        .unwrap();
+        assert_eq!(
+            outcome.response,
+            Reply::error("ERR Unrecognized XCLAIM option 'FORCE'")
+        );
+        let outcome = handle(
+            &mut store,
+            &config,
+            &["XAUTOCLAIM", "s", "missing", "bob", "0", "0"],
+        )
+        .unwrap();
+        assert_eq!(
+            outcome.response,
+            Reply::error("NOGROUP No such key 's' or consumer group 'missing'")
+        );
+    }
+
+    #[test]
+    fn container_commands_reply_to_help() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+
+        for command in ["OBJECT", "CONFIG", "XGROUP", "COMMAND"] {
+            let Reply::Array(lines) = handle(&mut store, &config, &[command, "help"])
+                .unwrap()
+                .response
+            else {
+                panic!("{command} HELP should reply with an array");
+            };
+            assert_eq!(
+                lines.first(),
+                Some(&Reply::Simple(format!(
+                    "{command} <subcommand> [<arg> [value] [opt] ...]. Subcommands are:"
+                )))
+            );
+            assert_eq!(
+                lines.last(),
+                Some(&Reply::Simple(String::from("    Print this help.")))
+            );
+        }
+    }
+
+    #[test]
+    fn subcommands_are_case_insensitive() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::from([(String::from("dir"), String::from("/data"))]));
+
+        let mut run = |command: &[&str]| handle(&mut store, &config, command).unwrap().response;
+        let dir = Reply::Array(vec![Reply::bulk("dir"), Reply::bulk("/data")]);
+        assert_eq!(run(&["CONFIG", "get", "dir"]), dir);
+        assert_eq!(run(&["config", "Get", "DIR"]), dir);
+        assert!(matches!(
+            run(&["INFO", "REPLICATION"]),
+            Reply::Bulk(Some(info)) if info.starts_with(b"# Replication\r\n")
+        ));
+
+        run(&["SET", "k", "v", "PX", "100"]);
+        assert!(store.get_expiry("k").is_some());
+
+        assert_eq!(
+            parse_xread_arguments(&to_args_strings(&[
+                "XREAD", "BLOCK", "10", "STREAMS", "s", "0-0"
+            ])),
+            parse_xread_arguments(&to_args_strings(&[
+                "XREAD", "block", "10", "streams", "s", "0-0"
+            ]))
+        );
+        assert!(
+            parse_xread_arguments(&to_args_strings(&["XREAD", "Streams", "s", "0-0"])).is_some()
+        );
+    }
+
+    fn to_args_strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn info_sections() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+
+        let mut info = |command: &[&str]| {
+            let Reply::Bulk(Some(info)) = handle(&mut store, &config, command).unwrap().response
+            else {
+                panic!("INFO should reply with a bulk string");
+            };
+            String::from_utf8(info).unwrap()
+        };
+        assert!(info(&["INFO"]).contains("# Replication\r\nrole:master\r\n"));
+        assert!(info(&["INFO", "everything"]).contains("role:master\r\n"));
+        assert!(info(&["INFO", "server", "replication"]).contains("role:master\r\n"));
+        assert_eq!(info(&["INFO", "server"]), "");
+    }
+
+    #[test]
+    fn lcs_replies() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+        store.set_string("key1", b"ohmytext", None);
+        store.set_string("key2", b"mynewtext", None);
+
+        let mut lcs = |command: &[&str]| {
+            let outcome = handle(&mut store, &config, command).unwrap();
+            String::from_utf8(outcome.response.encode(Protocol::RESP2)).unwrap()
+        };
+        assert_eq!(lcs(&["LCS", "key1", "key2"]), "$6\r\nmytext\r\n");
+        assert_eq!(lcs(&["LCS", "key1", "key2", "len"]), ":6\r\n");
+        assert_eq!(lcs(&["LCS", "key1", "missing"]), "$0\r\n\r\n");
+        assert_eq!(
+            lcs(&[
+                "LCS",
+                "key1",
+                "key2",
+                "IDX",
+                "MINMATCHLEN",
+                "4",
+                "WITHMATCHLEN"
+            ]),
+            "*4\r\n$7\r\nmatches\r\n*1\r\n*3\r\n*2\r\n:4\r\n:7\r\n*2\r\n:5\r\n:8\r\n:4\r\n\
+             $3\r\nlen\r\n:6\r\n"
+        );
+        assert_eq!(
+            lcs(&["LCS", "key1", "key2", "LEN", "IDX"]),
+            "-ERR If you want both the length and indexes, please just use IDX.\r\n"
+        );
+
+        lcs(&["XADD", "stream", "*", "a", "b"]);
+        assert_eq!(
+            lcs(&["LCS", "key1", "stream"]),
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+    }
+
+    #[test]
+    fn bitpos_arguments() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+        store.set_string("mykey", b"\x00\xff\xf0", None);
+
+        let mut bitpos = |command: &[&str]| handle(&mut store, &config, command).unwrap().response;
+        assert_eq!(bitpos(&["BITPOS", "mykey", "1"]), Reply::Integer(8));
+        assert_eq!(
+            bitpos(&["BITPOS", "mykey", "1", "2", "-1", "bit"]),
+            Reply::Integer(8)
+        );
+        assert_eq!(
+            bitpos(&["BITPOS", "mykey", "2"]),
+            Reply::error("ERR The bit argument must be 1 or 0.")
+        );
+        assert_eq!(
+            bitpos(&["BITPOS", "mykey", "1", "a"]),
+            Reply::error("ERR value is not an integer or out of range")
+        );
+        assert_eq!(
+            bitpos(&["BITPOS", "mykey", "1", "0", "1", "WORD"]),
+            Reply::error("ERR syntax error")
+        );
+    }
+
+    #[test]
+    fn bitfield_replies_and_propagation() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+
+        let command = [
+            "BITFIELD", "mykey", "INCRBY", "u2", "#1", "3", "OVERFLOW", "FAIL", "INCRBY", "u2",
+            "#1", "1", "GET", "u4", "0",
+        ];
+        let outcome = handle(&mut store, &config, &command).unwrap();
+        assert_eq!(
+            outcome.response,
+            Reply::Array(vec![Reply::Integer(3), Reply::Null, Reply::Integer(3)])
+        );
+        assert_eq!(outcome.modified_keys, vec![String::from("mykey")]);
+        assert!(outcome.propagate.is_some());
+
+        let outcome = handle(
+            &mut store,
+            &config,
+            &["BITFIELD", "mykey", "GET", "i8", "0"],
+        );
+        assert_eq!(outcome.unwrap().propagate, None);
+
+        for (command, error) in [
+            (
+                &["BITFIELD", "mykey", "GET", "u64", "0"][..],
+                "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not \
+                 supported but i64 is.",
+            ),
+            (
+                &["BITFIELD", "mykey", "GET", "u8", "-1"],
+                "ERR bit offset is not an integer or out of range",
+            ),
+            (
+                &["BITFIELD", "mykey", "OVERFLOW", "NONE"],
+                "ERR Invalid OVERFLOW type specified",
+            ),
+            (&["BITFIELD", "mykey", "SET", "u8", "0"], "ERR syntax error"),
+        ] {
+            let outcome = handle(&mut store, &config, command).unwrap();
+            assert_eq!(outcome.response, Reply::error(error));
+        }
+    }
+
+    #[test]
+    fn missing_arguments() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+
+        let outcome = handle(&mut store, &config, &["GET"]).unwrap();
+        assert_eq!(
+            outcome.response,
+            Reply::error("ERR wrong number of arguments for 'get' command")
+        );
+    }
+
+    #[test]
+    fn command_info() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+
+        let outcome = handle(
+            &mut store,
+            &config,
+            &["COMMAND", "INFO", "get", "set", "nope"],
+        )
+        .unwrap();
+        let Reply::Array(infos) = outcome.response else {
+            panic!("COMMAND INFO should reply with an array");
+        };
+        let Reply::Array(get) = &infos[0] else {
+            panic!("Missing get metadata");
+        };
+        assert_eq!(
+            get[..3],
+            [
+                Reply::bulk("get"),
+                Reply::Integer(2),
+                Reply::Array(vec![
+                    Reply::Simple(String::from("readonly")),
+                    Reply::Simple(String::from("fast")),
+                ])
+            ]
+        );
+        let Reply::Array(set) = &infos[1] else {
+            panic!("Missing set metadata");
+        };
+        assert_eq!(set[1], Reply::Integer(-3));
+        assert!(
+            matches!(&set[2], Reply::Array(flags) if flags.contains(&Reply::Simple(String::from("write"))))
+        );
+        assert_eq!(infos[2], Reply::Null);
+    }
+
+    #[test]
+    fn actor_commands_are_not_handled() {
+        let mut store = Store::new();
+        let config = Config::from_args(Args::new());
+
+        assert_eq!(handle(&mut store, &config, &["MULTI"]), None);
+        assert_eq!(handle(&mut store, &config, &["WAIT", "0", "0"]), None);
+    }
+
+    #[test]
+    fn requested_stream_entry_id_invalid() {
+        let arg = String::from("toto");
+        assert_eq!(parse_requested_stream_entry_id(&arg), None);
+    }
+
+    #[test]
+    fn requested_stream_entry_id_auto_generate() {
+        let arg = String::from("*");
+        assert_eq!(
+            parse_requested_stream_entry_id(&arg),
+            Some(RequestedStreamEntryId::AutoGenerate)
+        );
+    }
+
+    #[test]
+    fn requested_stream_entry_id_auto_generate_sequence() {
+        let arg = String::from("1526919030474-*");
+        assert_eq!(
+            parse_requested_stream_entry_id(&arg),
+            Some(RequestedStreamEntryId::AutoGenerateSequence(1526919030474))
+        );
+    }
+
+    #[test]
+    fn requested_stream_entry_id_bare_timestamp() {
+        let arg = String::from("5");
+        assert_eq!(
+            parse_requested_stream_entry_id(&arg),
+            Some(RequestedStreamEntryId::AutoGenerateSequence(5))
+        );
+        assert_eq!(parse_requested_stream_entry_id(&String::from("+5")), None);
+        assert_eq!(parse_requested_stream_entry_id(&String::from("5-")), None);
+    }
+
+    #[test]
+    fn requested_stream_entry_id_explicit() {
+        let arg = String::from("1526919030474-12");
+        assert_eq!(
+            parse_requested_stream_entry_id(&arg),
+            Some(RequestedStreamEntryId::Explicit(StreamEntryId {
+                timestamp: 1526919030474,
+                sequence_number: 12
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_xread_arguments() {
+        let cmd: Vec<String> = String::from("XREAD streams stream_key other_stream_key 0-0 0-1")
+            .split(" ")
+            .map(|s| s.to_string())
+            .collect();
+
+        let res = parse_xread_arguments(&cmd);
+        let expected_res = Some(XREADArguments {
+            streams: vec![
+                (
+                    String::from("stream_key"),
+                    Some(StreamEntryId {
+                        timestamp: 0,
+                        sequence_number: 0,
+                    }),
+                ),
+                (
+                    String::from("other_stream_key"),
+                    Some(StreamEntryId {
+                        timestamp: 0,
+                        sequence_number: 1,
+                    }),
+                ),
+            ],
+            block_for: None,
+        });
+        assert_eq!(res, expected_res);
+    }
+
+    #[test]
+    fn test_parse_xread_arguments_blocking() {
+        let cmd: Vec<String> =
+            String::from("XREAD block 1000 streams stream_key other_stream_key 0-0 0-1")
+                .split(" ")
+                .map(|s| s.to_string())
+                .collect();
+
+        let res = parse_xread_arguments(&cmd);
+        let expected_res = Some(XREADArguments {
+            streams: vec![
+                (
+                    String::from("stream_key"),
+                    Some(StreamEntryId {
+                        timestamp: 0,
+                        sequence_number: 0,
+                    }),
+                ),
+                (
+                    String::from("other_stream_key"),
+                    Some(StreamEntryId {
+                        timestamp: 0,
+                        sequence_number: 1,
+                    }),
+                ),
+            ],
+            block_for: Some(1000),
+        });
+        assert_eq!(res, expected_res);
+    }
+
+    #[test]
+    fn test_parse_xread_arguments_missing_streams() {
+        let cmd: Vec<String> = String::from("XREAD stream_key other_stream_key 0-0 0-1")
+            .split(" ")
+            .map(|s| s.to_string())
+            .collect();
+
+        assert_eq!(parse_xread_arguments(&cmd), None);
+    }
+}
@@ -1,50 +1,95 @@
 use std::{
-    fs,
-    iter::zip,
+    collections::HashSet,
+    fs, io,
+    net::SocketAddr,
     sync::mpsc::{channel, Receiver, Sender},
+    thread::{self, JoinHandle},
     time::{Duration, Instant},
 };
 
+use chrono::Utc;
 use indexmap::IndexMap;
-use itertools::Itertools;
 
 use crate::{
     actor::{ConnectionMessage, StoreMessage},
-    config::{Config, ReplicationRole},
+    config::Config,
     connection::{
-        fmt::{format_array, format_stream, format_string},
+        fmt::{command_reply, help_reply, monitor_reply, stream_reply},
         parser::{BufferType, Command, CommandVerb},
+        reply::{Protocol, Reply},
     },
-    store::{
-        stream::{RequestedStreamEntryId, StreamEntry, StreamEntryId},
-        ItemType, Store,
+    store::{dbfile::write_dbfile, stream::StreamEntryId, Store},
+};
+
+use super::{
+    backlog::ReplicationBacklog,
+    handler::{
+        check_arity, check_auth, check_authenticated, config_set, hello_reply, is_config_set,
+        parse_hello, parse_xread_arguments, parse_xreadgroup_arguments, unknown_command_error,
+        CommandHandler, CommandOutcome, ReplicaInfo, ReplicationInfo, ServerStats, XREADArguments,
+        XREADGROUPArguments,
     },
+    pubsub::{PubSub, SubscriptionKind},
+    slowlog::SlowLog,
+    tracking::Tracking,
+    ConnectionID,
 };
 
-use super::ConnectionID;
+/// Minimum time between two active expiry cycles.
+const ACTIVE_EXPIRE_PERIOD: Duration = Duration::from_millis(100);
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 struct Replication {
     replication_offset: usize,
-    last_offset_checked: usize,
+    /// Database selected in the replication stream.
+    db: usize,
+    backlog: ReplicationBacklog,
 }
 
-impl Replication {
-    fn match_offsets(&mut self) {
-        self.last_offset_checked = self.replication_offset;
-    }
+/// Information announced by a replica with `REPLCONF` during the handshake.
+#[derive(Default)]
+struct ReplicaHandshake {
+    listening_port: Option<u16>,
+    capabilities: Vec<String>,
+}
+
+struct Replica {
+    tx: Sender<ConnectionMessage>,
+    addr: Option<SocketAddr>,
+    /// Port the replica listens on for clients, as announced with `REPLCONF listening-port`.
+    listening_port: Option<u16>,
+    /// Last replication offset acknowledged by the replica through `REPLCONF ACK`.
+    acked_offset: usize,
+    last_ack: Instant,
 }
 
 struct WaitForReplicationAcks {
     initial_client_tx: Sender<ConnectionMessage>,
+    connection_id: ConnectionID,
     expected_number_of_acks: usize,
-    number_of_acks: usize,
+    /// Master replication offset when WAIT was received, replicas must ack at least this offset.
+    target_offset: usize,
     timeout: Option<Instant>,
 }
 
 struct BlockingXREAD {
     initial_client_tx: Sender<ConnectionMessage>,
-    streams: Vec<String>,
+    connection_id: ConnectionID,
+    /// Database selected by the client when it blocked.
+    db: usize,
+    /// Streams waited on, with the last entry ID already seen by the client for each of them.
+    streams: Vec<(String, StreamEntryId)>,
+    timeout: Option<Instant>,
+}
+
+struct BlockingXREADGROUP {
+    initial_client_tx: Sender<ConnectionMessage>,
+    connection_id: ConnectionID,
+    /// Database selected by the client when it blocked.
+    db: usize,
+    /// Command replayed when one of its streams gets new entries.
+    command: Command,
+    keys: Vec<String>,
     timeout: Option<Instant>,
 }
 
@@ -53,35 +98,103 @@ struct Transaction {
     commands: Vec<Command>,
 }
 
+struct Persistence {
+    /// Number of key modifications since the last successful save.
+    changes_since_save: usize,
+    last_save: Instant,
+    /// Ongoing BGSAVE, with the number of changes it covers.
+    bgsave: Option<(JoinHandle<io::Result<()>>, usize)>,
+}
+
+#[derive(Default)]
+struct WatchedKeys {
+    /// Watched keys, with the database they belong to.
+    keys: HashSet<(usize, String)>,
+    dirty: bool,
+}
+
 pub struct MasterActor {
     store: Store,
     config: Config,
     tx: Sender<StoreMessage>,
     rx: Receiver<StoreMessage>,
     replication: Replication,
-    replicas: Vec<Sender<ConnectionMessage>>,
-    wait_for_replication_acks: Option<WaitForReplicationAcks>,
+    replicas: IndexMap<ConnectionID, Replica>,
+    replica_handshakes: IndexMap<ConnectionID, ReplicaHandshake>,
+    wait_for_replication_acks: Vec<WaitForReplicationAcks>,
     blocking_xreads: Vec<BlockingXREAD>,
+    blocking_xreadgroups: Vec<BlockingXREADGROUP>,
     transactions: IndexMap<ConnectionID, Transaction>,
+    watched_keys: IndexMap<ConnectionID, WatchedKeys>,
+    client_addrs: IndexMap<ConnectionID, SocketAddr>,
+    /// Database selected by each connection, the first one when missing.
+    selected_dbs: IndexMap<ConnectionID, usize>,
+    /// Connections in `CLIENT NO-TOUCH` mode, whose reads do not alter the keys access time.
+    no_touch: HashSet<ConnectionID>,
+    /// Connections that authenticated with `AUTH` or `HELLO ... AUTH`.
+    authenticated: HashSet<ConnectionID>,
+    /// Numeric IDs of connections, as reported by `CLIENT ID`.
+    client_ids: IndexMap<ConnectionID, u64>,
+    next_client_id: u64,
+    /// Protocol negotiated by each connection with `HELLO`, RESP2 when missing.
+    protocols: IndexMap<ConnectionID, Protocol>,
+    tracking: Tracking,
+    /// Connections in MONITOR mode, receiving every processed command.
+    monitors: IndexMap<ConnectionID, Sender<ConnectionMessage>>,
+    pubsub: PubSub,
+    persistence: Persistence,
+    stats: ServerStats,
+    slowlog: SlowLog,
+    /// Whether expired keys are periodically removed, on top of being removed when accessed.
+    active_expire: bool,
+    last_active_expire: Instant,
 }
 
 impl MasterActor {
     pub fn new(store: Store, config: Config) -> MasterActor {
         let (tx, rx) = channel();
-        let replicas: Vec<Sender<ConnectionMessage>> = vec![];
+        let replicas: IndexMap<ConnectionID, Replica> = IndexMap::new();
         let blocking_xreads: Vec<BlockingXREAD> = Vec::new();
         let transactions: IndexMap<ConnectionID, Transaction> = IndexMap::new();
 
+        let backlog = ReplicationBacklog::new(config.repl_backlog_size);
+
         MasterActor {
             store,
             config,
             tx,
             rx,
-            replication: Replication::default(),
+            replication: Replication {
+                replication_offset: 0,
+                db: 0,
+                backlog,
+            },
             replicas,
+            replica_handshakes: IndexMap::new(),
             blocking_xreads,
-            wait_for_replication_acks: None,
+            blocking_xreadgroups: Vec::new(),
+            wait_for_replication_acks: Vec::new(),
             transactions,
+            watched_keys: IndexMap::new(),
+            client_addrs: IndexMap::new(),
+            selected_dbs: IndexMap::new(),
+            no_touch: HashSet::new(),
+            authenticated: HashSet::new(),
+            client_ids: IndexMap::new(),
+            next_client_id: 1,
+            protocols: IndexMap::new(),
+            tracking: Tracking::default(),
+            monitors: IndexMap::new(),
+            pubsub: PubSub::default(),
+            stats: ServerStats::default(),
+            slowlog: SlowLog::default(),
+            active_expire: true,
+            last_active_expire: Instant::now(),
+            persistence: Persistence {
+                changes_since_save: 0,
+                last_save: Instant::now(),
+                bgsave: None,
+            },
         }
     }
 
@@ -96,35 +209,136 @@ impl MasterActor {
                     println!("{cmd:?}");
                     self.process_command(cmd, tx_back, connection_id);
                 }
+                StoreMessage::NewBuffer {
+                    value: BufferType::UnknownCommand(args),
+                    tx_back,
+                    connection_id,
+                } => {
+                    // Replicas never get replies, see `process_command`
+                    if !self.replicas.contains_key(&connection_id) {
+                        tx_back
+                            .send(ConnectionMessage::SendReply(unknown_command_error(&args)))
+                            .unwrap();
+                    }
+                }
+                // Stray replies, as sent by replicas or RESP3 clients, are not commands
+                StoreMessage::NewBuffer {
+                    value: BufferType::Integer(_) | BufferType::Double(_),
+                    ..
+                } => {}
+                StoreMessage::ConnectionOpened {
+                    connection_id,
+                    addr,
+                } => {
+                    self.client_id(&connection_id);
+                    self.client_addrs.insert(connection_id, addr);
+                }
+                StoreMessage::ConnectionClosed { connection_id } => {
+                    self.process_connection_closed(connection_id);
+                }
                 _ => todo!(),
             }
         }
 
         self.check_on_replication_waits();
         self.check_on_blocking_xreads();
+        self.check_on_blocking_xreadgroups();
+        self.check_on_save_points();
+        self.expire_keys();
     }
 
     pub fn get_tx(&self) -> Sender<StoreMessage> {
         self.tx.clone()
     }
 
+    fn process_connection_closed(&mut self, connection_id: ConnectionID) {
+        self.transactions.swap_remove(&connection_id);
+        self.watched_keys.swap_remove(&connection_id);
+        self.client_addrs.swap_remove(&connection_id);
+        self.selected_dbs.swap_remove(&connection_id);
+        self.no_touch.remove(&connection_id);
+        self.authenticated.remove(&connection_id);
+        self.client_ids.swap_remove(&connection_id);
+        self.protocols.swap_remove(&connection_id);
+        self.tracking.remove(&connection_id);
+        self.monitors.swap_remove(&connection_id);
+        self.replicas.swap_remove(&connection_id);
+        self.replica_handshakes.swap_remove(&connection_id);
+        self.pubsub.remove(&connection_id);
+        self.blocking_xreads
+            .retain(|task| task.connection_id != connection_id);
+        self.blocking_xreadgroups
+            .retain(|task| task.connection_id != connection_id);
+        self.wait_for_replication_acks
+            .retain(|task| task.connection_id != connection_id);
+    }
+
     fn process_command(
         &mut self,
         command: Command,
         tx_back: Sender<ConnectionMessage>,
         connection_id: ConnectionID,
     ) {
+        // Past PSYNC, a replica only sends acknowledgements of the replication stream, and never
+        // gets any reply
+        if self.replicas.contains_key(&connection_id) {
+            if command.verb == CommandVerb::REPLCONF
+                && command
+                    .cmd
+                    .get(1)
+                    .is_some_and(|opt| opt.eq_ignore_ascii_case("ack"))
+            {
+                self.process_replica_ack(&command.cmd, &connection_id);
+            } else {
+                println!("Ignoring {:?} from replica {connection_id}", command.verb);
+            }
+            return;
+        }
+
+        if let Some(error) = check_arity(&command) {
+            tx_back.send(ConnectionMessage::SendReply(error)).unwrap();
+            return;
+        }
+        let authenticated = self.authenticated.contains(&connection_id);
+        if let Some(error) = check_authenticated(&command, &self.config, authenticated) {
+            tx_back.send(ConnectionMessage::SendReply(error)).unwrap();
+            return;
+        }
+
+        if command.verb == CommandVerb::QUIT {
+            self.transactions.swap_remove(&connection_id);
+            self.process_quit(tx_back);
+            return;
+        }
+
         if let Some(mut transaction) = self.transactions.swap_remove(&connection_id) {
             if command.verb == CommandVerb::EXEC {
                 self.process_exec(transaction, connection_id);
             } else if command.verb == CommandVerb::DISCARD {
                 println!("Discarding transaction");
+                self.watched_keys.swap_remove(&connection_id);
+                tx_back
+                    .send(ConnectionMessage::SendReply(Reply::ok()))
+                    .unwrap();
+            } else if command.verb == CommandVerb::MULTI {
+                tx_back
+                    .send(ConnectionMessage::SendReply(Reply::error(
+                        "ERR MULTI calls can not be nested",
+                    )))
+                    .unwrap();
+                self.transactions.insert(connection_id, transaction);
+            } else if command.verb == CommandVerb::WATCH {
                 tx_back
-                    .send(ConnectionMessage::SendString("+OK\r\n".to_owned()))
+                    .send(ConnectionMessage::SendReply(Reply::error(
+                        "ERR WATCH inside MULTI is not allowed",
+                    )))
                     .unwrap();
+                self.transactions.insert(connection_id, transaction);
             } else {
                 tx_back
-                    .send(ConnectionMessage::SendString("+QUEUED\r\n".to_owned()))
+                    .send(ConnectionMessage::SendReply(Reply::Simple(String::from(
+                        "QUEUED",
+                    ))))
                     .unwrap();
                 transaction.commands.push(command);
                 self.transactions.insert(connection_id, transaction);
@@ -141,398 +355,961 @@ impl MasterActor {
         tx_back: Sender<ConnectionMessage>,
         connection_id: String,
     ) {
-        let Command { verb, cmd } = command;
-        match verb {
-            CommandVerb::PING => self.process_ping(tx_back),
-            CommandVerb::ECHO => self.process_echo(&cmd, tx_back),
-            CommandVerb::SET => self.process_set(&cmd, tx_back),
-            CommandVerb::GET => self.process_get(&cmd, tx_back),
-            CommandVerb::INCR => self.process_incr(&cmd, tx_back),
-            CommandVerb::MULTI => self.process_multi(&cmd, tx_back, connection_id),
+        self.feed_monitors(&command, &connection_id);
+        let verb = command.verb.clone();
+        let slowlog_threshold = u64::try_from(self.config.slowlog_log_slower_than).ok();
+        let start = Instant::now();
+        self.dispatch_command(&command, tx_back, connection_id.clone());
+        let duration = start.elapsed();
+
+        let stat = self.stats.commands.entry(verb).or_default();
+        stat.calls += 1;
+        stat.usec += duration.as_micros() as u64;
+        if let Some(threshold) = slowlog_threshold {
+            if duration.as_micros() >= u128::from(threshold) {
+                let client_addr = self
+                    .client_addrs
+                    .get(&connection_id)
+                    .map_or_else(String::new, |addr| addr.to_string());
+                self.slowlog.push(
+                    &command.raw,
+                    duration,
+                    client_addr,
+                    self.config.slowlog_max_len,
+                );
+            }
+        }
+    }
+
+    fn dispatch_command(
+        &mut self,
+        command: &Command,
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: String,
+    ) {
+        self.select_connection_db(&connection_id);
+        self.store
+            .set_no_touch(self.no_touch.contains(&connection_id));
+
+        // Expired keys are removed when accessed, on top of the active expiry cycles
+        for key in command.keys() {
+            if self.store.remove_if_expired(key) {
+                self.stats.expired_keys += 1;
+                self.tracking
+                    .invalidate(self.store.selected(), key, &mut self.pubsub);
+            }
+        }
+        if command.verb.metadata().flags.contains(&"readonly") {
+            self.tracking
+                .track(&connection_id, self.store.selected(), &command.keys());
+        }
+
+        match command.verb {
+            CommandVerb::MULTI => self.process_multi(&command.cmd, tx_back, connection_id),
             CommandVerb::DISCARD => {
                 tx_back
-                    .send(ConnectionMessage::SendString(
-                        "-ERR DISCARD without MULTI\r\n".to_owned(),
-                    ))
+                    .send(ConnectionMessage::SendReply(Reply::error(
+                        "ERR DISCARD without MULTI",
+                    )))
                     .unwrap();
             }
             CommandVerb::EXEC => tx_back
-                .send(ConnectionMessage::SendString(
-                    "-ERR EXEC without MULTI\r\n".to_owned(),
-                ))
+                .send(ConnectionMessage::SendReply(Reply::error(
+                    "ERR EXEC without MULTI",
+                )))
                 .unwrap(),
-            CommandVerb::TYPE => self.process_type(&cmd, tx_back),
-            CommandVerb::XADD => self.process_xadd(&cmd, tx_back),
-            CommandVerb::XRANGE => self.process_xrange(&cmd, tx_back),
-            CommandVerb::XREAD => self.process_xread(&cmd, tx_back),
-            CommandVerb::CONFIG => self.process_config(&cmd, tx_back),
-            CommandVerb::KEYS => self.process_keys(tx_back),
-            CommandVerb::INFO => self.process_info(&cmd, tx_back),
-            CommandVerb::REPLCONF => self.process_replconf(&cmd, tx_back),
-            CommandVerb::PSYNC => self.process_psync(tx_back),
-            CommandVerb::WAIT => self.process_wait(&cmd, tx_back),
+            CommandVerb::XREAD if is_blocking_xread(&command.cmd) => {
+                self.process_blocking_xread(&command.cmd, tx_back, connection_id)
+            }
+            CommandVerb::XREADGROUP if is_blocking_xreadgroup(&command.cmd) => {
+                self.process_blocking_xreadgroup(command.clone(), tx_back, connection_id)
+            }
+            CommandVerb::REPLCONF => self.process_replconf(&command.cmd, tx_back, connection_id),
+            CommandVerb::PSYNC => self.process_psync(&command.cmd, tx_back, connection_id),
+            CommandVerb::WAIT => self.process_wait(&command.cmd, tx_back, connection_id),
+            CommandVerb::QUIT => self.process_quit(tx_back),
+            CommandVerb::CONFIG if is_config_set(&command.cmd) => {
+                self.process_config_set(&command.cmd, tx_back)
+            }
+            CommandVerb::WATCH => self.process_watch(&command.cmd, tx_back, connection_id),
+            CommandVerb::UNWATCH => self.process_unwatch(tx_back, connection_id),
+            CommandVerb::SUBSCRIBE => self.pubsub.subscribe(
+                SubscriptionKind::Channel,
+                &connection_id,
+                &tx_back,
+                &command.raw[1..],
+            ),
+            CommandVerb::UNSUBSCRIBE => self.pubsub.unsubscribe(
+                SubscriptionKind::Channel,
+                &connection_id,
+                &tx_back,
+                &command.raw[1..],
+            ),
+            CommandVerb::PSUBSCRIBE => self.pubsub.subscribe(
+                SubscriptionKind::Pattern,
+                &connection_id,
+                &tx_back,
+                &command.raw[1..],
+            ),
+            CommandVerb::PUNSUBSCRIBE => self.pubsub.unsubscribe(
+                SubscriptionKind::Pattern,
+                &connection_id,
+                &tx_back,
+                &command.raw[1..],
+            ),
+            CommandVerb::SSUBSCRIBE => self.pubsub.subscribe(
+                SubscriptionKind::ShardChannel,
+                &connection_id,
+                &tx_back,
+                &command.raw[1..],
+            ),
+            CommandVerb::SUNSUBSCRIBE => self.pubsub.unsubscribe(
+                SubscriptionKind::ShardChannel,
+                &connection_id,
+                &tx_back,
+                &command.raw[1..],
+            ),
+            CommandVerb::PUBLISH | CommandVerb::SPUBLISH => {
+                let receivers = if command.verb == CommandVerb::PUBLISH {
+                    self.pubsub.publish(&command.raw[1], &command.raw[2])
+                } else {
+                    self.pubsub.publish_shard(&command.raw[1], &command.raw[2])
+                };
+                tx_back
+                    .send(ConnectionMessage::SendReply(Reply::Integer(
+                        receivers as i64,
+                    )))
+                    .unwrap();
+                // Subscribers of the replicas receive the message too
+                self.propagate(&command.raw);
+            }
+            CommandVerb::HELLO => self.process_hello(&command.cmd, tx_back, connection_id),
+            CommandVerb::AUTH => self.process_auth(&command.cmd, tx_back, connection_id),
+            CommandVerb::CLIENT => self.process_client(&command.cmd, tx_back, connection_id),
+            CommandVerb::SELECT => self.process_select(&command.cmd, tx_back, connection_id),
+            CommandVerb::SLOWLOG => self.process_slowlog(&command.cmd, tx_back),
+            CommandVerb::MONITOR => self.process_monitor(tx_back, connection_id),
+            CommandVerb::SAVE => self.process_save(tx_back),
+            CommandVerb::BGSAVE => self.process_bgsave(tx_back),
+            CommandVerb::DEBUG => self.process_debug(&command.cmd, tx_back),
+            _ => self.process_store_command(command, tx_back),
         };
     }
 
-    fn process_ping(&mut self, tx_back: Sender<ConnectionMessage>) {
-        tx_back
-            .send(ConnectionMessage::SendString(String::from("+PONG\r\n")))
-            .unwrap();
+    fn process_store_command(&mut self, command: &Command, tx_back: Sender<ConnectionMessage>) {
+        let mut handler = CommandHandler::new(&mut self.store, &self.config);
+        if command.verb == CommandVerb::INFO {
+            handler =
+                handler
+                    .with_stats(self.stats.clone())
+                    .with_replication(ReplicationInfo::Master {
+                        offset: self.replication.replication_offset,
+                        replicas: self
+                            .replicas
+                            .values()
+                            .map(|replica| ReplicaInfo {
+                                addr: replica.addr,
+                                listening_port: replica.listening_port,
+                                offset: replica.acked_offset,
+                                lag: replica.last_ack.elapsed().as_secs(),
+                            })
+                            .collect(),
+                    });
+        }
+        let Some(outcome) = handler.handle(command) else {
+            println!("{:?} not implemented for master", command.verb);
+            return;
+        };
+        self.apply_outcome(command, outcome, &tx_back);
     }
 
-    fn process_echo(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
-        if let Some(message) = command.get(1) {
-            let message = format!("${}\r\n{}\r\n", message.len(), message);
-            tx_back
-                .send(ConnectionMessage::SendString(message))
-                .unwrap();
+    /// Reply to the client, then apply the side effects of a store command.
+    fn apply_outcome(
+        &mut self,
+        command: &Command,
+        outcome: CommandOutcome,
+        tx_back: &Sender<ConnectionMessage>,
+    ) {
+        let _ = tx_back.send(ConnectionMessage::SendReply(outcome.response));
+        self.persistence.changes_since_save += outcome.modified_keys.len();
+        self.persistence.changes_since_save += outcome.other_db_keys.len();
+        for (db, key) in &outcome.other_db_keys {
+            self.signal_modified_key(*db, key);
+        }
+        for key in &outcome.modified_keys {
+            self.signal_modified_key(self.store.selected(), key);
+            if command.verb == CommandVerb::XADD {
+                self.notify_blocking_xreads(key);
+                self.notify_blocking_xreadgroups(key);
+            }
+        }
+        if let Some(propagate) = outcome.propagate {
+            self.propagate(&propagate);
         }
     }
 
-    fn process_set(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
-        let (Some(key), Some(value)) = (command.get(1), command.get(2)) else {
-            return;
-        };
-
-        let option = command.get(3);
-        let option_value: Option<usize> = match command.get(4) {
-            Some(option_value) => option_value.parse::<usize>().ok(),
-            _ => None,
-        };
-        let ttl = match (option, option_value) {
-            (Some(cmd), Some(cmd_value)) if cmd == "px" => Some(cmd_value),
-            _ => None,
-        };
+    /// Update replication offset and propagate a write command to connected replicas
+    fn propagate(&mut self, command: &[Vec<u8>]) {
+        // Replicas apply writes to the database of the client that issued them
+        if self.store.selected() != self.replication.db {
+            self.propagate_select(self.store.selected());
+        }
+        let message = command_reply(command);
+        let bytes = message.encode(Protocol::RESP2);
+        self.replication.replication_offset += bytes.len();
+        self.replication.backlog.feed(&bytes);
+        self.send_to_replicas(&message);
+    }
 
-        println!("Setting {}: {}", key, value);
-        self.store.set_string(key, value, ttl);
-        tx_back
-            .send(ConnectionMessage::SendString(String::from("+OK\r\n")))
-            .unwrap();
+    fn propagate_select(&mut self, db: usize) {
+        self.replication.db = db;
+        self.propagate(&[b"SELECT".to_vec(), db.to_string().into_bytes()]);
+    }
 
-        // Update replication offset and propagate to connected replicas
-        self.replication.replication_offset +=
-            command.iter().fold(0, |acc, s| acc + s.as_bytes().len());
-        for tx_replica in &self.replicas {
-            tx_replica
-                .send(ConnectionMessage::SendString(format_array(
-                    &command.to_vec(),
-                )))
-                .unwrap();
-        }
+    /// Send a message to every replica, forgetting the ones whose connection is gone.
+    fn send_to_replicas(&mut self, message: &Reply) {
+        self.replicas.retain(|connection_id, replica| {
+            let sent = replica
+                .tx
+                .send(ConnectionMessage::SendReply(message.clone()))
+                .is_ok();
+            if !sent {
+                println!("Dropping disconnected replica {connection_id}");
+            }
+            sent
+        });
     }
 
-    fn process_get(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
-        let Some(key) = command.get(1) else {
+    /// Prepare for exit: wait for an ongoing BGSAVE, then save the dataset when `save` points
+    /// are configured.
+    pub fn shutdown(&mut self) {
+        if let Some((handle, _)) = self.persistence.bgsave.take() {
+            let _ = handle.join();
+        }
+        if self.config.save_points.is_empty() {
             return;
-        };
-        let value = self.store.get_string(key);
-        let message = ConnectionMessage::SendString(format_string(value));
-        tx_back.send(message).unwrap();
+        }
+        println!("Saving the final RDB snapshot before exiting");
+        if let Err(err) = write_dbfile(&self.config.dbfile_path(), &self.store.to_rdb()) {
+            println!("Error trying to save the DB: {err}");
+        }
     }
 
-    fn process_type(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
-        let Some(key) = command.get(1) else {
-            return;
-        };
-        let response = match self.store.get_item_type(key) {
-            None => "+none\r\n",
-            Some(ItemType::String) => "+string\r\n",
-            Some(ItemType::Stream) => "+stream\r\n",
+    fn process_save(&mut self, tx_back: Sender<ConnectionMessage>) {
+        let response = if self.persistence.bgsave.is_some() {
+            Reply::error("ERR Background save already in progress")
+        } else {
+            match write_dbfile(&self.config.dbfile_path(), &self.store.to_rdb()) {
+                Ok(()) => {
+                    self.persistence.changes_since_save = 0;
+                    self.persistence.last_save = Instant::now();
+                    Reply::ok()
+                }
+                Err(err) => Reply::Error(format!("ERR Saving DB: {err}")),
+            }
         };
-
         tx_back
-            .send(ConnectionMessage::SendString(response.to_owned()))
+            .send(ConnectionMessage::SendReply(response))
             .unwrap();
     }
 
-    fn process_xadd(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
-        let Some(stream_key) = command.get(1) else {
-            return;
-        };
-        let Some(entry_id) = command.get(2).and_then(parse_requested_stream_entry_id) else {
-            return;
+    fn process_debug(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
+        let response = match command[1].to_uppercase().as_str() {
+            "HELP" => help_reply(
+                "DEBUG",
+                &[
+                    "OBJECT <key>",
+                    "    Show low level info about the key and associated value.",
+                    "RELOAD",
+                    "    Save the RDB on disk and reload it back to memory.",
+                    "SET-ACTIVE-EXPIRE <0|1>",
+                    "    Setting it to 0 disables expiring keys in background when they are not",
+                    "    accessed (otherwise the Redis behavior). Setting it to 1 reenables back the",
+                    "    default.",
+                ],
+            ),
+            "OBJECT" if command.len() == 3 => self.debug_object(&command[2]),
+            "RELOAD" => match self.reload() {
+                Ok(()) => Reply::ok(),
+                Err(err) => Reply::Error(format!("ERR Error trying to reload the RDB dump: {err}")),
+            },
+            "SET-ACTIVE-EXPIRE" if command.len() == 3 => match command[2].parse::<i64>() {
+                Ok(enabled) => {
+                    self.active_expire = enabled != 0;
+                    Reply::ok()
+                }
+                Err(_) => Reply::error("ERR value is not an integer or out of range"),
+            },
+            _ => Reply::Error(format!(
+                "ERR unknown subcommand '{}'. Try DEBUG HELP.",
+                command[1]
+            )),
         };
+        tx_back
+            .send(ConnectionMessage::SendReply(response))
+            .unwrap();
+    }
 
-        let entries: IndexMap<String, String> = command[3..]
-            .iter()
-            .tuple_windows::<(_, _)>()
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect();
-
-        match self
-            .store
-            .add_stream_entry(stream_key, &entry_id, &entries, None)
-        {
-            Ok(entry_id) => {
-                tx_back
-                    .send(ConnectionMessage::SendString(format_string(Some(format!(
-                        "{entry_id}"
-                    )))))
-                    .unwrap();
-                self.propagate_xadd(stream_key, &entry_id, &entries);
-            }
-            Err(err) => {
-                tx_back
-                    .send(ConnectionMessage::SendString(format!("-{err}\r\n")))
-                    .unwrap();
-            }
+    /// Low level information about a key, in the format of Redis. Values are not shared, and
+    /// their address is not exposed.
+    fn debug_object(&self, key: &str) -> Reply {
+        let (Some(encoding), Some(length), Some(idle)) = (
+            self.store.get_encoding(key),
+            self.store.serialized_length(key),
+            self.store.get_idle_time(key),
+        ) else {
+            return Reply::error("ERR no such key");
         };
+        Reply::Simple(format!(
+            "Value at:0x0 refcount:1 encoding:{encoding} serializedlength:{length} lru_seconds_idle:{idle}"
+        ))
     }
 
-    fn propagate_xadd(
-        &mut self,
-        stream_key: &str,
-        entry_id: &StreamEntryId,
-        entries: &IndexMap<String, String>,
-    ) {
-        for task in self
-            .blocking_xreads
-            .iter()
-            .filter(|task| task.streams.contains(&stream_key.to_owned()))
-        {
-            println!("Propagating XADD for {stream_key}, {entry_id}");
-            task.initial_client_tx
-                .send(ConnectionMessage::SendString(format!(
-                    "*1\r\n*2\r\n{}{}",
-                    format_string(Some(stream_key.to_owned())),
-                    format_stream(&vec![StreamEntry {
-                        id: *entry_id,
-                        values: entries.clone()
-                    }])
-                )))
-                .unwrap();
-        }
+    /// Save the dataset to the RDB file, then replace it with what is loaded back from the file.
+    fn reload(&mut self) -> io::Result<()> {
+        let path = self.config.dbfile_path();
+        write_dbfile(&path, &self.store.to_rdb())?;
+        let store = Store::from_rdb(fs::read(&path)?, self.config.databases).ok_or(
+            io::Error::new(io::ErrorKind::InvalidData, "invalid RDB content"),
+        )?;
+        self.store = store;
+        self.persistence.changes_since_save = 0;
+        self.persistence.last_save = Instant::now();
+        Ok(())
     }
 
-    fn process_xrange(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
-        let Some(stream_key) = command.get(1) else {
-            return;
+    fn process_bgsave(&mut self, tx_back: Sender<ConnectionMessage>) {
+        let response = if self.start_bgsave() {
+            Reply::Simple(String::from("Background saving started"))
+        } else {
+            Reply::error("ERR Background save already in progress")
         };
-        let start_id = command.get(2).and_then(|s| parse_stream_entry_id(s));
-        let end_id = command.get(3).and_then(|s| parse_stream_entry_id(s));
-
-        let stream = self
-            .store
-            .get_stream_range(stream_key, start_id.as_ref(), end_id.as_ref());
         tx_back
-            .send(ConnectionMessage::SendString(format_stream(&stream)))
+            .send(ConnectionMessage::SendReply(response))
             .unwrap();
     }
 
-    fn process_xread(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
-        let Some(XREADArguments { block_for, streams }) = parse_xread_arguments(command) else {
-            return;
-        };
-        let mut message = format!("*{}\r\n", streams.len());
-        for (stream, id) in &streams {
-            let stream_values = self.store.get_stream_range(stream, id.as_ref(), None);
-            message.push_str(&format!(
-                "*2\r\n{}{}",
-                format_string(Some(stream.clone())),
-                format_stream(&stream_values)
-            ));
+    /// Snapshot the store and write it to the RDB file from another thread. Returns `false` if a
+    /// background save is already running.
+    fn start_bgsave(&mut self) -> bool {
+        if self.persistence.bgsave.is_some() {
+            return false;
         }
+        let content = self.store.to_rdb();
+        let path = self.config.dbfile_path();
+        println!("Background saving to {}", path.display());
+        let handle = thread::spawn(move || write_dbfile(&path, &content));
+        self.persistence.bgsave = Some((handle, self.persistence.changes_since_save));
+        true
+    }
 
-        // Keep track to propagate futur XADD commands
-        if let Some(block_for) = block_for {
-            let timeout = if block_for > 0 {
-                Some(Instant::now() + Duration::from_millis(block_for.try_into().unwrap()))
-            } else {
-                None
-            };
-            self.blocking_xreads.push(BlockingXREAD {
-                initial_client_tx: tx_back.clone(),
-                streams: streams.into_iter().map(|stream| stream.0).collect(),
-                timeout,
-            });
-        } else {
-            tx_back
-                .send(ConnectionMessage::SendString(message))
-                .unwrap();
+    /// Collect a finished BGSAVE, then start a new one if a `save` point is reached.
+    fn check_on_save_points(&mut self) {
+        if let Some((handle, changes)) = self
+            .persistence
+            .bgsave
+            .take_if(|(handle, _)| handle.is_finished())
+        {
+            match handle.join() {
+                Ok(Ok(())) => {
+                    println!("Background saving terminated with success");
+                    self.persistence.changes_since_save -= changes;
+                    self.persistence.last_save = Instant::now();
+                }
+                Ok(Err(err)) => println!("Background saving error: {err}"),
+                Err(_) => println!("Background saving thread panicked"),
+            }
         }
-    }
 
-    fn process_config(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
-        let (Some(action), Some(key)) = (command.get(1), command.get(2)) else {
-            return;
-        };
-        if *action == "GET" {
-            let Some(value) = self.config.get_arg(key) else {
-                return;
-            };
-            let message = format!(
-                "*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n",
-                key.len(),
-                key,
-                value.len(),
-                value
+        let elapsed = self.persistence.last_save.elapsed();
+        let changes = self.persistence.changes_since_save;
+        if self.persistence.bgsave.is_none()
+            && self.config.save_points.iter().any(|point| {
+                changes > 0
+                    && changes >= point.changes
+                    && elapsed >= Duration::from_secs(point.seconds)
+            })
+        {
+            println!(
+                "{changes} changes in {} seconds. Saving...",
+                elapsed.as_secs()
             );
-            tx_back
-                .send(ConnectionMessage::SendString(message))
-                .unwrap();
+            self.start_bgsave();
         }
     }
 
-    fn process_keys(&mut self, tx_back: Sender<ConnectionMessage>) {
-        let mut response = String::new();
-        let keys = self.store.get_keys();
-        response.push_str(&format!("*{}\r\n", keys.len()));
-        for key in keys {
-            response.push_str(&format!("${}\r\n{}\r\n", key.len(), key));
-        }
+    fn process_select(
+        &mut self,
+        command: &[String],
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: ConnectionID,
+    ) {
+        let response = match command[1].parse::<i64>() {
+            Ok(db) if (0..self.store.databases() as i64).contains(&db) => {
+                self.selected_dbs.insert(connection_id, db as usize);
+                Reply::ok()
+            }
+            Ok(_) => Reply::error("ERR DB index is out of range"),
+            Err(_) => Reply::error("ERR value is not an integer or out of range"),
+        };
         tx_back
-            .send(ConnectionMessage::SendString(response))
+            .send(ConnectionMessage::SendReply(response))
             .unwrap();
     }
 
-    fn process_info(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
-        match command.get(1) {
-            Some(section) if *section == "replication" => {
-                let mut response = String::new();
-                let role = match self.config.replication.role {
-                    ReplicationRole::Master => String::from("master"),
-                    ReplicationRole::Replica(_) => String::from("slave"),
-                };
-                response.push_str(&format!("role:{role}\r\n"));
-                response.push_str(&format!(
-                    "master_replid:{}\r\n",
-                    self.config.replication.replid
-                ));
-                response.push_str(&format!(
-                    "master_repl_offset:{}\r\n",
-                    self.config.replication.repl_offset
-                ));
-                tx_back
-                    .send(ConnectionMessage::SendString(format_string(Some(response))))
-                    .unwrap();
-            }
-            _ => panic!(),
+    /// ID of a connection, assigned on first use.
+    fn client_id(&mut self, connection_id: &str) -> u64 {
+        if let Some(id) = self.client_ids.get(connection_id) {
+            return *id;
         }
+        let id = self.next_client_id;
+        self.next_client_id += 1;
+        self.client_ids.insert(connection_id.to_owned(), id);
+        id
     }
 
-    fn process_replconf(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
-        match command.get(1) {
-            Some(option) if option == "ACK" => {
-                if let Some(ref mut replication_task) = self.wait_for_replication_acks {
-                    replication_task.number_of_acks += 1;
+    fn process_hello(
+        &mut self,
+        command: &[String],
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: ConnectionID,
+    ) {
+        let current = self
+            .protocols
+            .get(&connection_id)
+            .copied()
+            .unwrap_or_default();
+        let authenticated = self.authenticated.contains(&connection_id);
+        let response = match parse_hello(command, current, &self.config, authenticated) {
+            Ok((protocol, authenticated)) => {
+                if authenticated {
+                    self.authenticated.insert(connection_id.clone());
                 }
-                // self.replication.match_offsets();
-            }
-            _ => {
-                tx_back
-                    .send(ConnectionMessage::SendString(String::from("+OK\r\n")))
-                    .unwrap();
+                if protocol != current {
+                    self.protocols.insert(connection_id.clone(), protocol);
+                    tx_back
+                        .send(ConnectionMessage::SetProtocol(protocol))
+                        .unwrap();
+                }
+                let id = self.client_id(&connection_id);
+                hello_reply(&self.config, protocol, id)
             }
-        }
+            Err(error) => error,
+        };
+        tx_back
+            .send(ConnectionMessage::SendReply(response))
+            .unwrap();
     }
 
-    fn process_psync(&mut self, tx_back: Sender<ConnectionMessage>) {
+    fn process_auth(
+        &mut self,
+        command: &[String],
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: ConnectionID,
+    ) {
+        let response = match check_auth(command, &self.config) {
+            Ok(()) => {
+                self.authenticated.insert(connection_id);
+                Reply::ok()
+            }
+            Err(error) => error,
+        };
         tx_back
-            .send(ConnectionMessage::SendString(format_string(Some(format!(
-                "+FULLRESYNC {} {}",
-                self.config.replication.replid, self.config.replication.repl_offset
-            )))))
+            .send(ConnectionMessage::SendReply(response))
             .unwrap();
+    }
 
-        let Ok(empty_db) = fs::read("empty.rdb") else {
-            return;
+    fn process_config_set(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
+        let response = match config_set(command, &self.config) {
+            Ok(config) => {
+                self.config = config;
+                Reply::ok()
+            }
+            Err(error) => error,
         };
-
         tx_back
-            .send(ConnectionMessage::SendString(format!(
-                "${}\r\n",
-                empty_db.len()
-            )))
+            .send(ConnectionMessage::SendReply(response))
             .unwrap();
+    }
+
+    fn process_client(
+        &mut self,
+        command: &[String],
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: ConnectionID,
+    ) {
+        let response = match command[1].to_uppercase().as_str() {
+            "HELP" => help_reply(
+                "CLIENT",
+                &[
+                    "ID",
+                    "    Return the ID of the current connection.",
+                    "NO-EVICT (ON|OFF)",
+                    "    Protect the current connection from eviction, accepted as a no-op.",
+                    "NO-TOUCH (ON|OFF)",
+                    "    Will not touch LRU/LFU stats when this mode is on.",
+                    "TRACKING (ON|OFF) [REDIRECT <id>]",
+                    "    Control server assisted client side caching.",
+                    "UNPAUSE",
+                    "    Stop the current client pause, accepted as a no-op.",
+                ],
+            ),
+            "ID" => Reply::Integer(self.client_id(&connection_id) as i64),
+            // Client connections are never evicted
+            "NO-EVICT" => match command.get(2).map(|mode| mode.to_uppercase()).as_deref() {
+                Some("ON" | "OFF") if command.len() == 3 => Reply::ok(),
+                _ => Reply::error("ERR syntax error"),
+            },
+            "NO-TOUCH" => match command.get(2).map(|mode| mode.to_uppercase()).as_deref() {
+                Some("ON") if command.len() == 3 => {
+                    self.no_touch.insert(connection_id.clone());
+                    Reply::ok()
+                }
+                Some("OFF") if command.len() == 3 => {
+                    self.no_touch.remove(&connection_id);
+                    Reply::ok()
+                }
+                _ => Reply::error("ERR syntax error"),
+            },
+            // Clients are never paused
+            "UNPAUSE" if command.len() == 2 => Reply::ok(),
+            "TRACKING" => self.process_client_tracking(&command[2..], &tx_back, &connection_id),
+            _ => Reply::Error(format!(
+                "ERR unknown subcommand '{}'. Try CLIENT HELP.",
+                command[1]
+            )),
+        };
         tx_back
-            .send(ConnectionMessage::SendBytes(empty_db))
+            .send(ConnectionMessage::SendReply(response))
             .unwrap();
-        self.replicas.push(tx_back.clone());
     }
 
-    fn process_wait(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
-        let Some(expected_number_of_acks) = command.get(1).and_then(|n| n.parse::<usize>().ok())
-        else {
-            println!("Cannot process invalid WAIT command: {command:?}");
-            return;
+    /// `CLIENT TRACKING ON|OFF [REDIRECT id]`. Only the default mode is supported, the BCAST,
+    /// OPTIN, OPTOUT, NOLOOP and PREFIX options are rejected.
+    fn process_client_tracking(
+        &mut self,
+        args: &[String],
+        tx_back: &Sender<ConnectionMessage>,
+        connection_id: &str,
+    ) -> Reply {
+        let Some(mode) = args.first() else {
+            return Reply::error("ERR wrong number of arguments for 'client|tracking' command");
         };
-
-        // Edge case: if the number of acks the client wants is 0, we can respond immediately with 0.
-        if expected_number_of_acks == 0 {
-            tx_back
-                .send(ConnectionMessage::SendString(String::from(":0\r\n")))
-                .unwrap();
-            return;
+        let mut redirect = None;
+        let mut options = args[1..].iter();
+        while let Some(option) = options.next() {
+            if !option.eq_ignore_ascii_case("REDIRECT") {
+                return Reply::error("ERR syntax error");
+            }
+            let Some(Ok(id)) = options.next().map(|id| id.parse::<u64>()) else {
+                return Reply::error("ERR value is not an integer or out of range");
+            };
+            let Some((target, _)) = self.client_ids.iter().find(|(_, client)| **client == id)
+            else {
+                return Reply::error("ERR The client ID you want redirect to does not exist");
+            };
+            redirect = Some(target.clone());
         }
 
-        // Edge case: if the last acked offset has not changed, we can respond immediately with the
-        // number of replicas currently connected to the master instance.
-        println!(
-            "Replication offset: {} (last checked: {})",
-            self.replication.replication_offset, self.replication.last_offset_checked
-        );
-        if self.replication.last_offset_checked == self.replication.replication_offset {
-            tx_back
-                .send(ConnectionMessage::SendString(format!(
-                    ":{}\r\n",
-                    self.replicas.len()
-                )))
-                .unwrap();
-            return;
+        match mode.to_uppercase().as_str() {
+            "ON" => self.tracking.enable(connection_id, tx_back, redirect),
+            "OFF" => self.tracking.disable(connection_id),
+            _ => return Reply::error("ERR syntax error"),
         }
+        Reply::ok()
+    }
 
-        // Else, we send all replicas a REPLCONF GETACK * command.
-        for replica in &self.replicas {
-            replica
-                .send(ConnectionMessage::SendString(format_array(&vec![
-                    "REPLCONF".to_owned(),
-                    "GETACK".to_owned(),
-                    "*".to_owned(),
-                ])))
-                .unwrap();
-        }
+    fn process_quit(&mut self, tx_back: Sender<ConnectionMessage>) {
+        tx_back
+            .send(ConnectionMessage::SendReply(Reply::ok()))
+            .unwrap();
+        tx_back.send(ConnectionMessage::Close).unwrap();
+    }
 
-        let timeout = command
-            .get(2)
-            .and_then(|n| n.parse::<u64>().ok())
-            .map(|ms| Instant::now() + Duration::from_millis(ms));
-        self.wait_for_replication_acks = Some(WaitForReplicationAcks {
-            expected_number_of_acks,
-            initial_client_tx: tx_back,
-            timeout,
-            number_of_acks: 0,
+    /// Serve, then drop, every blocked XREAD waiting on `stream_key` of the selected database for
+    /// which new entries are available. A client blocked on several streams gets a single reply
+    /// grouping all of them.
+    fn notify_blocking_xreads(&mut self, stream_key: &str) {
+        let store = &mut self.store;
+        let db = store.selected();
+        self.blocking_xreads.retain(|task| {
+            if task.db != db || !task.streams.iter().any(|(stream, _)| stream == stream_key) {
+                return true;
+            }
+            let response: Vec<Reply> = task
+                .streams
+                .iter()
+                .filter_map(|(stream, last_id)| {
+                    let entries: Vec<_> = store
+                        .get_stream_range(stream, Some(last_id), None)
+                        .iter()
+                        .filter(|entry| entry.id > *last_id)
+                        .collect();
+                    if entries.is_empty() {
+                        return None;
+                    }
+                    Some(Reply::Array(vec![
+                        Reply::bulk(stream),
+                        stream_reply(entries),
+                    ]))
+                })
+                .collect();
+            if response.is_empty() {
+                return true;
+            }
+            println!("Serving blocked XREAD on {stream_key}");
+            let _ = task
+                .initial_client_tx
+                .send(ConnectionMessage::SendReply(Reply::Array(response)));
+            false
         });
     }
 
-    fn check_on_replication_waits(&mut self) {
-        let Some(ref task) = self.wait_for_replication_acks else {
+    fn process_blocking_xread(
+        &mut self,
+        command: &[String],
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: ConnectionID,
+    ) {
+        let Some(XREADArguments {
+            block_for: Some(block_for),
+            streams,
+        }) = parse_xread_arguments(command)
+        else {
             return;
         };
 
-        if let Some(timeout) = task.timeout {
-            if timeout <= Instant::now() {
-                task.initial_client_tx
-                    .send(ConnectionMessage::SendString(format!(
-                        ":{}\r\n",
-                        task.number_of_acks
-                    )))
-                    .unwrap();
-                self.replication.match_offsets();
-                self.wait_for_replication_acks = None;
-                return;
-            }
-        }
-
-        if task.number_of_acks >= task.expected_number_of_acks {
-            task.initial_client_tx
-                .send(ConnectionMessage::SendString(format!(
-                    ":{}\r\n",
-                    task.number_of_acks
-                )))
+        // Keep track to propagate futur XADD commands
+        let timeout = if block_for > 0 {
+            Some(Instant::now() + Duration::from_millis(block_for.try_into().unwrap()))
+        } else {
+            None
+        };
+        // `$` (or any ID that cannot be parsed) means only entries added from now on
+        let streams = streams
+            .into_iter()
+            .map(|(stream, last_id)| {
+                let last_id = last_id.unwrap_or_else(|| {
+                    self.store
+                        .get_last_stream_entry(&stream)
+                        .map_or(StreamEntryId::default(), |entry| entry.id)
+                });
+                (stream, last_id)
+            })
+            .collect();
+        self.blocking_xreads.push(BlockingXREAD {
+            initial_client_tx: tx_back.clone(),
+            connection_id,
+            db: self.store.selected(),
+            streams,
+            timeout,
+        });
+    }
+
+    /// Serve right away when entries are available, otherwise wait for an XADD on one of the
+    /// streams.
+    fn process_blocking_xreadgroup(
+        &mut self,
+        command: Command,
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: ConnectionID,
+    ) {
+        let Ok(XREADGROUPArguments {
+            block_for: Some(block_for),
+            streams,
+            ..
+        }) = parse_xreadgroup_arguments(&command.cmd)
+        else {
+            return;
+        };
+        let Some(outcome) = CommandHandler::new(&mut self.store, &self.config).handle(&command)
+        else {
+            return;
+        };
+        if outcome.response != Reply::NullArray {
+            self.apply_outcome(&command, outcome, &tx_back);
+            return;
+        }
+
+        let timeout = (block_for > 0)
+            .then(|| Instant::now() + Duration::from_millis(block_for.try_into().unwrap()));
+        self.blocking_xreadgroups.push(BlockingXREADGROUP {
+            initial_client_tx: tx_back,
+            connection_id,
+            db: self.store.selected(),
+            command,
+            keys: streams.into_iter().map(|(key, _)| key).collect(),
+            timeout,
+        });
+    }
+
+    /// Replay the blocked XREADGROUP commands waiting on `stream_key` of the selected database,
+    /// dropping the served ones.
+    fn notify_blocking_xreadgroups(&mut self, stream_key: &str) {
+        let db = self.store.selected();
+        let tasks = std::mem::take(&mut self.blocking_xreadgroups);
+        for task in tasks {
+            if task.db != db || !task.keys.iter().any(|key| key == stream_key) {
+                self.blocking_xreadgroups.push(task);
+                continue;
+            }
+            let outcome = CommandHandler::new(&mut self.store, &self.config).handle(&task.command);
+            match outcome {
+                Some(outcome) if outcome.response != Reply::NullArray => {
+                    println!("Serving blocked XREADGROUP on {stream_key}");
+                    self.apply_outcome(&task.command, outcome, &task.initial_client_tx);
+                }
+                _ => self.blocking_xreadgroups.push(task),
+            }
+        }
+    }
+
+    fn process_replconf(
+        &mut self,
+        command: &[String],
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: ConnectionID,
+    ) {
+        match command.get(1) {
+            Some(option) if option.eq_ignore_ascii_case("ack") => {
+                self.process_replica_ack(command, &connection_id)
+            }
+            Some(option) if option.eq_ignore_ascii_case("listening-port") => {
+                let Some(port) = command.get(2).and_then(|port| port.parse::<u16>().ok()) else {
+                    tx_back
+                        .send(ConnectionMessage::SendReply(Reply::error(
+                            "ERR value is not an integer or out of range",
+                        )))
+                        .unwrap();
+                    return;
+                };
+                self.replica_handshakes
+                    .entry(connection_id)
+                    .or_default()
+                    .listening_port = Some(port);
+                tx_back
+                    .send(ConnectionMessage::SendReply(Reply::ok()))
+                    .unwrap();
+            }
+            Some(option) if option.eq_ignore_ascii_case("capa") => {
+                self.replica_handshakes
+                    .entry(connection_id)
+                    .or_default()
+                    .capabilities
+                    .extend(command[2..].iter().map(|capa| capa.to_lowercase()));
+                tx_back
+                    .send(ConnectionMessage::SendReply(Reply::ok()))
+                    .unwrap();
+            }
+            _ => {
+                tx_back
+                    .send(ConnectionMessage::SendReply(Reply::ok()))
+                    .unwrap();
+            }
+        }
+    }
+
+    /// Record the replication offset acknowledged by a replica with `REPLCONF ACK <offset>`.
+    fn process_replica_ack(&mut self, command: &[String], connection_id: &ConnectionID) {
+        let offset = command
+            .get(2)
+            .and_then(|offset| offset.parse::<usize>().ok());
+        if let (Some(replica), Some(offset)) = (self.replicas.get_mut(connection_id), offset) {
+            replica.acked_offset = replica.acked_offset.max(offset);
+            replica.last_ack = Instant::now();
+        }
+    }
+
+    /// Full resynchronization: send a snapshot of the dataset, then register the replica so that it
+    /// receives every write from now on. Both happen within the same step of the actor, so no
+    /// write can be missed or applied twice by the replica.
+    fn process_psync(
+        &mut self,
+        command: &[String],
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: ConnectionID,
+    ) {
+        if let Some((offset, missing)) = self.missing_replication_stream(command) {
+            self.process_partial_resync(offset, missing, tx_back, connection_id);
+            return;
+        }
+
+        // The new replica starts on the first database, switch the others back to it
+        if self.replication.db != 0 {
+            self.propagate_select(0);
+        }
+        let offset = self.replication.replication_offset;
+        tx_back
+            .send(ConnectionMessage::SendReply(Reply::Simple(format!(
+                "FULLRESYNC {} {offset}",
+                self.config.replication.replid
+            ))))
+            .unwrap();
+
+        let snapshot = self.store.to_rdb();
+        tx_back
+            .send(ConnectionMessage::SendString(format!(
+                "${}\r\n",
+                snapshot.len()
+            )))
+            .unwrap();
+        tx_back
+            .send(ConnectionMessage::SendBytes(snapshot))
+            .unwrap();
+        self.register_replica(tx_back, connection_id, offset);
+    }
+
+    /// Offset and bytes of the replication stream missed by a replica asking to continue with
+    /// `PSYNC <replid> <offset>`, when the backlog still holds them. As in Redis, the requested
+    /// offset is the one of the first missing byte, counting from 1.
+    fn missing_replication_stream(&self, command: &[String]) -> Option<(usize, Vec<u8>)> {
+        let (Some(replid), Some(offset)) = (command.get(1), command.get(2)) else {
+            return None;
+        };
+        let offset = offset.parse::<usize>().ok()?.checked_sub(1)?;
+        let replication = &self.config.replication;
+        let previous_history = *replid == replication.replid2
+            && replication
+                .second_repl_offset
+                .is_some_and(|second_offset| offset <= second_offset);
+        if *replid != replication.replid && !previous_history {
+            return None;
+        }
+        let missing = self.replication.backlog.since(offset)?;
+        Some((offset, missing))
+    }
+
+    fn process_partial_resync(
+        &mut self,
+        offset: usize,
+        missing: Vec<u8>,
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: ConnectionID,
+    ) {
+        println!(
+            "Partial resync of replica {connection_id} from offset {offset} ({} bytes)",
+            missing.len()
+        );
+        tx_back
+            .send(ConnectionMessage::SendReply(Reply::Simple(String::from(
+                "CONTINUE",
+            ))))
+            .unwrap();
+        if !missing.is_empty() {
+            tx_back.send(ConnectionMessage::SendBytes(missing)).unwrap();
+        }
+        self.register_replica(tx_back, connection_id, offset);
+    }
+
+    fn register_replica(
+        &mut self,
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: ConnectionID,
+        acked_offset: usize,
+    ) {
+        let addr = self.client_addrs.get(&connection_id).copied();
+        let handshake = self
+            .replica_handshakes
+            .swap_remove(&connection_id)
+            .unwrap_or_default();
+        println!(
+            "Registering replica {connection_id} with capabilities {:?}",
+            handshake.capabilities
+        );
+        self.replicas.insert(
+            connection_id,
+            Replica {
+                tx: tx_back.clone(),
+                addr,
+                listening_port: handshake.listening_port,
+                acked_offset,
+                last_ack: Instant::now(),
+            },
+        );
+    }
+
+    fn process_wait(
+        &mut self,
+        command: &[String],
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: ConnectionID,
+    ) {
+        let Some(expected_number_of_acks) = command.get(1).and_then(|n| n.parse::<usize>().ok())
+        else {
+            println!("Cannot process invalid WAIT command: {command:?}");
+            return;
+        };
+
+        // Edge case: if the number of acks the client wants is 0, we can respond immediately with 0.
+        if expected_number_of_acks == 0 {
+            tx_back
+                .send(ConnectionMessage::SendReply(Reply::Integer(0)))
+                .unwrap();
+            return;
+        }
+
+        // Edge case: if enough replicas have already acked the current offset, or if every replica
+        // has, we can respond immediately with the number of up-to-date replicas.
+        let target_offset = self.replication.replication_offset;
+        let number_of_acks = self.count_acked_replicas(target_offset);
+        println!("Replication offset: {target_offset} ({number_of_acks} replicas up to date)");
+        if number_of_acks >= expected_number_of_acks || number_of_acks == self.replicas.len() {
+            tx_back
+                .send(ConnectionMessage::SendReply(Reply::Integer(
+                    number_of_acks as i64,
+                )))
                 .unwrap();
-            self.replication.match_offsets();
-            self.wait_for_replication_acks = None;
+            return;
+        }
+
+        // Else, we send the replicas a REPLCONF GETACK * command. It is part of the replication
+        // stream, so every replica gets it for their offsets to stay in line with the backlog.
+        let getack = command_reply(&[b"REPLCONF".to_vec(), b"GETACK".to_vec(), b"*".to_vec()]);
+        let getack_bytes = getack.encode(Protocol::RESP2);
+        self.replication.replication_offset += getack_bytes.len();
+        self.replication.backlog.feed(&getack_bytes);
+        self.send_to_replicas(&getack);
+
+        // A timeout of 0 (or no timeout at all) blocks until enough replicas have acknowledged.
+        let timeout = command
+            .get(2)
+            .and_then(|n| n.parse::<u64>().ok())
+            .filter(|ms| *ms > 0)
+            .map(|ms| Instant::now() + Duration::from_millis(ms));
+        self.wait_for_replication_acks.push(WaitForReplicationAcks {
+            expected_number_of_acks,
+            initial_client_tx: tx_back,
+            connection_id,
+            target_offset,
+            timeout,
+        });
+    }
+
+    /// Number of replicas that have acknowledged at least `offset`.
+    fn count_acked_replicas(&self, offset: usize) -> usize {
+        self.replicas
+            .values()
+            .filter(|replica| replica.acked_offset >= offset)
+            .count()
+    }
+
+    /// Reply to, then drop, every WAIT that got enough acks or timed out.
+    fn check_on_replication_waits(&mut self) {
+        let tasks = std::mem::take(&mut self.wait_for_replication_acks);
+        for task in tasks {
+            let number_of_acks = self.count_acked_replicas(task.target_offset);
+            let timed_out = task
+                .timeout
+                .is_some_and(|timeout| timeout <= Instant::now());
+            if timed_out || number_of_acks >= task.expected_number_of_acks {
+                let _ = task
+                    .initial_client_tx
+                    .send(ConnectionMessage::SendReply(Reply::Integer(
+                        number_of_acks as i64,
+                    )));
+            } else {
+                self.wait_for_replication_acks.push(task);
+            }
         }
     }
 
@@ -540,7 +1317,7 @@ impl MasterActor {
         self.blocking_xreads.retain(|task| match task.timeout {
             Some(timeout) if timeout <= Instant::now() => {
                 task.initial_client_tx
-                    .send(ConnectionMessage::SendString("$-1\r\n".to_owned()))
+                    .send(ConnectionMessage::SendReply(Reply::Null))
                     .unwrap();
                 false
             }
@@ -548,21 +1325,87 @@ impl MasterActor {
         });
     }
 
-    fn process_incr(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
-        let Some(key) = command.get(1) else {
+    /// Active expiry: periodically remove expired keys, even if they are never accessed again.
+    fn expire_keys(&mut self) {
+        if !self.active_expire || self.last_active_expire.elapsed() < ACTIVE_EXPIRE_PERIOD {
             return;
-        };
+        }
+        self.last_active_expire = Instant::now();
+        let (_, nearest_expiry) = self.store.expiry_stats();
+        if nearest_expiry.is_some_and(|expiry| expiry <= Utc::now()) {
+            self.stats.expired_keys += self.store.flush_expired();
+        }
+    }
 
-        let Some(new_value) = self.store.incr(key) else {
-            tx_back
-                .send(ConnectionMessage::SendString(
-                    "-ERR value is not an integer or out of range\r\n".to_owned(),
-                ))
-                .unwrap();
+    fn check_on_blocking_xreadgroups(&mut self) {
+        self.blocking_xreadgroups.retain(|task| match task.timeout {
+            Some(timeout) if timeout <= Instant::now() => {
+                let _ = task
+                    .initial_client_tx
+                    .send(ConnectionMessage::SendReply(Reply::NullArray));
+                false
+            }
+            _ => true,
+        });
+    }
+
+    fn process_monitor(&mut self, tx_back: Sender<ConnectionMessage>, connection_id: ConnectionID) {
+        tx_back
+            .send(ConnectionMessage::SendReply(Reply::ok()))
+            .unwrap();
+        self.monitors.insert(connection_id, tx_back);
+    }
+
+    /// Send `command` to every MONITOR connection, forgetting the ones that are gone. Admin
+    /// commands are not reported, like in Redis.
+    fn feed_monitors(&mut self, command: &Command, connection_id: &ConnectionID) {
+        if self.monitors.is_empty() || command.verb.metadata().flags.contains(&"admin") {
             return;
+        }
+        let client = self
+            .client_addrs
+            .get(connection_id)
+            .map_or_else(|| connection_id.clone(), |addr| addr.to_string());
+        let line = monitor_reply(Utc::now(), &client, &command.raw);
+        self.monitors
+            .retain(|_, tx| tx.send(ConnectionMessage::SendReply(line.clone())).is_ok());
+    }
+
+    fn process_slowlog(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
+        let response = match command[1].to_uppercase().as_str() {
+            "HELP" => help_reply(
+                "SLOWLOG",
+                &[
+                    "GET [<count>]",
+                    "    Return top <count> entries from the slowlog (default: 10, -1 mean all).",
+                    "    Entries are made of:",
+                    "    id, timestamp, time in microseconds, arguments array, client IP and port,",
+                    "    client name",
+                    "LEN",
+                    "    Return the length of the slowlog.",
+                    "RESET",
+                    "    Reset the slowlog.",
+                ],
+            ),
+            "LEN" => Reply::Integer(self.slowlog.len() as i64),
+            "RESET" => {
+                self.slowlog.reset();
+                Reply::ok()
+            }
+            "GET" => match command.get(2).map(|count| count.parse::<i64>()) {
+                None => self.slowlog.get(Some(10)),
+                Some(Ok(-1)) => self.slowlog.get(None),
+                Some(Ok(count)) if count >= 0 => self.slowlog.get(Some(count as usize)),
+                Some(Ok(_)) => Reply::error("ERR count should be greater than or equal to -1"),
+                Some(Err(_)) => Reply::error("ERR value is not an integer or out of range"),
+            },
+            _ => Reply::Error(format!(
+                "ERR unknown subcommand '{}'. Try SLOWLOG HELP.",
+                command[1]
+            )),
         };
         tx_back
-            .send(ConnectionMessage::SendString(format!(":{new_value}\r\n")))
+            .send(ConnectionMessage::SendReply(response))
             .unwrap();
     }
 
@@ -580,213 +1423,1641 @@ impl MasterActor {
             },
         );
         tx_back
-            .send(ConnectionMessage::SendString("+OK\r\n".to_owned()))
+            .send(ConnectionMessage::SendReply(Reply::ok()))
+            .unwrap();
+    }
+
+    fn process_watch(
+        &mut self,
+        command: &[String],
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: ConnectionID,
+    ) {
+        let db = self.store.selected();
+        let watched_keys = self.watched_keys.entry(connection_id).or_default();
+        watched_keys
+            .keys
+            .extend(command[1..].iter().map(|key| (db, key.clone())));
+        tx_back
+            .send(ConnectionMessage::SendReply(Reply::ok()))
+            .unwrap();
+    }
+
+    fn process_unwatch(&mut self, tx_back: Sender<ConnectionMessage>, connection_id: ConnectionID) {
+        self.watched_keys.swap_remove(&connection_id);
+        tx_back
+            .send(ConnectionMessage::SendReply(Reply::ok()))
             .unwrap();
     }
 
+    /// Flag every connection watching `key` of database `db` so that its next EXEC is aborted,
+    /// and invalidate it for connections tracking it.
+    fn signal_modified_key(&mut self, db: usize, key: &str) {
+        let watched_key = (db, key.to_owned());
+        for watched_keys in self.watched_keys.values_mut() {
+            if watched_keys.keys.contains(&watched_key) {
+                watched_keys.dirty = true;
+            }
+        }
+        self.tracking.invalidate(db, key, &mut self.pubsub);
+    }
+
+    /// Select the database the connection last switched to with SELECT.
+    fn select_connection_db(&mut self, connection_id: &str) {
+        self.store
+            .select(self.selected_dbs.get(connection_id).copied().unwrap_or(0));
+    }
+
     fn process_exec(&mut self, transaction: Transaction, connection_id: ConnectionID) {
         println!("Commands to execute: {:?}", transaction.commands);
-        let mut message = format!("*{}\r\n", transaction.commands.len());
+        if self
+            .watched_keys
+            .swap_remove(&connection_id)
+            .is_some_and(|watched_keys| watched_keys.dirty)
+        {
+            transaction
+                .client_tx
+                .send(ConnectionMessage::SendReply(Reply::NullArray))
+                .unwrap();
+            return;
+        }
+
+        let mut responses = Vec::with_capacity(transaction.commands.len());
         let (dummy_tx, dummy_rx) = channel::<ConnectionMessage>();
         for cmd in &transaction.commands {
-            self.process_simple_command(cmd.clone(), dummy_tx.clone(), connection_id.clone());
-            let ConnectionMessage::SendString(response) = dummy_rx.recv().unwrap() else {
-                return;
-            };
-            message.push_str(&response);
+            match cmd.verb {
+                // Commands that may block reply at once inside a transaction, as if they had
+                // timed out
+                CommandVerb::WAIT => {
+                    let number_of_acks =
+                        self.count_acked_replicas(self.replication.replication_offset);
+                    dummy_tx
+                        .send(ConnectionMessage::SendReply(Reply::Integer(
+                            number_of_acks as i64,
+                        )))
+                        .unwrap();
+                }
+                CommandVerb::XREAD | CommandVerb::XREADGROUP => {
+                    self.select_connection_db(&connection_id);
+                    self.process_store_command(cmd, dummy_tx.clone())
+                }
+                _ => self.process_simple_command(
+                    cmd.clone(),
+                    dummy_tx.clone(),
+                    connection_id.clone(),
+                ),
+            }
+            // Every command has replied synchronously by now, most with a single reply. A command
+            // that did not reply gets a null instead of stalling the whole transaction.
+            let replies: Vec<Reply> = dummy_rx
+                .try_iter()
+                .filter_map(|message| match message {
+                    ConnectionMessage::SendReply(reply) => Some(reply),
+                    _ => None,
+                })
+                .collect();
+            if replies.is_empty() {
+                responses.push(Reply::Null);
+            }
+            responses.extend(replies);
         }
         transaction
             .client_tx
-            .send(ConnectionMessage::SendString(message))
+            .send(ConnectionMessage::SendReply(Reply::Array(responses)))
             .unwrap();
         self.transactions.swap_remove(&connection_id);
     }
 }
 
-fn parse_requested_stream_entry_id(arg: &String) -> Option<RequestedStreamEntryId> {
-    if arg == "*" {
-        return Some(RequestedStreamEntryId::AutoGenerate);
+fn is_blocking_xread(command: &[String]) -> bool {
+    parse_xread_arguments(command).is_some_and(|args| args.block_for.is_some())
+}
+
+fn is_blocking_xreadgroup(command: &[String]) -> bool {
+    parse_xreadgroup_arguments(command).is_ok_and(|args| args.block_for.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::mpsc::{channel, Receiver, Sender},
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        actor::{ConnectionMessage, StoreMessage},
+        config::{Args, Config},
+        connection::{
+            parser::{parse_buffer, BufferType, Command, CommandVerb},
+            reply::{Protocol, Reply},
+        },
+        store::{Store, DEFAULT_DATABASES},
+    };
+
+    use super::MasterActor;
+
+    fn build_actor() -> MasterActor {
+        MasterActor::new(Store::new(), Config::from_args(Args::new()))
     }
 
-    let (first, second) = arg.split_at_checked(arg.find("-")?)?;
-    let timestamp = first.parse::<usize>().ok()?;
-    let second = second.strip_prefix("-")?;
+    fn send_command(
+        actor: &MasterActor,
+        cmd: &[&str],
+        tx_back: &Sender<ConnectionMessage>,
+        connection_id: &str,
+    ) {
+        let command = Command::new(cmd.iter().map(|s| s.as_bytes().to_vec()).collect()).unwrap();
+        actor
+            .get_tx()
+            .send(StoreMessage::NewBuffer {
+                value: BufferType::Command(command),
+                tx_back: tx_back.clone(),
+                connection_id: connection_id.to_owned(),
+            })
+            .unwrap();
+    }
 
-    if second == "*" {
-        return Some(RequestedStreamEntryId::AutoGenerateSequence(timestamp));
+    fn received_strings(rx: &Receiver<ConnectionMessage>) -> Vec<String> {
+        rx.try_iter()
+            .filter_map(|msg| match msg {
+                ConnectionMessage::SendString(msg) => Some(msg),
+                ConnectionMessage::SendReply(reply) => {
+                    String::from_utf8(reply.encode(Protocol::RESP2)).ok()
+                }
+                _ => None,
+            })
+            .collect()
     }
 
-    let sequence_number = second.parse::<usize>().ok()?;
-    Some(RequestedStreamEntryId::Explicit(StreamEntryId {
-        timestamp,
-        sequence_number,
-    }))
-}
+    #[test]
+    fn wait_without_timeout_completes_once_replicas_acked() {
+        let mut actor = build_actor();
+        let (replica_tx, replica_rx) = channel();
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["PSYNC", "?", "-1"], &replica_tx, "replica");
+        send_command(&actor, &["SET", "foo", "bar"], &client_tx, "client");
+        send_command(&actor, &["WAIT", "1", "0"], &client_tx, "client");
+        actor.poll();
+
+        assert_eq!(received_strings(&client_rx), vec!["+OK\r\n".to_owned()]);
+        assert!(received_strings(&replica_rx)
+            .iter()
+            .any(|msg| msg.contains("GETACK")));
+
+        send_command(&actor, &["REPLCONF", "ACK", "31"], &replica_tx, "replica");
+        actor.poll();
 
-fn parse_stream_entry_id(arg: &str) -> Option<StreamEntryId> {
-    if arg == "+" || arg == "-" {
-        return None;
+        assert_eq!(received_strings(&client_rx), vec![":1\r\n".to_owned()]);
+        assert!(actor.wait_for_replication_acks.is_empty());
     }
 
-    let (first, second) = arg.split_at_checked(arg.find("-")?)?;
-    let timestamp = first.parse::<usize>().ok()?;
+    #[test]
+    fn concurrent_waits_are_served_independently() {
+        let mut actor = build_actor();
+        let (replica_tx, _replica_rx) = channel();
+        let (first_tx, first_rx) = channel();
+        let (second_tx, second_rx) = channel();
+
+        send_command(&actor, &["PSYNC", "?", "-1"], &replica_tx, "replica");
+        send_command(&actor, &["SET", "foo", "bar"], &first_tx, "first");
+        send_command(&actor, &["WAIT", "1", "0"], &first_tx, "first");
+        send_command(&actor, &["SET", "foo", "baz"], &second_tx, "second");
+        send_command(&actor, &["WAIT", "1", "0"], &second_tx, "second");
+        actor.poll();
+        assert_eq!(actor.wait_for_replication_acks.len(), 2);
+
+        // Acking the first write only releases the first client
+        send_command(&actor, &["REPLCONF", "ACK", "31"], &replica_tx, "replica");
+        actor.poll();
+        assert_eq!(
+            received_strings(&first_rx),
+            vec!["+OK\r\n".to_owned(), ":1\r\n".to_owned()]
+        );
+        assert_eq!(received_strings(&second_rx), vec!["+OK\r\n".to_owned()]);
 
-    let sequence_number = second.strip_prefix("-")?.parse::<usize>().ok()?;
-    Some(StreamEntryId {
-        timestamp,
-        sequence_number,
-    })
-}
+        send_command(&actor, &["REPLCONF", "ACK", "99"], &replica_tx, "replica");
+        actor.poll();
+        assert_eq!(received_strings(&second_rx), vec![":1\r\n".to_owned()]);
+        assert!(actor.wait_for_replication_acks.is_empty());
+    }
 
-#[derive(PartialEq, Debug)]
-struct XREADArguments {
-    streams: Vec<(String, Option<StreamEntryId>)>,
-    block_for: Option<usize>,
-}
+    #[test]
+    fn reconnecting_replica_is_partially_resynchronized() {
+        let mut actor = build_actor();
+        let (replica_tx, replica_rx) = channel();
+        let (client_tx, _client_rx) = channel();
+
+        send_command(&actor, &["PSYNC", "?", "-1"], &replica_tx, "replica");
+        send_command(&actor, &["SET", "foo", "bar"], &client_tx, "client");
+        actor.poll();
+        let _ = replica_rx.try_iter().count();
+        let offset = actor.replication.replication_offset;
+
+        // Writes missed while the replica is disconnected
+        actor
+            .get_tx()
+            .send(StoreMessage::ConnectionClosed {
+                connection_id: "replica".to_owned(),
+            })
+            .unwrap();
+        send_command(&actor, &["SET", "foo", "baz"], &client_tx, "client");
+        actor.poll();
+        assert!(actor.replicas.is_empty());
+
+        let (replica_tx, replica_rx) = channel();
+        let replid = actor.config.replication.replid.clone();
+        let requested = (offset + 1).to_string();
+        send_command(
+            &actor,
+            &["PSYNC", &replid, &requested],
+            &replica_tx,
+            "replica",
+        );
+        actor.poll();
 
-fn parse_xread_arguments(cmd: &[String]) -> Option<XREADArguments> {
-    let mut iter = cmd[1..].iter();
+        let messages: Vec<ConnectionMessage> = replica_rx.try_iter().collect();
+        assert_eq!(messages.len(), 2);
+        assert!(
+            matches!(&messages[0], ConnectionMessage::SendReply(Reply::Simple(s)) if s == "CONTINUE")
+        );
+        assert!(matches!(
+            &messages[1],
+            ConnectionMessage::SendBytes(bytes)
+                if bytes == b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbaz\r\n"
+        ));
+        assert_eq!(actor.replicas["replica"].acked_offset, offset);
+
+        // Offsets out of the backlog, or from another replication id, need a full resync
+        for (replid, offset, connection_id) in [(replid.as_str(), "1000", "r1"), ("?", "1", "r2")] {
+            let (replica_tx, replica_rx) = channel();
+            send_command(
+                &actor,
+                &["PSYNC", replid, offset],
+                &replica_tx,
+                connection_id,
+            );
+            actor.poll();
+            assert!(received_strings(&replica_rx)[0].starts_with("+FULLRESYNC"));
+        }
 
-    let mut option = iter.next()?;
-    let timeout = if option == "block" {
-        let timeout = iter.next().and_then(|t| t.as_str().parse::<usize>().ok());
-        option = iter.next()?;
-        timeout
-    } else {
-        None
-    };
-    if option != "streams" {
-        return None;
-    }
-    let cmd = iter.as_slice();
-    let midpoint = cmd.len() / 2;
-    let names = cmd[..midpoint].iter();
-    let ids = cmd[midpoint..].iter();
-
-    let streams: Vec<(String, Option<StreamEntryId>)> = zip(names, ids)
-        .map(|(name, id)| (name.clone(), parse_stream_entry_id(id)))
-        .collect();
-
-    Some(XREADArguments {
-        streams,
-        block_for: timeout,
-    })
-    // Check for optionnal block timeout (ms)
-}
+        // Replicas acknowledge offsets of the master stream, however they synchronized
+        let (ack_tx, _ack_rx) = channel();
+        let current = actor.replication.replication_offset.to_string();
+        for connection_id in ["replica", "r1"] {
+            send_command(
+                &actor,
+                &["REPLCONF", "ACK", &current],
+                &ack_tx,
+                connection_id,
+            );
+        }
+        actor.poll();
+        for connection_id in ["replica", "r1"] {
+            assert_eq!(
+                actor.replicas[connection_id].acked_offset,
+                actor.replication.replication_offset
+            );
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use crate::{
-        actor::master::parse_requested_stream_entry_id,
-        store::stream::{RequestedStreamEntryId, StreamEntryId},
-    };
+    #[test]
+    fn previous_replication_id_is_accepted_up_to_the_switch() {
+        let mut actor = build_actor();
+        let (client_tx, _client_rx) = channel();
+
+        send_command(&actor, &["SET", "foo", "bar"], &client_tx, "client");
+        actor.poll();
+        let offset = actor.replication.replication_offset;
+        let previous = std::mem::replace(&mut actor.config.replication.replid, "1".repeat(40));
+        actor.config.replication.replid2 = previous.clone();
+        actor.config.replication.second_repl_offset = Some(offset);
+        send_command(&actor, &["SET", "foo", "baz"], &client_tx, "client");
+        actor.poll();
+
+        let (replica_tx, replica_rx) = channel();
+        let requested = (offset + 1).to_string();
+        send_command(&actor, &["PSYNC", &previous, &requested], &replica_tx, "r1");
+        let (replica_tx, other_rx) = channel();
+        let requested = (actor.replication.replication_offset + 1).to_string();
+        send_command(&actor, &["PSYNC", &previous, &requested], &replica_tx, "r2");
+        actor.poll();
+
+        assert_eq!(received_strings(&replica_rx), vec!["+CONTINUE\r\n"]);
+        assert!(received_strings(&other_rx)[0].starts_with("+FULLRESYNC"));
+    }
+
+    #[test]
+    fn monitor_receives_processed_commands() {
+        let mut actor = build_actor();
+        let (monitor_tx, monitor_rx) = channel();
+        let (client_tx, _client_rx) = channel();
+
+        send_command(&actor, &["MONITOR"], &monitor_tx, "monitor");
+        send_command(&actor, &["SET", "foo", "a b"], &client_tx, "client");
+        send_command(&actor, &["DEBUG", "HELP"], &client_tx, "client");
+        send_command(&actor, &["GET", "foo"], &client_tx, "client");
+        actor.poll();
+
+        let received = received_strings(&monitor_rx);
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[0], "+OK\r\n");
+        assert!(received[1].ends_with(" [0 client] \"SET\" \"foo\" \"a b\"\r\n"));
+        assert!(received[2].ends_with(" [0 client] \"GET\" \"foo\"\r\n"));
+        assert!(actor.replicas.is_empty());
+
+        actor
+            .get_tx()
+            .send(StoreMessage::ConnectionClosed {
+                connection_id: "monitor".to_owned(),
+            })
+            .unwrap();
+        actor.poll();
+        assert!(actor.monitors.is_empty());
+    }
+
+    #[test]
+    fn subscriptions_are_dropped_with_the_connection() {
+        let mut actor = build_actor();
+        let (subscriber_tx, _subscriber_rx) = channel();
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["SUBSCRIBE", "foo"], &subscriber_tx, "subscriber");
+        send_command(&actor, &["PSUBSCRIBE", "f*"], &subscriber_tx, "subscriber");
+        actor
+            .get_tx()
+            .send(StoreMessage::ConnectionClosed {
+                connection_id: "subscriber".to_owned(),
+            })
+            .unwrap();
+        send_command(&actor, &["PUBLISH", "foo", "hello"], &client_tx, "client");
+        actor.poll();
 
-    use super::{parse_xread_arguments, XREADArguments};
+        assert_eq!(received_strings(&client_rx), vec![":0\r\n".to_owned()]);
+    }
 
     #[test]
-    fn requested_stream_entry_id_invalid() {
-        let arg = String::from("toto");
-        assert_eq!(parse_requested_stream_entry_id(&arg), None);
+    fn replica_connection_only_sends_acks() {
+        let mut actor = build_actor();
+        let (replica_tx, replica_rx) = channel();
+
+        send_command(&actor, &["PSYNC", "?", "-1"], &replica_tx, "replica");
+        actor.poll();
+        received_strings(&replica_rx);
+
+        send_command(&actor, &["REPLCONF", "ACK", "0"], &replica_tx, "replica");
+        send_command(&actor, &["PING"], &replica_tx, "replica");
+        send_command(&actor, &["GET"], &replica_tx, "replica");
+        send_command(&actor, &["replconf", "ack", "14"], &replica_tx, "replica");
+        actor.poll();
+
+        assert!(received_strings(&replica_rx).is_empty());
+        assert_eq!(actor.replicas["replica"].acked_offset, 14);
     }
 
     #[test]
-    fn requested_stream_entry_id_auto_generate() {
-        let arg = String::from("*");
+    fn wait_sends_getack_to_every_replica() {
+        let mut actor = build_actor();
+        let (up_to_date_tx, up_to_date_rx) = channel();
+        let (lagging_tx, lagging_rx) = channel();
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["PSYNC", "?", "-1"], &up_to_date_tx, "up-to-date");
+        send_command(&actor, &["PSYNC", "?", "-1"], &lagging_tx, "lagging");
+        send_command(&actor, &["SET", "foo", "bar"], &client_tx, "client");
+        send_command(
+            &actor,
+            &["REPLCONF", "ACK", "31"],
+            &up_to_date_tx,
+            "up-to-date",
+        );
+        send_command(&actor, &["WAIT", "2", "0"], &client_tx, "client");
+        actor.poll();
+
+        let getack_sent = |rx| {
+            received_strings(rx)
+                .iter()
+                .any(|msg| msg.contains("GETACK"))
+        };
+        assert!(getack_sent(&up_to_date_rx));
+        assert!(getack_sent(&lagging_rx));
+        send_command(&actor, &["REPLCONF", "ACK", "31"], &lagging_tx, "lagging");
+        actor.poll();
         assert_eq!(
-            parse_requested_stream_entry_id(&arg),
-            Some(RequestedStreamEntryId::AutoGenerate)
+            received_strings(&client_rx),
+            vec!["+OK\r\n".to_owned(), ":2\r\n".to_owned()]
         );
+
+        // The up-to-date replica processed the GETACK too, and resumes right after it
+        let offset = actor.replication.replication_offset;
+        actor
+            .get_tx()
+            .send(StoreMessage::ConnectionClosed {
+                connection_id: "up-to-date".to_owned(),
+            })
+            .unwrap();
+        send_command(&actor, &["SET", "foo", "baz"], &client_tx, "client");
+        actor.poll();
+
+        let (replica_tx, replica_rx) = channel();
+        let replid = actor.config.replication.replid.clone();
+        let requested = (offset + 1).to_string();
+        send_command(
+            &actor,
+            &["PSYNC", &replid, &requested],
+            &replica_tx,
+            "up-to-date",
+        );
+        actor.poll();
+
+        let messages: Vec<ConnectionMessage> = replica_rx.try_iter().collect();
+        assert_eq!(messages.len(), 2);
+        assert!(
+            matches!(&messages[0], ConnectionMessage::SendReply(Reply::Simple(s)) if s == "CONTINUE")
+        );
+        assert!(matches!(
+            &messages[1],
+            ConnectionMessage::SendBytes(bytes)
+                if bytes == b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbaz\r\n"
+        ));
+        assert_eq!(actor.replicas["up-to-date"].acked_offset, offset);
+    }
+
+    #[test]
+    fn wait_counts_only_replicas_that_acked_the_write() {
+        let mut actor = build_actor();
+        let (replica_tx, _replica_rx) = channel();
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["PSYNC", "?", "-1"], &replica_tx, "replica-1");
+        send_command(&actor, &["PSYNC", "?", "-1"], &replica_tx, "replica-2");
+        send_command(&actor, &["SET", "foo", "bar"], &client_tx, "client");
+        send_command(&actor, &["WAIT", "2", "0"], &client_tx, "client");
+        send_command(&actor, &["REPLCONF", "ACK", "31"], &replica_tx, "replica-1");
+        send_command(&actor, &["REPLCONF", "ACK", "0"], &replica_tx, "replica-2");
+        actor.poll();
+
+        assert_eq!(received_strings(&client_rx), vec!["+OK\r\n".to_owned()]);
+        assert_eq!(actor.wait_for_replication_acks.len(), 1);
+
+        send_command(&actor, &["REPLCONF", "ACK", "31"], &replica_tx, "replica-2");
+        actor.poll();
+
+        assert_eq!(received_strings(&client_rx), vec![":2\r\n".to_owned()]);
     }
 
     #[test]
-    fn requested_stream_entry_id_auto_generate_sequence() {
-        let arg = String::from("1526919030474-*");
+    fn wait_ignores_disconnected_replicas() {
+        let mut actor = build_actor();
+        let (replica_tx, _replica_rx) = channel();
+        let (dead_replica_tx, dead_replica_rx) = channel();
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["PSYNC", "?", "-1"], &replica_tx, "replica");
+        send_command(
+            &actor,
+            &["PSYNC", "?", "-1"],
+            &dead_replica_tx,
+            "dead-replica",
+        );
+        actor.poll();
+        drop(dead_replica_rx);
+
+        send_command(&actor, &["SET", "foo", "bar"], &client_tx, "client");
+        send_command(&actor, &["WAIT", "2", "100"], &client_tx, "client");
+        actor.poll();
+        assert_eq!(actor.replicas.len(), 1);
+        assert_eq!(actor.wait_for_replication_acks.len(), 1);
+
+        send_command(&actor, &["REPLCONF", "ACK", "31"], &replica_tx, "replica");
+        thread::sleep(Duration::from_millis(150));
+        actor.poll();
+
         assert_eq!(
-            parse_requested_stream_entry_id(&arg),
-            Some(RequestedStreamEntryId::AutoGenerateSequence(1526919030474))
+            received_strings(&client_rx),
+            vec!["+OK\r\n".to_owned(), ":1\r\n".to_owned()]
         );
     }
 
     #[test]
-    fn requested_stream_entry_id_explicit() {
-        let arg = String::from("1526919030474-12");
+    fn blocking_xread_on_two_streams_is_served_once() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+        let (other_tx, _other_rx) = channel();
+
+        send_command(
+            &actor,
+            &["XREAD", "block", "0", "streams", "a", "b", "5-0", "$"],
+            &client_tx,
+            "client",
+        );
+        send_command(&actor, &["XADD", "a", "1-0", "f", "v"], &other_tx, "other");
+        actor.poll();
+        assert!(received_strings(&client_rx).is_empty());
+
+        send_command(&actor, &["XADD", "a", "6-0", "f", "v"], &other_tx, "other");
+        send_command(&actor, &["XADD", "b", "7-0", "f", "v"], &other_tx, "other");
+        actor.poll();
+
         assert_eq!(
-            parse_requested_stream_entry_id(&arg),
-            Some(RequestedStreamEntryId::Explicit(StreamEntryId {
-                timestamp: 1526919030474,
-                sequence_number: 12
-            }))
+            received_strings(&client_rx),
+            vec![
+                "*1\r\n*2\r\n$1\r\na\r\n*1\r\n*2\r\n$3\r\n6-0\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n"
+                    .to_owned()
+            ]
         );
+        assert!(actor.blocking_xreads.is_empty());
     }
 
     #[test]
-    fn test_parse_xread_arguments() {
-        let cmd: Vec<String> = String::from("XREAD streams stream_key other_stream_key 0-0 0-1")
-            .split(" ")
-            .map(|s| s.to_string())
-            .collect();
-
-        let res = parse_xread_arguments(&cmd);
-        let expected_res = Some(XREADArguments {
-            streams: vec![
-                (
-                    String::from("stream_key"),
-                    Some(StreamEntryId {
-                        timestamp: 0,
-                        sequence_number: 0,
-                    }),
-                ),
-                (
-                    String::from("other_stream_key"),
-                    Some(StreamEntryId {
-                        timestamp: 0,
-                        sequence_number: 1,
-                    }),
-                ),
+    fn blocking_xreadgroup_is_served_on_xadd() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+        let (other_tx, other_rx) = channel();
+
+        send_command(
+            &actor,
+            &["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"],
+            &other_tx,
+            "other",
+        );
+        send_command(
+            &actor,
+            &[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "c",
+                "BLOCK",
+                "0",
+                "STREAMS",
+                "s",
+                ">",
             ],
-            block_for: None,
-        });
-        assert_eq!(res, expected_res);
+            &client_tx,
+            "client",
+        );
+        actor.poll();
+        assert_eq!(received_strings(&other_rx), vec!["+OK\r\n".to_owned()]);
+        assert!(received_strings(&client_rx).is_empty());
+
+        send_command(&actor, &["XADD", "s", "1-0", "f", "v"], &other_tx, "other");
+        actor.poll();
+        assert_eq!(
+            received_strings(&client_rx),
+            vec![
+                "*1\r\n*2\r\n$1\r\ns\r\n*1\r\n*2\r\n$3\r\n1-0\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n"
+                    .to_owned()
+            ]
+        );
+        assert!(actor.blocking_xreadgroups.is_empty());
+        let group = actor.store.get_consumer_group("s", "g").unwrap();
+        assert_eq!(group.pending.len(), 1);
     }
 
     #[test]
-    fn test_parse_xread_arguments_blocking() {
-        let cmd: Vec<String> =
-            String::from("XREAD block 1000 streams stream_key other_stream_key 0-0 0-1")
-                .split(" ")
-                .map(|s| s.to_string())
-                .collect();
+    fn blocking_xread_is_served_only_by_writes_to_its_database() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+        let (other_tx, _other_rx) = channel();
+
+        send_command(
+            &actor,
+            &["XREAD", "BLOCK", "0", "STREAMS", "s", "$"],
+            &client_tx,
+            "client",
+        );
+        send_command(&actor, &["SELECT", "1"], &other_tx, "other");
+        send_command(&actor, &["XADD", "s", "1-0", "f", "v"], &other_tx, "other");
+        actor.poll();
+        assert!(received_strings(&client_rx).is_empty());
+
+        send_command(&actor, &["SELECT", "0"], &other_tx, "other");
+        send_command(&actor, &["XADD", "s", "2-0", "f", "v"], &other_tx, "other");
+        actor.poll();
+        assert_eq!(
+            received_strings(&client_rx),
+            vec![
+                "*1\r\n*2\r\n$1\r\ns\r\n*1\r\n*2\r\n$3\r\n2-0\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n"
+                    .to_owned()
+            ]
+        );
+    }
 
-        let res = parse_xread_arguments(&cmd);
-        let expected_res = Some(XREADArguments {
-            streams: vec![
-                (
-                    String::from("stream_key"),
-                    Some(StreamEntryId {
-                        timestamp: 0,
-                        sequence_number: 0,
-                    }),
-                ),
-                (
-                    String::from("other_stream_key"),
-                    Some(StreamEntryId {
-                        timestamp: 0,
-                        sequence_number: 1,
-                    }),
-                ),
+    #[test]
+    fn blocking_xreadgroup_is_served_only_by_writes_to_its_database() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+        let (other_tx, _other_rx) = channel();
+
+        send_command(&actor, &["SELECT", "1"], &client_tx, "client");
+        send_command(
+            &actor,
+            &["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"],
+            &client_tx,
+            "client",
+        );
+        send_command(
+            &actor,
+            &[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "c",
+                "BLOCK",
+                "0",
+                "STREAMS",
+                "s",
+                ">",
             ],
-            block_for: Some(1000),
-        });
-        assert_eq!(res, expected_res);
+            &client_tx,
+            "client",
+        );
+        send_command(&actor, &["XADD", "s", "1-0", "f", "v"], &other_tx, "other");
+        actor.poll();
+        assert_eq!(
+            received_strings(&client_rx),
+            vec!["+OK\r\n".to_owned(), "+OK\r\n".to_owned()]
+        );
+        assert_eq!(actor.blocking_xreadgroups.len(), 1);
+
+        send_command(&actor, &["SELECT", "1"], &other_tx, "other");
+        send_command(&actor, &["XADD", "s", "2-0", "f", "v"], &other_tx, "other");
+        actor.poll();
+        assert_eq!(
+            received_strings(&client_rx),
+            vec![
+                "*1\r\n*2\r\n$1\r\ns\r\n*1\r\n*2\r\n$3\r\n2-0\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n"
+                    .to_owned()
+            ]
+        );
+        assert!(actor.blocking_xreadgroups.is_empty());
     }
 
     #[test]
-    fn test_parse_xread_arguments_missing_streams() {
-        let cmd: Vec<String> = String::from("XREAD stream_key other_stream_key 0-0 0-1")
-            .split(" ")
-            .map(|s| s.to_string())
-            .collect();
+    fn commands_are_counted() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+
+        for _ in 0..3 {
+            send_command(&actor, &["SET", "foo", "bar"], &client_tx, "client");
+        }
+        for _ in 0..2 {
+            send_command(&actor, &["GET", "foo"], &client_tx, "client");
+        }
+        actor.poll();
+
+        let calls = |verb| actor.stats.commands.get(&verb).map(|stat| stat.calls);
+        assert_eq!(calls(CommandVerb::SET), Some(3));
+        assert_eq!(calls(CommandVerb::GET), Some(2));
+        assert_eq!(calls(CommandVerb::INCR), None);
+
+        received_strings(&client_rx);
+        send_command(&actor, &["INFO", "commandstats"], &client_tx, "client");
+        actor.poll();
+        let info = received_strings(&client_rx).concat();
+        assert!(info.contains("# Commandstats\r\n"));
+        assert!(info.contains("cmdstat_get:calls=2,usec="));
+        assert!(info.contains("cmdstat_set:calls=3,usec="));
+    }
+
+    #[test]
+    fn slowlog_records_commands_above_threshold() {
+        let mut args = Args::new();
+        args.insert(String::from("slowlog-log-slower-than"), String::from("0"));
+        args.insert(String::from("slowlog-max-len"), String::from("3"));
+        let mut actor = MasterActor::new(Store::new(), Config::from_args(args));
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["SET", "foo", "bar"], &client_tx, "client");
+        send_command(&actor, &["GET", "foo"], &client_tx, "client");
+        send_command(&actor, &["slowlog", "len"], &client_tx, "client");
+        actor.poll();
+        assert_eq!(
+            received_strings(&client_rx).last(),
+            Some(&":2\r\n".to_owned())
+        );
+
+        send_command(&actor, &["SLOWLOG", "GET", "-1"], &client_tx, "client");
+        actor.poll();
+        let entries = received_strings(&client_rx).concat();
+        assert!(entries.starts_with("*3\r\n*6\r\n:2\r\n"));
+        assert!(entries.contains("*2\r\n$7\r\nslowlog\r\n$3\r\nlen\r\n"));
+
+        // Capped at `slowlog-max-len` entries
+        send_command(&actor, &["SLOWLOG", "LEN"], &client_tx, "client");
+        actor.poll();
+        assert_eq!(received_strings(&client_rx), vec![":3\r\n".to_owned()]);
+
+        send_command(&actor, &["SLOWLOG", "RESET"], &client_tx, "client");
+        send_command(&actor, &["SLOWLOG", "LEN"], &client_tx, "client");
+        send_command(&actor, &["SLOWLOG", "GET", "-2"], &client_tx, "client");
+        actor.poll();
+        assert_eq!(
+            received_strings(&client_rx),
+            vec![
+                "+OK\r\n".to_owned(),
+                ":1\r\n".to_owned(),
+                "-ERR count should be greater than or equal to -1\r\n".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn active_expire_can_be_disabled() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+
+        send_command(
+            &actor,
+            &["DEBUG", "set-active-expire", "0"],
+            &client_tx,
+            "client",
+        );
+        send_command(
+            &actor,
+            &["SET", "foo", "v", "px", "1"],
+            &client_tx,
+            "client",
+        );
+        actor.poll();
+        thread::sleep(Duration::from_millis(5));
+
+        // Only removed when accessed
+        actor.last_active_expire -= super::ACTIVE_EXPIRE_PERIOD;
+        actor.poll();
+        assert_eq!(actor.stats.expired_keys, 0);
+        send_command(&actor, &["GET", "foo"], &client_tx, "client");
+        actor.poll();
+        assert_eq!(actor.stats.expired_keys, 1);
+
+        send_command(
+            &actor,
+            &["DEBUG", "SET-ACTIVE-EXPIRE", "1"],
+            &client_tx,
+            "client",
+        );
+        send_command(
+            &actor,
+            &["DEBUG", "SET-ACTIVE-EXPIRE", "x"],
+            &client_tx,
+            "client",
+        );
+        actor.poll();
+        assert!(actor.active_expire);
+        assert_eq!(
+            received_strings(&client_rx),
+            vec![
+                "+OK\r\n".to_owned(),
+                "+OK\r\n".to_owned(),
+                "$-1\r\n".to_owned(),
+                "+OK\r\n".to_owned(),
+                "-ERR value is not an integer or out of range\r\n".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn expired_keys_are_counted() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+
+        send_command(
+            &actor,
+            &["SET", "lazy", "v", "px", "1"],
+            &client_tx,
+            "client",
+        );
+        send_command(
+            &actor,
+            &["SET", "active", "v", "px", "1"],
+            &client_tx,
+            "client",
+        );
+        actor.poll();
+        thread::sleep(Duration::from_millis(5));
+
+        // Lazily removed when accessed
+        send_command(&actor, &["GET", "lazy"], &client_tx, "client");
+        actor.poll();
+        assert_eq!(actor.stats.expired_keys, 1);
+
+        // Removed by the next active expiry cycle
+        actor.last_active_expire -= super::ACTIVE_EXPIRE_PERIOD;
+        actor.poll();
+        assert_eq!(actor.stats.expired_keys, 2);
+        assert!(actor.store.get_keys().is_empty());
+
+        received_strings(&client_rx);
+        send_command(&actor, &["INFO", "stats"], &client_tx, "client");
+        actor.poll();
+        assert_eq!(
+            received_strings(&client_rx),
+            vec!["$25\r\n# Stats\r\nexpired_keys:2\r\n\r\n".to_owned()]
+        );
+    }
+
+    #[test]
+    fn save_point_triggers_background_save() {
+        let dir = std::env::temp_dir().join(format!("redis-save-points-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = Config::from_args(Args::from([
+            (String::from("dir"), dir.to_string_lossy().to_string()),
+            (String::from("dbfilename"), String::from("dump.rdb")),
+            (String::from("save"), String::from("0 2")),
+        ]));
+        let mut actor = MasterActor::new(Store::new(), config);
+        let (client_tx, _client_rx) = channel();
+
+        send_command(&actor, &["SET", "foo", "1"], &client_tx, "client");
+        actor.poll();
+        assert!(actor.persistence.bgsave.is_none());
+
+        send_command(&actor, &["SET", "bar", "2"], &client_tx, "client");
+        actor.poll();
+        assert!(actor.persistence.bgsave.is_some());
+
+        while actor.persistence.bgsave.is_some() {
+            actor.poll();
+        }
+        assert_eq!(actor.persistence.changes_since_save, 0);
+        let mut saved =
+            Store::from_dbfile(&dir.to_string_lossy(), "dump.rdb", DEFAULT_DATABASES).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(saved.get_string("bar"), Ok(Some(b"2".to_vec())));
+    }
+
+    #[test]
+    fn shutdown_saves_when_save_points_are_configured() {
+        let dir = std::env::temp_dir().join(format!("redis-shutdown-{}", std::process::id()));
+        let config = Config::from_args(Args::from([
+            (String::from("dir"), dir.to_string_lossy().to_string()),
+            (String::from("save"), String::from("3600 1")),
+        ]));
+        let mut actor = MasterActor::new(Store::new(), config.clone());
+        let (client_tx, _client_rx) = channel();
+
+        send_command(&actor, &["SET", "foo", "bar"], &client_tx, "client");
+        actor.poll();
+        actor.shutdown();
+
+        let saved = Store::from_dbfile(
+            &config.dbfile.dir,
+            &config.dbfile.dbfilename,
+            config.databases,
+        );
+        let _ = fs::remove_dir_all(&dir);
+        let Some(mut saved) = saved else {
+            panic!("Dataset not saved on shutdown");
+        };
+        assert_eq!(saved.get_string("foo"), Ok(Some(b"bar".to_vec())));
+    }
+
+    #[test]
+    fn save_writes_to_configured_dbfile() {
+        let root = std::env::temp_dir().join(format!("redis-save-{}", std::process::id()));
+        let dir = root.join("nested");
+        let config = Config::from_args(Args::from([
+            (String::from("dir"), dir.to_string_lossy().to_string()),
+            (String::from("dbfilename"), String::from("saved.rdb")),
+        ]));
+        let mut actor = MasterActor::new(Store::new(), config.clone());
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["SET", "foo", "bar"], &client_tx, "client");
+        send_command(&actor, &["SAVE"], &client_tx, "client");
+        actor.poll();
+        assert_eq!(received_strings(&client_rx), vec!["+OK\r\n", "+OK\r\n"]);
+        assert!(dir.join("saved.rdb").exists());
+
+        // Restarting with the same config loads the saved dataset
+        let store = Store::from_dbfile(
+            &config.dbfile.dir,
+            &config.dbfile.dbfilename,
+            config.databases,
+        );
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(store.unwrap().get_string("foo"), Ok(Some(b"bar".to_vec())));
+    }
+
+    #[test]
+    fn debug_object_reports_encoding_and_serialized_length() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["SET", "n", "12345"], &client_tx, "client");
+        send_command(&actor, &["SET", "s", "hello world"], &client_tx, "client");
+        send_command(&actor, &["DEBUG", "OBJECT", "n"], &client_tx, "client");
+        send_command(&actor, &["DEBUG", "OBJECT", "s"], &client_tx, "client");
+        send_command(
+            &actor,
+            &["DEBUG", "OBJECT", "missing"],
+            &client_tx,
+            "client",
+        );
+        actor.poll();
+
+        let replies = received_strings(&client_rx);
+        assert!(replies[2].starts_with("+Value at:"));
+        assert!(replies[2].contains(" encoding:int serializedlength:6 "));
+        assert!(replies[3].contains(" encoding:embstr serializedlength:12 "));
+        assert_eq!(replies[4], "-ERR no such key\r\n");
+    }
+
+    #[test]
+    fn debug_reload_keeps_the_dataset() {
+        let dir = std::env::temp_dir().join(format!("redis-debug-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = Config::from_args(Args::from([
+            (String::from("dir"), dir.to_string_lossy().to_string()),
+            (String::from("dbfilename"), String::from("dump.rdb")),
+        ]));
+        let mut actor = MasterActor::new(Store::new(), config);
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["SET", "foo", "bar"], &client_tx, "client");
+        send_command(&actor, &["SET", "n", "42"], &client_tx, "client");
+        send_command(
+            &actor,
+            &["XADD", "s", "1-1", "a", "1"],
+            &client_tx,
+            "client",
+        );
+        send_command(
+            &actor,
+            &["XADD", "s", "1700000000000-5", "b", "x"],
+            &client_tx,
+            "client",
+        );
+        actor.poll();
+        let stream = actor.store.get_stream_range("s", None, None).to_vec();
+        received_strings(&client_rx);
+
+        send_command(&actor, &["DEBUG", "RELOAD"], &client_tx, "client");
+        send_command(&actor, &["GET", "foo"], &client_tx, "client");
+        send_command(&actor, &["GET", "n"], &client_tx, "client");
+        actor.poll();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(
+            received_strings(&client_rx),
+            vec!["+OK\r\n", "$3\r\nbar\r\n", "$2\r\n42\r\n"]
+        );
+        assert_eq!(actor.store.get_stream_range("s", None, None), stream);
+    }
+
+    #[test]
+    fn subscribe_replies_with_running_count() {
+        let mut actor = build_actor();
+        let (subscriber_tx, subscriber_rx) = channel();
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["SUBSCRIBE", "foo"], &subscriber_tx, "subscriber");
+        send_command(&actor, &["SUBSCRIBE", "bar"], &subscriber_tx, "subscriber");
+        send_command(&actor, &["PUBLISH", "bar", "hello"], &client_tx, "client");
+        actor.poll();
 
-        assert_eq!(parse_xread_arguments(&cmd), None);
+        assert_eq!(
+            received_strings(&subscriber_rx),
+            vec![
+                "*3\r\n$9\r\nsubscribe\r\n$3\r\nfoo\r\n:1\r\n",
+                "*3\r\n$9\r\nsubscribe\r\n$3\r\nbar\r\n:2\r\n",
+                "*3\r\n$7\r\nmessage\r\n$3\r\nbar\r\n$5\r\nhello\r\n",
+            ]
+        );
+        assert_eq!(received_strings(&client_rx), vec![":1\r\n"]);
+
+        actor.process_connection_closed("subscriber".to_owned());
+        send_command(&actor, &["PUBLISH", "bar", "hello"], &client_tx, "client");
+        actor.poll();
+        assert_eq!(received_strings(&client_rx), vec![":0\r\n"]);
+    }
+
+    #[test]
+    fn select_is_limited_to_configured_databases() {
+        let mut args = Args::new();
+        args.insert(String::from("databases"), String::from("2"));
+        let mut actor = MasterActor::new(Store::with_databases(2), Config::from_args(args));
+        let (client_tx, client_rx) = channel();
+        let (other_tx, other_rx) = channel();
+
+        send_command(&actor, &["SELECT", "1"], &client_tx, "client");
+        send_command(&actor, &["SET", "a", "1"], &client_tx, "client");
+        send_command(&actor, &["SELECT", "2"], &client_tx, "client");
+        send_command(&actor, &["SELECT", "-1"], &client_tx, "client");
+        send_command(&actor, &["GET", "a"], &client_tx, "client");
+        send_command(&actor, &["GET", "a"], &other_tx, "other");
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&client_rx),
+            vec![
+                "+OK\r\n",
+                "+OK\r\n",
+                "-ERR DB index is out of range\r\n",
+                "-ERR DB index is out of range\r\n",
+                "$1\r\n1\r\n"
+            ]
+        );
+        assert_eq!(received_strings(&other_rx), vec!["$-1\r\n"]);
+    }
+
+    #[test]
+    fn writes_to_other_databases_propagate_select() {
+        let mut actor = build_actor();
+        let (replica_tx, replica_rx) = channel();
+        let (client_tx, _client_rx) = channel();
+
+        send_command(&actor, &["PSYNC", "?", "-1"], &replica_tx, "replica");
+        actor.poll();
+        received_strings(&replica_rx);
+
+        send_command(&actor, &["SELECT", "3"], &client_tx, "client");
+        send_command(&actor, &["SET", "a", "1"], &client_tx, "client");
+        send_command(&actor, &["SET", "b", "1"], &client_tx, "client");
+        send_command(&actor, &["SET", "c", "1"], &client_tx, "other");
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&replica_rx),
+            vec![
+                "*2\r\n$6\r\nSELECT\r\n$1\r\n3\r\n",
+                "*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n",
+                "*3\r\n$3\r\nSET\r\n$1\r\nb\r\n$1\r\n1\r\n",
+                "*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n",
+                "*3\r\n$3\r\nSET\r\n$1\r\nc\r\n$1\r\n1\r\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn client_modes_are_acknowledged() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["SET", "foo", "bar"], &client_tx, "client");
+        actor.poll();
+        let frequency = actor.store.get_access_frequency("foo");
+        for command in [
+            &["CLIENT", "NO-EVICT", "ON"][..],
+            &["CLIENT", "NO-TOUCH", "ON"],
+            &["CLIENT", "UNPAUSE"],
+            &["GET", "foo"],
+            &["CLIENT", "NO-EVICT", "MAYBE"],
+        ] {
+            send_command(&actor, command, &client_tx, "client");
+        }
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&client_rx),
+            vec![
+                "+OK\r\n",
+                "+OK\r\n",
+                "+OK\r\n",
+                "+OK\r\n",
+                "$3\r\nbar\r\n",
+                "-ERR syntax error\r\n"
+            ]
+        );
+        assert_eq!(actor.store.get_access_frequency("foo"), frequency);
+
+        send_command(&actor, &["CLIENT", "NO-TOUCH", "OFF"], &client_tx, "client");
+        send_command(&actor, &["GET", "foo"], &client_tx, "client");
+        actor.poll();
+        assert!(actor.store.get_access_frequency("foo") > frequency);
+    }
+
+    #[test]
+    fn hello_reports_server_information() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["HELLO"], &client_tx, "client");
+        send_command(&actor, &["HELLO", "3"], &client_tx, "client");
+        send_command(&actor, &["HELLO", "4"], &client_tx, "client");
+        actor.poll();
+
+        let mut messages = client_rx.try_iter();
+        let Some(ConnectionMessage::SendReply(Reply::Map(bare))) = messages.next() else {
+            panic!("HELLO should reply with a map");
+        };
+        assert!(matches!(
+            messages.next(),
+            Some(ConnectionMessage::SetProtocol(Protocol::RESP3))
+        ));
+        let Some(ConnectionMessage::SendReply(Reply::Map(resp3))) = messages.next() else {
+            panic!("HELLO 3 should reply with a map");
+        };
+        let Some(ConnectionMessage::SendReply(error)) = messages.next() else {
+            panic!("HELLO 4 should be rejected");
+        };
+        let keys: Vec<Reply> = bare.iter().map(|(key, _)| key.clone()).collect();
+        assert_eq!(
+            keys,
+            ["server", "version", "proto", "id", "mode", "role", "modules"].map(Reply::bulk)
+        );
+        assert!(bare.contains(&(Reply::bulk("proto"), Reply::Integer(2))));
+        assert!(bare.contains(&(Reply::bulk("role"), Reply::bulk("master"))));
+        assert!(resp3.contains(&(Reply::bulk("proto"), Reply::Integer(3))));
+        assert_eq!(error, Reply::error("NOPROTO unsupported protocol version"));
+    }
+
+    #[test]
+    fn config_set_changes_the_actor_config() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+
+        send_command(
+            &actor,
+            &["CONFIG", "SET", "slowlog-max-len", "5", "MAXMEMORY", "1mb"],
+            &client_tx,
+            "client",
+        );
+        send_command(
+            &actor,
+            &["CONFIG", "GET", "maxmemory"],
+            &client_tx,
+            "client",
+        );
+        send_command(
+            &actor,
+            &["CONFIG", "SET", "slowlog-max-len", "6", "port", "6380"],
+            &client_tx,
+            "client",
+        );
+        send_command(&actor, &["CONFIG", "SET", "dir"], &client_tx, "client");
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&client_rx),
+            vec![
+                "+OK\r\n",
+                "*2\r\n$9\r\nmaxmemory\r\n$3\r\n1mb\r\n",
+                "-ERR CONFIG SET failed (possibly related to argument 'port') - can't set immutable config\r\n",
+                "-ERR wrong number of arguments for 'config|set' command\r\n",
+            ]
+        );
+        assert_eq!(actor.config.maxmemory, 1024 * 1024);
+        assert_eq!(actor.config.slowlog_max_len, 5);
+    }
+
+    #[test]
+    fn clients_must_authenticate_when_a_password_is_set() {
+        let config = Config::from_args(Args::from([(
+            String::from("requirepass"),
+            String::from("secret"),
+        )]));
+        let mut actor = MasterActor::new(Store::new(), config);
+        let (client_tx, client_rx) = channel();
+        let (other_tx, other_rx) = channel();
+
+        send_command(&actor, &["SET", "foo", "bar"], &client_tx, "client");
+        send_command(&actor, &["AUTH", "wrong"], &client_tx, "client");
+        send_command(&actor, &["AUTH", "secret"], &client_tx, "client");
+        send_command(&actor, &["SET", "foo", "bar"], &client_tx, "client");
+        send_command(&actor, &["HELLO", "2"], &other_tx, "other");
+        send_command(
+            &actor,
+            &["HELLO", "2", "AUTH", "default", "secret"],
+            &other_tx,
+            "other",
+        );
+        send_command(&actor, &["GET", "foo"], &other_tx, "other");
+        send_command(&actor, &["QUIT"], &client_tx, "client");
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&client_rx),
+            vec![
+                "-NOAUTH Authentication required.\r\n",
+                "-WRONGPASS invalid username-password pair or user is disabled.\r\n",
+                "+OK\r\n",
+                "+OK\r\n",
+                "+OK\r\n",
+            ]
+        );
+        let other = received_strings(&other_rx);
+        assert!(other[0].starts_with("-NOAUTH HELLO must be called"));
+        assert!(other[1].starts_with("*14\r\n"));
+        assert_eq!(other[2], "$3\r\nbar\r\n");
+    }
+
+    #[test]
+    fn tracked_keys_are_invalidated_on_writes() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+        let (writer_tx, _writer_rx) = channel();
+
+        send_command(&actor, &["CLIENT", "TRACKING", "ON"], &client_tx, "client");
+        send_command(&actor, &["GET", "foo"], &client_tx, "client");
+        send_command(&actor, &["SET", "foo", "1"], &writer_tx, "writer");
+        send_command(&actor, &["SET", "foo", "2"], &writer_tx, "writer");
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&client_rx),
+            vec![
+                "+OK\r\n",
+                "$-1\r\n",
+                "*2\r\n$10\r\ninvalidate\r\n*1\r\n$3\r\nfoo\r\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn tracked_keys_are_not_invalidated_by_writes_to_other_databases() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+        let (writer_tx, _writer_rx) = channel();
+
+        send_command(&actor, &["CLIENT", "TRACKING", "ON"], &client_tx, "client");
+        send_command(&actor, &["GET", "foo"], &client_tx, "client");
+        send_command(&actor, &["SELECT", "1"], &writer_tx, "writer");
+        send_command(&actor, &["SET", "foo", "1"], &writer_tx, "writer");
+        send_command(
+            &actor,
+            &["COPY", "foo", "foo", "DB", "0"],
+            &writer_tx,
+            "writer",
+        );
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&client_rx),
+            vec![
+                "+OK\r\n",
+                "$-1\r\n",
+                "*2\r\n$10\r\ninvalidate\r\n*1\r\n$3\r\nfoo\r\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn invalidations_can_be_redirected() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+        let (redirect_tx, redirect_rx) = channel();
+
+        send_command(&actor, &["CLIENT", "ID"], &redirect_tx, "redirect");
+        send_command(
+            &actor,
+            &["SUBSCRIBE", "__redis__:invalidate"],
+            &redirect_tx,
+            "redirect",
+        );
+        send_command(
+            &actor,
+            &["CLIENT", "TRACKING", "ON", "REDIRECT", "2"],
+            &client_tx,
+            "client",
+        );
+        send_command(
+            &actor,
+            &["CLIENT", "TRACKING", "ON", "REDIRECT", "1"],
+            &client_tx,
+            "client",
+        );
+        send_command(&actor, &["GET", "foo"], &client_tx, "client");
+        send_command(&actor, &["SET", "foo", "1"], &client_tx, "client");
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&client_rx),
+            vec![
+                "-ERR The client ID you want redirect to does not exist\r\n",
+                "+OK\r\n",
+                "$-1\r\n",
+                "+OK\r\n"
+            ]
+        );
+        assert_eq!(
+            received_strings(&redirect_rx)[2..],
+            ["*3\r\n$7\r\nmessage\r\n$20\r\n__redis__:invalidate\r\n*1\r\n$3\r\nfoo\r\n"]
+        );
+    }
+
+    #[test]
+    fn del_propagates_only_existing_keys() {
+        let mut actor = build_actor();
+        let (replica_tx, replica_rx) = channel();
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["SET", "a", "1"], &client_tx, "client");
+        send_command(&actor, &["SET", "c", "1"], &client_tx, "client");
+        send_command(&actor, &["PSYNC", "?", "-1"], &replica_tx, "replica");
+        actor.poll();
+        received_strings(&replica_rx);
+        let offset = actor.replication.replication_offset;
+
+        send_command(&actor, &["DEL", "a", "b", "c", "d"], &client_tx, "client");
+        send_command(&actor, &["UNLINK", "b", "d"], &client_tx, "client");
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&client_rx),
+            vec!["+OK\r\n", "+OK\r\n", ":2\r\n", ":0\r\n"]
+        );
+        let propagated = "*3\r\n$3\r\nDEL\r\n$1\r\na\r\n$1\r\nc\r\n";
+        assert_eq!(received_strings(&replica_rx), vec![propagated]);
+        assert_eq!(
+            actor.replication.replication_offset,
+            offset + propagated.len()
+        );
+    }
+
+    #[test]
+    fn info_replication_lists_replica_offsets() {
+        let mut actor = build_actor();
+        let (replica_tx, _replica_rx) = channel();
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["PSYNC", "?", "-1"], &replica_tx, "replica");
+        send_command(&actor, &["REPLCONF", "ACK", "42"], &replica_tx, "replica");
+        send_command(&actor, &["INFO", "replication"], &client_tx, "client");
+        actor.poll();
+
+        let info = received_strings(&client_rx).pop().unwrap();
+        assert!(info.contains("connected_slaves:1\r\n"));
+        assert!(info.contains("state=online,offset=42,lag=0\r\n"));
+        assert!(info.contains(&format!("master_replid2:{}\r\n", "0".repeat(40))));
+        assert!(info.contains("second_repl_offset:-1\r\n"));
+    }
+
+    #[test]
+    fn info_replication_reports_announced_listening_port() {
+        let mut actor = build_actor();
+        let (replica_tx, replica_rx) = channel();
+        let (client_tx, client_rx) = channel();
+
+        send_command(
+            &actor,
+            &["REPLCONF", "listening-port", "6380"],
+            &replica_tx,
+            "replica",
+        );
+        send_command(
+            &actor,
+            &["REPLCONF", "capa", "psync2"],
+            &replica_tx,
+            "replica",
+        );
+        send_command(&actor, &["PSYNC", "?", "-1"], &replica_tx, "replica");
+        send_command(&actor, &["replconf", "ack", "7"], &replica_tx, "replica");
+        send_command(&actor, &["INFO", "replication"], &client_tx, "client");
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&replica_rx)[..2],
+            ["+OK\r\n".to_owned(), "+OK\r\n".to_owned()]
+        );
+        let info = received_strings(&client_rx).pop().unwrap();
+        assert!(info.contains("slave0:ip=?,port=6380,state=online,offset=7,"));
+        assert!(actor.replica_handshakes.is_empty());
+    }
+
+    #[test]
+    fn wait_is_dropped_when_client_disconnects() {
+        let mut actor = build_actor();
+        let (replica_tx, _replica_rx) = channel();
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["PSYNC", "?", "-1"], &replica_tx, "replica");
+        send_command(&actor, &["SET", "foo", "bar"], &client_tx, "client");
+        send_command(&actor, &["WAIT", "1", "0"], &client_tx, "client");
+        actor.poll();
+        assert_eq!(actor.wait_for_replication_acks.len(), 1);
+
+        drop(client_rx);
+        actor
+            .get_tx()
+            .send(StoreMessage::ConnectionClosed {
+                connection_id: "client".to_owned(),
+            })
+            .unwrap();
+        actor.poll();
+
+        assert!(actor.wait_for_replication_acks.is_empty());
+    }
+
+    #[test]
+    fn unknown_commands_are_rejected() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+
+        for buffer in
+            parse_buffer(b"*2\r\n$6\r\nFOOBAR\r\n$3\r\nbaz\r\n*1\r\n$4\r\nPING\r\n").unwrap()
+        {
+            actor
+                .get_tx()
+                .send(StoreMessage::NewBuffer {
+                    value: buffer,
+                    tx_back: client_tx.clone(),
+                    connection_id: String::from("client"),
+                })
+                .unwrap();
+        }
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&client_rx),
+            vec![
+                "-ERR unknown command 'FOOBAR', with args beginning with: 'baz' \r\n",
+                "+PONG\r\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_transactions_are_executed_or_discarded() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["MULTI"], &client_tx, "client");
+        send_command(&actor, &["EXEC"], &client_tx, "client");
+        send_command(&actor, &["MULTI"], &client_tx, "client");
+        send_command(&actor, &["DISCARD"], &client_tx, "client");
+        send_command(&actor, &["EXEC"], &client_tx, "client");
+        send_command(&actor, &["DISCARD"], &client_tx, "client");
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&client_rx),
+            vec![
+                "+OK\r\n",
+                "*0\r\n",
+                "+OK\r\n",
+                "+OK\r\n",
+                "-ERR EXEC without MULTI\r\n",
+                "-ERR DISCARD without MULTI\r\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_multi_keeps_queued_commands() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["MULTI"], &client_tx, "client");
+        send_command(&actor, &["SET", "foo", "bar"], &client_tx, "client");
+        send_command(&actor, &["MULTI"], &client_tx, "client");
+        send_command(&actor, &["EXEC"], &client_tx, "client");
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&client_rx),
+            vec![
+                "+OK\r\n".to_owned(),
+                "+QUEUED\r\n".to_owned(),
+                "-ERR MULTI calls can not be nested\r\n".to_owned(),
+                "*1\r\n+OK\r\n".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn blocking_commands_reply_at_once_in_transaction() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["MULTI"], &client_tx, "client");
+        send_command(&actor, &["WAIT", "0", "100"], &client_tx, "client");
+        send_command(&actor, &["WAIT", "1", "0"], &client_tx, "client");
+        send_command(
+            &actor,
+            &["XREAD", "BLOCK", "0", "STREAMS", "s", "$"],
+            &client_tx,
+            "client",
+        );
+        send_command(&actor, &["EXEC"], &client_tx, "client");
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&client_rx).last(),
+            Some(&"*3\r\n:0\r\n:0\r\n$-1\r\n".to_owned())
+        );
+    }
+
+    #[test]
+    fn exec_replies_in_order() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+
+        send_command(&actor, &["MULTI"], &client_tx, "client");
+        send_command(&actor, &["SET", "a", "1"], &client_tx, "client");
+        send_command(&actor, &["INCR", "a"], &client_tx, "client");
+        send_command(&actor, &["INCRBY", "a", "x"], &client_tx, "client");
+        send_command(&actor, &["INCR", "a"], &client_tx, "client");
+        send_command(&actor, &["GET", "a"], &client_tx, "client");
+        send_command(&actor, &["GET", "missing"], &client_tx, "client");
+        send_command(&actor, &["EXEC"], &client_tx, "client");
+        send_command(&actor, &["GET", "a"], &client_tx, "client");
+        actor.poll();
+
+        let received = received_strings(&client_rx);
+        assert_eq!(
+            received[received.len() - 2..],
+            [
+                "*6\r\n+OK\r\n:2\r\n-ERR value is not an integer or out of range\r\n:3\r\n$1\r\n3\r\n$-1\r\n"
+                    .to_owned(),
+                "$1\r\n3\r\n".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn exec_is_aborted_when_a_watched_key_is_modified() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+        let (other_tx, _other_rx) = channel();
+
+        send_command(&actor, &["WATCH", "foo"], &client_tx, "client");
+        send_command(&actor, &["MULTI"], &client_tx, "client");
+        send_command(&actor, &["SET", "bar", "1"], &client_tx, "client");
+        send_command(&actor, &["SET", "foo", "2"], &other_tx, "other");
+        send_command(&actor, &["EXEC"], &client_tx, "client");
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&client_rx).last(),
+            Some(&"*-1\r\n".to_owned())
+        );
+    }
+
+    #[test]
+    fn watched_keys_belong_to_the_selected_database() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+        let (other_tx, _other_rx) = channel();
+
+        send_command(&actor, &["WATCH", "foo"], &client_tx, "client");
+        send_command(&actor, &["SELECT", "1"], &other_tx, "other");
+        send_command(&actor, &["SET", "foo", "1"], &other_tx, "other");
+        send_command(&actor, &["MULTI"], &client_tx, "client");
+        send_command(&actor, &["SET", "bar", "1"], &client_tx, "client");
+        send_command(&actor, &["EXEC"], &client_tx, "client");
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&client_rx).last(),
+            Some(&"*1\r\n+OK\r\n".to_owned())
+        );
+    }
+
+    #[test]
+    fn exec_reads_streams_from_the_connection_database() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+        let (other_tx, _other_rx) = channel();
+
+        send_command(&actor, &["SELECT", "1"], &client_tx, "client");
+        send_command(
+            &actor,
+            &["XADD", "s", "1-0", "f", "v"],
+            &client_tx,
+            "client",
+        );
+        send_command(&actor, &["MULTI"], &client_tx, "client");
+        send_command(
+            &actor,
+            &["XREAD", "STREAMS", "s", "0-0"],
+            &client_tx,
+            "client",
+        );
+        send_command(&actor, &["GET", "foo"], &other_tx, "other");
+        send_command(&actor, &["EXEC"], &client_tx, "client");
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&client_rx).last(),
+            Some(
+                &"*1\r\n*1\r\n*2\r\n$1\r\ns\r\n*1\r\n*2\r\n$3\r\n1-0\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n"
+                    .to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn discard_clears_watched_keys() {
+        let mut actor = build_actor();
+        let (client_tx, client_rx) = channel();
+        let (other_tx, _other_rx) = channel();
+
+        send_command(&actor, &["WATCH", "foo"], &client_tx, "client");
+        send_command(&actor, &["MULTI"], &client_tx, "client");
+        send_command(&actor, &["DISCARD"], &client_tx, "client");
+        send_command(&actor, &["SET", "foo", "2"], &other_tx, "other");
+        send_command(&actor, &["MULTI"], &client_tx, "client");
+        send_command(&actor, &["SET", "bar", "1"], &client_tx, "client");
+        send_command(&actor, &["EXEC"], &client_tx, "client");
+        actor.poll();
+
+        assert_eq!(
+            received_strings(&client_rx).last(),
+            Some(&"*1\r\n+OK\r\n".to_owned())
+        );
     }
 }
@@ -1,9 +1,17 @@
-use std::sync::mpsc::Sender;
+use std::{net::SocketAddr, sync::mpsc::Sender};
 
-use crate::connection::parser::BufferType;
+use crate::connection::{
+    parser::BufferType,
+    reply::{Protocol, Reply},
+};
 
+pub mod backlog;
+pub mod handler;
 pub mod master;
+pub mod pubsub;
 pub mod replica;
+pub mod slowlog;
+pub mod tracking;
 
 pub type ConnectionID = String;
 
@@ -14,10 +22,21 @@ pub enum StoreMessage {
         tx_back: Sender<ConnectionMessage>,
         connection_id: ConnectionID,
     },
+    ConnectionOpened {
+        connection_id: ConnectionID,
+        addr: SocketAddr,
+    },
+    ConnectionClosed {
+        connection_id: ConnectionID,
+    },
 }
 
 #[derive(Debug)]
 pub enum ConnectionMessage {
     SendString(String),
     SendBytes(Vec<u8>),
+    SendReply(Reply),
+    /// Encode the following replies with `protocol`, as negotiated by `HELLO`.
+    SetProtocol(Protocol),
+    Close,
 }
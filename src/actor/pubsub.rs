@@ -0,0 +1,399 @@
+use std::sync::mpsc::Sender;
+
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{
+    actor::{ConnectionID, ConnectionMessage},
+    connection::reply::Reply,
+    glob::glob_match,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubscriptionKind {
+    Channel,
+    Pattern,
+    /// Shard channels (SSUBSCRIBE), behaving like regular channels on a single node.
+    ShardChannel,
+}
+
+impl SubscriptionKind {
+    fn subscribe_reply_kind(&self) -> &'static str {
+        match self {
+            SubscriptionKind::Channel => "subscribe",
+            SubscriptionKind::Pattern => "psubscribe",
+            SubscriptionKind::ShardChannel => "ssubscribe",
+        }
+    }
+
+    fn unsubscribe_reply_kind(&self) -> &'static str {
+        match self {
+            SubscriptionKind::Channel => "unsubscribe",
+            SubscriptionKind::Pattern => "punsubscribe",
+            SubscriptionKind::ShardChannel => "sunsubscribe",
+        }
+    }
+}
+
+struct Subscriber {
+    tx: Sender<ConnectionMessage>,
+    channels: IndexSet<Vec<u8>>,
+    patterns: IndexSet<Vec<u8>>,
+    shard_channels: IndexSet<Vec<u8>>,
+}
+
+impl Subscriber {
+    fn subscriptions(&mut self, kind: SubscriptionKind) -> &mut IndexSet<Vec<u8>> {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::ShardChannel => &mut self.shard_channels,
+        }
+    }
+
+    /// Subscription count reported in (un)subscribe replies: shard channels are counted apart
+    /// from channels and patterns.
+    fn count(&self, kind: SubscriptionKind) -> usize {
+        match kind {
+            SubscriptionKind::Channel | SubscriptionKind::Pattern => {
+                self.channels.len() + self.patterns.len()
+            }
+            SubscriptionKind::ShardChannel => self.shard_channels.len(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.channels.is_empty() && self.patterns.is_empty() && self.shard_channels.is_empty()
+    }
+}
+
+/// Registry of the channels and patterns each connection is subscribed to.
+#[derive(Default)]
+pub struct PubSub {
+    subscribers: IndexMap<ConnectionID, Subscriber>,
+}
+
+impl PubSub {
+    /// Subscribe a connection to `names`, replying once per name with the running subscription
+    /// count of the connection.
+    pub fn subscribe(
+        &mut self,
+        kind: SubscriptionKind,
+        connection_id: &str,
+        tx: &Sender<ConnectionMessage>,
+        names: &[Vec<u8>],
+    ) {
+        let subscriber = self
+            .subscribers
+            .entry(connection_id.to_owned())
+            .or_insert_with(|| Subscriber {
+                tx: tx.clone(),
+                channels: IndexSet::new(),
+                patterns: IndexSet::new(),
+                shard_channels: IndexSet::new(),
+            });
+        for name in names {
+            subscriber.subscriptions(kind).insert(name.clone());
+            let reply = subscription_reply(
+                kind.subscribe_reply_kind(),
+                Some(name.clone()),
+                subscriber.count(kind),
+            );
+            let _ = tx.send(ConnectionMessage::SendReply(reply));
+        }
+    }
+
+    /// Unsubscribe a connection from `names`, or from every channel (or pattern) of `kind` when
+    /// `names` is empty.
+    pub fn unsubscribe(
+        &mut self,
+        kind: SubscriptionKind,
+        connection_id: &str,
+        tx: &Sender<ConnectionMessage>,
+        names: &[Vec<u8>],
+    ) {
+        let Some(subscriber) = self.subscribers.get_mut(connection_id) else {
+            let reply = subscription_reply(kind.unsubscribe_reply_kind(), None, 0);
+            let _ = tx.send(ConnectionMessage::SendReply(reply));
+            return;
+        };
+
+        let names: Vec<Vec<u8>> = if names.is_empty() {
+            subscriber.subscriptions(kind).iter().cloned().collect()
+        } else {
+            names.to_vec()
+        };
+        if names.is_empty() {
+            let reply =
+                subscription_reply(kind.unsubscribe_reply_kind(), None, subscriber.count(kind));
+            let _ = tx.send(ConnectionMessage::SendReply(reply));
+        }
+        for name in names {
+            subscriber.subscriptions(kind).shift_remove(&name);
+            let reply = subscription_reply(
+                kind.unsubscribe_reply_kind(),
+                Some(name),
+                subscriber.count(kind),
+            );
+            let _ = tx.send(ConnectionMessage::SendReply(reply));
+        }
+
+        if subscriber.is_empty() {
+            self.subscribers.shift_remove(connection_id);
+        }
+    }
+
+    /// Deliver `message` to the subscribers of `channel` and of the patterns matching it. Returns
+    /// the number of messages sent.
+    pub fn publish(&mut self, channel: &[u8], message: &[u8]) -> usize {
+        self.deliver(|subscriber| {
+            let mut replies = Vec::new();
+            if subscriber.channels.contains(channel) {
+                replies.push(Reply::Array(vec![
+                    Reply::bulk("message"),
+                    Reply::Bulk(Some(channel.to_vec())),
+                    Reply::Bulk(Some(message.to_vec())),
+                ]));
+            }
+            for pattern in &subscriber.patterns {
+                if glob_match(pattern, channel) {
+                    replies.push(Reply::Array(vec![
+                        Reply::bulk("pmessage"),
+                        Reply::Bulk(Some(pattern.clone())),
+                        Reply::Bulk(Some(channel.to_vec())),
+                        Reply::Bulk(Some(message.to_vec())),
+                    ]));
+                }
+            }
+            replies
+        })
+    }
+
+    /// Deliver `message` to the subscribers of the shard channel `channel`. Returns the number of
+    /// messages sent.
+    pub fn publish_shard(&mut self, channel: &[u8], message: &[u8]) -> usize {
+        self.deliver(|subscriber| {
+            if !subscriber.shard_channels.contains(channel) {
+                return Vec::new();
+            }
+            vec![Reply::Array(vec![
+                Reply::bulk("smessage"),
+                Reply::Bulk(Some(channel.to_vec())),
+                Reply::Bulk(Some(message.to_vec())),
+            ])]
+        })
+    }
+
+    /// Deliver `message` on `channel` to a single connection, when subscribed to it. Returns
+    /// whether the message was sent.
+    pub fn send_message(&mut self, connection_id: &str, channel: &[u8], message: Reply) -> bool {
+        let Some(subscriber) = self
+            .subscribers
+            .get(connection_id)
+            .filter(|subscriber| subscriber.channels.contains(channel))
+        else {
+            return false;
+        };
+        let reply = Reply::Array(vec![
+            Reply::bulk("message"),
+            Reply::Bulk(Some(channel.to_vec())),
+            message,
+        ]);
+        subscriber
+            .tx
+            .send(ConnectionMessage::SendReply(reply))
+            .is_ok()
+    }
+
+    /// Send each subscriber the messages built by `messages`. Subscribers whose connection is gone
+    /// are dropped and not counted. Returns the number of messages sent.
+    fn deliver(&mut self, messages: impl Fn(&Subscriber) -> Vec<Reply>) -> usize {
+        let mut sent = 0;
+        self.subscribers.retain(|connection_id, subscriber| {
+            let replies = messages(subscriber);
+            let count = replies.len();
+            for reply in replies {
+                if subscriber
+                    .tx
+                    .send(ConnectionMessage::SendReply(reply))
+                    .is_err()
+                {
+                    println!("Dropping disconnected subscriber {connection_id}");
+                    return false;
+                }
+            }
+            sent += count;
+            true
+        });
+        sent
+    }
+
+    /// Drop every subscription of a closed connection.
+    pub fn remove(&mut self, connection_id: &str) {
+        self.subscribers.shift_remove(connection_id);
+    }
+}
+
+fn subscription_reply(kind: &str, name: Option<Vec<u8>>, count: usize) -> Reply {
+    Reply::Array(vec![
+        Reply::bulk(kind),
+        Reply::Bulk(name),
+        Reply::Integer(count as i64),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{channel, Receiver};
+
+    use crate::{
+        actor::ConnectionMessage,
+        connection::reply::{Protocol, Reply},
+    };
+
+    use super::{PubSub, SubscriptionKind};
+
+    fn received_replies(rx: &Receiver<ConnectionMessage>) -> Vec<String> {
+        rx.try_iter()
+            .filter_map(|msg| match msg {
+                ConnectionMessage::SendReply(reply) => {
+                    String::from_utf8(reply.encode(Protocol::RESP2)).ok()
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn subscription_count_includes_channels_and_patterns() {
+        let mut pubsub = PubSub::default();
+        let (tx, rx) = channel();
+
+        pubsub.subscribe(
+            SubscriptionKind::Channel,
+            "client",
+            &tx,
+            &[b"foo".to_vec(), b"bar".to_vec()],
+        );
+        pubsub.subscribe(SubscriptionKind::Pattern, "client", &tx, &[b"f*".to_vec()]);
+        pubsub.unsubscribe(SubscriptionKind::Channel, "client", &tx, &[]);
+        pubsub.unsubscribe(SubscriptionKind::Pattern, "client", &tx, &[b"f*".to_vec()]);
+
+        assert_eq!(
+            received_replies(&rx),
+            vec![
+                "*3\r\n$9\r\nsubscribe\r\n$3\r\nfoo\r\n:1\r\n",
+                "*3\r\n$9\r\nsubscribe\r\n$3\r\nbar\r\n:2\r\n",
+                "*3\r\n$10\r\npsubscribe\r\n$2\r\nf*\r\n:3\r\n",
+                "*3\r\n$11\r\nunsubscribe\r\n$3\r\nfoo\r\n:2\r\n",
+                "*3\r\n$11\r\nunsubscribe\r\n$3\r\nbar\r\n:1\r\n",
+                "*3\r\n$12\r\npunsubscribe\r\n$2\r\nf*\r\n:0\r\n",
+            ]
+        );
+        assert!(pubsub.subscribers.is_empty());
+    }
+
+    #[test]
+    fn unsubscribe_without_subscriptions() {
+        let mut pubsub = PubSub::default();
+        let (tx, rx) = channel();
+
+        pubsub.unsubscribe(SubscriptionKind::Channel, "client", &tx, &[]);
+
+        assert_eq!(
+            received_replies(&rx),
+            vec!["*3\r\n$11\r\nunsubscribe\r\n$-1\r\n:0\r\n"]
+        );
+    }
+
+    #[test]
+    fn publish_to_channels_and_patterns() {
+        let mut pubsub = PubSub::default();
+        let (tx, rx) = channel();
+        let (other_tx, other_rx) = channel();
+
+        pubsub.subscribe(SubscriptionKind::Channel, "client", &tx, &[b"foo".to_vec()]);
+        pubsub.subscribe(
+            SubscriptionKind::Pattern,
+            "other",
+            &other_tx,
+            &[b"f?o".to_vec()],
+        );
+        received_replies(&rx);
+        received_replies(&other_rx);
+
+        assert_eq!(pubsub.publish(b"foo", b"hello"), 2);
+        assert_eq!(pubsub.publish(b"bar", b"hello"), 0);
+
+        assert_eq!(
+            received_replies(&rx),
+            vec!["*3\r\n$7\r\nmessage\r\n$3\r\nfoo\r\n$5\r\nhello\r\n"]
+        );
+        let Some(ConnectionMessage::SendReply(Reply::Array(pmessage))) = other_rx.try_iter().next()
+        else {
+            panic!("pattern subscriber should receive a pmessage");
+        };
+        assert_eq!(pmessage[0], Reply::bulk("pmessage"));
+        assert_eq!(pmessage[1], Reply::bulk("f?o"));
+
+        pubsub.remove("client");
+        assert_eq!(pubsub.publish(b"foo", b"hello"), 1);
+    }
+
+    #[test]
+    fn disconnected_subscribers_are_dropped() {
+        let mut pubsub = PubSub::default();
+        let (tx, rx) = channel();
+
+        pubsub.subscribe(SubscriptionKind::Channel, "client", &tx, &[b"foo".to_vec()]);
+        pubsub.subscribe(
+            SubscriptionKind::ShardChannel,
+            "client",
+            &tx,
+            &[b"foo".to_vec()],
+        );
+        drop(rx);
+
+        assert_eq!(pubsub.publish(b"foo", b"hello"), 0);
+        assert_eq!(pubsub.publish_shard(b"foo", b"hello"), 0);
+        assert!(pubsub.subscribers.is_empty());
+    }
+
+    #[test]
+    fn shard_channels_are_separate_from_channels() {
+        let mut pubsub = PubSub::default();
+        let (tx, rx) = channel();
+
+        pubsub.subscribe(SubscriptionKind::Channel, "client", &tx, &[b"foo".to_vec()]);
+        pubsub.subscribe(
+            SubscriptionKind::ShardChannel,
+            "client",
+            &tx,
+            &[b"foo".to_vec(), b"bar".to_vec()],
+        );
+        assert_eq!(
+            received_replies(&rx),
+            vec![
+                "*3\r\n$9\r\nsubscribe\r\n$3\r\nfoo\r\n:1\r\n",
+                "*3\r\n$10\r\nssubscribe\r\n$3\r\nfoo\r\n:1\r\n",
+                "*3\r\n$10\r\nssubscribe\r\n$3\r\nbar\r\n:2\r\n",
+            ]
+        );
+
+        assert_eq!(pubsub.publish_shard(b"bar", b"hello"), 1);
+        assert_eq!(pubsub.publish(b"bar", b"hello"), 0);
+        assert_eq!(
+            received_replies(&rx),
+            vec!["*3\r\n$8\r\nsmessage\r\n$3\r\nbar\r\n$5\r\nhello\r\n"]
+        );
+
+        pubsub.unsubscribe(SubscriptionKind::ShardChannel, "client", &tx, &[]);
+        assert_eq!(
+            received_replies(&rx),
+            vec![
+                "*3\r\n$12\r\nsunsubscribe\r\n$3\r\nfoo\r\n:1\r\n",
+                "*3\r\n$12\r\nsunsubscribe\r\n$3\r\nbar\r\n:0\r\n",
+            ]
+        );
+        assert_eq!(pubsub.publish(b"foo", b"hello"), 1);
+    }
+}
@@ -1,20 +1,35 @@
 use std::{
-    net::TcpStream,
+    collections::{HashMap, HashSet},
+    net::{TcpStream, ToSocketAddrs},
     sync::mpsc::{channel, Receiver, Sender},
+    time::{Duration, Instant},
 };
 
 use crate::{
-    actor::{ConnectionMessage, StoreMessage},
+    actor::{
+        handler::{
+            check_arity, check_auth, check_authenticated, config_set, hello_reply, is_config_set,
+            parse_hello, unknown_command_error, CommandHandler, ReplicationInfo,
+        },
+        pubsub::{PubSub, SubscriptionKind},
+        ConnectionID, ConnectionMessage, StoreMessage,
+    },
     config::{Config, ReplicationRole},
     connection::{
-        fmt::{format_array, format_string},
+        fmt::{command_reply, format_array},
         parser::{BufferType, Command, CommandVerb},
+        reply::{Protocol, Reply},
         stream::RedisStream,
         Connection,
     },
-    store::Store,
+    store::{dbfile::write_dbfile, Store},
 };
 
+const MIN_RECONNECT_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(5);
+/// Time allowed to connect to the master, and for the master to answer each handshake step.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
 pub struct ReplicaActor {
     store: Store,
     config: Config,
@@ -23,6 +38,26 @@ pub struct ReplicaActor {
     tx_master: Sender<StoreMessage>,
     replication_offset: usize,
     rx_master: Receiver<StoreMessage>,
+    master_link_up: bool,
+    /// Replication ID of the master, known once a full synchronization happened, to continue its
+    /// stream after reconnecting.
+    master_replid: Option<String>,
+    reconnect_backoff: Duration,
+    next_reconnect: Option<Instant>,
+    pubsub: PubSub,
+    /// Database selected by each connection, including the one with the master.
+    selected_dbs: HashMap<ConnectionID, usize>,
+    /// Protocol negotiated by each client with `HELLO`, RESP2 when missing.
+    protocols: HashMap<ConnectionID, Protocol>,
+    /// IDs of clients, as reported by `HELLO`.
+    client_ids: HashMap<ConnectionID, u64>,
+    next_client_id: u64,
+    /// Replicas synchronized with this replica, to which the master stream is relayed.
+    sub_replicas: HashMap<ConnectionID, Sender<ConnectionMessage>>,
+    /// Database selected in the stream received from the master.
+    master_db: usize,
+    /// Connections that authenticated with `AUTH` or `HELLO ... AUTH`.
+    authenticated: HashSet<ConnectionID>,
 }
 
 impl ReplicaActor {
@@ -38,20 +73,51 @@ impl ReplicaActor {
             rx_master,
             tx_master,
             replication_offset: 0,
+            master_link_up: false,
+            master_replid: None,
+            reconnect_backoff: MIN_RECONNECT_BACKOFF,
+            next_reconnect: None,
+            pubsub: PubSub::default(),
+            selected_dbs: HashMap::new(),
+            protocols: HashMap::new(),
+            client_ids: HashMap::new(),
+            next_client_id: 1,
+            sub_replicas: HashMap::new(),
+            master_db: 0,
+            authenticated: HashSet::new(),
         }
     }
 
     pub fn poll(&mut self) {
         while let Ok(message) = self.rx_master.try_recv() {
-            if let StoreMessage::NewBuffer {
-                value: BufferType::Command(cmd),
-                tx_back,
-                connection_id: _,
-            } = message
-            {
-                println!("{cmd:?}");
-                self.process_command(&cmd, tx_back);
-                self.track_replication_offset(cmd.cmd);
+            match message {
+                StoreMessage::NewBuffer {
+                    value: BufferType::Command(cmd),
+                    tx_back,
+                    connection_id,
+                } => {
+                    println!("{cmd:?}");
+                    self.process_command(&cmd, tx_back, connection_id, true);
+                    self.track_replication_offset(&cmd);
+                    self.relay_to_sub_replicas(&cmd);
+                }
+                StoreMessage::NewBuffer {
+                    value: BufferType::DBFile(content),
+                    ..
+                } => {
+                    // Snapshot of the master dataset sent after PSYNC
+                    println!("Loading dataset received from master");
+                    self.store =
+                        Store::from_rdb(content, self.config.databases).unwrap_or_else(|| {
+                            println!("Could not parse dataset received from master");
+                            Store::with_databases(self.config.databases)
+                        });
+                }
+                StoreMessage::ConnectionClosed { .. } => {
+                    println!("Lost connection with master");
+                    self.master_link_up = false;
+                }
+                _ => {}
             }
         }
         while let Ok(message) = self.rx_clients.try_recv() {
@@ -59,37 +125,118 @@ impl ReplicaActor {
                 StoreMessage::NewBuffer {
                     value: BufferType::Command(cmd),
                     tx_back,
-                    connection_id: _,
+                    connection_id,
                 } => {
                     println!("{cmd:?}");
-                    self.process_command(&cmd, tx_back);
+                    self.process_command(&cmd, tx_back, connection_id, false);
                 }
+                StoreMessage::NewBuffer {
+                    value: BufferType::UnknownCommand(args),
+                    tx_back,
+                    ..
+                } => tx_back
+                    .send(ConnectionMessage::SendReply(unknown_command_error(&args)))
+                    .unwrap(),
+                // Stray replies, as sent by sub-replicas or RESP3 clients, are not commands
+                StoreMessage::NewBuffer {
+                    value: BufferType::Integer(_) | BufferType::Double(_),
+                    ..
+                } => {}
+                StoreMessage::ConnectionClosed { connection_id } => {
+                    self.sub_replicas.remove(&connection_id);
+                    self.pubsub.remove(&connection_id);
+                    self.selected_dbs.remove(&connection_id);
+                    self.protocols.remove(&connection_id);
+                    self.client_ids.remove(&connection_id);
+                    self.authenticated.remove(&connection_id);
+                }
+                StoreMessage::ConnectionOpened { .. } => {}
                 _ => todo!(),
             }
         }
     }
 
+    /// Prepare for exit, saving the dataset when `save` points are configured.
+    pub fn shutdown(&mut self) {
+        if self.config.save_points.is_empty() {
+            return;
+        }
+        println!("Saving the final RDB snapshot before exiting");
+        if let Err(err) = write_dbfile(&self.config.dbfile_path(), &self.store.to_rdb()) {
+            println!("Error trying to save the DB: {err}");
+        }
+    }
+
     pub fn get_tx(&self) -> Sender<StoreMessage> {
         self.tx_clients.clone()
     }
 
-    pub fn init_replication(&mut self) -> Option<Connection> {
-        let ReplicationRole::Replica((host, port)) = &self.config.replication.role else {
+    /// (Re)connect to the master when the link is down, retrying with an exponential backoff.
+    /// Returns the new connection with the master once the handshake succeeded.
+    pub fn connect_to_master(&mut self) -> Option<Connection> {
+        if self.master_link_up
+            || self
+                .next_reconnect
+                .is_some_and(|next_reconnect| next_reconnect > Instant::now())
+        {
+            return None;
+        }
+
+        let Some((connection, resync)) = self.init_replication() else {
+            println!(
+                "Could not connect to master instance, retrying in {:?}",
+                self.reconnect_backoff
+            );
+            self.next_reconnect = Some(Instant::now() + self.reconnect_backoff);
+            self.reconnect_backoff = (self.reconnect_backoff * 2).min(MAX_RECONNECT_BACKOFF);
             return None;
         };
-        let Some(master_stream) = TcpStream::connect(format!("{host}:{port}")).ok() else {
-            panic!("Could not connect to master instance.");
+
+        match resync {
+            Resync::Partial => {
+                println!(
+                    "Continuing replication from offset {}",
+                    self.replication_offset
+                );
+            }
+            // A new full resynchronization starts from a fresh dataset and replication stream,
+            // which sub-replicas have to synchronize with again
+            Resync::Full { replid, offset } => {
+                if self.master_replid.is_some() {
+                    self.store = Store::with_databases(self.config.databases);
+                }
+                for (_, tx) in self.sub_replicas.drain() {
+                    let _ = tx.send(ConnectionMessage::Close);
+                }
+                self.master_replid = Some(replid);
+                self.replication_offset = offset;
+                self.master_db = 0;
+            }
+        }
+        self.reconnect_backoff = MIN_RECONNECT_BACKOFF;
+        self.next_reconnect = None;
+        Some(connection)
+    }
+
+    fn init_replication(&mut self) -> Option<(Connection, Resync)> {
+        let ReplicationRole::Replica((host, port)) = &self.config.replication.role else {
+            return None;
         };
-        let mut master_stream = RedisStream::new(master_stream);
+        let master_stream = format!("{host}:{port}")
+            .to_socket_addrs()
+            .ok()?
+            .find_map(|addr| TcpStream::connect_timeout(&addr, HANDSHAKE_TIMEOUT).ok())?;
+        let mut master_stream = RedisStream::new(master_stream, usize::MAX);
 
         println!("Starting replication handshake with {host}:{port}");
         println!("Enabling blocking behavior of the TCP stream");
         master_stream.set_stream_nonblocking_behavior(false);
+        master_stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT));
         // let master_conn = Connection::new(master_stream, self.tx_master);
 
         println!("Replication: sending PING");
         master_stream.send_string(&format_array(&vec![String::from("PING")]));
-        let res = master_stream.read();
+        let res = read_handshake_reply(&mut master_stream)?;
         println!("{res:?}");
 
         println!("Replication: sending REPLCONF (1/2)");
@@ -98,7 +245,7 @@ impl ReplicaActor {
             String::from("listening-port"),
             format!("{}", self.config.port),
         ]));
-        let res = master_stream.read();
+        let res = read_handshake_reply(&mut master_stream)?;
         println!("{res:?}");
 
         println!("Replication: sending REPLCONF (2/2)");
@@ -107,147 +254,327 @@ impl ReplicaActor {
             String::from("capa"),
             String::from("psync2"),
         ]));
-        let res = master_stream.read();
+        let res = read_handshake_reply(&mut master_stream)?;
         println!("{res:?}");
 
-        println!("Replication: sending PSYNC");
-        master_stream.send_string(&format_array(&vec![
-            String::from("PSYNC"),
-            String::from("?"),
-            String::from("-1"),
-        ]));
+        // Ask to continue from the first byte not processed yet, as counted by the master
+        let (replid, offset) = match &self.master_replid {
+            Some(replid) => (replid.clone(), format!("{}", self.replication_offset + 1)),
+            None => (String::from("?"), String::from("-1")),
+        };
+        println!("Replication: sending PSYNC {replid} {offset}");
+        master_stream.send_string(&format_array(&vec![String::from("PSYNC"), replid, offset]));
+        // The FULLRESYNC or CONTINUE reply can come with the snapshot and the first writes, which
+        // must be applied like the rest of the replication stream
+        let res = read_handshake_reply(&mut master_stream)?;
         println!("{res:?}");
+        let resync = res.iter().find_map(|element| match element {
+            BufferType::String(reply) => Resync::parse(reply),
+            _ => None,
+        })?;
 
         println!("Handshake done");
 
+        master_stream.set_read_timeout(None);
         master_stream.set_stream_nonblocking_behavior(true);
-        Some(Connection::new(master_stream, self.tx_master.clone()))
+        self.master_link_up = true;
+        let connection = Connection::new(master_stream, self.tx_master.clone());
+        connection.forward(res);
+        Some((connection, resync))
     }
 
-    fn track_replication_offset(&mut self, cmd: Vec<String>) {
-        let n_bytes = format_array(&cmd).len();
-        match cmd.first() {
-            Some(cmd) if cmd == "PING" || cmd == "SET" || cmd == "REPLCONF" => {
-                self.replication_offset += n_bytes;
-                println!("New replication offset: {}", self.replication_offset);
+    /// Forward a command of the master stream, as received, to the sub-replicas.
+    fn relay_to_sub_replicas(&mut self, cmd: &Command) {
+        let message = command_reply(&cmd.raw);
+        self.sub_replicas.retain(|connection_id, tx| {
+            let sent = tx
+                .send(ConnectionMessage::SendReply(message.clone()))
+                .is_ok();
+            if !sent {
+                println!("Dropping disconnected sub-replica {connection_id}");
             }
-            _ => {}
-        }
+            sent
+        });
     }
 
-    fn process_command(&mut self, cmd: &Command, tx_back: Sender<ConnectionMessage>) {
-        println!("Processing command: {cmd:?}");
-        let Command { verb, cmd } = cmd;
-        match verb {
-            CommandVerb::ECHO => self.process_echo(cmd, tx_back),
-            CommandVerb::SET => self.process_set(cmd, tx_back),
-            CommandVerb::GET => self.process_get(cmd, tx_back),
-            CommandVerb::CONFIG => self.process_config(cmd, tx_back),
-            CommandVerb::KEYS => self.process_keys(tx_back),
-            CommandVerb::INFO => self.process_info(cmd, tx_back),
-            CommandVerb::REPLCONF => self.process_replconf(cmd, tx_back),
-            unsupported_verb => self.log_unsupported_verb(unsupported_verb),
-        };
+    fn track_replication_offset(&mut self, cmd: &Command) {
+        self.replication_offset += cmd.received_bytes;
+        println!("New replication offset: {}", self.replication_offset);
     }
 
-    fn process_echo(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
-        if let Some(message) = command.get(1) {
-            let message = format!("${}\r\n{}\r\n", message.len(), message);
+    fn process_command(
+        &mut self,
+        command: &Command,
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: ConnectionID,
+        from_master: bool,
+    ) {
+        println!("Processing command: {command:?}");
+        if let Some(error) = check_arity(command) {
+            if !from_master {
+                tx_back.send(ConnectionMessage::SendReply(error)).unwrap();
+            }
+            return;
+        }
+        let authenticated = from_master || self.authenticated.contains(&connection_id);
+        if let Some(error) = check_authenticated(command, &self.config, authenticated) {
+            tx_back.send(ConnectionMessage::SendReply(error)).unwrap();
+            return;
+        }
+        // Writes are only accepted from the master stream
+        if !from_master && command.verb.metadata().is_write() {
             tx_back
-                .send(ConnectionMessage::SendString(message))
+                .send(ConnectionMessage::SendReply(Reply::error(
+                    "READONLY You can't write against a read only replica.",
+                )))
                 .unwrap();
+            return;
         }
+        self.store
+            .select(self.selected_dbs.get(&connection_id).copied().unwrap_or(0));
+        match command.verb {
+            CommandVerb::REPLCONF => self.process_replconf(&command.cmd, tx_back),
+            CommandVerb::PSYNC if !from_master => self.process_psync(tx_back, connection_id),
+            CommandVerb::HELLO => self.process_hello(&command.cmd, tx_back, connection_id),
+            CommandVerb::AUTH => self.process_auth(&command.cmd, tx_back, connection_id),
+            CommandVerb::CONFIG if is_config_set(&command.cmd) => {
+                self.process_config_set(&command.cmd, tx_back)
+            }
+            // Replicas have no replicas of their own to wait for
+            CommandVerb::WAIT if !from_master => tx_back
+                .send(ConnectionMessage::SendReply(Reply::error(
+                    "ERR WAIT cannot be used with replica instances.",
+                )))
+                .unwrap(),
+            CommandVerb::SELECT => {
+                self.process_select(&command.cmd, tx_back, connection_id, from_master)
+            }
+            CommandVerb::QUIT => self.process_quit(tx_back),
+            CommandVerb::SUBSCRIBE => self.pubsub.subscribe(
+                SubscriptionKind::Channel,
+                &connection_id,
+                &tx_back,
+                &command.raw[1..],
+            ),
+            CommandVerb::UNSUBSCRIBE => self.pubsub.unsubscribe(
+                SubscriptionKind::Channel,
+                &connection_id,
+                &tx_back,
+                &command.raw[1..],
+            ),
+            CommandVerb::PSUBSCRIBE => self.pubsub.subscribe(
+                SubscriptionKind::Pattern,
+                &connection_id,
+                &tx_back,
+                &command.raw[1..],
+            ),
+            CommandVerb::PUNSUBSCRIBE => self.pubsub.unsubscribe(
+                SubscriptionKind::Pattern,
+                &connection_id,
+                &tx_back,
+                &command.raw[1..],
+            ),
+            CommandVerb::SSUBSCRIBE => self.pubsub.subscribe(
+                SubscriptionKind::ShardChannel,
+                &connection_id,
+                &tx_back,
+                &command.raw[1..],
+            ),
+            CommandVerb::SUNSUBSCRIBE => self.pubsub.unsubscribe(
+                SubscriptionKind::ShardChannel,
+                &connection_id,
+                &tx_back,
+                &command.raw[1..],
+            ),
+            CommandVerb::PUBLISH | CommandVerb::SPUBLISH => {
+                // Messages published on the master are delivered to local subscribers as well
+                let receivers = if command.verb == CommandVerb::PUBLISH {
+                    self.pubsub.publish(&command.raw[1], &command.raw[2])
+                } else {
+                    self.pubsub.publish_shard(&command.raw[1], &command.raw[2])
+                };
+                if !from_master {
+                    tx_back
+                        .send(ConnectionMessage::SendReply(Reply::Integer(
+                            receivers as i64,
+                        )))
+                        .unwrap();
+                }
+            }
+            _ => self.process_store_command(command, tx_back, from_master),
+        };
     }
 
-    fn process_set(&mut self, command: &[String], _tx_back: Sender<ConnectionMessage>) {
-        let (Some(key), Some(value)) = (command.get(1), command.get(2)) else {
+    fn process_store_command(
+        &mut self,
+        command: &Command,
+        tx_back: Sender<ConnectionMessage>,
+        from_master: bool,
+    ) {
+        let mut handler = CommandHandler::new(&mut self.store, &self.config);
+        if command.verb == CommandVerb::INFO {
+            handler = handler.with_replication(ReplicationInfo::Replica {
+                offset: self.replication_offset,
+                link_up: self.master_link_up,
+            });
+        }
+        let Some(outcome) = handler.handle(command) else {
+            self.log_unsupported_verb(&command.verb);
             return;
         };
 
-        let option = command.get(3);
-        let option_value: Option<usize> = match command.get(4) {
-            Some(option_value) => option_value.parse::<usize>().ok(),
-            _ => None,
-        };
-        let ttl = match (option, option_value) {
-            (Some(cmd), Some(cmd_value)) if cmd == "px" => Some(cmd_value),
-            _ => None,
+        // Writes propagated by the master are applied without replying
+        if !from_master {
+            tx_back
+                .send(ConnectionMessage::SendReply(outcome.response))
+                .unwrap();
+        }
+    }
+
+    fn process_auth(
+        &mut self,
+        command: &[String],
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: ConnectionID,
+    ) {
+        let response = match check_auth(command, &self.config) {
+            Ok(()) => {
+                self.authenticated.insert(connection_id);
+                Reply::ok()
+            }
+            Err(error) => error,
         };
+        tx_back
+            .send(ConnectionMessage::SendReply(response))
+            .unwrap();
+    }
 
-        println!("Setting {}: {}", key, value);
-        self.store.set_string(key, value, ttl);
+    fn process_config_set(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
+        let response = match config_set(command, &self.config) {
+            Ok(config) => {
+                self.config = config;
+                Reply::ok()
+            }
+            Err(error) => error,
+        };
+        tx_back
+            .send(ConnectionMessage::SendReply(response))
+            .unwrap();
     }
 
-    fn process_get(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
-        let Some(key) = command.get(1) else {
-            return;
+    fn process_hello(
+        &mut self,
+        command: &[String],
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: ConnectionID,
+    ) {
+        let current = self
+            .protocols
+            .get(&connection_id)
+            .copied()
+            .unwrap_or_default();
+        let authenticated = self.authenticated.contains(&connection_id);
+        let response = match parse_hello(command, current, &self.config, authenticated) {
+            Ok((protocol, authenticated)) => {
+                if authenticated {
+                    self.authenticated.insert(connection_id.clone());
+                }
+                if protocol != current {
+                    self.protocols.insert(connection_id.clone(), protocol);
+                    tx_back
+                        .send(ConnectionMessage::SetProtocol(protocol))
+                        .unwrap();
+                }
+                let id = *self.client_ids.entry(connection_id).or_insert_with(|| {
+                    self.next_client_id += 1;
+                    self.next_client_id - 1
+                });
+                hello_reply(&self.config, protocol, id)
+            }
+            Err(error) => error,
         };
-        let value = self.store.get_string(key);
-        let message = ConnectionMessage::SendString(format_string(value));
-        tx_back.send(message).unwrap();
+        tx_back
+            .send(ConnectionMessage::SendReply(response))
+            .unwrap();
     }
 
-    fn process_config(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
-        let (Some(action), Some(key)) = (command.get(1), command.get(2)) else {
-            return;
+    fn process_select(
+        &mut self,
+        command: &[String],
+        tx_back: Sender<ConnectionMessage>,
+        connection_id: ConnectionID,
+        from_master: bool,
+    ) {
+        let response = match command[1].parse::<i64>() {
+            Ok(db) if (0..self.store.databases() as i64).contains(&db) => {
+                if from_master {
+                    self.master_db = db as usize;
+                }
+                self.selected_dbs.insert(connection_id, db as usize);
+                Reply::ok()
+            }
+            Ok(_) => Reply::error("ERR DB index is out of range"),
+            Err(_) => Reply::error("ERR value is not an integer or out of range"),
         };
-        if *action == "GET" {
-            let Some(value) = self.config.get_arg(key) else {
-                return;
-            };
-            let message = format!(
-                "*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n",
-                key.len(),
-                key,
-                value.len(),
-                value
-            );
+        if !from_master {
             tx_back
-                .send(ConnectionMessage::SendString(message))
+                .send(ConnectionMessage::SendReply(response))
                 .unwrap();
         }
     }
 
-    fn process_keys(&mut self, tx_back: Sender<ConnectionMessage>) {
-        let mut response = String::new();
-        let keys = self.store.get_keys();
-        response.push_str(&format!("*{}\r\n", keys.len()));
-        for key in keys {
-            response.push_str(&format!("${}\r\n{}\r\n", key.len(), key));
+    /// Fully synchronize a sub-replica with the dataset of this replica, then relay it the stream
+    /// received from the master.
+    fn process_psync(&mut self, tx_back: Sender<ConnectionMessage>, connection_id: ConnectionID) {
+        if !self.master_link_up {
+            tx_back
+                .send(ConnectionMessage::SendReply(Reply::error(
+                    "NOMASTERLINK Can't SYNC while not connected with my master",
+                )))
+                .unwrap();
+            return;
         }
         tx_back
-            .send(ConnectionMessage::SendString(response))
+            .send(ConnectionMessage::SendReply(Reply::Simple(format!(
+                "FULLRESYNC {} {}",
+                self.config.replication.replid, self.replication_offset
+            ))))
             .unwrap();
+        let snapshot = self.store.to_rdb();
+        tx_back
+            .send(ConnectionMessage::SendString(format!(
+                "${}\r\n",
+                snapshot.len()
+            )))
+            .unwrap();
+        tx_back
+            .send(ConnectionMessage::SendBytes(snapshot))
+            .unwrap();
+        // The relayed stream goes on in the database the master last selected
+        if self.master_db != 0 {
+            tx_back
+                .send(ConnectionMessage::SendReply(command_reply(&[
+                    b"SELECT".to_vec(),
+                    self.master_db.to_string().into_bytes(),
+                ])))
+                .unwrap();
+        }
+        println!("Registering sub-replica {connection_id}");
+        self.sub_replicas.insert(connection_id, tx_back);
     }
 
-    fn process_info(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
-        match command.get(1) {
-            Some(section) if *section == "replication" => {
-                let mut response = String::new();
-                let role = match self.config.replication.role {
-                    ReplicationRole::Master => String::from("master"),
-                    ReplicationRole::Replica(_) => String::from("slave"),
-                };
-                response.push_str(&format!("role:{role}\r\n"));
-                response.push_str(&format!(
-                    "master_replid:{}\r\n",
-                    self.config.replication.replid
-                ));
-                response.push_str(&format!(
-                    "master_repl_offset:{}\r\n",
-                    self.config.replication.repl_offset
-                ));
-                tx_back
-                    .send(ConnectionMessage::SendString(format_string(Some(response))))
-                    .unwrap();
-            }
-            _ => panic!(),
-        }
+    fn process_quit(&mut self, tx_back: Sender<ConnectionMessage>) {
+        tx_back
+            .send(ConnectionMessage::SendReply(Reply::ok()))
+            .unwrap();
+        tx_back.send(ConnectionMessage::Close).unwrap();
     }
 
     fn process_replconf(&mut self, command: &[String], tx_back: Sender<ConnectionMessage>) {
         match command.get(1) {
-            Some(option) if option == "GETACK" => {
+            // Acknowledgements of sub-replicas, which nothing waits for on a replica
+            Some(option) if option.eq_ignore_ascii_case("ack") => {}
+            Some(option) if option.eq_ignore_ascii_case("getack") => {
                 let message = format_array(&vec![
                     String::from("REPLCONF"),
                     String::from("ACK"),
@@ -259,7 +586,7 @@ impl ReplicaActor {
             }
             _ => {
                 tx_back
-                    .send(ConnectionMessage::SendString(String::from("+OK\r\n")))
+                    .send(ConnectionMessage::SendReply(Reply::ok()))
                     .unwrap();
             }
         };
@@ -269,3 +596,630 @@ impl ReplicaActor {
         println!("{verb:?} not implemented for Replica to Master connection");
     }
 }
+
+/// Reply of the master to `PSYNC`.
+#[derive(Debug, PartialEq)]
+enum Resync {
+    /// `FULLRESYNC <replid> <offset>`: a snapshot follows, then the stream from `offset`.
+    Full { replid: String, offset: usize },
+    /// `CONTINUE [<replid>]`: the stream goes on from the offset asked for.
+    Partial,
+}
+
+impl Resync {
+    fn parse(reply: &[u8]) -> Option<Resync> {
+        let reply = String::from_utf8_lossy(reply);
+        let mut words = reply.split_whitespace();
+        match words.next()? {
+            "FULLRESYNC" => Some(Resync::Full {
+                replid: words.next()?.to_owned(),
+                offset: words.next()?.parse().ok()?,
+            }),
+            "CONTINUE" => Some(Resync::Partial),
+            _ => None,
+        }
+    }
+}
+
+/// Wait for the master's reply to a handshake step, giving up after [`HANDSHAKE_TIMEOUT`].
+fn read_handshake_reply(stream: &mut RedisStream<TcpStream>) -> Option<Vec<BufferType>> {
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    while Instant::now() < deadline {
+        let reply = stream.read()?;
+        if !reply.is_empty() {
+            return Some(reply);
+        }
+    }
+    println!("Replication: master did not reply in time");
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::mpsc::{channel, Receiver, Sender},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        actor::{master::MasterActor, ConnectionMessage, StoreMessage},
+        config::{Args, Config},
+        connection::{
+            parser::{parse_buffer, BufferType, Command},
+            reply::{Protocol, Reply},
+        },
+        store::Store,
+    };
+
+    use super::{ReplicaActor, HANDSHAKE_TIMEOUT, MIN_RECONNECT_BACKOFF};
+
+    #[test]
+    fn getack_reports_bytes_received_from_master() {
+        let mut actor = ReplicaActor::new(Store::new(), Config::from_args(Args::new()));
+        let (tx_back, rx_back) = channel();
+
+        let stream = b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$1\r\n1\r\n\
+            *5\r\n$4\r\nXADD\r\n$1\r\ns\r\n$3\r\n1-0\r\n$1\r\nf\r\n$1\r\nv\r\n\
+            *2\r\n$3\r\nDEL\r\n$3\r\nfoo\r\n\
+            *1\r\n$4\r\nPING\r\n\
+            *3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n";
+        for buffer in parse_buffer(stream).unwrap() {
+            actor
+                .tx_master
+                .send(StoreMessage::NewBuffer {
+                    value: buffer,
+                    tx_back: tx_back.clone(),
+                    connection_id: "master".to_owned(),
+                })
+                .unwrap();
+        }
+        actor.poll();
+
+        let replies: Vec<String> = rx_back
+            .try_iter()
+            .filter_map(|msg| match msg {
+                ConnectionMessage::SendString(msg) => Some(msg),
+                _ => None,
+            })
+            .collect();
+        // The GETACK itself (37 bytes) is only counted once processed
+        let offset = stream.len() - 37;
+        assert_eq!(
+            replies,
+            vec![format!(
+                "*3\r\n$8\r\nREPLCONF\r\n$3\r\nACK\r\n${}\r\n{offset}\r\n",
+                offset.to_string().len()
+            )]
+        );
+        assert_eq!(actor.replication_offset, stream.len());
+    }
+
+    #[test]
+    fn getack_is_case_insensitive() {
+        let mut actor = ReplicaActor::new(Store::new(), Config::from_args(Args::new()));
+        let (tx_back, rx_back) = channel();
+
+        send_command(
+            &actor.tx_master,
+            &["replconf", "getack", "*"],
+            &tx_back,
+            "master",
+        );
+        actor.poll();
+
+        assert!(matches!(
+            rx_back.try_recv(),
+            Ok(ConnectionMessage::SendString(message)) if message.contains("ACK")
+        ));
+    }
+
+    #[test]
+    fn wait_is_rejected_on_replicas() {
+        let mut actor = ReplicaActor::new(Store::new(), Config::from_args(Args::new()));
+        let (tx_back, rx_back) = channel();
+
+        send_command(&actor.get_tx(), &["WAIT", "1", "0"], &tx_back, "client");
+        actor.poll();
+
+        assert!(matches!(
+            rx_back.try_recv(),
+            Ok(ConnectionMessage::SendReply(Reply::Error(error)))
+                if error == "ERR WAIT cannot be used with replica instances."
+        ));
+    }
+
+    #[test]
+    fn client_writes_are_rejected_on_replicas() {
+        let mut actor = ReplicaActor::new(Store::new(), Config::from_args(Args::new()));
+        let (tx_back, rx_back) = channel();
+
+        send_command(&actor.get_tx(), &["SET", "foo", "bar"], &tx_back, "client");
+        actor.poll();
+
+        assert!(matches!(
+            rx_back.try_recv(),
+            Ok(ConnectionMessage::SendReply(Reply::Error(error)))
+                if error == "READONLY You can't write against a read only replica."
+        ));
+        assert_eq!(actor.store.get_string("foo"), Ok(None));
+    }
+
+    #[test]
+    fn hello_without_version_reports_replica_role() {
+        let config = Config::from_args(Args::from([(
+            String::from("replicaof"),
+            String::from("localhost 6379"),
+        )]));
+        let mut actor = ReplicaActor::new(Store::new(), config);
+        let (tx_back, rx_back) = channel();
+
+        send_command(&actor.get_tx(), &["HELLO"], &tx_back, "client");
+        actor.poll();
+
+        let Ok(ConnectionMessage::SendReply(Reply::Map(entries))) = rx_back.try_recv() else {
+            panic!("HELLO should reply with a map");
+        };
+        assert!(entries.contains(&(Reply::bulk("proto"), Reply::Integer(2))));
+        assert!(entries.contains(&(Reply::bulk("role"), Reply::bulk("replica"))));
+    }
+
+    #[test]
+    fn info_replication_reports_master_link() {
+        let config = Config::from_args(Args::from([(
+            String::from("replicaof"),
+            String::from("localhost 6379"),
+        )]));
+        let mut actor = ReplicaActor::new(Store::new(), config);
+        let (tx_back, rx_back) = channel();
+
+        for buffer in parse_buffer(b"*2\r\n$4\r\nINFO\r\n$11\r\nreplication\r\n").unwrap() {
+            actor
+                .get_tx()
+                .send(StoreMessage::NewBuffer {
+                    value: buffer,
+                    tx_back: tx_back.clone(),
+                    connection_id: "client".to_owned(),
+                })
+                .unwrap();
+        }
+        actor.poll();
+
+        let Ok(ConnectionMessage::SendReply(Reply::Bulk(Some(info)))) = rx_back.try_recv() else {
+            panic!("INFO should reply with a bulk string");
+        };
+        let info = String::from_utf8(info).unwrap();
+        assert!(info.contains("role:slave\r\nmaster_host:localhost\r\nmaster_port:6379\r\n"));
+        assert!(info.contains("master_link_status:down\r\nslave_repl_offset:0\r\n"));
+    }
+
+    #[test]
+    fn writes_sent_with_fullresync_are_applied() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut snapshot = Store::new();
+        snapshot.set_string("foo", b"bar", None);
+        let snapshot = snapshot.to_rdb();
+
+        // Scripted master sending everything following the PSYNC in a single write
+        let master = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer = [0u8; 1024];
+            for reply in ["+PONG\r\n", "+OK\r\n", "+OK\r\n"] {
+                let _ = stream.read(&mut buffer).unwrap();
+                stream.write_all(reply.as_bytes()).unwrap();
+            }
+            let _ = stream.read(&mut buffer).unwrap();
+            let mut payload = format!("+FULLRESYNC {} 0\r\n", "0".repeat(40)).into_bytes();
+            payload.extend(format!("${}\r\n", snapshot.len()).into_bytes());
+            payload.extend(&snapshot);
+            payload.extend(b"*3\r\n$3\r\nSET\r\n$3\r\nbaz\r\n$3\r\nqux\r\n");
+            stream.write_all(&payload).unwrap();
+            stream
+        });
+
+        let config = Config::from_args(Args::from([(
+            String::from("replicaof"),
+            format!("127.0.0.1 {port}"),
+        )]));
+        let mut actor = ReplicaActor::new(Store::new(), config);
+        let mut connection = actor.connect_to_master().unwrap();
+        let _master_stream = master.join().unwrap();
+        connection.poll();
+        actor.poll();
+
+        assert_eq!(actor.store.get_string("foo"), Ok(Some(b"bar".to_vec())));
+        assert_eq!(actor.store.get_string("baz"), Ok(Some(b"qux".to_vec())));
+    }
+
+    #[test]
+    fn reconnection_continues_the_master_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let replid = "a".repeat(40);
+        let snapshot = Store::new().to_rdb();
+
+        // Scripted master fully synchronizing the replica, then accepting to continue the stream
+        let master = thread::spawn(move || {
+            let mut psyncs = Vec::new();
+            let mut streams = Vec::new();
+            for payload in [
+                [
+                    format!("+FULLRESYNC {replid} 100\r\n${}\r\n", snapshot.len()).into_bytes(),
+                    snapshot.clone(),
+                    b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n".to_vec(),
+                ]
+                .concat(),
+                b"+CONTINUE\r\n*3\r\n$3\r\nSET\r\n$3\r\nbaz\r\n$3\r\nqux\r\n".to_vec(),
+            ] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buffer = [0u8; 1024];
+                for reply in ["+PONG\r\n", "+OK\r\n", "+OK\r\n"] {
+                    let _ = stream.read(&mut buffer).unwrap();
+                    stream.write_all(reply.as_bytes()).unwrap();
+                }
+                let n = stream.read(&mut buffer).unwrap();
+                psyncs.push(String::from_utf8(buffer[..n].to_vec()).unwrap());
+                stream.write_all(&payload).unwrap();
+                streams.push(stream);
+            }
+            (psyncs, streams)
+        });
+
+        let config = Config::from_args(Args::from([(
+            String::from("replicaof"),
+            format!("127.0.0.1 {port}"),
+        )]));
+        let mut actor = ReplicaActor::new(Store::new(), config);
+        let mut connection = actor.connect_to_master().unwrap();
+        thread::sleep(Duration::from_millis(50));
+        connection.poll();
+        actor.poll();
+        assert_eq!(actor.replication_offset, 131);
+
+        // Link lost, the replica asks for the stream from its first unprocessed byte
+        actor.master_link_up = false;
+        let mut connection = actor.connect_to_master().unwrap();
+        let (psyncs, _streams) = master.join().unwrap();
+        connection.poll();
+        actor.poll();
+
+        assert_eq!(psyncs[0], "*3\r\n$5\r\nPSYNC\r\n$1\r\n?\r\n$2\r\n-1\r\n");
+        assert_eq!(
+            psyncs[1],
+            format!(
+                "*3\r\n$5\r\nPSYNC\r\n$40\r\n{}\r\n$3\r\n132\r\n",
+                "a".repeat(40)
+            )
+        );
+        assert_eq!(actor.store.get_string("foo"), Ok(Some(b"bar".to_vec())));
+        assert_eq!(actor.store.get_string("baz"), Ok(Some(b"qux".to_vec())));
+        assert_eq!(actor.replication_offset, 162);
+    }
+
+    #[test]
+    fn reconnection_to_master_backs_off() {
+        let config = Config::from_args(Args::from([(
+            String::from("replicaof"),
+            String::from("127.0.0.1 1"),
+        )]));
+        let mut actor = ReplicaActor::new(Store::new(), config);
+
+        assert!(actor.connect_to_master().is_none());
+        assert!(!actor.master_link_up);
+        assert_eq!(actor.reconnect_backoff, MIN_RECONNECT_BACKOFF * 2);
+
+        // Too early for another attempt
+        let next_reconnect = actor.next_reconnect;
+        assert!(actor.connect_to_master().is_none());
+        assert_eq!(actor.next_reconnect, next_reconnect);
+        assert_eq!(actor.reconnect_backoff, MIN_RECONNECT_BACKOFF * 2);
+    }
+
+    #[test]
+    fn handshake_gives_up_on_a_silent_master() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let config = Config::from_args(Args::from([(
+            String::from("replicaof"),
+            format!("127.0.0.1 {port}"),
+        )]));
+        let mut actor = ReplicaActor::new(Store::new(), config);
+        let started = Instant::now();
+
+        assert!(actor.connect_to_master().is_none());
+        assert!(!actor.master_link_up);
+        assert!(started.elapsed() < HANDSHAKE_TIMEOUT * 2);
+        drop(listener);
+    }
+
+    fn send_command(
+        tx: &Sender<StoreMessage>,
+        cmd: &[&str],
+        tx_back: &Sender<ConnectionMessage>,
+        connection_id: &str,
+    ) {
+        let command = Command::new(cmd.iter().map(|s| s.as_bytes().to_vec()).collect()).unwrap();
+        tx.send(StoreMessage::NewBuffer {
+            value: BufferType::Command(command),
+            tx_back: tx_back.clone(),
+            connection_id: connection_id.to_owned(),
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn replica_receives_master_dataset_on_sync() {
+        let mut master = MasterActor::new(Store::new(), Config::from_args(Args::new()));
+        let mut replica = ReplicaActor::new(Store::new(), Config::from_args(Args::new()));
+        let (link_tx, link_rx) = channel();
+        let (client_tx, _client_rx) = channel();
+
+        send_command(
+            &master.get_tx(),
+            &["SET", "foo", "bar"],
+            &client_tx,
+            "client",
+        );
+        send_command(&master.get_tx(), &["SET", "n", "42"], &client_tx, "client");
+        send_command(
+            &master.get_tx(),
+            &["XADD", "s", "1-0", "f", "v"],
+            &client_tx,
+            "client",
+        );
+        send_command(&master.get_tx(), &["PSYNC", "?", "-1"], &link_tx, "replica");
+        send_command(
+            &master.get_tx(),
+            &["SET", "foo", "baz"],
+            &client_tx,
+            "client",
+        );
+        master.poll();
+
+        // Replay the replication link: FULLRESYNC, snapshot, then the writes made after it
+        let mut link = Vec::new();
+        for message in link_rx.try_iter() {
+            match message {
+                ConnectionMessage::SendReply(reply) => link.extend(reply.encode(Protocol::RESP2)),
+                ConnectionMessage::SendString(string) => link.extend(string.into_bytes()),
+                ConnectionMessage::SendBytes(bytes) => link.extend(bytes),
+                ConnectionMessage::SetProtocol(_) | ConnectionMessage::Close => {}
+            }
+        }
+        let (ack_tx, _ack_rx) = channel();
+        for buffer in parse_buffer(&link).unwrap() {
+            replica
+                .tx_master
+                .send(StoreMessage::NewBuffer {
+                    value: buffer,
+                    tx_back: ack_tx.clone(),
+                    connection_id: "master".to_owned(),
+                })
+                .unwrap();
+        }
+        replica.poll();
+
+        let mut keys = replica.store.get_keys();
+        keys.sort();
+        assert_eq!(keys, ["foo", "n", "s"]);
+        assert_eq!(replica.store.get_string("foo"), Ok(Some(b"baz".to_vec())));
+        assert_eq!(replica.store.get_string("n"), Ok(Some(b"42".to_vec())));
+        let entries = replica.store.get_stream_range("s", None, None);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].id.to_string(), "1-0");
+    }
+
+    /// Replay the messages sent on a replication link to the replica at the other end.
+    fn replay_link(link_rx: &Receiver<ConnectionMessage>, replica: &mut ReplicaActor) {
+        let mut link = Vec::new();
+        for message in link_rx.try_iter() {
+            match message {
+                ConnectionMessage::SendReply(reply) => link.extend(reply.encode(Protocol::RESP2)),
+                ConnectionMessage::SendString(string) => link.extend(string.into_bytes()),
+                ConnectionMessage::SendBytes(bytes) => link.extend(bytes),
+                ConnectionMessage::SetProtocol(_) | ConnectionMessage::Close => {}
+            }
+        }
+        let (ack_tx, _ack_rx) = channel();
+        for buffer in parse_buffer(&link).unwrap() {
+            replica
+                .tx_master
+                .send(StoreMessage::NewBuffer {
+                    value: buffer,
+                    tx_back: ack_tx.clone(),
+                    connection_id: "master".to_owned(),
+                })
+                .unwrap();
+        }
+        replica.poll();
+    }
+
+    #[test]
+    fn master_writes_are_relayed_to_sub_replicas() {
+        let mut master = MasterActor::new(Store::new(), Config::from_args(Args::new()));
+        let mut replica = ReplicaActor::new(Store::new(), Config::from_args(Args::new()));
+        let mut sub_replica = ReplicaActor::new(Store::new(), Config::from_args(Args::new()));
+        let (link_tx, link_rx) = channel();
+        let (sub_link_tx, sub_link_rx) = channel();
+        let (client_tx, _client_rx) = channel();
+
+        send_command(
+            &master.get_tx(),
+            &["SET", "foo", "bar"],
+            &client_tx,
+            "client",
+        );
+        send_command(&master.get_tx(), &["PSYNC", "?", "-1"], &link_tx, "replica");
+        master.poll();
+        replica.master_link_up = true;
+        replay_link(&link_rx, &mut replica);
+
+        send_command(
+            &replica.get_tx(),
+            &["PSYNC", "?", "-1"],
+            &sub_link_tx,
+            "sub",
+        );
+        replica.poll();
+        send_command(&master.get_tx(), &["SELECT", "1"], &client_tx, "client");
+        send_command(
+            &master.get_tx(),
+            &["SET", "foo", "baz"],
+            &client_tx,
+            "client",
+        );
+        master.poll();
+        replay_link(&link_rx, &mut replica);
+        replay_link(&sub_link_rx, &mut sub_replica);
+
+        sub_replica.store.select(0);
+        assert_eq!(
+            sub_replica.store.get_string("foo"),
+            Ok(Some(b"bar".to_vec()))
+        );
+        sub_replica.store.select(1);
+        assert_eq!(
+            sub_replica.store.get_string("foo"),
+            Ok(Some(b"baz".to_vec()))
+        );
+    }
+
+    #[test]
+    fn ttl_changes_converge_on_replicas() {
+        let mut master = MasterActor::new(Store::new(), Config::from_args(Args::new()));
+        let mut replica = ReplicaActor::new(Store::new(), Config::from_args(Args::new()));
+        let (link_tx, link_rx) = channel();
+        let (client_tx, client_rx) = channel();
+        let in_a_minute = (chrono::Utc::now().timestamp() + 60).to_string();
+
+        send_command(&master.get_tx(), &["PSYNC", "?", "-1"], &link_tx, "replica");
+        for key in ["a", "b", "c", "d"] {
+            send_command(&master.get_tx(), &["SET", key, "v"], &client_tx, "client");
+        }
+        send_command(
+            &master.get_tx(),
+            &["EXPIRE", "a", "100"],
+            &client_tx,
+            "client",
+        );
+        send_command(
+            &master.get_tx(),
+            &["PEXPIRE", "b", "50000"],
+            &client_tx,
+            "client",
+        );
+        send_command(
+            &master.get_tx(),
+            &["EXPIREAT", "c", &in_a_minute],
+            &client_tx,
+            "client",
+        );
+        send_command(
+            &master.get_tx(),
+            &["EXPIRE", "d", "100"],
+            &client_tx,
+            "client",
+        );
+        send_command(&master.get_tx(), &["PERSIST", "d"], &client_tx, "client");
+        master.poll();
+        replay_link(&link_rx, &mut replica);
+        client_rx.try_iter().for_each(drop);
+
+        let (replica_client_tx, replica_client_rx) = channel();
+        for key in ["a", "b", "c", "d"] {
+            send_command(&master.get_tx(), &["PTTL", key], &client_tx, "client");
+            send_command(
+                &replica.get_tx(),
+                &["PTTL", key],
+                &replica_client_tx,
+                "client",
+            );
+        }
+        master.poll();
+        replica.poll();
+
+        let ttls = |rx: &Receiver<ConnectionMessage>| -> Vec<i64> {
+            rx.try_iter()
+                .map(|message| match message {
+                    ConnectionMessage::SendReply(Reply::Integer(ttl)) => ttl,
+                    message => panic!("Expected a TTL, got {message:?}"),
+                })
+                .collect()
+        };
+        let master_ttls = ttls(&client_rx);
+        let replica_ttls = ttls(&replica_client_rx);
+        assert_eq!(master_ttls[3], -1);
+        assert_eq!(replica_ttls[3], -1);
+        for (master_ttl, replica_ttl) in master_ttls.iter().zip(&replica_ttls).take(3) {
+            assert!(*master_ttl > 0);
+            assert!((master_ttl - replica_ttl).abs() < 100);
+        }
+    }
+
+    #[test]
+    fn publish_on_master_reaches_replica_subscribers() {
+        let mut master = MasterActor::new(Store::new(), Config::from_args(Args::new()));
+        let mut replica = ReplicaActor::new(Store::new(), Config::from_args(Args::new()));
+        let (link_tx, link_rx) = channel();
+        let (subscriber_tx, subscriber_rx) = channel();
+        let (publisher_tx, publisher_rx) = channel();
+
+        send_command(&master.get_tx(), &["PSYNC", "?", "-1"], &link_tx, "replica");
+        send_command(
+            &replica.get_tx(),
+            &["SUBSCRIBE", "news"],
+            &subscriber_tx,
+            "subscriber",
+        );
+        send_command(
+            &master.get_tx(),
+            &["PUBLISH", "news", "hello"],
+            &publisher_tx,
+            "publisher",
+        );
+        master.poll();
+        replica.poll();
+        subscriber_rx.try_iter().for_each(drop);
+        assert!(matches!(
+            publisher_rx.try_recv(),
+            Ok(ConnectionMessage::SendReply(Reply::Integer(0)))
+        ));
+
+        // Forward the commands the master propagated on the replication link
+        let (ack_tx, ack_rx) = channel();
+        for message in link_rx.try_iter() {
+            let ConnectionMessage::SendReply(reply @ Reply::Array(_)) = message else {
+                continue;
+            };
+            for buffer in parse_buffer(&reply.encode(Protocol::RESP2)).unwrap() {
+                replica
+                    .tx_master
+                    .send(StoreMessage::NewBuffer {
+                        value: buffer,
+                        tx_back: ack_tx.clone(),
+                        connection_id: "master".to_owned(),
+                    })
+                    .unwrap();
+            }
+        }
+        replica.poll();
+
+        let Ok(ConnectionMessage::SendReply(Reply::Array(message))) = subscriber_rx.try_recv()
+        else {
+            panic!("replica subscriber should receive the message");
+        };
+        assert_eq!(
+            message,
+            vec![
+                Reply::bulk("message"),
+                Reply::bulk("news"),
+                Reply::bulk("hello")
+            ]
+        );
+        assert!(ack_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use std::{collections::VecDeque, time::Duration};
+
+use chrono::Utc;
+
+use crate::connection::reply::Reply;
+
+/// Arguments kept for a logged command, the last one summarizing the others.
+const MAX_ARGS: usize = 32;
+/// Bytes kept for each logged argument.
+const MAX_ARG_LEN: usize = 128;
+
+struct SlowLogEntry {
+    id: u64,
+    timestamp: i64,
+    duration: Duration,
+    args: Vec<Vec<u8>>,
+    client_addr: String,
+}
+
+/// Commands that took longer than `slowlog-log-slower-than`, most recent first.
+#[derive(Default)]
+pub struct SlowLog {
+    entries: VecDeque<SlowLogEntry>,
+    next_id: u64,
+}
+
+impl SlowLog {
+    /// Log a command, dropping the oldest entries beyond `max_len`.
+    pub fn push(
+        &mut self,
+        args: &[Vec<u8>],
+        duration: Duration,
+        client_addr: String,
+        max_len: usize,
+    ) {
+        let mut logged_args: Vec<Vec<u8>> = args
+            .iter()
+            .take(if args.len() > MAX_ARGS {
+                MAX_ARGS - 1
+            } else {
+                MAX_ARGS
+            })
+            .map(|arg| {
+                if arg.len() <= MAX_ARG_LEN {
+                    return arg.clone();
+                }
+                let mut truncated = arg[..MAX_ARG_LEN].to_vec();
+                truncated.extend(format!("... ({} more bytes)", arg.len() - MAX_ARG_LEN).bytes());
+                truncated
+            })
+            .collect();
+        if args.len() > MAX_ARGS {
+            let more = args.len() - logged_args.len();
+            logged_args.push(format!("... ({more} more arguments)").into_bytes());
+        }
+
+        self.entries.push_front(SlowLogEntry {
+            id: self.next_id,
+            timestamp: Utc::now().timestamp(),
+            duration,
+            args: logged_args,
+            client_addr,
+        });
+        self.next_id += 1;
+        self.entries.truncate(max_len);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn reset(&mut self) {
+        self.entries.clear();
+    }
+
+    /// The `count` most recent entries, every entry when `None`.
+    pub fn get(&self, count: Option<usize>) -> Reply {
+        let count = count.unwrap_or(self.entries.len());
+        Reply::Array(
+            self.entries
+                .iter()
+                .take(count)
+                .map(|entry| {
+                    Reply::Array(vec![
+                        Reply::Integer(entry.id as i64),
+                        Reply::Integer(entry.timestamp),
+                        Reply::Integer(entry.duration.as_micros() as i64),
+                        Reply::Array(
+                            entry
+                                .args
+                                .iter()
+                                .map(|arg| Reply::Bulk(Some(arg.clone())))
+                                .collect(),
+                        ),
+                        Reply::bulk(&entry.client_addr),
+                        Reply::bulk(""),
+                    ])
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::connection::reply::Reply;
+
+    use super::SlowLog;
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn keeps_most_recent_entries() {
+        let mut slowlog = SlowLog::default();
+        for key in ["a", "b", "c"] {
+            slowlog.push(
+                &args(&["GET", key]),
+                Duration::from_micros(15),
+                String::new(),
+                2,
+            );
+        }
+        assert_eq!(slowlog.len(), 2);
+
+        let Reply::Array(entries) = slowlog.get(Some(1)) else {
+            panic!("SLOWLOG GET should reply with an array");
+        };
+        assert_eq!(entries.len(), 1);
+        let Reply::Array(entry) = &entries[0] else {
+            panic!("slow log entries should be arrays");
+        };
+        assert_eq!(entry[0], Reply::Integer(2));
+        assert_eq!(entry[2], Reply::Integer(15));
+        assert_eq!(
+            entry[3],
+            Reply::Array(vec![Reply::bulk("GET"), Reply::bulk("c")])
+        );
+
+        slowlog.reset();
+        assert!(slowlog.is_empty());
+    }
+
+    #[test]
+    fn truncates_long_commands() {
+        let mut slowlog = SlowLog::default();
+        let long_value = "x".repeat(130);
+        let mut command = vec!["DEL"; 40];
+        command[1] = &long_value;
+        slowlog.push(&args(&command), Duration::ZERO, String::new(), 10);
+
+        let Reply::Array(entries) = slowlog.get(None) else {
+            panic!("SLOWLOG GET should reply with an array");
+        };
+        let Reply::Array(entry) = &entries[0] else {
+            panic!("slow log entries should be arrays");
+        };
+        let Reply::Array(logged) = &entry[3] else {
+            panic!("logged arguments should be an array");
+        };
+        assert_eq!(logged.len(), 32);
+        assert_eq!(
+            logged[1],
+            Reply::bulk(&format!("{}... (2 more bytes)", "x".repeat(128)))
+        );
+        assert_eq!(logged[31], Reply::bulk("... (9 more arguments)"));
+    }
+}
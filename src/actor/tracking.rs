@@ -0,0 +1,150 @@
+use std::{collections::HashMap, sync::mpsc::Sender};
+
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{
+    actor::{pubsub::PubSub, ConnectionID, ConnectionMessage},
+    connection::reply::Reply,
+};
+
+/// Channel invalidation messages are published on for redirected connections.
+const INVALIDATE_CHANNEL: &[u8] = b"__redis__:invalidate";
+
+/// Where the invalidation messages of a tracking connection are sent.
+enum Target {
+    /// Pushed on the tracking connection itself.
+    Push(Sender<ConnectionMessage>),
+    /// Published to another connection, subscribed to `__redis__:invalidate`.
+    Redirect(ConnectionID),
+}
+
+/// Server assisted client side caching, in its default mode: connections with tracking enabled
+/// get an invalidation message the first time a key they read is modified.
+#[derive(Default)]
+pub struct Tracking {
+    clients: IndexMap<ConnectionID, Target>,
+    /// Connections to notify on the next modification of each key, by database.
+    keys: HashMap<(usize, String), IndexSet<ConnectionID>>,
+}
+
+impl Tracking {
+    /// Enable tracking for a connection, redirecting its invalidation messages to another
+    /// connection when `redirect` is set.
+    pub fn enable(
+        &mut self,
+        connection_id: &str,
+        tx: &Sender<ConnectionMessage>,
+        redirect: Option<ConnectionID>,
+    ) {
+        let target = match redirect {
+            Some(redirect) => Target::Redirect(redirect),
+            None => Target::Push(tx.clone()),
+        };
+        self.clients.insert(connection_id.to_owned(), target);
+    }
+
+    /// Disable tracking for a connection, forgetting the keys it read.
+    pub fn disable(&mut self, connection_id: &str) {
+        if self.clients.shift_remove(connection_id).is_none() {
+            return;
+        }
+        self.keys.retain(|_, clients| {
+            clients.shift_remove(connection_id);
+            !clients.is_empty()
+        });
+    }
+
+    /// Remember that a connection read `keys` of database `db`, when it has tracking enabled.
+    pub fn track(&mut self, connection_id: &str, db: usize, keys: &[&String]) {
+        if !self.clients.contains_key(connection_id) {
+            return;
+        }
+        for key in keys {
+            self.keys
+                .entry((db, key.to_string()))
+                .or_default()
+                .insert(connection_id.to_owned());
+        }
+    }
+
+    /// Notify the connections that read `key` of database `db` of its modification. They will not
+    /// be notified again until they read it anew.
+    pub fn invalidate(&mut self, db: usize, key: &str, pubsub: &mut PubSub) {
+        let Some(connection_ids) = self.keys.remove(&(db, key.to_owned())) else {
+            return;
+        };
+        let keys = Reply::Array(vec![Reply::bulk(key)]);
+        for connection_id in connection_ids {
+            match self.clients.get(&connection_id) {
+                Some(Target::Push(tx)) => {
+                    let message = Reply::Push(vec![Reply::bulk("invalidate"), keys.clone()]);
+                    let _ = tx.send(ConnectionMessage::SendReply(message));
+                }
+                Some(Target::Redirect(redirect)) => {
+                    pubsub.send_message(redirect, INVALIDATE_CHANNEL, keys.clone());
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Drop the tracking state of a closed connection.
+    pub fn remove(&mut self, connection_id: &str) {
+        self.disable(connection_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use crate::{
+        actor::{pubsub::PubSub, ConnectionMessage},
+        connection::reply::Reply,
+    };
+
+    use super::Tracking;
+
+    #[test]
+    fn keys_are_invalidated_once() {
+        let mut tracking = Tracking::default();
+        let mut pubsub = PubSub::default();
+        let (tx, rx) = channel();
+        let key = String::from("foo");
+
+        tracking.enable("client", &tx, None);
+        tracking.track("client", 0, &[&key]);
+        tracking.invalidate(1, "foo", &mut pubsub);
+        tracking.invalidate(0, "foo", &mut pubsub);
+        tracking.invalidate(0, "foo", &mut pubsub);
+
+        let messages: Vec<ConnectionMessage> = rx.try_iter().collect();
+        assert_eq!(messages.len(), 1);
+        let ConnectionMessage::SendReply(reply) = &messages[0] else {
+            panic!("Expected an invalidation message");
+        };
+        assert_eq!(
+            reply,
+            &Reply::Push(vec![
+                Reply::bulk("invalidate"),
+                Reply::Array(vec![Reply::bulk("foo")])
+            ])
+        );
+    }
+
+    #[test]
+    fn keys_are_not_tracked_once_disabled() {
+        let mut tracking = Tracking::default();
+        let mut pubsub = PubSub::default();
+        let (tx, rx) = channel();
+        let key = String::from("foo");
+
+        tracking.track("client", 0, &[&key]);
+        tracking.enable("client", &tx, None);
+        tracking.disable("client");
+        tracking.track("client", 0, &[&key]);
+        tracking.invalidate(0, "foo", &mut pubsub);
+
+        assert!(rx.try_recv().is_err());
+    }
+}
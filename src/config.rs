@@ -1,19 +1,267 @@
-use std::{collections::HashMap, env};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    error::Error,
+    fmt, fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
 
-type Args = HashMap<String, String>;
+use itertools::Itertools;
+
+use crate::store::DEFAULT_DATABASES;
+
+pub type Args = HashMap<String, String>;
+
+/// Directives only read when the server starts, which `CONFIG SET` refuses to change.
+const STARTUP_DIRECTIVES: [&str; 10] = [
+    "port",
+    "bind",
+    "protected-mode",
+    "replicaof",
+    "databases",
+    "proto-max-bulk-len",
+    "tcp-backlog",
+    "tcp-nodelay",
+    "repl-backlog-size",
+    "max-pipeline-commands",
+];
 
 #[derive(Clone)]
 pub struct Config {
     pub port: i32,
+    pub bind: String,
+    /// Refuse connections from non-loopback addresses when no password nor `bind` address is set.
+    pub protected_mode: bool,
     pub replication: Replication,
-    pub dbfile: Option<DBFile>,
+    pub dbfile: DBFile,
+    /// Path of the config file the server was started with, if any.
+    pub config_file: Option<String>,
+    pub appendonly: bool,
+    pub requirepass: Option<String>,
+    /// Memory limit in bytes, 0 meaning no limit.
+    pub maxmemory: usize,
+    /// Automatic RDB snapshots rules, from the `save` directive.
+    pub save_points: Vec<SavePoint>,
+    /// Largest bulk string accepted from clients, in bytes.
+    pub proto_max_bulk_len: usize,
+    /// Execution time, in microseconds, above which commands are logged in the slow log. A
+    /// negative value disables the slow log.
+    pub slowlog_log_slower_than: i64,
+    /// Number of entries kept in the slow log.
+    pub slowlog_max_len: usize,
+    /// Number of databases, selected with `SELECT`.
+    pub databases: usize,
+    /// Size of the queue of connections waiting to be accepted.
+    pub tcp_backlog: u32,
+    /// Whether `TCP_NODELAY` is set on client connections, sending small replies at once.
+    pub tcp_nodelay: bool,
+    /// Size of the replication backlog, in bytes, kept for replicas to partially resynchronize.
+    pub repl_backlog_size: usize,
+    /// Commands of a client pipeline handed to the store per poll of its connection.
+    pub max_pipeline_commands: usize,
     args: Args,
 }
 
 impl Config {
+    pub fn from_args(args: Args) -> Config {
+        let port = args
+            .get("port")
+            .map_or(6379, |value| value.parse::<i32>().unwrap_or(6379));
+
+        let bind = args
+            .get("bind")
+            .cloned()
+            .unwrap_or(String::from("127.0.0.1"));
+        let protected_mode = args.get("protected-mode").is_none_or(|value| value != "no");
+
+        let dbfile = dbfile_config(&args);
+
+        let replication_role = match args.get("replicaof") {
+            Some(url) => {
+                if let (Some(host), Some(port)) = (url.split(" ").next(), url.split(" ").nth(1)) {
+                    ReplicationRole::Replica((host.to_string(), port.to_string()))
+                } else {
+                    ReplicationRole::Master
+                }
+            }
+            None => ReplicationRole::Master,
+        };
+        println!("Replication role: {replication_role:?}");
+
+        let replication = Replication {
+            role: replication_role,
+            repl_offset: 0,
+            replid: String::from("8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb"),
+            replid2: "0".repeat(40),
+            second_repl_offset: None,
+        };
+
+        Config {
+            port,
+            bind,
+            protected_mode,
+            dbfile,
+            replication,
+            config_file: args.get("config-file").cloned(),
+            appendonly: args.get("appendonly").is_some_and(|value| value == "yes"),
+            requirepass: args
+                .get("requirepass")
+                .filter(|password| !password.is_empty())
+                .cloned(),
+            maxmemory: args
+                .get("maxmemory")
+                .and_then(|value| parse_memory(value))
+                .unwrap_or(0),
+            save_points: args
+                .get("save")
+                .map(|value| parse_save_points(value))
+                .unwrap_or_default(),
+            proto_max_bulk_len: args
+                .get("proto-max-bulk-len")
+                .and_then(|value| parse_memory(value))
+                .unwrap_or(512 * 1024 * 1024),
+            slowlog_log_slower_than: args
+                .get("slowlog-log-slower-than")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(10_000),
+            slowlog_max_len: args
+                .get("slowlog-max-len")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(128),
+            databases: args
+                .get("databases")
+                .and_then(|value| value.parse().ok())
+                .filter(|databases| *databases > 0)
+                .unwrap_or(DEFAULT_DATABASES),
+            tcp_backlog: args
+                .get("tcp-backlog")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(511),
+            tcp_nodelay: args.get("tcp-nodelay").is_none_or(|value| value != "no"),
+            repl_backlog_size: args
+                .get("repl-backlog-size")
+                .and_then(|value| parse_memory(value))
+                .unwrap_or(1024 * 1024),
+            max_pipeline_commands: args
+                .get("max-pipeline-commands")
+                .and_then(|value| value.parse().ok())
+                .filter(|max| *max > 0)
+                .unwrap_or(1024),
+            args,
+        }
+    }
+
     pub fn get_arg(&self, key: &str) -> Option<String> {
         self.args.get(key).cloned()
     }
+
+    /// Path RDB snapshots are loaded from and written to, `dump.rdb` in the working directory by
+    /// default.
+    pub fn dbfile_path(&self) -> PathBuf {
+        Path::new(&self.dbfile.dir).join(&self.dbfile.dbfilename)
+    }
+
+    /// Change a directive at runtime, as done by `CONFIG SET`. The [`STARTUP_DIRECTIVES`] are
+    /// refused.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigSetError> {
+        let invalid = || ConfigSetError::InvalidValue(key.to_owned(), value.to_owned());
+        match key {
+            "dir" => self.dbfile.dir = value.to_owned(),
+            "dbfilename" => self.dbfile.dbfilename = value.to_owned(),
+            "appendonly" => {
+                self.appendonly = match value {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return Err(invalid()),
+                }
+            }
+            "requirepass" => {
+                self.requirepass = Some(value.to_owned()).filter(|password| !password.is_empty())
+            }
+            "maxmemory" => self.maxmemory = parse_memory(value).ok_or_else(invalid)?,
+            "save" => {
+                let save_points = parse_save_points(value);
+                if save_points.len() * 2 != value.split_whitespace().count() {
+                    return Err(invalid());
+                }
+                self.save_points = save_points;
+            }
+            "slowlog-log-slower-than" => {
+                self.slowlog_log_slower_than = value.parse().map_err(|_| invalid())?
+            }
+            "slowlog-max-len" => self.slowlog_max_len = value.parse().map_err(|_| invalid())?,
+            _ if STARTUP_DIRECTIVES.contains(&key) => {
+                return Err(ConfigSetError::Immutable(key.to_owned()))
+            }
+            _ => return Err(ConfigSetError::Unknown(key.to_owned())),
+        }
+        self.args.insert(key.to_owned(), value.to_owned());
+        Ok(())
+    }
+
+    /// Write the effective configuration back to the config file the server was started with.
+    /// Comments and lines already matching the configuration are kept as they are, changed
+    /// directives are rewritten in place and new ones appended.
+    pub fn rewrite(&self) -> Result<(), RewriteError> {
+        let Some(path) = &self.config_file else {
+            return Err(RewriteError::NoConfigFile);
+        };
+        let current = match fs::read_to_string(path) {
+            Ok(current) => current,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(RewriteError::Io(err)),
+        };
+
+        // As in Redis, directives left to their default are not written, an explicit `bind` for
+        // instance disabling protected mode
+        let mut effective: BTreeMap<&str, &str> = self
+            .args
+            .iter()
+            .filter(|(key, _)| *key != "config-file")
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+
+        let format_directive = |key: &str, value: &str| match value {
+            "" => format!("{key} \"\"\n"),
+            value => format!("{key} {value}\n"),
+        };
+        let mut rewritten = HashSet::new();
+        let mut content = String::new();
+        for line in current.lines() {
+            let Some((key, value)) = parse_config_line(line) else {
+                content.push_str(line);
+                content.push('\n');
+                continue;
+            };
+            match effective.remove(key.as_str()) {
+                Some(effective) if *effective == value => {
+                    content.push_str(line);
+                    content.push('\n');
+                }
+                Some(effective) => content.push_str(&format_directive(&key, effective)),
+                // Directive repeated in the file, such as `save`, already rewritten on its first line
+                None if rewritten.contains(&key) => continue,
+                None => {
+                    content.push_str(line);
+                    content.push('\n');
+                }
+            }
+            rewritten.insert(key);
+        }
+        for (key, value) in &effective {
+            content.push_str(&format_directive(key, value));
+        }
+        fs::write(path, content).map_err(RewriteError::Io)
+    }
+
+    /// Whether a client connecting from `addr` must be refused because of protected mode.
+    pub fn is_protected_from(&self, addr: &IpAddr) -> bool {
+        self.protected_mode
+            && self.requirepass.is_none()
+            && !self.args.contains_key("bind")
+            && !addr.is_loopback()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -27,6 +275,11 @@ pub struct Replication {
     pub role: ReplicationRole,
     pub replid: String,
     pub repl_offset: usize,
+    /// Previous replication id, still accepted for partial resynchronizations up to
+    /// `second_repl_offset` after the id changed.
+    pub replid2: String,
+    /// Offset up to which `replid2` is valid, None when there was no previous id.
+    pub second_repl_offset: Option<usize>,
 }
 
 #[derive(Clone)]
@@ -35,48 +288,159 @@ pub struct DBFile {
     pub dbfilename: String,
 }
 
-pub fn parse_config() -> Config {
-    let args = parse_args();
+/// Save the dataset once at least `changes` writes happened and `seconds` elapsed since the last
+/// save.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavePoint {
+    pub seconds: u64,
+    pub changes: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigSetError {
+    Unknown(String),
+    /// Directive only read when the server starts.
+    Immutable(String),
+    InvalidValue(String, String),
+}
+impl Error for ConfigSetError {}
 
-    let port = args
-        .get("port")
-        .map_or(6379, |value| value.parse::<i32>().unwrap_or(6379));
+impl fmt::Display for ConfigSetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigSetError::Unknown(key) => write!(
+                f,
+                "ERR Unknown option or number of arguments for CONFIG SET - '{key}'"
+            ),
+            ConfigSetError::Immutable(key) => write!(
+                f,
+                "ERR CONFIG SET failed (possibly related to argument '{key}') - can't set immutable config"
+            ),
+            ConfigSetError::InvalidValue(key, value) => write!(
+                f,
+                "ERR CONFIG SET failed (possibly related to argument '{key}') - Invalid argument '{value}'"
+            ),
+        }
+    }
+}
 
-    let dbfile = dbfile_config(&args);
+#[derive(Debug)]
+pub enum RewriteError {
+    NoConfigFile,
+    Io(io::Error),
+}
+impl Error for RewriteError {}
 
-    let replication_role = match args.get("replicaof") {
-        Some(url) => {
-            if let (Some(host), Some(port)) = (url.split(" ").next(), url.split(" ").nth(1)) {
-                ReplicationRole::Replica((host.to_string(), port.to_string()))
-            } else {
-                ReplicationRole::Master
+impl fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RewriteError::NoConfigFile => {
+                write!(f, "ERR The server is running without a config file")
             }
+            RewriteError::Io(err) => write!(f, "ERR Rewriting config file: {err}"),
         }
-        None => ReplicationRole::Master,
+    }
+}
+
+/// Build the config from the config file given by `--config-file`, if any, overridden by the other
+/// CLI arguments.
+pub fn parse_config() -> Config {
+    let cli_args = parse_args();
+    let mut args = match cli_args.get("config-file") {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(content) => parse_config_file(&content),
+            Err(err) => {
+                println!("Cannot read config file {path}: {err}");
+                Args::new()
+            }
+        },
+        None => Args::new(),
     };
-    println!("Replication role: {replication_role:?}");
+    args.extend(cli_args);
+    Config::from_args(args)
+}
 
-    let replication = Replication {
-        role: replication_role,
-        repl_offset: 0,
-        replid: String::from("8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb"),
+/// Parse a memory amount such as `100mb` or `1g` into bytes, using redis.conf units.
+fn parse_memory(value: &str) -> Option<usize> {
+    let value = value.to_lowercase();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
     };
+    amount.parse::<usize>().ok()?.checked_mul(multiplier)
+}
 
-    Config {
-        port,
-        dbfile,
-        replication,
-        args,
+/// Parse `save` values such as `3600 1 300 100`, made of `<seconds> <changes>` pairs.
+fn parse_save_points(value: &str) -> Vec<SavePoint> {
+    value
+        .split_whitespace()
+        .tuples()
+        .filter_map(|(seconds, changes)| {
+            Some(SavePoint {
+                seconds: seconds.parse().ok()?,
+                changes: changes.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Parse a `directive arg1 arg2` line, `None` for blank lines and `#` comments. Arguments are kept
+/// together as a single value.
+fn parse_config_line(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
     }
+    let (key, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+    Some((key.to_lowercase(), value.to_string()))
+}
+
+fn parse_config_file(content: &str) -> Args {
+    content
+        .lines()
+        .filter_map(parse_config_line)
+        .fold(Args::new(), |mut args, (key, value)| {
+            // Each `save` line adds save points to the previous ones
+            match args.get_mut(&key) {
+                Some(points) if key == "save" && !points.is_empty() && !value.is_empty() => {
+                    points.push(' ');
+                    points.push_str(&value);
+                }
+                _ => {
+                    args.insert(key, value);
+                }
+            }
+            args
+        })
 }
 
 fn parse_args() -> Args {
-    let mut args_iter = env::args();
+    let mut args_iter = env::args().peekable();
     let mut args: Args = HashMap::new();
 
     // Drop first args, see `env::args()`
     let _ = args_iter.next();
 
+    // Like redis-server, the config file can be given as the first positional argument
+    if let Some(path) = args_iter.next_if(|arg| !arg.starts_with("--")) {
+        args.insert(String::from("config-file"), path);
+    }
+
     while let (Some(cmd), Some(param)) = (args_iter.next(), args_iter.next()) {
         let (prefix, cmd) = cmd.split_at(2);
         if prefix == "--" {
@@ -87,12 +451,189 @@ fn parse_args() -> Args {
     args
 }
 
-fn dbfile_config(args: &Args) -> Option<DBFile> {
-    if let (Some(dir), Some(dbfilename)) = (args.get("dir"), args.get("dbfilename")) {
-        return Some(DBFile {
-            dir: dir.clone(),
-            dbfilename: dbfilename.clone(),
-        });
+fn dbfile_config(args: &Args) -> DBFile {
+    DBFile {
+        dir: args.get("dir").cloned().unwrap_or(String::from(".")),
+        dbfilename: args
+            .get("dbfilename")
+            .cloned()
+            .unwrap_or(String::from("dump.rdb")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        path::PathBuf,
+    };
+
+    use super::{
+        parse_config_file, parse_memory, Args, Config, ConfigSetError, ReplicationRole,
+        RewriteError, SavePoint,
+    };
+
+    #[test]
+    fn protected_mode_refuses_remote_clients_without_password() {
+        let remote = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10));
+        let local = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let config = Config::from_args(Args::new());
+        assert_eq!(config.bind, "127.0.0.1");
+        assert!(config.is_protected_from(&remote));
+        assert!(!config.is_protected_from(&local));
+
+        let config = Config::from_args(Args::from([(
+            String::from("protected-mode"),
+            String::from("no"),
+        )]));
+        assert!(!config.is_protected_from(&remote));
+
+        let config = Config::from_args(Args::from([(
+            String::from("requirepass"),
+            String::from("secret"),
+        )]));
+        assert!(!config.is_protected_from(&remote));
+
+        let config = Config::from_args(Args::from([(
+            String::from("bind"),
+            String::from("0.0.0.0"),
+        )]));
+        assert!(!config.is_protected_from(&remote));
+    }
+
+    #[test]
+    fn parse_config_file_lines() {
+        let args = parse_config_file(
+            "# comment\n\nport 6380\nreplicaof localhost 6379\nrequirepass \"\"\n",
+        );
+        assert_eq!(
+            args,
+            Args::from([
+                (String::from("port"), String::from("6380")),
+                (String::from("replicaof"), String::from("localhost 6379")),
+                (String::from("requirepass"), String::from("")),
+            ])
+        );
+    }
+
+    #[test]
+    fn known_directives_are_mapped() {
+        let config = Config::from_args(parse_config_file(
+            "dir /data\ndbfilename dump.rdb\nappendonly yes\nrequirepass secret\nmaxmemory 100mb\nreplicaof localhost 6379\nproto-max-bulk-len 1mb\n",
+        ));
+        assert_eq!(config.dbfile_path(), PathBuf::from("/data/dump.rdb"));
+        assert!(config.appendonly);
+        assert_eq!(config.requirepass, Some(String::from("secret")));
+        assert_eq!(config.maxmemory, 100 * 1024 * 1024);
+        assert_eq!(config.proto_max_bulk_len, 1024 * 1024);
+        assert_eq!(
+            config.replication.role,
+            ReplicationRole::Replica((String::from("localhost"), String::from("6379")))
+        );
+        assert_eq!(parse_memory("1k"), Some(1000));
+        assert_eq!(parse_memory("12"), Some(12));
+        assert_eq!(parse_memory("1tb"), None);
+    }
+
+    #[test]
+    fn save_points() {
+        let config = Config::from_args(parse_config_file("save 3600 1\nsave 300 100 60 10000\n"));
+        assert_eq!(
+            config.save_points,
+            vec![
+                SavePoint {
+                    seconds: 3600,
+                    changes: 1
+                },
+                SavePoint {
+                    seconds: 300,
+                    changes: 100
+                },
+                SavePoint {
+                    seconds: 60,
+                    changes: 10000
+                },
+            ]
+        );
+
+        let config = Config::from_args(parse_config_file("save 3600 1\nsave \"\"\n"));
+        assert!(config.save_points.is_empty());
+    }
+
+    #[test]
+    fn rewrite_config_file() {
+        let config = Config::from_args(Args::new());
+        assert!(matches!(config.rewrite(), Err(RewriteError::NoConfigFile)));
+
+        let path = std::env::temp_dir().join(format!("redis-rewrite-{}.conf", std::process::id()));
+        std::fs::write(
+            &path,
+            "# Server\nport 6380\nsave 3600 1\nsave 300 100\n\n# Storage\ndir /data\nunknown-directive  kept\n",
+        )
+        .unwrap();
+        let mut args = parse_config_file(&std::fs::read_to_string(&path).unwrap());
+        args.insert(
+            String::from("config-file"),
+            path.to_string_lossy().to_string(),
+        );
+        let mut config = Config::from_args(args);
+        config.set("dir", "/tmp/redis").unwrap();
+        config.set("maxmemory", "100mb").unwrap();
+        config.rewrite().unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            content,
+            "# Server\nport 6380\nsave 3600 1 300 100\n\n# Storage\ndir /tmp/redis\nunknown-directive  kept\nmaxmemory 100mb\n"
+        );
+        let args = parse_config_file(&content);
+        assert_eq!(args.get("bind"), None);
+        assert_eq!(args.get("config-file"), None);
+    }
+
+    #[test]
+    fn set_runtime_directives() {
+        let mut config = Config::from_args(Args::new());
+
+        config.set("requirepass", "secret").unwrap();
+        config.set("save", "60 10").unwrap();
+        config.set("slowlog-max-len", "3").unwrap();
+        assert_eq!(config.requirepass, Some(String::from("secret")));
+        assert_eq!(
+            config.save_points,
+            vec![SavePoint {
+                seconds: 60,
+                changes: 10
+            }]
+        );
+        assert_eq!(config.slowlog_max_len, 3);
+        assert_eq!(config.get_arg("slowlog-max-len"), Some(String::from("3")));
+
+        assert_eq!(
+            config.set("port", "6380"),
+            Err(ConfigSetError::Immutable(String::from("port")))
+        );
+        assert_eq!(
+            config.set("maxmemory", "lots"),
+            Err(ConfigSetError::InvalidValue(
+                String::from("maxmemory"),
+                String::from("lots")
+            ))
+        );
+        assert_eq!(
+            config.set("save", "60"),
+            Err(ConfigSetError::InvalidValue(
+                String::from("save"),
+                String::from("60")
+            ))
+        );
+        assert_eq!(
+            config.set("foo", "bar"),
+            Err(ConfigSetError::Unknown(String::from("foo")))
+        );
+        assert_eq!(config.port, 6379);
+        assert_eq!(config.maxmemory, 0);
     }
-    None
 }
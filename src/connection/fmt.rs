@@ -1,4 +1,9 @@
-use crate::store::stream::{Stream, StreamEntry};
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+
+use crate::store::stream::StreamEntry;
+
+use super::reply::Reply;
 
 pub fn format_string(value: Option<String>) -> String {
     if let Some(value) = value {
@@ -20,25 +25,71 @@ pub fn format_array(values: &Vec<String>) -> String {
     response
 }
 
-pub fn format_stream(stream: &Stream) -> String {
-    let mut response = format!("*{}\r\n", stream.len());
-    for entry in stream {
-        response.push_str(&format_stream_entry(entry));
+/// Array of bulk strings holding raw command arguments, as sent to replicas.
+pub fn command_reply(args: &[Vec<u8>]) -> Reply {
+    Reply::Array(
+        args.iter()
+            .map(|arg| Reply::Bulk(Some(arg.clone())))
+            .collect(),
+    )
+}
+
+/// Usage lines of a container command, in reply to its `HELP` subcommand. `subcommands` describe
+/// every subcommand but `HELP`, which is appended.
+pub fn help_reply(command: &str, subcommands: &[&str]) -> Reply {
+    let header = format!("{command} <subcommand> [<arg> [value] [opt] ...]. Subcommands are:");
+    let lines = [header.as_str()]
+        .into_iter()
+        .chain(subcommands.iter().copied())
+        .chain(["HELP", "    Print this help."]);
+    Reply::Array(lines.map(|line| Reply::Simple(line.to_owned())).collect())
+}
+
+/// Line sent to MONITOR clients for a processed command, e.g.
+/// `+1339518083.107412 [0 127.0.0.1:60866] "keys" "*"`.
+pub fn monitor_reply(timestamp: DateTime<Utc>, client: &str, args: &[Vec<u8>]) -> Reply {
+    let args = args.iter().map(|arg| quoted(arg)).join(" ");
+    Reply::Simple(format!(
+        "{}.{:06} [0 {client}] {args}",
+        timestamp.timestamp(),
+        timestamp.timestamp_subsec_micros()
+    ))
+}
+
+/// Quote `value`, escaping non printable characters like Redis does.
+fn quoted(value: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for &byte in value {
+        match byte {
+            b'\\' | b'"' => quoted.extend(['\\', byte as char]),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            byte if byte.is_ascii_graphic() || byte == b' ' => quoted.push(byte as char),
+            byte => quoted.push_str(&format!("\\x{byte:02x}")),
+        }
     }
-    response
+    quoted.push('"');
+    quoted
+}
+
+pub fn stream_reply<'a>(entries: impl IntoIterator<Item = &'a StreamEntry>) -> Reply {
+    Reply::Array(entries.into_iter().map(stream_entry_reply).collect())
 }
 
-pub fn format_stream_entry(entry: &StreamEntry) -> String {
-    let entry_id = format_string(Some(entry.id.to_string()));
-    let entry_values: Vec<String> = entry
+pub fn stream_entry_reply(entry: &StreamEntry) -> Reply {
+    let entry_values: Vec<Reply> = entry
         .values
         .iter()
-        .flat_map(|(k, v)| vec![[k.clone(), v.clone()]])
-        .flatten()
+        .flat_map(|(k, v)| [Reply::bulk(k), Reply::bulk(v)])
         .collect();
-    let entries = format_array(&entry_values);
 
-    format!("*2\r\n{entry_id}{entries}")
+    Reply::Array(vec![
+        Reply::bulk(&entry.id.to_string()),
+        Reply::Array(entry_values),
+    ])
 }
 
 #[cfg(test)]
@@ -46,7 +97,7 @@ mod tests {
 
     use indexmap::IndexMap;
 
-    use crate::store::stream::StreamEntryId;
+    use crate::{connection::reply::Protocol, store::stream::StreamEntryId};
 
     use super::*;
 
@@ -80,6 +131,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_help_reply() {
+        assert_eq!(
+            help_reply("DEBUG", &["RELOAD", "    Reload the dataset."]).encode(Protocol::RESP2),
+            b"*5\r\n+DEBUG <subcommand> [<arg> [value] [opt] ...]. Subcommands are:\r\n+RELOAD\r\n+    Reload the dataset.\r\n+HELP\r\n+    Print this help.\r\n"
+        );
+    }
+
+    #[test]
+    fn test_monitor_reply() {
+        let timestamp = DateTime::from_timestamp(1339518083, 107_412_000).unwrap();
+        let args = vec![b"SET".to_vec(), b"a \"b\"".to_vec(), b"\r\n\x01".to_vec()];
+        assert_eq!(
+            monitor_reply(timestamp, "127.0.0.1:60866", &args),
+            Reply::Simple(String::from(
+                r#"1339518083.107412 [0 127.0.0.1:60866] "SET" "a \"b\"" "\r\n\x01""#
+            ))
+        );
+    }
+
     #[test]
     fn test_format_stream_entry() {
         let entry = StreamEntry {
@@ -94,7 +165,10 @@ mod tests {
         };
 
         let expected = "*2\r\n$15\r\n1526985054069-0\r\n*4\r\n$11\r\ntemperature\r\n$2\r\n36\r\n$8\r\nhumidity\r\n$2\r\n95\r\n".to_owned();
-        assert_eq!(format_stream_entry(&entry), expected);
+        assert_eq!(
+            stream_entry_reply(&entry).encode(Protocol::RESP2),
+            expected.into_bytes()
+        );
     }
 
     #[test]
@@ -123,6 +197,9 @@ mod tests {
 
         let expected = "*2\r\n*2\r\n$15\r\n1526985054069-0\r\n*4\r\n$11\r\ntemperature\r\n$2\r\n36\r\n$8\r\nhumidity\r\n$2\r\n95\r\n*2\r\n$15\r\n1526985054079-0\r\n*4\r\n$11\r\ntemperature\r\n$2\r\n37\r\n$8\r\nhumidity\r\n$2\r\n94\r\n".to_owned();
 
-        assert_eq!(format_stream(&stream), expected);
+        assert_eq!(
+            stream_reply(&stream).encode(Protocol::RESP2),
+            expected.into_bytes()
+        );
     }
 }
@@ -1,4 +1,5 @@
 use std::{
+    collections::VecDeque,
     net::TcpStream,
     sync::mpsc::{channel, Receiver, Sender},
 };
@@ -7,11 +8,16 @@ use uuid::Uuid;
 
 use crate::{
     actor::{ConnectionID, ConnectionMessage, StoreMessage},
-    connection::stream::RedisStream,
+    connection::{
+        parser::BufferType,
+        reply::{Protocol, Reply},
+        stream::RedisStream,
+    },
 };
 
 pub mod fmt;
 pub mod parser;
+pub mod reply;
 pub mod stream;
 
 pub struct Connection {
@@ -20,37 +26,84 @@ pub struct Connection {
     tx: Sender<ConnectionMessage>,
     rx: Receiver<ConnectionMessage>,
     connection_id: ConnectionID,
+    protocol: Protocol,
+    closed: bool,
+    /// Messages read but not yet handed to the store.
+    pending: VecDeque<BufferType>,
+    max_pipeline_commands: usize,
 }
 
 impl Connection {
     pub fn new(stream: RedisStream<TcpStream>, tx_store: Sender<StoreMessage>) -> Connection {
         let (tx, rx) = channel();
         let connection_id = Uuid::new_v4().to_string();
+        if let Some(addr) = stream.peer_addr() {
+            let _ = tx_store.send(StoreMessage::ConnectionOpened {
+                connection_id: connection_id.clone(),
+                addr,
+            });
+        }
         Connection {
             stream,
             tx_store,
             tx,
             rx,
             connection_id,
+            protocol: Protocol::default(),
+            closed: false,
+            pending: VecDeque::new(),
+            max_pipeline_commands: usize::MAX,
         }
     }
 
+    /// Hand at most `max` messages to the store per poll, so that a client pipelining many
+    /// commands does not starve the other connections nor flood the store queue.
+    pub fn with_max_pipeline_commands(mut self, max: usize) -> Connection {
+        self.max_pipeline_commands = max;
+        self
+    }
+
     pub fn get_tx(&self) -> Sender<ConnectionMessage> {
         self.tx.clone()
     }
 
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
     pub fn poll(&mut self) {
-        if let Some(messages) = self.stream.read() {
-            for msg in messages {
-                println!("Received message: {msg:?}");
-                self.tx_store
-                    .send(StoreMessage::NewBuffer {
-                        value: msg,
-                        tx_back: self.tx.clone(),
-                        connection_id: self.connection_id.clone(),
-                    })
-                    .unwrap();
+        if self.closed {
+            return;
+        }
+
+        // Nothing more is read until the pending messages are handed over, leaving the rest of
+        // the pipeline in the socket buffers
+        if self.pending.is_empty() {
+            let Some(messages) = self.stream.read() else {
+                self.close();
+                return;
+            };
+            self.pending.extend(messages);
+        }
+        for _ in 0..self.max_pipeline_commands {
+            let Some(msg) = self.pending.pop_front() else {
+                break;
+            };
+            println!("Received message: {msg:?}");
+            if let BufferType::ProtocolError(err) = msg {
+                let reply = Reply::Error(err.to_string());
+                self.stream.send_bytes(&reply.encode(self.protocol));
+                self.stream.shutdown();
+                self.close();
+                return;
             }
+            self.tx_store
+                .send(StoreMessage::NewBuffer {
+                    value: msg,
+                    tx_back: self.tx.clone(),
+                    connection_id: self.connection_id.clone(),
+                })
+                .unwrap();
         }
 
         while let Ok(msg) = self.rx.try_recv() {
@@ -58,7 +111,133 @@ impl Connection {
             match msg {
                 ConnectionMessage::SendString(msg) => self.stream.send_string(&msg),
                 ConnectionMessage::SendBytes(bytes) => self.stream.send_bytes(&bytes),
+                ConnectionMessage::SendReply(reply) => {
+                    self.stream.send_bytes(&reply.encode(self.protocol))
+                }
+                ConnectionMessage::SetProtocol(protocol) => self.protocol = protocol,
+                ConnectionMessage::Close => {
+                    self.stream.shutdown();
+                    self.close();
+                    return;
+                }
             }
         }
     }
+
+    /// Hand messages read before the connection was created to the store, in order and as if
+    /// they had been received by `poll`.
+    pub fn forward(&self, messages: Vec<BufferType>) {
+        for msg in messages {
+            println!("Forwarding message: {msg:?}");
+            let _ = self.tx_store.send(StoreMessage::NewBuffer {
+                value: msg,
+                tx_back: self.tx.clone(),
+                connection_id: self.connection_id.clone(),
+            });
+        }
+    }
+
+    fn close(&mut self) {
+        println!("Closing connection {}", self.connection_id);
+        self.closed = true;
+        let _ = self.tx_store.send(StoreMessage::ConnectionClosed {
+            connection_id: self.connection_id.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        sync::mpsc::channel,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        actor::{master::MasterActor, StoreMessage},
+        config::{Args, Config},
+        connection::stream::RedisStream,
+        store::Store,
+    };
+
+    use super::Connection;
+
+    #[test]
+    fn unknown_frames_close_only_their_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let (tx_store, rx_store) = channel();
+        let mut connection = Connection::new(RedisStream::new(stream, usize::MAX), tx_store);
+
+        client.write_all(b"PING\r\n").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !connection.is_closed() && Instant::now() < deadline {
+            connection.poll();
+        }
+
+        assert!(connection.is_closed());
+        assert!(rx_store
+            .try_iter()
+            .any(|message| matches!(message, StoreMessage::ConnectionClosed { .. })));
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "-ERR Protocol error: unknown frame type\r\n");
+    }
+
+    #[test]
+    fn quit_replies_then_closes_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut actor = MasterActor::new(Store::new(), Config::from_args(Args::new()));
+        let mut connection = Connection::new(RedisStream::new(stream, usize::MAX), actor.get_tx());
+
+        client.write_all(b"*1\r\n$4\r\nQUIT\r\n").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !connection.is_closed() && Instant::now() < deadline {
+            connection.poll();
+            actor.poll();
+        }
+
+        assert!(connection.is_closed());
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "+OK\r\n");
+    }
+
+    #[test]
+    fn pipelines_are_handed_over_across_polls() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let (tx_store, rx_store) = channel();
+        let mut connection = Connection::new(RedisStream::new(stream, usize::MAX), tx_store)
+            .with_max_pipeline_commands(4);
+
+        client
+            .write_all(&b"*1\r\n$4\r\nPING\r\n".repeat(10))
+            .unwrap();
+        let mut handed_over = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while handed_over.iter().sum::<usize>() < 10 && Instant::now() < deadline {
+            connection.poll();
+            let count = rx_store
+                .try_iter()
+                .filter(|message| matches!(message, StoreMessage::NewBuffer { .. }))
+                .count();
+            if count > 0 {
+                handed_over.push(count);
+            }
+        }
+
+        assert_eq!(handed_over.iter().sum::<usize>(), 10);
+        assert!(handed_over.len() >= 3);
+        assert!(handed_over.iter().all(|count| *count <= 4));
+    }
 }
@@ -1,21 +1,105 @@
-use std::str::from_utf8;
-
-use itertools::Itertools;
+use std::{error::Error, fmt, str::from_utf8};
 
 #[derive(Debug, PartialEq)]
 pub enum BufferType {
-    String(String),
+    String(Vec<u8>),
     DBFile(Vec<u8>),
     Command(Command),
+    /// Command whose name is not supported, with its raw arguments (name included).
+    UnknownCommand(Vec<Vec<u8>>),
+    /// Top-level RESP integer, such as a stray reply on a replication link.
+    Integer(i64),
+    /// Top-level RESP3 double.
+    Double(f64),
+    /// Malformed input, always the last element parsed from a buffer. The connection must reply
+    /// with the error and be closed.
+    ProtocolError(ProtocolError),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProtocolError {
+    InvalidBulkLength,
+    InvalidMultibulkLength,
+    /// Frame starting with a byte other than the supported `+`, `$`, `:`, `,` and `*` prefixes.
+    UnknownFrameType,
+    /// Element of a command array that is not a bulk string, with its first byte.
+    ExpectedBulkString(u8),
+}
+impl Error for ProtocolError {}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            ProtocolError::InvalidBulkLength => "ERR Protocol error: invalid bulk length",
+            ProtocolError::InvalidMultibulkLength => "ERR Protocol error: invalid multibulk length",
+            ProtocolError::UnknownFrameType => "ERR Protocol error: unknown frame type",
+            ProtocolError::ExpectedBulkString(prefix) => {
+                return write!(
+                    f,
+                    "ERR Protocol error: expected '$', got '{}'",
+                    *prefix as char
+                );
+            }
+        };
+        write!(f, "{message}")
+    }
 }
 
+/// Default limit of the bulk strings length, see `Config::proto_max_bulk_len`.
+pub const DEFAULT_PROTO_MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Command {
     pub verb: CommandVerb,
+    /// Arguments decoded as text, used for command names, keys and options.
     pub cmd: Vec<String>,
+    /// Raw arguments, used wherever values must stay binary-safe.
+    pub raw: Vec<Vec<u8>>,
+    /// Number of bytes the command took on the wire.
+    pub received_bytes: usize,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+impl Command {
+    pub fn new(raw: Vec<Vec<u8>>) -> Option<Command> {
+        let cmd: Vec<String> = raw
+            .iter()
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        let verb = CommandVerb::try_from(cmd.first()?.clone()).ok()?;
+        // Size of the RESP array encoding, overridden by the parser with the actual bytes read
+        let received_bytes = format!("*{}\r\n", raw.len()).len()
+            + raw
+                .iter()
+                .map(|arg| format!("${}\r\n", arg.len()).len() + arg.len() + 2)
+                .sum::<usize>();
+        Some(Command {
+            verb,
+            cmd,
+            raw,
+            received_bytes,
+        })
+    }
+
+    /// Keys the command operates on, as given by the key specification of its verb. Commands with
+    /// movable keys report none.
+    pub fn keys(&self) -> Vec<&String> {
+        let meta = self.verb.metadata();
+        if meta.first_key <= 0 || meta.step <= 0 {
+            return Vec::new();
+        }
+        let last_key = if meta.last_key < 0 {
+            self.cmd.len() as i64 + meta.last_key
+        } else {
+            meta.last_key
+        };
+        (meta.first_key..=last_key)
+            .step_by(meta.step as usize)
+            .filter_map(|index| self.cmd.get(index as usize))
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum CommandVerb {
     PING,
     ECHO,
@@ -35,6 +119,62 @@ pub enum CommandVerb {
     MULTI,
     EXEC,
     DISCARD,
+    QUIT,
+    OBJECT,
+    WATCH,
+    UNWATCH,
+    GETDEL,
+    GETEX,
+    DEL,
+    EXPIRE,
+    PEXPIRE,
+    EXPIREAT,
+    PEXPIREAT,
+    PERSIST,
+    COMMAND,
+    UNLINK,
+    SUBSCRIBE,
+    UNSUBSCRIBE,
+    PSUBSCRIBE,
+    PUNSUBSCRIBE,
+    PUBLISH,
+    SAVE,
+    BGSAVE,
+    DEBUG,
+    INCRBY,
+    DECR,
+    DECRBY,
+    LCS,
+    BITPOS,
+    BITFIELD,
+    SSUBSCRIBE,
+    SUNSUBSCRIBE,
+    SPUBLISH,
+    XGROUP,
+    XREADGROUP,
+    XACK,
+    XPENDING,
+    XCLAIM,
+    XAUTOCLAIM,
+    APPEND,
+    SETRANGE,
+    GETRANGE,
+    PTTL,
+    RENAME,
+    COPY,
+    MOVE,
+    SLOWLOG,
+    SETBIT,
+    GETBIT,
+    MONITOR,
+    XDEL,
+    XLEN,
+    SELECT,
+    CLIENT,
+    HELLO,
+    AUTH,
+    EXISTS,
+    XTRIM,
 }
 
 impl TryFrom<String> for CommandVerb {
@@ -61,113 +201,470 @@ impl TryFrom<String> for CommandVerb {
             "MULTI" => Ok(Self::MULTI),
             "EXEC" => Ok(Self::EXEC),
             "DISCARD" => Ok(Self::DISCARD),
+            "QUIT" => Ok(Self::QUIT),
+            "OBJECT" => Ok(Self::OBJECT),
+            "WATCH" => Ok(Self::WATCH),
+            "UNWATCH" => Ok(Self::UNWATCH),
+            "GETDEL" => Ok(Self::GETDEL),
+            "GETEX" => Ok(Self::GETEX),
+            "DEL" => Ok(Self::DEL),
+            "EXPIRE" => Ok(Self::EXPIRE),
+            "PEXPIRE" => Ok(Self::PEXPIRE),
+            "EXPIREAT" => Ok(Self::EXPIREAT),
+            "PEXPIREAT" => Ok(Self::PEXPIREAT),
+            "PERSIST" => Ok(Self::PERSIST),
+            "COMMAND" => Ok(Self::COMMAND),
+            "UNLINK" => Ok(Self::UNLINK),
+            "SUBSCRIBE" => Ok(Self::SUBSCRIBE),
+            "UNSUBSCRIBE" => Ok(Self::UNSUBSCRIBE),
+            "PSUBSCRIBE" => Ok(Self::PSUBSCRIBE),
+            "PUNSUBSCRIBE" => Ok(Self::PUNSUBSCRIBE),
+            "PUBLISH" => Ok(Self::PUBLISH),
+            "SAVE" => Ok(Self::SAVE),
+            "BGSAVE" => Ok(Self::BGSAVE),
+            "DEBUG" => Ok(Self::DEBUG),
+            "INCRBY" => Ok(Self::INCRBY),
+            "DECR" => Ok(Self::DECR),
+            "DECRBY" => Ok(Self::DECRBY),
+            "LCS" => Ok(Self::LCS),
+            "BITPOS" => Ok(Self::BITPOS),
+            "BITFIELD" => Ok(Self::BITFIELD),
+            "SSUBSCRIBE" => Ok(Self::SSUBSCRIBE),
+            "SUNSUBSCRIBE" => Ok(Self::SUNSUBSCRIBE),
+            "SPUBLISH" => Ok(Self::SPUBLISH),
+            "XGROUP" => Ok(Self::XGROUP),
+            "XREADGROUP" => Ok(Self::XREADGROUP),
+            "XACK" => Ok(Self::XACK),
+            "XPENDING" => Ok(Self::XPENDING),
+            "XCLAIM" => Ok(Self::XCLAIM),
+            "XAUTOCLAIM" => Ok(Self::XAUTOCLAIM),
+            "APPEND" => Ok(Self::APPEND),
+            "SETRANGE" => Ok(Self::SETRANGE),
+            "GETRANGE" => Ok(Self::GETRANGE),
+            "PTTL" => Ok(Self::PTTL),
+            "RENAME" => Ok(Self::RENAME),
+            "COPY" => Ok(Self::COPY),
+            "MOVE" => Ok(Self::MOVE),
+            "SLOWLOG" => Ok(Self::SLOWLOG),
+            "SETBIT" => Ok(Self::SETBIT),
+            "GETBIT" => Ok(Self::GETBIT),
+            "MONITOR" => Ok(Self::MONITOR),
+            "XDEL" => Ok(Self::XDEL),
+            "XLEN" => Ok(Self::XLEN),
+            "SELECT" => Ok(Self::SELECT),
+            "CLIENT" => Ok(Self::CLIENT),
+            "HELLO" => Ok(Self::HELLO),
+            "AUTH" => Ok(Self::AUTH),
+            "EXISTS" => Ok(Self::EXISTS),
+            "XTRIM" => Ok(Self::XTRIM),
             _ => Err("Unsupported command verb"),
         }
     }
 }
 
+/// Static description of a command, as reported by `COMMAND INFO`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CommandMeta {
+    pub name: &'static str,
+    /// Exact number of arguments (command name included) when positive, minimum when negative.
+    pub arity: i64,
+    pub flags: &'static [&'static str],
+    pub first_key: i64,
+    pub last_key: i64,
+    pub step: i64,
+}
+
+impl CommandMeta {
+    const fn new(
+        name: &'static str,
+        arity: i64,
+        flags: &'static [&'static str],
+        (first_key, last_key, step): (i64, i64, i64),
+    ) -> CommandMeta {
+        CommandMeta {
+            name,
+            arity,
+            flags,
+            first_key,
+            last_key,
+            step,
+        }
+    }
+
+    /// Whether a command made of `argc` arguments (command name included) satisfies the arity.
+    pub fn accepts(&self, argc: usize) -> bool {
+        let argc = argc as i64;
+        if self.arity >= 0 {
+            argc == self.arity
+        } else {
+            argc >= -self.arity
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        self.flags.contains(&"write")
+    }
+}
+
+const PUBSUB_FLAGS: &[&str] = &["pubsub", "noscript", "loading", "stale"];
+
+impl CommandVerb {
+    pub fn metadata(&self) -> CommandMeta {
+        match self {
+            Self::PING => CommandMeta::new("ping", -1, &["fast"], (0, 0, 0)),
+            Self::ECHO => CommandMeta::new("echo", 2, &["fast"], (0, 0, 0)),
+            Self::SET => CommandMeta::new("set", -3, &["write", "denyoom"], (1, 1, 1)),
+            Self::GET => CommandMeta::new("get", 2, &["readonly", "fast"], (1, 1, 1)),
+            Self::TYPE => CommandMeta::new("type", 2, &["readonly", "fast"], (1, 1, 1)),
+            Self::XADD => CommandMeta::new("xadd", -5, &["write", "denyoom", "fast"], (1, 1, 1)),
+            Self::XRANGE => CommandMeta::new("xrange", -4, &["readonly"], (1, 1, 1)),
+            Self::XREAD => CommandMeta::new(
+                "xread",
+                -4,
+                &["readonly", "blocking", "movablekeys"],
+                (0, 0, 0),
+            ),
+            Self::CONFIG => CommandMeta::new("config", -2, &[], (0, 0, 0)),
+            Self::KEYS => CommandMeta::new("keys", 2, &["readonly"], (0, 0, 0)),
+            Self::INFO => CommandMeta::new("info", -1, &["loading", "stale"], (0, 0, 0)),
+            Self::REPLCONF => CommandMeta::new(
+                "replconf",
+                -1,
+                &["admin", "noscript", "loading", "stale"],
+                (0, 0, 0),
+            ),
+            Self::PSYNC => CommandMeta::new("psync", -3, &["admin", "noscript"], (0, 0, 0)),
+            Self::WAIT => CommandMeta::new("wait", 3, &[], (0, 0, 0)),
+            Self::INCR => CommandMeta::new("incr", 2, &["write", "denyoom", "fast"], (1, 1, 1)),
+            Self::MULTI => CommandMeta::new(
+                "multi",
+                1,
+                &["noscript", "loading", "stale", "fast"],
+                (0, 0, 0),
+            ),
+            Self::EXEC => CommandMeta::new("exec", 1, &["noscript", "loading", "stale"], (0, 0, 0)),
+            Self::DISCARD => CommandMeta::new(
+                "discard",
+                1,
+                &["noscript", "loading", "stale", "fast"],
+                (0, 0, 0),
+            ),
+            Self::QUIT => CommandMeta::new(
+                "quit",
+                -1,
+                &["noscript", "loading", "stale", "fast", "no_auth"],
+                (0, 0, 0),
+            ),
+            Self::OBJECT => CommandMeta::new("object", -2, &[], (0, 0, 0)),
+            Self::WATCH => CommandMeta::new(
+                "watch",
+                -2,
+                &["noscript", "loading", "stale", "fast"],
+                (1, -1, 1),
+            ),
+            Self::UNWATCH => CommandMeta::new(
+                "unwatch",
+                1,
+                &["noscript", "loading", "stale", "fast"],
+                (0, 0, 0),
+            ),
+            Self::GETDEL => CommandMeta::new("getdel", 2, &["write", "fast"], (1, 1, 1)),
+            Self::GETEX => CommandMeta::new("getex", -2, &["write", "fast"], (1, 1, 1)),
+            Self::DEL => CommandMeta::new("del", -2, &["write"], (1, -1, 1)),
+            Self::EXPIRE => CommandMeta::new("expire", -3, &["write", "fast"], (1, 1, 1)),
+            Self::PEXPIRE => CommandMeta::new("pexpire", -3, &["write", "fast"], (1, 1, 1)),
+            Self::EXPIREAT => CommandMeta::new("expireat", -3, &["write", "fast"], (1, 1, 1)),
+            Self::PEXPIREAT => CommandMeta::new("pexpireat", -3, &["write", "fast"], (1, 1, 1)),
+            Self::PERSIST => CommandMeta::new("persist", 2, &["write", "fast"], (1, 1, 1)),
+            Self::COMMAND => CommandMeta::new("command", -1, &["loading", "stale"], (0, 0, 0)),
+            Self::UNLINK => CommandMeta::new("unlink", -2, &["write", "fast"], (1, -1, 1)),
+            Self::SUBSCRIBE => CommandMeta::new("subscribe", -2, PUBSUB_FLAGS, (0, 0, 0)),
+            Self::UNSUBSCRIBE => CommandMeta::new("unsubscribe", -1, PUBSUB_FLAGS, (0, 0, 0)),
+            Self::PSUBSCRIBE => CommandMeta::new("psubscribe", -2, PUBSUB_FLAGS, (0, 0, 0)),
+            Self::PUNSUBSCRIBE => CommandMeta::new("punsubscribe", -1, PUBSUB_FLAGS, (0, 0, 0)),
+            Self::PUBLISH => CommandMeta::new(
+                "publish",
+                3,
+                &["pubsub", "loading", "stale", "fast"],
+                (0, 0, 0),
+            ),
+            Self::SAVE => CommandMeta::new(
+                "save",
+                1,
+                &["admin", "noscript", "no_async_loading", "no_multi"],
+                (0, 0, 0),
+            ),
+            Self::BGSAVE => CommandMeta::new(
+                "bgsave",
+                -1,
+                &["admin", "noscript", "no_async_loading"],
+                (0, 0, 0),
+            ),
+            Self::DEBUG => CommandMeta::new(
+                "debug",
+                -2,
+                &["admin", "noscript", "loading", "stale"],
+                (0, 0, 0),
+            ),
+            Self::INCRBY => CommandMeta::new("incrby", 3, &["write", "denyoom", "fast"], (1, 1, 1)),
+            Self::DECR => CommandMeta::new("decr", 2, &["write", "denyoom", "fast"], (1, 1, 1)),
+            Self::DECRBY => CommandMeta::new("decrby", 3, &["write", "denyoom", "fast"], (1, 1, 1)),
+            Self::LCS => CommandMeta::new("lcs", -3, &["readonly"], (1, 2, 1)),
+            Self::BITPOS => CommandMeta::new("bitpos", -3, &["readonly"], (1, 1, 1)),
+            Self::BITFIELD => CommandMeta::new("bitfield", -2, &["write", "denyoom"], (1, 1, 1)),
+            Self::SSUBSCRIBE => CommandMeta::new("ssubscribe", -2, PUBSUB_FLAGS, (1, -1, 1)),
+            Self::SUNSUBSCRIBE => CommandMeta::new("sunsubscribe", -1, PUBSUB_FLAGS, (1, -1, 1)),
+            Self::SPUBLISH => CommandMeta::new(
+                "spublish",
+                3,
+                &["pubsub", "loading", "stale", "fast"],
+                (1, 1, 1),
+            ),
+            Self::XGROUP => CommandMeta::new("xgroup", -2, &["write", "denyoom"], (2, 2, 1)),
+            Self::XREADGROUP => CommandMeta::new(
+                "xreadgroup",
+                -7,
+                &["write", "blocking", "movablekeys"],
+                (0, 0, 0),
+            ),
+            Self::XACK => CommandMeta::new("xack", -4, &["write", "fast"], (1, 1, 1)),
+            Self::XPENDING => CommandMeta::new("xpending", -3, &["readonly"], (1, 1, 1)),
+            Self::XCLAIM => CommandMeta::new("xclaim", -6, &["write", "fast"], (1, 1, 1)),
+            Self::XAUTOCLAIM => CommandMeta::new("xautoclaim", -6, &["write", "fast"], (1, 1, 1)),
+            Self::APPEND => CommandMeta::new("append", 3, &["write", "denyoom", "fast"], (1, 1, 1)),
+            Self::SETRANGE => CommandMeta::new("setrange", 4, &["write", "denyoom"], (1, 1, 1)),
+            Self::GETRANGE => CommandMeta::new("getrange", 4, &["readonly"], (1, 1, 1)),
+            Self::PTTL => CommandMeta::new("pttl", 2, &["readonly", "random", "fast"], (1, 1, 1)),
+            Self::RENAME => CommandMeta::new("rename", 3, &["write"], (1, 2, 1)),
+            Self::COPY => CommandMeta::new("copy", -3, &["write", "denyoom"], (1, 2, 1)),
+            Self::MOVE => CommandMeta::new("move", 3, &["write", "fast"], (1, 1, 1)),
+            Self::SLOWLOG => CommandMeta::new(
+                "slowlog",
+                -2,
+                &["admin", "random", "loading", "stale"],
+                (0, 0, 0),
+            ),
+            Self::SETBIT => CommandMeta::new("setbit", 4, &["write", "denyoom"], (1, 1, 1)),
+            Self::GETBIT => CommandMeta::new("getbit", 3, &["readonly", "fast"], (1, 1, 1)),
+            Self::MONITOR => CommandMeta::new(
+                "monitor",
+                1,
+                &["admin", "noscript", "loading", "stale"],
+                (0, 0, 0),
+            ),
+            Self::XDEL => CommandMeta::new("xdel", -3, &["write", "fast"], (1, 1, 1)),
+            Self::XLEN => CommandMeta::new("xlen", 2, &["readonly", "fast"], (1, 1, 1)),
+            Self::SELECT => CommandMeta::new("select", 2, &["loading", "stale", "fast"], (0, 0, 0)),
+            Self::CLIENT => CommandMeta::new(
+                "client",
+                -2,
+                &["admin", "noscript", "random", "loading", "stale"],
+                (0, 0, 0),
+            ),
+            Self::HELLO => CommandMeta::new(
+                "hello",
+                -1,
+                &["noscript", "loading", "stale", "fast", "no_auth"],
+                (0, 0, 0),
+            ),
+            Self::AUTH => CommandMeta::new(
+                "auth",
+                -2,
+                &["noscript", "loading", "stale", "fast", "no_auth"],
+                (0, 0, 0),
+            ),
+            Self::EXISTS => CommandMeta::new("exists", -2, &["readonly", "fast"], (1, -1, 1)),
+            Self::XTRIM => CommandMeta::new("xtrim", -4, &["write"], (1, 1, 1)),
+        }
+    }
+}
+
 pub fn parse_buffer(buffer: &[u8]) -> Option<Vec<BufferType>> {
-    let mut buffer_iter = buffer.iter();
+    parse_buffer_with_max_bulk_len(buffer, DEFAULT_PROTO_MAX_BULK_LEN)
+}
+
+/// Parse a buffer, bulk strings announcing more than `max_bulk_len` bytes being rejected. A
+/// trailing incomplete frame is ignored, see `parse_frames` to keep it for later.
+pub fn parse_buffer_with_max_bulk_len(
+    buffer: &[u8],
+    max_bulk_len: usize,
+) -> Option<Vec<BufferType>> {
+    Some(parse_frames(buffer, max_bulk_len).0)
+}
 
+/// Why a frame could not be parsed.
+enum FrameError {
+    /// The frame is not complete yet, more bytes are needed.
+    Incomplete,
+    Protocol(ProtocolError),
+}
+
+impl From<ProtocolError> for FrameError {
+    fn from(err: ProtocolError) -> Self {
+        FrameError::Protocol(err)
+    }
+}
+
+/// Parse the complete frames at the start of `buffer`. Returns them with the number of bytes they
+/// took, the remaining bytes being the beginning of a frame to parse again once the rest of it is
+/// received.
+pub fn parse_frames(buffer: &[u8], max_bulk_len: usize) -> (Vec<BufferType>, usize) {
     let mut elements: Vec<BufferType> = Vec::new();
-    while let Some(byte) = buffer_iter.next() {
-        match byte {
-            b'+' => {
-                if let Some(word) = parse_simple_string(&mut buffer_iter) {
-                    elements.push(word);
+    let mut consumed = 0;
+    while consumed < buffer.len() {
+        let mut buffer_iter = buffer[consumed..].iter();
+        match parse_frame(&mut buffer_iter, max_bulk_len) {
+            Ok(element) => {
+                let frame_len = buffer.len() - consumed - buffer_iter.as_slice().len();
+                consumed += frame_len;
+                match element {
+                    Some(BufferType::Command(mut command)) => {
+                        command.received_bytes = frame_len;
+                        elements.push(BufferType::Command(command));
+                    }
+                    Some(element) => elements.push(element),
+                    None => {}
                 }
             }
-            b'$' => {
-                if let Some(word) = parse_bulk_string_like(&mut buffer_iter) {
-                    elements.push(word);
-                }
-            }
-            b'*' => {
-                if let Some(array) = parse_array_into_command(&mut buffer_iter) {
-                    elements.push(array);
-                }
+            Err(FrameError::Incomplete) => break,
+            Err(FrameError::Protocol(err)) => {
+                elements.push(BufferType::ProtocolError(err));
+                consumed = buffer.len();
+                break;
             }
-            _ => panic!(),
         }
     }
-    Some(elements)
+    (elements, consumed)
 }
 
-fn parse_simple_string(iterator: &mut std::slice::Iter<'_, u8>) -> Option<BufferType> {
-    let bytes = find_until_next_delimiter(iterator);
-    from_utf8(&bytes)
-        .map(|word| BufferType::String(word.to_string()))
-        .ok()
+fn parse_frame(
+    iterator: &mut std::slice::Iter<'_, u8>,
+    max_bulk_len: usize,
+) -> Result<Option<BufferType>, FrameError> {
+    let Some(byte) = iterator.next() else {
+        return Err(FrameError::Incomplete);
+    };
+    match byte {
+        b'+' => Ok(Some(BufferType::String(read_line(iterator)?))),
+        b'$' => parse_bulk_string_like(iterator, max_bulk_len, true),
+        b':' => Ok(parse_number(iterator)?.map(BufferType::Integer)),
+        b',' => Ok(parse_number(iterator)?.map(BufferType::Double)),
+        b'*' => parse_array_into_command(iterator, max_bulk_len),
+        _ => Err(ProtocolError::UnknownFrameType.into()),
+    }
+}
+
+/// Integer or double up to the next delimiter, skipped when malformed.
+fn parse_number<T: std::str::FromStr>(
+    iterator: &mut std::slice::Iter<'_, u8>,
+) -> Result<Option<T>, FrameError> {
+    let line = read_line(iterator)?;
+    Ok(from_utf8(&line).ok().and_then(|line| line.parse().ok()))
 }
 
-fn parse_bulk_string_like(iterator: &mut std::slice::Iter<'_, u8>) -> Option<BufferType> {
-    let len = from_utf8(&find_until_next_delimiter(iterator))
+/// Bulk string, or RDB file when not followed by a delimiter. RDB files are only sent at the top
+/// level, by a master after `FULLRESYNC`.
+fn parse_bulk_string_like(
+    iterator: &mut std::slice::Iter<'_, u8>,
+    max_bulk_len: usize,
+    top_level: bool,
+) -> Result<Option<BufferType>, FrameError> {
+    let len = from_utf8(&read_line(iterator)?)
         .ok()
-        .and_then(|bytes| bytes.parse::<usize>().ok())?;
-    let mut bytes: Vec<u8> = Vec::new();
-    for _ in 0..len {
-        let _ = iterator.next().map(|byte| bytes.push(*byte));
+        .and_then(|bytes| bytes.parse::<i64>().ok())
+        .ok_or(ProtocolError::InvalidBulkLength)?;
+    // Null bulk string
+    if len == -1 {
+        return Ok(None);
     }
+    let len = usize::try_from(len)
+        .ok()
+        .filter(|len| *len <= max_bulk_len)
+        .ok_or(ProtocolError::InvalidBulkLength)?;
+    let rest = iterator.as_slice();
+    if rest.len() < len {
+        return Err(FrameError::Incomplete);
+    }
+    let bytes = rest[..len].to_vec();
 
     // Check if the next 2 bytes are a delimiter (it's a bulk string, consume those 2 bytes) or not
     // (it's a DB file, do not consume those 2 bytes)
-    let mut iter_peek = iterator.clone().tuple_windows::<(_, _)>().peekable();
-    match iter_peek.peek() {
-        Some((first, second)) if (**first, **second) == (b'\r', b'\n') => {
-            iterator.next();
-            iterator.next();
-            from_utf8(&bytes)
-                .ok()
-                .map(|word| BufferType::String(word.to_string()))
+    match &rest[len..] {
+        [b'\r', b'\n', ..] => {
+            *iterator = rest[len + 2..].iter();
+            Ok(Some(BufferType::String(bytes)))
+        }
+        [b'\r'] => Err(FrameError::Incomplete),
+        [] if !top_level => Err(FrameError::Incomplete),
+        _ if top_level => {
+            *iterator = rest[len..].iter();
+            Ok(Some(BufferType::DBFile(bytes)))
         }
-        _ => Some(BufferType::DBFile(bytes)),
+        _ => Err(ProtocolError::InvalidBulkLength.into()),
     }
 }
 
-fn parse_array_into_command(iterator: &mut std::slice::Iter<'_, u8>) -> Option<BufferType> {
-    let len = from_utf8(&find_until_next_delimiter(iterator))
+/// Parse an array of bulk strings into a command, or into an unknown command when its name is
+/// not supported.
+fn parse_array_into_command(
+    iterator: &mut std::slice::Iter<'_, u8>,
+    max_bulk_len: usize,
+) -> Result<Option<BufferType>, FrameError> {
+    let len = from_utf8(&read_line(iterator)?)
         .ok()
-        .and_then(|bytes| bytes.parse::<usize>().ok())?;
+        .and_then(|bytes| bytes.parse::<usize>().ok())
+        .filter(|len| *len <= i32::MAX as usize)
+        .ok_or(ProtocolError::InvalidMultibulkLength)?;
 
-    let mut elements: Vec<String> = Vec::new();
+    let mut elements: Vec<Vec<u8>> = Vec::new();
     for _ in 0..len {
-        iterator.next();
-        if let Some(BufferType::String(elem)) = parse_bulk_string_like(iterator) {
-            elements.push(elem);
+        match iterator.next() {
+            None => return Err(FrameError::Incomplete),
+            Some(b'$') => {}
+            Some(prefix) => return Err(ProtocolError::ExpectedBulkString(*prefix).into()),
         }
+        // Commands have no null arguments
+        let Some(BufferType::String(elem)) = parse_bulk_string_like(iterator, max_bulk_len, false)?
+        else {
+            return Err(ProtocolError::InvalidBulkLength.into());
+        };
+        elements.push(elem);
     }
 
-    let verb = CommandVerb::try_from(elements.first().unwrap_or(&String::from("")).clone()).ok()?;
-
-    Some(BufferType::Command(Command {
-        cmd: elements,
-        verb,
-    }))
+    let Some(name) = elements.first() else {
+        return Ok(None);
+    };
+    if CommandVerb::try_from(String::from_utf8_lossy(name).into_owned()).is_err() {
+        return Ok(Some(BufferType::UnknownCommand(elements)));
+    }
+    Ok(Command::new(elements).map(BufferType::Command))
 }
 
-fn find_until_next_delimiter<'a, I>(iterator: &mut I) -> Vec<u8>
-where
-    I: Iterator<Item = &'a u8>,
-{
-    let mut elements: Vec<u8> = vec![];
-    let double_iterator = iterator.by_ref().tuple_windows::<(_, _)>();
-    for (first, second) in double_iterator {
-        if (*first, *second) == (b'\r', b'\n') {
-            break;
-        };
-        elements.push(*first);
-    }
-    elements
+/// Bytes up to the next delimiter, which is consumed as well.
+fn read_line(iterator: &mut std::slice::Iter<'_, u8>) -> Result<Vec<u8>, FrameError> {
+    let rest = iterator.as_slice();
+    let Some(position) = rest.windows(2).position(|window| window == b"\r\n") else {
+        return Err(FrameError::Incomplete);
+    };
+    *iterator = rest[position + 2..].iter();
+    Ok(rest[..position].to_vec())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{parse_buffer, BufferType, Command, CommandVerb};
+    use super::{
+        parse_buffer, parse_buffer_with_max_bulk_len, BufferType, Command, CommandVerb,
+        ProtocolError,
+    };
+
+    fn command(args: &[&str]) -> Command {
+        Command::new(args.iter().map(|arg| arg.as_bytes().to_vec()).collect()).unwrap()
+    }
 
     #[test]
     fn buffer_with_simple_string() {
         let buffer = String::from("+OK\r\n").into_bytes();
-        let expected_response = vec![BufferType::String(String::from("OK"))];
+        let expected_response = vec![BufferType::String(b"OK".to_vec())];
         assert_eq!(parse_buffer(&buffer), Some(expected_response));
     }
 
@@ -175,16 +672,46 @@ mod tests {
     fn buffer_with_2_simple_strings() {
         let buffer = String::from("+OK\r\n+hello\r\n").into_bytes();
         let expected_response = vec![
-            BufferType::String(String::from("OK")),
-            BufferType::String(String::from("hello")),
+            BufferType::String(b"OK".to_vec()),
+            BufferType::String(b"hello".to_vec()),
         ];
         assert_eq!(parse_buffer(&buffer), Some(expected_response));
     }
 
+    #[test]
+    fn buffer_with_integer_and_double() {
+        let buffer = String::from(":5\r\n,-1.5\r\n:-12\r\n").into_bytes();
+        let expected_response = vec![
+            BufferType::Integer(5),
+            BufferType::Double(-1.5),
+            BufferType::Integer(-12),
+        ];
+        assert_eq!(parse_buffer(&buffer), Some(expected_response));
+    }
+
+    #[test]
+    fn unknown_frame_types_are_protocol_errors() {
+        for buffer in ["PING\r\n", "-ERR oops\r\n", "_\r\n", "%1\r\n"] {
+            assert_eq!(
+                parse_buffer(buffer.as_bytes()),
+                Some(vec![BufferType::ProtocolError(
+                    ProtocolError::UnknownFrameType
+                )])
+            );
+        }
+        assert_eq!(
+            parse_buffer(b"+OK\r\n#t\r\n+ignored\r\n"),
+            Some(vec![
+                BufferType::String(b"OK".to_vec()),
+                BufferType::ProtocolError(ProtocolError::UnknownFrameType)
+            ])
+        );
+    }
+
     #[test]
     fn buffer_with_bulk_string() {
         let buffer = String::from("$4\r\nPING\r\n").into_bytes();
-        let expected_response = vec![BufferType::String(String::from("PING"))];
+        let expected_response = vec![BufferType::String(b"PING".to_vec())];
         assert_eq!(parse_buffer(&buffer), Some(expected_response));
     }
 
@@ -206,8 +733,8 @@ mod tests {
     fn buffer_with_2_bulk_strings() {
         let buffer = String::from("$4\r\nPING\r\n$4\r\nPONG\r\n").into_bytes();
         let expected_response = vec![
-            BufferType::String(String::from("PING")),
-            BufferType::String(String::from("PONG")),
+            BufferType::String(b"PING".to_vec()),
+            BufferType::String(b"PONG".to_vec()),
         ];
         assert_eq!(parse_buffer(&buffer), Some(expected_response));
     }
@@ -215,13 +742,35 @@ mod tests {
     #[test]
     fn test_buffer_with_array() {
         let buffer = String::from("*2\r\n$4\r\nECHO\r\n$4\r\ntoto\r\n").into_bytes();
-        let expected_response = vec![BufferType::Command(Command {
-            cmd: vec![String::from("ECHO"), String::from("toto")],
-            verb: CommandVerb::ECHO,
-        })];
+        let expected_response = vec![BufferType::Command(command(&["ECHO", "toto"]))];
         assert_eq!(parse_buffer(&buffer), Some(expected_response));
     }
 
+    #[test]
+    fn command_arity() {
+        let get = CommandVerb::GET.metadata();
+        assert!(get.accepts(2));
+        assert!(!get.accepts(3));
+
+        let set = CommandVerb::SET.metadata();
+        assert!(set.is_write());
+        assert!(!set.accepts(2));
+        assert!(set.accepts(5));
+    }
+
+    #[test]
+    fn test_buffer_with_binary_argument() {
+        let buffer = b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$2\r\n\xff\x00\r\n".to_vec();
+        let Some(mut elements) = parse_buffer(&buffer) else {
+            panic!("Could not parse buffer");
+        };
+        let Some(BufferType::Command(command)) = elements.pop() else {
+            panic!("Expected a command");
+        };
+        assert_eq!(command.verb, CommandVerb::SET);
+        assert_eq!(command.raw[2], vec![0xff, 0x00]);
+    }
+
     #[test]
     fn test_buffer_with_simple_string_and_db_file() {
         let buffer = vec![
@@ -242,9 +791,7 @@ mod tests {
             192, 0, 255, 240, 110, 59, 254, 192, 255, 90, 162,
         ];
         let expected_response = vec![
-            BufferType::String(String::from(
-                "FULLRESYNC 75cd7bc10c49047e0d163660f3b90625b1af31dc 0",
-            )),
+            BufferType::String(b"FULLRESYNC 75cd7bc10c49047e0d163660f3b90625b1af31dc 0".to_vec()),
             BufferType::DBFile(db_file),
         ];
         assert_eq!(parse_buffer(&buffer), Some(expected_response));
@@ -274,35 +821,67 @@ mod tests {
             192, 0, 255, 240, 110, 59, 254, 192, 255, 90, 162,
         ];
         let expected_response = vec![
-            BufferType::String(String::from(
-                "FULLRESYNC 75cd7bc10c49047e0d163660f3b90625b1af31dc 0",
-            )),
+            BufferType::String(b"FULLRESYNC 75cd7bc10c49047e0d163660f3b90625b1af31dc 0".to_vec()),
             BufferType::DBFile(db_file),
-            BufferType::Command(Command {
-                cmd: vec![
-                    String::from("SET"),
-                    String::from("foo"),
-                    String::from("123"),
-                ],
-                verb: CommandVerb::SET,
-            }),
-            BufferType::Command(Command {
-                cmd: vec![
-                    String::from("SET"),
-                    String::from("bar"),
-                    String::from("456"),
-                ],
-                verb: CommandVerb::SET,
-            }),
-            BufferType::Command(Command {
-                cmd: vec![
-                    String::from("SET"),
-                    String::from("baz"),
-                    String::from("789"),
-                ],
-                verb: CommandVerb::SET,
-            }),
+            BufferType::Command(command(&["SET", "foo", "123"])),
+            BufferType::Command(command(&["SET", "bar", "456"])),
+            BufferType::Command(command(&["SET", "baz", "789"])),
         ];
         assert_eq!(parse_buffer(&buffer), Some(expected_response));
     }
+
+    #[test]
+    fn invalid_multibulk_length() {
+        for buffer in [&b"*-5\r\n"[..], b"*abc\r\n", b"*99999999999\r\n"] {
+            assert_eq!(
+                parse_buffer(buffer),
+                Some(vec![BufferType::ProtocolError(
+                    ProtocolError::InvalidMultibulkLength
+                )])
+            );
+        }
+        assert_eq!(
+            ProtocolError::InvalidMultibulkLength.to_string(),
+            "ERR Protocol error: invalid multibulk length"
+        );
+    }
+
+    #[test]
+    fn bulk_length_over_limit() {
+        let buffer = b"*1\r\n$4\r\nPING\r\n*2\r\n$3\r\nGET\r\n$11\r\nlong-string\r\n";
+        assert_eq!(
+            parse_buffer_with_max_bulk_len(buffer, 10),
+            Some(vec![
+                BufferType::Command(command(&["PING"])),
+                BufferType::ProtocolError(ProtocolError::InvalidBulkLength),
+            ])
+        );
+        assert_eq!(
+            parse_buffer(b"*1\r\n$abc\r\nPING\r\n"),
+            Some(vec![BufferType::ProtocolError(
+                ProtocolError::InvalidBulkLength
+            )])
+        );
+    }
+
+    #[test]
+    fn command_elements_must_be_bulk_strings() {
+        assert_eq!(
+            parse_buffer(b"*1\r\n$4\r\nPING\r\n*2\r\n$3\r\nGET\r\n:1\r\n"),
+            Some(vec![
+                BufferType::Command(command(&["PING"])),
+                BufferType::ProtocolError(ProtocolError::ExpectedBulkString(b':')),
+            ])
+        );
+        assert_eq!(
+            ProtocolError::ExpectedBulkString(b':').to_string(),
+            "ERR Protocol error: expected '$', got ':'"
+        );
+        assert_eq!(
+            parse_buffer(b"*2\r\n$3\r\nGET\r\n$-1\r\n"),
+            Some(vec![BufferType::ProtocolError(
+                ProtocolError::InvalidBulkLength
+            )])
+        );
+    }
 }
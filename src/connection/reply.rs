@@ -0,0 +1,197 @@
+/// Wire protocol negotiated by a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    #[default]
+    RESP2,
+    RESP3,
+}
+
+/// Reply to a client command, independent of the protocol used to serialize it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+    Map(Vec<(Reply, Reply)>),
+    /// Out of band data, sent as an array in RESP2.
+    Push(Vec<Reply>),
+    Double(f64),
+    Null,
+    /// Null array (`*-1`) in RESP2, used by EXEC on aborted transactions or XREAD on timeouts.
+    NullArray,
+}
+
+impl Reply {
+    pub fn ok() -> Reply {
+        Reply::Simple(String::from("OK"))
+    }
+
+    pub fn bulk(value: &str) -> Reply {
+        Reply::Bulk(Some(value.as_bytes().to_vec()))
+    }
+
+    pub fn error(message: &str) -> Reply {
+        Reply::Error(message.to_owned())
+    }
+
+    pub fn encode(&self, protocol: Protocol) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        self.encode_into(protocol, &mut buffer);
+        buffer
+    }
+
+    fn encode_into(&self, protocol: Protocol, buffer: &mut Vec<u8>) {
+        match (self, protocol) {
+            (Reply::Simple(value), _) => push_line(buffer, b'+', value),
+            (Reply::Error(message), _) => push_line(buffer, b'-', message),
+            (Reply::Integer(value), _) => push_line(buffer, b':', &value.to_string()),
+            (Reply::Bulk(Some(value)), _) => {
+                push_line(buffer, b'$', &value.len().to_string());
+                buffer.extend_from_slice(value);
+                buffer.extend_from_slice(b"\r\n");
+            }
+            (Reply::Bulk(None), Protocol::RESP2) | (Reply::Null, Protocol::RESP2) => {
+                buffer.extend_from_slice(b"$-1\r\n")
+            }
+            (Reply::NullArray, Protocol::RESP2) => buffer.extend_from_slice(b"*-1\r\n"),
+            (Reply::Bulk(None), Protocol::RESP3)
+            | (Reply::Null, Protocol::RESP3)
+            | (Reply::NullArray, Protocol::RESP3) => buffer.extend_from_slice(b"_\r\n"),
+            (Reply::Array(values), _) | (Reply::Push(values), Protocol::RESP2) => {
+                push_line(buffer, b'*', &values.len().to_string());
+                for value in values {
+                    value.encode_into(protocol, buffer);
+                }
+            }
+            (Reply::Push(values), Protocol::RESP3) => {
+                push_line(buffer, b'>', &values.len().to_string());
+                for value in values {
+                    value.encode_into(protocol, buffer);
+                }
+            }
+            (Reply::Map(entries), Protocol::RESP2) => {
+                push_line(buffer, b'*', &(entries.len() * 2).to_string());
+                for (key, value) in entries {
+                    key.encode_into(protocol, buffer);
+                    value.encode_into(protocol, buffer);
+                }
+            }
+            (Reply::Map(entries), Protocol::RESP3) => {
+                push_line(buffer, b'%', &entries.len().to_string());
+                for (key, value) in entries {
+                    key.encode_into(protocol, buffer);
+                    value.encode_into(protocol, buffer);
+                }
+            }
+            (Reply::Double(value), Protocol::RESP2) => {
+                Reply::bulk(&format_double(*value)).encode_into(protocol, buffer)
+            }
+            (Reply::Double(value), Protocol::RESP3) => {
+                push_line(buffer, b',', &format_double(*value))
+            }
+        }
+    }
+}
+
+fn push_line(buffer: &mut Vec<u8>, prefix: u8, value: &str) {
+    buffer.push(prefix);
+    buffer.extend_from_slice(value.as_bytes());
+    buffer.extend_from_slice(b"\r\n");
+}
+
+fn format_double(value: f64) -> String {
+    match value {
+        f64::INFINITY => String::from("inf"),
+        f64::NEG_INFINITY => String::from("-inf"),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Protocol, Reply};
+
+    fn encode(reply: Reply, protocol: Protocol) -> String {
+        String::from_utf8(reply.encode(protocol)).unwrap()
+    }
+
+    #[test]
+    fn encode_scalars() {
+        assert_eq!(encode(Reply::ok(), Protocol::RESP2), "+OK\r\n");
+        assert_eq!(
+            encode(Reply::error("ERR oops"), Protocol::RESP2),
+            "-ERR oops\r\n"
+        );
+        assert_eq!(encode(Reply::Integer(-3), Protocol::RESP2), ":-3\r\n");
+        assert_eq!(
+            encode(Reply::bulk("toto"), Protocol::RESP2),
+            "$4\r\ntoto\r\n"
+        );
+        assert_eq!(encode(Reply::bulk(""), Protocol::RESP2), "$0\r\n\r\n");
+    }
+
+    #[test]
+    fn encode_binary_bulk() {
+        let reply = Reply::Bulk(Some(vec![0xff, 0x00]));
+        assert_eq!(reply.encode(Protocol::RESP2), b"$2\r\n\xff\x00\r\n");
+    }
+
+    #[test]
+    fn encode_nulls() {
+        assert_eq!(encode(Reply::Null, Protocol::RESP2), "$-1\r\n");
+        assert_eq!(encode(Reply::Bulk(None), Protocol::RESP2), "$-1\r\n");
+        assert_eq!(encode(Reply::NullArray, Protocol::RESP2), "*-1\r\n");
+        assert_eq!(encode(Reply::Null, Protocol::RESP3), "_\r\n");
+        assert_eq!(encode(Reply::NullArray, Protocol::RESP3), "_\r\n");
+    }
+
+    #[test]
+    fn encode_nested_array() {
+        let reply = Reply::Array(vec![
+            Reply::bulk("toto"),
+            Reply::Array(vec![Reply::Integer(1)]),
+        ]);
+        assert_eq!(
+            encode(reply, Protocol::RESP2),
+            "*2\r\n$4\r\ntoto\r\n*1\r\n:1\r\n"
+        );
+    }
+
+    #[test]
+    fn encode_map() {
+        let reply = Reply::Map(vec![(Reply::bulk("proto"), Reply::Integer(3))]);
+        assert_eq!(
+            encode(reply.clone(), Protocol::RESP2),
+            "*2\r\n$5\r\nproto\r\n:3\r\n"
+        );
+        assert_eq!(
+            encode(reply, Protocol::RESP3),
+            "%1\r\n$5\r\nproto\r\n:3\r\n"
+        );
+    }
+
+    #[test]
+    fn encode_push() {
+        let reply = Reply::Push(vec![Reply::bulk("invalidate"), Reply::Array(vec![])]);
+        assert_eq!(
+            encode(reply.clone(), Protocol::RESP2),
+            "*2\r\n$10\r\ninvalidate\r\n*0\r\n"
+        );
+        assert_eq!(
+            encode(reply, Protocol::RESP3),
+            ">2\r\n$10\r\ninvalidate\r\n*0\r\n"
+        );
+    }
+
+    #[test]
+    fn encode_double() {
+        assert_eq!(encode(Reply::Double(1.5), Protocol::RESP2), "$3\r\n1.5\r\n");
+        assert_eq!(encode(Reply::Double(1.5), Protocol::RESP3), ",1.5\r\n");
+        assert_eq!(
+            encode(Reply::Double(f64::INFINITY), Protocol::RESP3),
+            ",inf\r\n"
+        );
+    }
+}
@@ -1,32 +1,59 @@
 use std::{
     io::{ErrorKind, Read, Write},
-    net::TcpStream,
+    net::{Shutdown, SocketAddr, TcpStream},
+    time::Duration,
 };
 
-use super::parser::{parse_buffer, BufferType};
+use super::parser::{parse_frames, BufferType};
+
+/// Size of the chunks read from the underlying stream.
+const READ_CHUNK_SIZE: usize = 16 * 1024;
 
 pub struct RedisStream<S: Write + Read> {
     stream: S,
-    buffer: [u8; 512],
+    buffer: Vec<u8>,
+    /// Bytes of a frame not entirely received yet.
+    pending: Vec<u8>,
+    max_bulk_len: usize,
 }
 
 impl<S: Write + Read> RedisStream<S> {
-    pub fn new(stream: S) -> Self {
-        let buffer = [0u8; 512];
-
-        Self { stream, buffer }
+    pub fn new(stream: S, max_bulk_len: usize) -> Self {
+        let buffer = vec![0u8; READ_CHUNK_SIZE];
+
+        Self {
+            stream,
+            buffer,
+            pending: Vec::new(),
+            max_bulk_len,
+        }
     }
 
+    /// Read everything currently available, chunk by chunk, and parse the complete frames. The
+    /// bytes of an incomplete frame are kept until the rest of it is read.
     pub fn read(&mut self) -> Option<Vec<BufferType>> {
-        match self.stream.read(&mut self.buffer) {
-            Ok(0) => None,
-            Ok(n) => parse_buffer(&self.buffer[0..n]),
-            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Some(Vec::new()),
-            Err(err) => {
-                println!("Stream terminated with err: {}", err);
-                Some(Vec::new())
+        let mut received = 0;
+        loop {
+            match self.stream.read(&mut self.buffer) {
+                Ok(0) if received == 0 => return None,
+                Ok(0) => break,
+                Ok(n) => {
+                    received += n;
+                    self.pending.extend_from_slice(&self.buffer[0..n]);
+                    if n < self.buffer.len() {
+                        break;
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    println!("Stream terminated with err: {}", err);
+                    break;
+                }
             }
         }
+        let (elements, consumed) = parse_frames(&self.pending, self.max_bulk_len);
+        self.pending.drain(..consumed);
+        Some(elements)
     }
 
     pub fn send_string(&mut self, message: &str) {
@@ -53,28 +80,136 @@ impl RedisStream<TcpStream> {
             .set_nonblocking(non_blocking)
             .expect("Cannot put TCP stream in non-blocking mode");
     }
+
+    /// Bound how long a blocking read waits for data, `None` waiting indefinitely.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        if let Err(err) = self.stream.set_read_timeout(timeout) {
+            println!(
+                "Error when trying to set the stream read timeout: {:?}",
+                err
+            );
+        }
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.stream.peer_addr().ok()
+    }
+
+    pub fn shutdown(&mut self) {
+        if let Err(err) = self.stream.shutdown(Shutdown::Both) {
+            println!("Error when trying to shutdown stream: {:?}", err);
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::RedisStream;
-    use crate::connection::stream::BufferType;
-    use std::collections::VecDeque;
+    use crate::connection::{
+        parser::{ProtocolError, DEFAULT_PROTO_MAX_BULK_LEN},
+        stream::BufferType,
+    };
+    use std::{
+        collections::VecDeque,
+        io::{self, ErrorKind, Read, Write},
+    };
 
     #[test]
     fn test_parse_simple_string() {
         let stream = VecDeque::from(String::from("+OK\r\n").into_bytes());
 
-        let mut redis_stream = RedisStream::new(stream);
-        let expected_response = vec![BufferType::String(String::from("OK"))];
+        let mut redis_stream = RedisStream::new(stream, DEFAULT_PROTO_MAX_BULK_LEN);
+        let expected_response = vec![BufferType::String(b"OK".to_vec())];
         assert_eq!(redis_stream.read(), Some(expected_response))
     }
 
+    #[test]
+    fn read_command_larger_than_a_chunk() {
+        let value = "a".repeat(40_000);
+        let message = format!(
+            "*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n${}\r\n{value}\r\n",
+            value.len()
+        );
+        let stream = VecDeque::from(message.into_bytes());
+
+        let mut redis_stream = RedisStream::new(stream, DEFAULT_PROTO_MAX_BULK_LEN);
+        let Some(mut elements) = redis_stream.read() else {
+            panic!("stream should not be closed");
+        };
+        let Some(BufferType::Command(command)) = elements.pop() else {
+            panic!("expected a command");
+        };
+        assert_eq!(command.raw[2].len(), 40_000);
+    }
+
+    /// Stream handing its content one chunk per read, as when a frame is split across TCP
+    /// segments.
+    struct ChunkedStream(VecDeque<Vec<u8>>);
+
+    impl Read for ChunkedStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some(chunk) = self.0.pop_front() else {
+                return Err(io::Error::from(ErrorKind::WouldBlock));
+            };
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    impl Write for ChunkedStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn command_split_across_reads() {
+        let stream = ChunkedStream(VecDeque::from([
+            b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$5\r\nhe".to_vec(),
+            b"llo\r\n*1\r\n$4\r".to_vec(),
+            b"\nPING\r\n".to_vec(),
+        ]));
+
+        let mut redis_stream = RedisStream::new(stream, DEFAULT_PROTO_MAX_BULK_LEN);
+        assert_eq!(redis_stream.read(), Some(Vec::new()));
+        let Some(elements) = redis_stream.read() else {
+            panic!("stream should not be closed");
+        };
+        let [BufferType::Command(command)] = elements.as_slice() else {
+            panic!("expected a single command, got {elements:?}");
+        };
+        assert_eq!(command.cmd, ["SET", "foo", "hello"]);
+        assert_eq!(command.received_bytes, 33);
+        let Some(elements) = redis_stream.read() else {
+            panic!("stream should not be closed");
+        };
+        assert!(
+            matches!(elements.as_slice(), [BufferType::Command(command)] if command.cmd == ["PING"])
+        );
+    }
+
+    #[test]
+    fn reject_bulk_length_over_limit() {
+        let stream = VecDeque::from(b"*2\r\n$3\r\nGET\r\n$2000000000\r\nfoo\r\n".to_vec());
+
+        let mut redis_stream = RedisStream::new(stream, 1024);
+        assert_eq!(
+            redis_stream.read(),
+            Some(vec![BufferType::ProtocolError(
+                ProtocolError::InvalidBulkLength
+            )])
+        );
+    }
+
     #[test]
     fn test_send_string() {
         let stream: VecDeque<u8> = VecDeque::new();
 
-        let mut redis_stream = RedisStream::new(stream);
+        let mut redis_stream = RedisStream::new(stream, DEFAULT_PROTO_MAX_BULK_LEN);
 
         let message = String::from("+OK\r\n");
         redis_stream.send_string(&message);
@@ -89,7 +224,7 @@ mod tests {
     fn should_exit_when_stream_empty_on_read() {
         let stream: VecDeque<u8> = VecDeque::new();
 
-        let mut redis_stream = RedisStream::new(stream);
+        let mut redis_stream = RedisStream::new(stream, DEFAULT_PROTO_MAX_BULK_LEN);
 
         assert_eq!(redis_stream.read(), None)
     }
@@ -0,0 +1,182 @@
+/// Redis-style glob matching (see `stringmatchlen`), on bytes so that it is binary-safe.
+///
+/// Supports `*`, `?`, character classes (`[abc]`, `[a-z]`, `[^abc]`) and `\` escaping.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let mut skip_longer_matches = false;
+    match_impl(pattern, text, &mut skip_longer_matches, 0)
+}
+
+/// Protection against patterns with a huge number of `*` exhausting the stack.
+const MAX_NESTING: usize = 1000;
+
+fn match_impl(pattern: &[u8], text: &[u8], skip_longer_matches: &mut bool, nesting: usize) -> bool {
+    if nesting > MAX_NESTING {
+        return false;
+    }
+
+    let (mut p, mut s) = (0, 0);
+    while p < pattern.len() && s < text.len() {
+        match pattern[p] {
+            b'*' => {
+                while pattern.get(p + 1) == Some(&b'*') {
+                    p += 1;
+                }
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+                for start in s..text.len() {
+                    if match_impl(
+                        &pattern[p + 1..],
+                        &text[start..],
+                        skip_longer_matches,
+                        nesting + 1,
+                    ) {
+                        return true;
+                    }
+                    // The rest of the pattern did not match a full suffix of the text: matching
+                    // shorter suffixes cannot succeed either.
+                    if *skip_longer_matches {
+                        return false;
+                    }
+                }
+                *skip_longer_matches = true;
+                return false;
+            }
+            b'?' => s += 1,
+            b'[' => {
+                p += 1;
+                let negate = pattern.get(p) == Some(&b'^');
+                if negate {
+                    p += 1;
+                }
+                let mut matched = false;
+                loop {
+                    match pattern.get(p) {
+                        // Unterminated class, stop on the last pattern byte
+                        None => {
+                            p -= 1;
+                            break;
+                        }
+                        Some(b'\\') if p + 1 < pattern.len() => {
+                            p += 1;
+                            matched |= pattern[p] == text[s];
+                        }
+                        Some(b']') => break,
+                        Some(&start) if p + 2 < pattern.len() && pattern[p + 1] == b'-' => {
+                            let end = pattern[p + 2];
+                            let (low, high) = if start <= end {
+                                (start, end)
+                            } else {
+                                (end, start)
+                            };
+                            matched |= (low..=high).contains(&text[s]);
+                            p += 2;
+                        }
+                        Some(&c) => matched |= c == text[s],
+                    }
+                    p += 1;
+                }
+                if matched == negate {
+                    return false;
+                }
+                s += 1;
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                p += 1;
+                if pattern[p] != text[s] {
+                    return false;
+                }
+                s += 1;
+            }
+            c => {
+                if c != text[s] {
+                    return false;
+                }
+                s += 1;
+            }
+        }
+        p += 1;
+        if s == text.len() {
+            while pattern.get(p) == Some(&b'*') {
+                p += 1;
+            }
+            break;
+        }
+    }
+
+    p == pattern.len() && s == text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    #[test]
+    fn glob_match_table() {
+        let cases: &[(&str, &str, bool)] = &[
+            // As in Redis, an empty text never enters the matching loop
+            ("*", "", false),
+            ("*", "anything", true),
+            ("", "", true),
+            ("", "a", false),
+            ("a", "", false),
+            ("hello", "hello", true),
+            ("hello", "hell", false),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h*llo", "hllo", true),
+            ("h*llo", "heeeello", true),
+            ("h**llo", "heello", true),
+            ("h*", "h", true),
+            ("*o", "hello", true),
+            ("*o", "hellx", false),
+            ("a*b*c", "axxbyyc", true),
+            ("a*b*c", "axxbyy", false),
+            ("h[ae]llo", "hello", true),
+            ("h[ae]llo", "hallo", true),
+            ("h[ae]llo", "hillo", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-b]llo", "hbllo", true),
+            ("h[a-b]llo", "hcllo", false),
+            ("h[b-a]llo", "hallo", true),
+            ("[a-]", "-", false),
+            ("[a-]", "a", true),
+            ("[]", "a", false),
+            ("[]a", "a", false),
+            ("[abc", "a", true),
+            ("[abc", "d", false),
+            ("[\\]]", "]", true),
+            ("[\\-]", "-", true),
+            ("h\\*llo", "h*llo", true),
+            ("h\\*llo", "hello", false),
+            ("h\\?", "h?", true),
+            ("a\\", "a\\", true),
+            ("a\\", "a", false),
+            ("\\", "\\", true),
+            ("*[", "a", false),
+            ("*?", "", false),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                glob_match(pattern.as_bytes(), text.as_bytes()),
+                *expected,
+                "pattern {pattern:?} against {text:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn glob_match_binary() {
+        assert!(glob_match(b"\xff*", b"\xff\x00\x01"));
+        assert!(glob_match(b"[\x00-\x10]", b"\x05"));
+        assert!(!glob_match(b"?", b""));
+    }
+
+    #[test]
+    fn glob_match_many_stars_is_fast() {
+        let pattern = "a*".repeat(30) + "b";
+        let text = "a".repeat(60);
+        assert!(!glob_match(pattern.as_bytes(), text.as_bytes()));
+    }
+}
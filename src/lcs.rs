@@ -0,0 +1,116 @@
+/// A run of consecutive matching bytes, as inclusive `(start, end)` offsets in each string.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct LcsMatch {
+    pub a: (usize, usize),
+    pub b: (usize, usize),
+}
+
+impl LcsMatch {
+    pub fn match_len(&self) -> usize {
+        self.a.1 - self.a.0 + 1
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Lcs {
+    pub subsequence: Vec<u8>,
+    /// Matching runs, from the end of the strings to their start like Redis reports them.
+    pub matches: Vec<LcsMatch>,
+}
+
+/// Longest common subsequence of `a` and `b`, ported from Redis' `lcsCommand` so that ties are
+/// broken, and matches reported, the same way.
+pub fn lcs(a: &[u8], b: &[u8]) -> Lcs {
+    // lengths[i][j] is the length of the LCS of a[..i] and b[..j]
+    let width = b.len() + 1;
+    let mut lengths = vec![0usize; (a.len() + 1) * width];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            lengths[i * width + j] = if a[i - 1] == b[j - 1] {
+                lengths[(i - 1) * width + j - 1] + 1
+            } else {
+                lengths[(i - 1) * width + j].max(lengths[i * width + j - 1])
+            };
+        }
+    }
+
+    let mut subsequence = Vec::with_capacity(lengths[a.len() * width + b.len()]);
+    let mut matches = Vec::new();
+    let mut current: Option<LcsMatch> = None;
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > 0 && j > 0 {
+        let mut emit = false;
+        if a[i - 1] == b[j - 1] {
+            subsequence.push(a[i - 1]);
+            match current.as_mut() {
+                None => {
+                    current = Some(LcsMatch {
+                        a: (i - 1, i - 1),
+                        b: (j - 1, j - 1),
+                    })
+                }
+                Some(run) if run.a.0 == i && run.b.0 == j => {
+                    run.a.0 -= 1;
+                    run.b.0 -= 1;
+                }
+                Some(_) => emit = true,
+            }
+            if current.is_some_and(|run| run.a.0 == 0 || run.b.0 == 0) {
+                emit = true;
+            }
+            i -= 1;
+            j -= 1;
+        } else {
+            if lengths[(i - 1) * width + j] > lengths[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            emit = current.is_some();
+        }
+
+        if emit {
+            matches.extend(current.take());
+        }
+    }
+    subsequence.reverse();
+
+    Lcs {
+        subsequence,
+        matches,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lcs, LcsMatch};
+
+    #[test]
+    fn lcs_subsequence() {
+        assert_eq!(lcs(b"ohmytext", b"mynewtext").subsequence, b"mytext");
+        assert_eq!(lcs(b"", b"abc").subsequence, b"");
+        assert_eq!(lcs(b"abc", b"xyz").subsequence, b"");
+        assert_eq!(lcs(b"abc", b"abc").subsequence, b"abc");
+    }
+
+    #[test]
+    fn lcs_matches() {
+        // Example from the Redis documentation
+        let result = lcs(b"ohmytext", b"mynewtext");
+        assert_eq!(
+            result.matches,
+            vec![
+                LcsMatch {
+                    a: (4, 7),
+                    b: (5, 8)
+                },
+                LcsMatch {
+                    a: (2, 3),
+                    b: (0, 1)
+                },
+            ]
+        );
+        assert_eq!(result.matches[0].match_len(), 4);
+        assert_eq!(result.matches[1].match_len(), 2);
+    }
+}
@@ -1,15 +1,35 @@
 use actor::{master::MasterActor, replica::ReplicaActor};
-use config::{parse_config, Config, DBFile, ReplicationRole};
+use config::{parse_config, Config, ReplicationRole};
 use connection::{stream::RedisStream, Connection};
 use store::Store;
 
-use std::net::{TcpListener, TcpStream};
+use std::{
+    io::{self, Write},
+    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use tokio::{
+    net::TcpSocket,
+    signal::unix::{signal, SignalKind},
+};
 
 pub mod actor;
 pub mod config;
 pub mod connection;
+pub mod glob;
+pub mod lcs;
 pub mod store;
 
+/// Redis version the server is compatible with, as reported to clients and in RDB files.
+pub const REDIS_VERSION: &str = "7.2.0";
+
+const PROTECTED_MODE_ERROR: &str = "-DENIED Redis is running in protected mode because protected mode is enabled and no password is set for the default user. Disable protected mode with --protected-mode no, or set a password with --requirepass.\r\n";
+
 fn main() {
     println!("Logs from your program will appear here!");
     let config = parse_config();
@@ -25,18 +45,22 @@ pub fn build_and_run_replica() {
     let config = parse_config();
     let store = build_store(&config);
 
-    let listener = TcpListener::bind(format!("127.0.0.1:{}", config.port)).unwrap();
-    listener
-        .set_nonblocking(true)
-        .expect("Cannot put TCP listener in non-blocking mode");
+    let listener = bind_listener(&config).expect("Cannot listen on the configured address");
 
     let mut store = MasterActor::new(store, config.clone());
+    let shutdown = install_shutdown_handler();
 
     let mut connections: Vec<Connection> = Vec::new();
 
     loop {
-        if let Some(stream) = check_for_new_connections(&listener) {
-            let conn = Connection::new(stream, store.get_tx());
+        if shutdown.load(Ordering::SeqCst) {
+            store.shutdown();
+            return;
+        }
+
+        if let Some(stream) = check_for_new_connections(&listener, &config) {
+            let conn = Connection::new(stream, store.get_tx())
+                .with_max_pipeline_commands(config.max_pipeline_commands);
             connections.push(conn);
         }
 
@@ -44,6 +68,7 @@ pub fn build_and_run_replica() {
             conn.poll();
             store.poll();
         }
+        connections.retain(|conn| !conn.is_closed());
     }
 }
 
@@ -51,24 +76,26 @@ pub fn build_and_run_master() {
     let config = parse_config();
     let store = build_store(&config);
 
-    let listener = TcpListener::bind(format!("127.0.0.1:{}", config.port)).unwrap();
-    listener
-        .set_nonblocking(true)
-        .expect("Cannot put TCP listener in non-blocking mode");
+    let listener = bind_listener(&config).expect("Cannot listen on the configured address");
 
     let mut store = ReplicaActor::new(store, config.clone());
-    let Some(mut connection_with_master) = store.init_replication() else {
-        return;
-    };
-    connection_with_master.poll();
-    store.poll();
+    let shutdown = install_shutdown_handler();
 
     let mut connections: Vec<Connection> = Vec::new();
-    connections.push(connection_with_master);
 
     loop {
-        if let Some(stream) = check_for_new_connections(&listener) {
-            let conn = Connection::new(stream, store.get_tx());
+        if shutdown.load(Ordering::SeqCst) {
+            store.shutdown();
+            return;
+        }
+
+        if let Some(connection_with_master) = store.connect_to_master() {
+            connections.push(connection_with_master);
+        }
+
+        if let Some(stream) = check_for_new_connections(&listener, &config) {
+            let conn = Connection::new(stream, store.get_tx())
+                .with_max_pipeline_commands(config.max_pipeline_commands);
             connections.push(conn);
         }
 
@@ -76,25 +103,125 @@ pub fn build_and_run_master() {
             conn.poll();
             store.poll();
         }
+        connections.retain(|conn| !conn.is_closed());
     }
 }
 
-fn check_for_new_connections(listener: &TcpListener) -> Option<RedisStream<TcpStream>> {
-    if let Ok((stream, _)) = listener.accept() {
-        stream
-            .set_nonblocking(true)
-            .expect("Cannot put TCP stream in non-blocking mode");
+/// Wait for SIGTERM or SIGINT in a background thread, returning the flag raised once one of
+/// them is received so that the run loop can exit cleanly.
+fn install_shutdown_handler() -> Arc<AtomicBool> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let flag = shutdown.clone();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .expect("Cannot build the signal handling runtime");
+    let (mut sigterm, mut sigint) = runtime.block_on(async {
+        (
+            signal(SignalKind::terminate()).expect("Cannot install SIGTERM handler"),
+            signal(SignalKind::interrupt()).expect("Cannot install SIGINT handler"),
+        )
+    });
+    thread::spawn(move || {
+        runtime.block_on(async {
+            tokio::select! {
+                _ = sigterm.recv() => println!("Received SIGTERM, shutting down"),
+                _ = sigint.recv() => println!("Received SIGINT, shutting down"),
+            }
+        });
+        flag.store(true, Ordering::SeqCst);
+    });
+    shutdown
+}
+
+/// Listen on the configured address, with an accept queue of `tcp-backlog` connections.
+fn bind_listener(config: &Config) -> io::Result<TcpListener> {
+    let addr = (config.bind.as_str(), config.port as u16)
+        .to_socket_addrs()?
+        .next()
+        .ok_or(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no address to bind to",
+        ))?;
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    // Tokio listeners are registered with a runtime, only needed until turned into a std one
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()?;
+    let _guard = runtime.enter();
+    let listener = socket.listen(config.tcp_backlog)?.into_std()?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// Socket options of accepted client connections.
+fn configure_stream(stream: &TcpStream, config: &Config) -> io::Result<()> {
+    stream.set_nonblocking(true)?;
+    stream.set_nodelay(config.tcp_nodelay)
+}
+
+fn check_for_new_connections(
+    listener: &TcpListener,
+    config: &Config,
+) -> Option<RedisStream<TcpStream>> {
+    if let Ok((mut stream, addr)) = listener.accept() {
+        if config.is_protected_from(&addr.ip()) {
+            println!("Refusing connection from {addr} (protected mode)");
+            let _ = stream.write_all(PROTECTED_MODE_ERROR.as_bytes());
+            let _ = stream.shutdown(Shutdown::Both);
+            return None;
+        }
+        if let Err(err) = configure_stream(&stream, config) {
+            println!("Dropping connection from {addr}, cannot configure it: {err}");
+            let _ = stream.shutdown(Shutdown::Both);
+            return None;
+        }
         println!("New client connection");
-        return Some(RedisStream::new(stream));
+        return Some(RedisStream::new(stream, config.proto_max_bulk_len));
     }
     None
 }
 
 fn build_store(config: &Config) -> Store {
-    if let Some(DBFile { dir, dbfilename }) = &config.dbfile {
-        if let Some(store) = Store::from_dbfile(dir, dbfilename) {
-            return store;
-        }
+    Store::from_dbfile(
+        &config.dbfile.dir,
+        &config.dbfile.dbfilename,
+        config.databases,
+    )
+    .unwrap_or_else(|| Store::with_databases(config.databases))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpStream;
+
+    use crate::{
+        bind_listener,
+        config::{Args, Config},
+        configure_stream,
+    };
+
+    #[test]
+    fn client_connections_have_nodelay() {
+        let config = Config::from_args(Args::from([
+            (String::from("port"), String::from("0")),
+            (String::from("tcp-backlog"), String::from("16")),
+        ]));
+        let listener = bind_listener(&config).unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+        let (stream, _) = loop {
+            if let Ok(accepted) = listener.accept() {
+                break accepted;
+            }
+        };
+        configure_stream(&stream, &config).unwrap();
+        assert!(stream.nodelay().unwrap());
     }
-    Store::new()
 }
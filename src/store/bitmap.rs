@@ -0,0 +1,470 @@
+use super::{Store, WrongTypeError};
+
+/// Unit of the `start` and `end` offsets of bitmap range commands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitUnit {
+    Byte,
+    Bit,
+}
+
+/// Signed or unsigned integer type of a BITFIELD field, `i1` to `i64` or `u1` to `u63`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BitFieldType {
+    pub signed: bool,
+    pub bits: usize,
+}
+
+impl TryFrom<&str> for BitFieldType {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let error = "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not \
+                     supported but i64 is.";
+        let (signed, bits) = match value.split_at_checked(1) {
+            Some(("i" | "I", bits)) => (true, bits),
+            Some(("u" | "U", bits)) => (false, bits),
+            _ => return Err(error),
+        };
+        let bits = bits.parse::<usize>().map_err(|_| error)?;
+        let max_bits = if signed { 64 } else { 63 };
+        if !(1..=max_bits).contains(&bits) {
+            return Err(error);
+        }
+        Ok(BitFieldType { signed, bits })
+    }
+}
+
+impl BitFieldType {
+    fn range(&self) -> (i128, i128) {
+        if self.signed {
+            (-(1 << (self.bits - 1)), (1 << (self.bits - 1)) - 1)
+        } else {
+            (0, (1 << self.bits) - 1)
+        }
+    }
+}
+
+/// Behavior of BITFIELD writes whose result does not fit in the field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitFieldOverflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+impl BitFieldOverflow {
+    /// Bring `value` within the range of `field_type`, `None` meaning the write must not happen.
+    fn apply(&self, value: i128, field_type: BitFieldType) -> Option<i64> {
+        let (min, max) = field_type.range();
+        if (min..=max).contains(&value) {
+            return Some(value as i64);
+        }
+        let value = match self {
+            BitFieldOverflow::Wrap => (value - min).rem_euclid(max - min + 1) + min,
+            BitFieldOverflow::Sat => value.clamp(min, max),
+            BitFieldOverflow::Fail => return None,
+        };
+        Some(value as i64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitFieldOperation {
+    Get {
+        field_type: BitFieldType,
+        offset: usize,
+    },
+    Set {
+        field_type: BitFieldType,
+        offset: usize,
+        value: i64,
+        overflow: BitFieldOverflow,
+    },
+    IncrBy {
+        field_type: BitFieldType,
+        offset: usize,
+        increment: i64,
+        overflow: BitFieldOverflow,
+    },
+}
+
+impl BitFieldOperation {
+    pub fn is_write(&self) -> bool {
+        !matches!(self, BitFieldOperation::Get { .. })
+    }
+}
+
+/// Value of the bit at `offset`, bits being numbered from the most significant bit of the first
+/// byte.
+fn bit_at(value: &[u8], offset: usize) -> bool {
+    value[offset / 8] & (0x80 >> (offset % 8)) != 0
+}
+
+/// Read a field, bits past the end of the string being zeros.
+fn read_field(value: &[u8], offset: usize, field_type: BitFieldType) -> i64 {
+    let mut field: u64 = 0;
+    for position in offset..offset + field_type.bits {
+        let bit = position / 8 < value.len() && bit_at(value, position);
+        field = field << 1 | u64::from(bit);
+    }
+    if field_type.signed && field_type.bits < 64 {
+        let shift = 64 - field_type.bits;
+        ((field << shift) as i64) >> shift
+    } else {
+        field as i64
+    }
+}
+
+/// Write the `bits` low bits of `field`, the string being large enough to hold them.
+fn write_field(value: &mut [u8], offset: usize, bits: usize, field: i64) {
+    for (i, position) in (offset..offset + bits).enumerate() {
+        let mask = 0x80 >> (position % 8);
+        if (field as u64) >> (bits - 1 - i) & 1 == 1 {
+            value[position / 8] |= mask;
+        } else {
+            value[position / 8] &= !mask;
+        }
+    }
+}
+
+impl Store {
+    /// Value of the bit at `offset` in the string stored at `key`, bits past the end being zeros.
+    pub fn get_bit(&mut self, key: &str, offset: usize) -> Result<bool, WrongTypeError> {
+        let value = self.get_string(key)?.unwrap_or_default();
+        Ok(offset / 8 < value.len() && bit_at(&value, offset))
+    }
+
+    /// Set or clear the bit at `offset`, growing the string with zeros as needed. Returns the
+    /// previous value of the bit.
+    pub fn set_bit(&mut self, key: &str, offset: usize, bit: bool) -> Result<bool, WrongTypeError> {
+        let mut value = self.get_string(key)?.unwrap_or_default();
+        if value.len() <= offset / 8 {
+            value.resize(offset / 8 + 1, 0);
+        }
+        let previous = bit_at(&value, offset);
+        write_field(&mut value, offset, 1, i64::from(bit));
+        self.update_string(key, value);
+        Ok(previous)
+    }
+
+    /// Position of the first bit set to `bit` in the string stored at `key`, within the optional
+    /// `start`..=`end` range (negative offsets counting from the end of the string).
+    ///
+    /// When looking for a clear bit without an explicit `end`, the string is considered padded
+    /// with zeros on the right, so the position of the first bit past the end is returned.
+    pub fn bitpos(
+        &mut self,
+        key: &str,
+        bit: bool,
+        start: Option<i64>,
+        end: Option<i64>,
+        unit: BitUnit,
+    ) -> Result<i64, WrongTypeError> {
+        let Some(value) = self.get_string(key)? else {
+            return Ok(if bit { -1 } else { 0 });
+        };
+
+        let len = match unit {
+            BitUnit::Byte => value.len() as i64,
+            BitUnit::Bit => value.len() as i64 * 8,
+        };
+        let resolve = |index: i64| {
+            if index < 0 {
+                (len + index).max(0)
+            } else {
+                index
+            }
+        };
+        let first = resolve(start.unwrap_or(0));
+        let last = resolve(end.unwrap_or(-1)).min(len - 1);
+        if first > last {
+            return Ok(-1);
+        }
+        let (first_bit, last_bit) = match unit {
+            BitUnit::Byte => (first * 8, last * 8 + 7),
+            BitUnit::Bit => (first, last),
+        };
+
+        let position =
+            (first_bit..=last_bit).find(|&offset| bit_at(&value, offset as usize) == bit);
+        Ok(match position {
+            Some(position) => position,
+            None if !bit && end.is_none() => last_bit + 1,
+            None => -1,
+        })
+    }
+
+    /// Apply BITFIELD operations in order, returning one result per operation: the value read by
+    /// GET, the previous value for SET, the new value for INCRBY, or `None` for a write that
+    /// failed on overflow.
+    ///
+    /// As in Redis, the string is created or zero-padded up front when any write could extend it.
+    pub fn bitfield(
+        &mut self,
+        key: &str,
+        operations: &[BitFieldOperation],
+    ) -> Result<Vec<Option<i64>>, WrongTypeError> {
+        let mut value = self.get_string(key)?;
+        let write_end = operations
+            .iter()
+            .filter_map(|operation| match operation {
+                BitFieldOperation::Get { .. } => None,
+                BitFieldOperation::Set {
+                    field_type, offset, ..
+                }
+                | BitFieldOperation::IncrBy {
+                    field_type, offset, ..
+                } => Some((offset + field_type.bits).div_ceil(8)),
+            })
+            .max();
+        if let Some(write_end) = write_end {
+            let value = value.get_or_insert_with(Vec::new);
+            if value.len() < write_end {
+                value.resize(write_end, 0);
+            }
+        }
+
+        let mut results = Vec::with_capacity(operations.len());
+        for operation in operations {
+            let bytes = value.as_deref_mut().unwrap_or_default();
+            let result = match *operation {
+                BitFieldOperation::Get { field_type, offset } => {
+                    Some(read_field(bytes, offset, field_type))
+                }
+                BitFieldOperation::Set {
+                    field_type,
+                    offset,
+                    value,
+                    overflow,
+                } => overflow.apply(value.into(), field_type).map(|new_value| {
+                    let previous = read_field(bytes, offset, field_type);
+                    write_field(bytes, offset, field_type.bits, new_value);
+                    previous
+                }),
+                BitFieldOperation::IncrBy {
+                    field_type,
+                    offset,
+                    increment,
+                    overflow,
+                } => {
+                    let previous = read_field(bytes, offset, field_type);
+                    let new_value =
+                        overflow.apply(i128::from(previous) + i128::from(increment), field_type);
+                    if let Some(new_value) = new_value {
+                        write_field(bytes, offset, field_type.bits, new_value);
+                    }
+                    new_value
+                }
+            };
+            results.push(result);
+        }
+
+        if let (Some(value), Some(_)) = (value, write_end) {
+            self.update_string(key, value);
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::{
+        bitmap::{BitFieldOperation, BitFieldOverflow, BitFieldType, BitUnit},
+        Store,
+    };
+
+    fn field(field_type: &str) -> BitFieldType {
+        BitFieldType::try_from(field_type).unwrap()
+    }
+
+    #[test]
+    fn set_and_get_bit() {
+        let mut store = Store::new();
+
+        assert_eq!(store.set_bit("mykey", 7, true), Ok(false));
+        assert_eq!(store.set_bit("mykey", 7, true), Ok(true));
+        assert_eq!(store.set_bit("mykey", 17, true), Ok(false));
+        assert_eq!(
+            store.get_string("mykey"),
+            Ok(Some(b"\x01\x00\x40".to_vec()))
+        );
+        assert_eq!(store.get_bit("mykey", 7), Ok(true));
+        assert_eq!(store.get_bit("mykey", 6), Ok(false));
+        assert_eq!(store.get_bit("mykey", 100), Ok(false));
+        assert_eq!(store.set_bit("mykey", 7, false), Ok(true));
+        assert_eq!(store.get_bit("missing", 0), Ok(false));
+    }
+
+    #[test]
+    fn bitpos() {
+        let mut store = Store::new();
+        store.set_string("mykey", b"\xff\xf0\x00", None);
+
+        assert_eq!(
+            store.bitpos("mykey", false, None, None, BitUnit::Byte),
+            Ok(12)
+        );
+        assert_eq!(
+            store.bitpos("mykey", true, Some(2), None, BitUnit::Byte),
+            Ok(-1)
+        );
+        assert_eq!(
+            store.bitpos("mykey", true, Some(-3), Some(-1), BitUnit::Byte),
+            Ok(0)
+        );
+        assert_eq!(
+            store.bitpos("mykey", true, Some(7), Some(15), BitUnit::Bit),
+            Ok(7)
+        );
+        assert_eq!(
+            store.bitpos("mykey", false, Some(1), Some(0), BitUnit::Byte),
+            Ok(-1)
+        );
+
+        assert_eq!(
+            store.bitpos("missing", false, None, None, BitUnit::Byte),
+            Ok(0)
+        );
+        assert_eq!(
+            store.bitpos("missing", true, None, None, BitUnit::Byte),
+            Ok(-1)
+        );
+    }
+
+    #[test]
+    fn bitpos_clear_bit_on_all_ones() {
+        let mut store = Store::new();
+        store.set_string("ones", b"\xff\xff\xff", None);
+
+        // Without an explicit end, the first bit past the end of the string
+        assert_eq!(
+            store.bitpos("ones", false, None, None, BitUnit::Byte),
+            Ok(24)
+        );
+        assert_eq!(
+            store.bitpos("ones", false, Some(1), None, BitUnit::Byte),
+            Ok(24)
+        );
+        // With an explicit end, not found
+        assert_eq!(
+            store.bitpos("ones", false, Some(0), Some(-1), BitUnit::Byte),
+            Ok(-1)
+        );
+        assert_eq!(
+            store.bitpos("ones", false, Some(0), Some(10), BitUnit::Bit),
+            Ok(-1)
+        );
+    }
+
+    #[test]
+    fn bitfield_types() {
+        assert_eq!(
+            BitFieldType::try_from("i64"),
+            Ok(BitFieldType {
+                signed: true,
+                bits: 64
+            })
+        );
+        assert_eq!(
+            BitFieldType::try_from("u63"),
+            Ok(BitFieldType {
+                signed: false,
+                bits: 63
+            })
+        );
+        for invalid in ["u64", "i0", "i65", "x8", "i", ""] {
+            assert!(BitFieldType::try_from(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn bitfield_get_and_set() {
+        let mut store = Store::new();
+        store.set_string("mykey", b"\xff\x0f", None);
+
+        let results = store.bitfield(
+            "mykey",
+            &[
+                BitFieldOperation::Get {
+                    field_type: field("u4"),
+                    offset: 0,
+                },
+                BitFieldOperation::Get {
+                    field_type: field("i4"),
+                    offset: 4,
+                },
+                BitFieldOperation::Set {
+                    field_type: field("u8"),
+                    offset: 12,
+                    value: 0xAB,
+                    overflow: BitFieldOverflow::Wrap,
+                },
+                BitFieldOperation::Get {
+                    field_type: field("u8"),
+                    offset: 12,
+                },
+            ],
+        );
+        assert_eq!(
+            results,
+            Ok(vec![Some(15), Some(-1), Some(0xF0), Some(0xAB)])
+        );
+        assert_eq!(
+            store.get_string("mykey"),
+            Ok(Some(b"\xff\x0a\xb0".to_vec()))
+        );
+
+        // Reads past the end of the string, and of missing keys, are zeros
+        let get = [BitFieldOperation::Get {
+            field_type: field("i64"),
+            offset: 100,
+        }];
+        assert_eq!(store.bitfield("mykey", &get), Ok(vec![Some(0)]));
+        assert_eq!(store.bitfield("missing", &get), Ok(vec![Some(0)]));
+        assert_eq!(store.get_string("missing"), Ok(None));
+    }
+
+    #[test]
+    fn bitfield_overflow() {
+        let mut store = Store::new();
+        let incrby = |increment, overflow| BitFieldOperation::IncrBy {
+            field_type: field("u2"),
+            offset: 0,
+            increment,
+            overflow,
+        };
+
+        let results = store.bitfield(
+            "counters",
+            &[
+                incrby(5, BitFieldOverflow::Wrap),
+                incrby(5, BitFieldOverflow::Sat),
+                incrby(1, BitFieldOverflow::Fail),
+                incrby(-4, BitFieldOverflow::Wrap),
+                incrby(-4, BitFieldOverflow::Sat),
+            ],
+        );
+        assert_eq!(results, Ok(vec![Some(1), Some(3), None, Some(3), Some(0)]));
+
+        let set = |field_type, value, overflow| BitFieldOperation::Set {
+            field_type: field(field_type),
+            offset: 8,
+            value,
+            overflow,
+        };
+        let results = store.bitfield(
+            "counters",
+            &[
+                set("i8", 200, BitFieldOverflow::Wrap),
+                set("i8", 200, BitFieldOverflow::Sat),
+                set("i8", -200, BitFieldOverflow::Fail),
+                set("u8", -1, BitFieldOverflow::Wrap),
+                set("i64", i64::MIN, BitFieldOverflow::Fail),
+            ],
+        );
+        assert_eq!(
+            results,
+            Ok(vec![Some(0), Some(-56), None, Some(127), Some(-(1 << 56))])
+        );
+    }
+}
@@ -1,22 +1,52 @@
-use std::{collections::HashMap, fs, path::Path};
+use std::{collections::BTreeMap, fs, io, path::Path};
 
 use chrono::{DateTime, Utc};
+use indexmap::IndexMap;
 
-use crate::store::{Item, ValueType};
+use crate::{
+    store::{
+        listpack::{decode_listpack, encode_listpack, ListpackEntry},
+        stream::{ConsumerGroup, PendingEntry, Stream, StreamEntry, StreamEntryId},
+        Item, ValueType,
+    },
+    REDIS_VERSION,
+};
 
 use super::Store;
 
+/// Write an RDB file, creating its directory if needed.
+pub fn write_dbfile(path: &Path, content: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, content)
+}
+
+const RDB_TYPE_STRING: u8 = 0;
+const RDB_TYPE_STREAM_LISTPACKS: u8 = 15;
+const RDB_TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const RDB_TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+
 impl Store {
-    pub fn from_dbfile(dir: &str, dbname: &str) -> Option<Store> {
+    pub fn from_dbfile(dir: &str, dbname: &str, databases: usize) -> Option<Store> {
         let path = Path::new(dir).join(dbname);
-        let mut content = fs::read(path).ok()?.into_iter();
+        Store::from_rdb(fs::read(path).ok()?, databases)
+    }
+
+    /// Load an RDB file into a store with `databases` databases. Files selecting a database out
+    /// of this range are rejected.
+    pub fn from_rdb(content: Vec<u8>, databases: usize) -> Option<Store> {
+        let mut content = content.into_iter();
 
         let magic_word = parse_magic_word(&mut content)?;
         let version = parse_version(&mut content)?;
         println!("magic word: {magic_word}");
         println!("version: {version}");
 
-        let mut store: HashMap<String, Item> = HashMap::new();
+        let mut store = Store::with_databases(databases);
 
         while let Some(op_code) = content.next() {
             match op_code {
@@ -25,8 +55,12 @@ impl Store {
                     println!("Auxiliary field : {:?} = {:?}", key, value);
                 }
                 0xFE => {
-                    let db_number = content.next()?;
+                    let db_number = parse_length_encoded_int(&mut content)?;
                     println!("Selecting Database num: {db_number:?}");
+                    if !store.select(db_number as usize) {
+                        println!("Database {db_number} is out of range");
+                        return None;
+                    }
                 }
                 0xFB => {
                     let hash_table_size = parse_length_encoded_int(&mut content)?;
@@ -36,13 +70,7 @@ impl Store {
                     for _ in 0..hash_table_size {
                         let (key, value, expiry) = parse_key_value(&mut content)?;
                         println!("{key:?}: {value:?} (expired at {expiry:?})");
-                        store.insert(
-                            key,
-                            Item {
-                                value: ValueType::String(value),
-                                expiry,
-                            },
-                        );
+                        store.insert_item(store.selected, key, Item::new(value, expiry));
                     }
                 }
                 0xFF => {
@@ -57,13 +85,184 @@ impl Store {
                 }
             }
         }
-        Some(Store {
-            store,
-            n_replicas: 0,
-        })
+        store.select(0);
+        Some(store)
+    }
+
+    /// Serialize the store in the RDB format read by `Store::from_rdb`.
+    /// Length of the RDB encoding of the value at `key`, as reported by `DEBUG OBJECT`.
+    pub fn serialized_length(&self, key: &str) -> Option<usize> {
+        let item = self.get_item(key)?;
+        let mut content = Vec::new();
+        encode_value(&item.value, &mut content);
+        Some(content.len())
+    }
+
+    pub fn to_rdb(&self) -> Vec<u8> {
+        let mut content = b"REDIS0011".to_vec();
+        content.push(0xFA);
+        encode_string(b"redis-ver", &mut content);
+        encode_string(REDIS_VERSION.as_bytes(), &mut content);
+
+        for (index, db) in self.databases.iter().enumerate() {
+            let items: Vec<(&String, &Item)> =
+                db.iter().filter(|(_, item)| !item.is_expired()).collect();
+            // Like Redis, only non empty databases are written, except the first one
+            if items.is_empty() && index > 0 {
+                continue;
+            }
+            content.push(0xFE);
+            encode_length(index, &mut content);
+            encode_items(&items, &mut content);
+        }
+
+        // No checksum, signaled by 8 zero bytes
+        content.push(0xFF);
+        content.extend([0u8; 8]);
+        content
+    }
+}
+
+fn encode_items(items: &[(&String, &Item)], content: &mut Vec<u8>) {
+    let with_expiry = items
+        .iter()
+        .filter(|(_, item)| item.expiry.is_some())
+        .count();
+
+    content.push(0xFB);
+    encode_length(items.len(), content);
+    encode_length(with_expiry, content);
+    for (key, item) in items {
+        if let Some(expiry) = item.expiry {
+            content.push(0xFC);
+            content.extend((expiry.timestamp_millis() as u64).to_le_bytes());
+        }
+        content.push(match &item.value {
+            ValueType::String(_) => RDB_TYPE_STRING,
+            ValueType::Stream(_) => RDB_TYPE_STREAM_LISTPACKS,
+        });
+        encode_string(key.as_bytes(), content);
+        encode_value(&item.value, content);
+    }
+}
+
+fn encode_value(value: &ValueType, content: &mut Vec<u8>) {
+    match value {
+        ValueType::String(value) => encode_string(value, content),
+        ValueType::Stream(stream) => encode_stream(stream, content),
+    }
+}
+
+fn encode_length(length: usize, content: &mut Vec<u8>) {
+    if length < 1 << 6 {
+        content.push(length as u8);
+    } else if length < 1 << 14 {
+        content.extend([0b01000000 | (length >> 8) as u8, length as u8]);
+    } else if length <= u32::MAX as usize {
+        content.push(0b10000000);
+        content.extend((length as u32).to_be_bytes());
+    } else {
+        content.push(0b10000001);
+        content.extend((length as u64).to_be_bytes());
+    }
+}
+
+fn encode_string(value: &[u8], content: &mut Vec<u8>) {
+    encode_length(value.len(), content);
+    content.extend(value);
+}
+
+/// Stream entries are written one per listpack node, followed by the consumer groups.
+fn encode_stream(stream: &Stream, content: &mut Vec<u8>) {
+    encode_length(stream.entries.len(), content);
+    for entry in &stream.entries {
+        let mut master_id = (entry.id.timestamp as u64).to_be_bytes().to_vec();
+        master_id.extend((entry.id.sequence_number as u64).to_be_bytes());
+        encode_string(&master_id, content);
+
+        let fields = entry.values.len() as i64;
+        // Master entry: count, deleted, fields and the master entry terminator
+        let mut node = vec![
+            ListpackEntry::Integer(1),
+            ListpackEntry::Integer(0),
+            ListpackEntry::Integer(fields),
+        ];
+        node.extend(
+            entry
+                .values
+                .keys()
+                .map(|field| ListpackEntry::String(field.as_bytes().to_vec())),
+        );
+        node.push(ListpackEntry::Integer(0));
+        // The entry itself, with the same fields as the master entry and a 0-0 ID delta
+        node.extend([
+            ListpackEntry::Integer(STREAM_ITEM_FLAG_SAMEFIELDS),
+            ListpackEntry::Integer(0),
+            ListpackEntry::Integer(0),
+        ]);
+        node.extend(
+            entry
+                .values
+                .values()
+                .map(|value| ListpackEntry::String(value.as_bytes().to_vec())),
+        );
+        node.push(ListpackEntry::Integer(fields + 3));
+        encode_string(&encode_listpack(&node), content);
+    }
+
+    encode_length(stream.entries.len(), content);
+    encode_length(stream.last_id.timestamp, content);
+    encode_length(stream.last_id.sequence_number, content);
+    let mut groups: Vec<_> = stream.groups.iter().collect();
+    groups.sort_by_key(|(name, _)| *name);
+    encode_length(groups.len(), content);
+    for (name, group) in groups {
+        encode_consumer_group(name, group, content);
     }
 }
 
+/// A group is written with its whole pending entries list, then its consumers with the IDs of
+/// the entries delivered to each of them. Consumers are only known through their pending entries.
+fn encode_consumer_group(name: &str, group: &ConsumerGroup, content: &mut Vec<u8>) {
+    encode_string(name.as_bytes(), content);
+    encode_length(group.last_delivered_id.timestamp, content);
+    encode_length(group.last_delivered_id.sequence_number, content);
+
+    encode_length(group.pending.len(), content);
+    for (id, pending) in &group.pending {
+        encode_raw_stream_id(id, content);
+        content.extend((pending.delivery_time.timestamp_millis() as u64).to_le_bytes());
+        encode_length(pending.delivery_count, content);
+    }
+
+    let mut consumers: BTreeMap<&str, Vec<(&StreamEntryId, &PendingEntry)>> = BTreeMap::new();
+    for (id, pending) in &group.pending {
+        consumers
+            .entry(&pending.consumer)
+            .or_default()
+            .push((id, pending));
+    }
+    encode_length(consumers.len(), content);
+    for (consumer, entries) in consumers {
+        encode_string(consumer.as_bytes(), content);
+        let seen_time = entries
+            .iter()
+            .map(|(_, pending)| pending.delivery_time.timestamp_millis())
+            .max()
+            .unwrap_or_default();
+        content.extend((seen_time as u64).to_le_bytes());
+        encode_length(entries.len(), content);
+        for (id, _) in entries {
+            encode_raw_stream_id(id, content);
+        }
+    }
+}
+
+fn encode_raw_stream_id(id: &StreamEntryId, content: &mut Vec<u8>) {
+    content.extend((id.timestamp as u64).to_be_bytes());
+    content.extend((id.sequence_number as u64).to_be_bytes());
+}
+
 fn parse_magic_word<I>(content: &mut I) -> Option<String>
 where
     I: Iterator<Item = u8>,
@@ -105,7 +304,7 @@ where
     content.next()
 }
 
-fn parse_key_value<I>(content: &mut I) -> Option<(String, String, Option<DateTime<Utc>>)>
+fn parse_key_value<I>(content: &mut I) -> Option<(String, ValueType, Option<DateTime<Utc>>)>
 where
     I: Iterator<Item = u8>,
 {
@@ -121,16 +320,187 @@ where
     };
 
     match first_byte {
-        0 => {
+        RDB_TYPE_STRING => {
             let (Some(Value::String(key)), Some(Value::String(value))) =
                 (parse(content), parse(content))
             else {
                 return None;
             };
-            Some((key, value, expiry))
+            Some((
+                String::from_utf8(key).ok()?,
+                ValueType::String(value),
+                expiry,
+            ))
+        }
+        RDB_TYPE_STREAM_LISTPACKS | RDB_TYPE_STREAM_LISTPACKS_2 | RDB_TYPE_STREAM_LISTPACKS_3 => {
+            let Some(Value::String(key)) = parse(content) else {
+                return None;
+            };
+            let stream = parse_stream(first_byte, content)?;
+            Some((
+                String::from_utf8(key).ok()?,
+                ValueType::Stream(stream),
+                expiry,
+            ))
+        }
+        first_byte => {
+            println!("Unsupported value type: {first_byte}");
+            None
+        }
+    }
+}
+
+/// Parse a stream value, with its consumer groups.
+fn parse_stream<I>(rdb_type: u8, content: &mut I) -> Option<Stream>
+where
+    I: Iterator<Item = u8>,
+{
+    let mut stream = Stream::default();
+    let nodes = parse_length_encoded_int(content)?;
+    for _ in 0..nodes {
+        let (Some(Value::String(master_id)), Some(Value::String(listpack))) =
+            (parse(content), parse(content))
+        else {
+            return None;
+        };
+        let master_id = StreamEntryId {
+            timestamp: u64::from_be_bytes(master_id.get(..8)?.try_into().ok()?) as usize,
+            sequence_number: u64::from_be_bytes(master_id.get(8..16)?.try_into().ok()?) as usize,
+        };
+        stream
+            .entries
+            .extend(parse_stream_node(&master_id, &decode_listpack(&listpack)?)?);
+    }
+
+    // Length and last ID, then first ID, max deleted ID and entries added for newer versions
+    parse_length_encoded_int(content)?;
+    stream.last_id = StreamEntryId {
+        timestamp: usize::try_from(parse_length_encoded_int(content)?).ok()?,
+        sequence_number: usize::try_from(parse_length_encoded_int(content)?).ok()?,
+    };
+    if rdb_type != RDB_TYPE_STREAM_LISTPACKS {
+        for _ in 0..5 {
+            parse_length_encoded_int(content)?;
         }
-        first_byte => panic!("Not implemented yet for first_byte={}.", first_byte),
     }
+    let consumer_groups = parse_length_encoded_int(content)?;
+    for _ in 0..consumer_groups {
+        let (name, group) = parse_consumer_group(rdb_type, content)?;
+        stream.groups.insert(name, group);
+    }
+    Some(stream)
+}
+
+/// Parse a consumer group. Pending entries that no consumer claims are dropped.
+fn parse_consumer_group<I>(rdb_type: u8, content: &mut I) -> Option<(String, ConsumerGroup)>
+where
+    I: Iterator<Item = u8>,
+{
+    let Some(Value::String(name)) = parse(content) else {
+        return None;
+    };
+    let last_delivered_id = StreamEntryId {
+        timestamp: usize::try_from(parse_length_encoded_int(content)?).ok()?,
+        sequence_number: usize::try_from(parse_length_encoded_int(content)?).ok()?,
+    };
+    if rdb_type != RDB_TYPE_STREAM_LISTPACKS {
+        // Entries read
+        parse_length_encoded_int(content)?;
+    }
+
+    let mut deliveries = BTreeMap::new();
+    for _ in 0..parse_length_encoded_int(content)? {
+        let id = parse_raw_stream_id(content)?;
+        let delivery_time = DateTime::from_timestamp_millis(parse_u64(content)? as i64)?;
+        let delivery_count = usize::try_from(parse_length_encoded_int(content)?).ok()?;
+        deliveries.insert(id, (delivery_time, delivery_count));
+    }
+
+    let mut group = ConsumerGroup {
+        last_delivered_id,
+        pending: BTreeMap::new(),
+    };
+    for _ in 0..parse_length_encoded_int(content)? {
+        let Some(Value::String(consumer)) = parse(content) else {
+            return None;
+        };
+        let consumer = String::from_utf8(consumer).ok()?;
+        // Seen time, and active time for newer versions
+        parse_u64(content)?;
+        if rdb_type == RDB_TYPE_STREAM_LISTPACKS_3 {
+            parse_u64(content)?;
+        }
+        for _ in 0..parse_length_encoded_int(content)? {
+            let id = parse_raw_stream_id(content)?;
+            let (delivery_time, delivery_count) = deliveries.remove(&id)?;
+            group.pending.insert(
+                id,
+                PendingEntry {
+                    consumer: consumer.clone(),
+                    delivery_time,
+                    delivery_count,
+                },
+            );
+        }
+    }
+    Some((String::from_utf8(name).ok()?, group))
+}
+
+fn parse_raw_stream_id<I>(content: &mut I) -> Option<StreamEntryId>
+where
+    I: Iterator<Item = u8>,
+{
+    let mut bytes = [0u8; 16];
+    for byte in &mut bytes {
+        *byte = content.next()?;
+    }
+    Some(StreamEntryId {
+        timestamp: u64::from_be_bytes(bytes[..8].try_into().ok()?) as usize,
+        sequence_number: u64::from_be_bytes(bytes[8..].try_into().ok()?) as usize,
+    })
+}
+
+/// Decode the entries of a listpack node, skipping deleted ones.
+fn parse_stream_node(
+    master_id: &StreamEntryId,
+    node: &[ListpackEntry],
+) -> Option<Vec<StreamEntry>> {
+    let mut node = node.iter();
+    let mut next_integer = || node.next()?.as_integer();
+    let count = next_integer()?;
+    let deleted = next_integer()?;
+    let master_fields: Vec<String> = (0..next_integer()?)
+        .map(|_| Some(node.next()?.to_text()))
+        .collect::<Option<_>>()?;
+    // Master entry terminator
+    node.next()?;
+
+    let mut entries = Vec::new();
+    for _ in 0..count + deleted {
+        let mut next_integer = || node.next()?.as_integer();
+        let flags = next_integer()?;
+        let id = StreamEntryId {
+            timestamp: master_id.timestamp + usize::try_from(next_integer()?).ok()?,
+            sequence_number: master_id.sequence_number + usize::try_from(next_integer()?).ok()?,
+        };
+        let values: IndexMap<String, String> = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|field| Some((field.clone(), node.next()?.to_text())))
+                .collect::<Option<_>>()?
+        } else {
+            let fields = node.next()?.as_integer()?;
+            (0..fields)
+                .map(|_| Some((node.next()?.to_text(), node.next()?.to_text())))
+                .collect::<Option<_>>()?
+        };
+        // lp-count
+        node.next()?;
+        if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+            entries.push(StreamEntry { id, values });
+        }
+    }
+    Some(entries)
 }
 
 fn parse_u32<I>(content: &mut I) -> Option<u32>
@@ -158,7 +528,7 @@ where
 #[derive(Debug, PartialEq, Eq)]
 
 enum Value {
-    String(String),
+    String(Vec<u8>),
     Integer(u32),
 }
 
@@ -168,16 +538,14 @@ where
 {
     let length_byte = content.next()?;
     match length_byte & 0b11000000 {
-        0b00000000 => {
-            let length_to_parse = length_byte & 0b00111111;
+        0b00000000 | 0b01000000 | 0b10000000 => {
+            let length_to_parse = parse_length(length_byte, content)?;
             let mut value: Vec<u8> = Vec::new();
             for _ in 0..length_to_parse {
                 value.push(content.next()?);
             }
-            Some(Value::String(String::from_utf8(value).ok()?))
+            Some(Value::String(value))
         }
-        0b01000000 => panic!("Not implemented"),
-        0b10000000 => panic!("Not implemented"),
         0b11000000 => match length_byte & 0b00111111 {
             0 => parse_u8(content).map(|value| Value::Integer(value.into())),
             1 => panic!("Not implemented"),
@@ -188,38 +556,206 @@ where
     }
 }
 
-fn parse_length_encoded_int<I>(content: &mut I) -> Option<u32>
+fn parse_length_encoded_int<I>(content: &mut I) -> Option<u64>
 where
     I: Iterator<Item = u8>,
 {
     let length_byte = content.next()?;
     match length_byte & 0b11000000 {
-        0b00000000 => Some((length_byte & 0b00111111).into()),
-        0b01000000 => panic!("Not implemented"),
-        0b10000000 => panic!("Not implemented"),
+        0b00000000 | 0b01000000 | 0b10000000 => parse_length(length_byte, content),
         0b11000000 => match length_byte & 0b00111111 {
             0 => parse_u8(content).map(|value| value.into()),
             1 => panic!("Not implemented"),
-            2 => parse_u32(content),
+            2 => parse_u32(content).map(|value| value.into()),
             n => panic!("Unknown integer to parse, expected 0, 1 or 2 but got {n}"),
         },
         _ => panic!("Wrong bits format for length encoding."),
     }
 }
 
+/// Decode a 6, 14, 32 or 64 bits length, `length_byte` being the first byte of the encoding.
+fn parse_length<I>(length_byte: u8, content: &mut I) -> Option<u64>
+where
+    I: Iterator<Item = u8>,
+{
+    match length_byte {
+        0b00000000..=0b00111111 => Some((length_byte & 0b00111111).into()),
+        0b01000000..=0b01111111 => {
+            Some(u64::from(length_byte & 0b00111111) << 8 | u64::from(content.next()?))
+        }
+        0b10000000 => {
+            let mut values = [0u8; 4];
+            for value in &mut values {
+                *value = content.next()?;
+            }
+            Some(u32::from_be_bytes(values).into())
+        }
+        0b10000001 => {
+            let mut values = [0u8; 8];
+            for value in &mut values {
+                *value = content.next()?;
+            }
+            Some(u64::from_be_bytes(values))
+        }
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::store::Store;
+    use chrono::{TimeDelta, Utc};
+    use indexmap::IndexMap;
+
+    use crate::store::{
+        stream::{ConsumerGroup, RequestedStreamEntryId, StreamEntryId},
+        Store, DEFAULT_DATABASES,
+    };
 
     #[test]
     fn load_store_from_dbfile() {
         let dir = "./tests/assets";
         let dbname = "dump.rdb";
 
-        let Some(store) = Store::from_dbfile(dir, dbname) else {
+        let Some(mut store) = Store::from_dbfile(dir, dbname, DEFAULT_DATABASES) else {
             panic!("Cannot load store from file");
         };
 
-        assert_eq!(store.get_string("mykey"), Some(String::from("myval")));
+        assert_eq!(store.get_string("mykey"), Ok(Some(b"myval".to_vec())));
+    }
+
+    #[test]
+    fn rdb_round_trip() {
+        let mut store = Store::new();
+        let long_value = vec![b'x'; 20_000];
+        store.set_string("short", b"value", None);
+        store.set_string("long", &long_value, Some(60_000));
+        store.set_string("expired", b"value", None);
+        store.set_expiry("expired", Some(Utc::now() - TimeDelta::seconds(1)));
+
+        let Some(mut reloaded) = Store::from_rdb(store.to_rdb(), DEFAULT_DATABASES) else {
+            panic!("Cannot reload store from its own RDB");
+        };
+
+        assert_eq!(reloaded.get_string("short"), Ok(Some(b"value".to_vec())));
+        assert_eq!(reloaded.get_string("long"), Ok(Some(long_value)));
+        assert_eq!(
+            reloaded
+                .get_expiry("long")
+                .map(|expiry| expiry.timestamp_millis()),
+            store
+                .get_expiry("long")
+                .map(|expiry| expiry.timestamp_millis())
+        );
+        assert_eq!(reloaded.get_string("expired"), Ok(None));
+    }
+
+    #[test]
+    fn consumer_groups_are_kept_in_rdb() {
+        let mut store = Store::new();
+        let value = IndexMap::from([(String::from("f"), String::from("v"))]);
+        for timestamp in 1..=3 {
+            let id = StreamEntryId {
+                timestamp,
+                sequence_number: 0,
+            };
+            let _ =
+                store.add_stream_entry("s", &RequestedStreamEntryId::Explicit(id), &value, None);
+        }
+        let _ = store.create_consumer_group("s", "g", Some(StreamEntryId::default()), false);
+        let _ = store.create_consumer_group("s", "idle", None, false);
+        let _ = store.read_group("s", "g", "alice", None, Some(2), false);
+        let _ = store.read_group("s", "g", "bob", None, None, false);
+        let _ = store.read_group(
+            "s",
+            "g",
+            "alice",
+            Some(StreamEntryId::default()),
+            None,
+            false,
+        );
+
+        let Some(reloaded) = Store::from_rdb(store.to_rdb(), DEFAULT_DATABASES) else {
+            panic!("Cannot reload store from its own RDB");
+        };
+
+        for name in ["g", "idle"] {
+            let group = store.get_consumer_group("s", name).unwrap();
+            let reloaded_group = reloaded.get_consumer_group("s", name).unwrap();
+            assert_eq!(reloaded_group.last_delivered_id, group.last_delivered_id);
+            let pending = |group: &ConsumerGroup| {
+                group
+                    .pending
+                    .iter()
+                    .map(|(id, pending)| {
+                        (
+                            *id,
+                            pending.consumer.clone(),
+                            pending.delivery_time.timestamp_millis(),
+                            pending.delivery_count,
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(pending(reloaded_group), pending(group));
+        }
+        assert_eq!(
+            reloaded.get_consumer_group("s", "g").unwrap().pending.len(),
+            3
+        );
+    }
+
+    #[test]
+    fn stream_last_id_is_kept_in_rdb() {
+        let mut store = Store::new();
+        let value = IndexMap::from([(String::from("f"), String::from("v"))]);
+        // Far enough in the future for `*` not to catch up by itself
+        let future = StreamEntryId {
+            timestamp: (Utc::now() + TimeDelta::hours(1)).timestamp_millis() as usize,
+            sequence_number: 5,
+        };
+        let ids = [
+            RequestedStreamEntryId::AutoGenerate,
+            RequestedStreamEntryId::Explicit(future),
+        ]
+        .map(|request| store.add_stream_entry("s", &request, &value, None).unwrap());
+        assert_eq!(store.delete_stream_entries("s", &[future]), Ok(1));
+
+        let Some(mut reloaded) = Store::from_rdb(store.to_rdb(), DEFAULT_DATABASES) else {
+            panic!("Cannot reload store from its own RDB");
+        };
+        let id = reloaded
+            .add_stream_entry("s", &RequestedStreamEntryId::AutoGenerate, &value, None)
+            .unwrap();
+        assert!(ids.iter().all(|previous| id > *previous));
+    }
+
+    #[test]
+    fn unsupported_value_types_are_rejected() {
+        let mut content = b"REDIS0011".to_vec();
+        // A list, encoded as a quicklist
+        content.extend([0xFE, 0x00, 0xFB, 0x01, 0x00, 0x12, 0x01, b'l']);
+        content.push(0xFF);
+        content.extend([0u8; 8]);
+
+        assert!(Store::from_rdb(content, DEFAULT_DATABASES).is_none());
+    }
+
+    #[test]
+    fn databases_are_kept_in_rdb() {
+        let mut store = Store::with_databases(4);
+        store.set_string("first", b"1", None);
+        store.select(3);
+        store.set_string("last", b"2", None);
+
+        let Some(mut reloaded) = Store::from_rdb(store.to_rdb(), 4) else {
+            panic!("Cannot reload store from its own RDB");
+        };
+        assert_eq!(reloaded.get_string("first"), Ok(Some(b"1".to_vec())));
+        assert_eq!(reloaded.get_string("last"), Ok(None));
+        reloaded.select(3);
+        assert_eq!(reloaded.get_string("last"), Ok(Some(b"2".to_vec())));
+
+        // Databases beyond the configured count cannot be loaded
+        assert!(Store::from_rdb(store.to_rdb(), 2).is_none());
     }
 }
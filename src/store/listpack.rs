@@ -0,0 +1,191 @@
+//! Listpack encoding, used by the RDB format to serialize streams.
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ListpackEntry {
+    Integer(i64),
+    String(Vec<u8>),
+}
+
+impl ListpackEntry {
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            ListpackEntry::Integer(value) => Some(*value),
+            ListpackEntry::String(value) => std::str::from_utf8(value).ok()?.parse().ok(),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        match self {
+            ListpackEntry::Integer(value) => value.to_string(),
+            ListpackEntry::String(value) => String::from_utf8_lossy(value).to_string(),
+        }
+    }
+}
+
+const EOF: u8 = 0xFF;
+const HEADER_SIZE: usize = 6;
+
+pub fn encode_listpack(entries: &[ListpackEntry]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for entry in entries {
+        let start = body.len();
+        match entry {
+            ListpackEntry::Integer(value @ 0..=127) => body.push(*value as u8),
+            ListpackEntry::Integer(value) => {
+                body.push(0xF4);
+                body.extend(value.to_le_bytes());
+            }
+            ListpackEntry::String(value) if value.len() < 1 << 6 => {
+                body.push(0x80 | value.len() as u8);
+                body.extend(value);
+            }
+            ListpackEntry::String(value) if value.len() < 1 << 12 => {
+                body.extend([0xE0 | (value.len() >> 8) as u8, value.len() as u8]);
+                body.extend(value);
+            }
+            ListpackEntry::String(value) => {
+                body.push(0xF0);
+                body.extend((value.len() as u32).to_le_bytes());
+                body.extend(value);
+            }
+        }
+        let entry_size = body.len() - start;
+        body.extend(encode_backlen(entry_size));
+    }
+    body.push(EOF);
+
+    let mut listpack = ((HEADER_SIZE + body.len()) as u32).to_le_bytes().to_vec();
+    // The number of elements saturates, meaning it must be counted by walking the listpack
+    listpack.extend((entries.len().min(u16::MAX as usize) as u16).to_le_bytes());
+    listpack.extend(body);
+    listpack
+}
+
+pub fn decode_listpack(listpack: &[u8]) -> Option<Vec<ListpackEntry>> {
+    let mut entries = Vec::new();
+    let mut position = HEADER_SIZE;
+    loop {
+        let encoding = *listpack.get(position)?;
+        if encoding == EOF {
+            return Some(entries);
+        }
+        let data = &listpack[position + 1..];
+        let (entry, entry_size) = match encoding {
+            _ if encoding & 0x80 == 0 => (ListpackEntry::Integer(encoding.into()), 1),
+            _ if encoding & 0xC0 == 0x80 => {
+                let length = usize::from(encoding & 0x3F);
+                (
+                    ListpackEntry::String(data.get(..length)?.to_vec()),
+                    1 + length,
+                )
+            }
+            _ if encoding & 0xE0 == 0xC0 => {
+                let value = i64::from(encoding & 0x1F) << 8 | i64::from(*data.first()?);
+                (ListpackEntry::Integer(sign_extend(value, 13)), 2)
+            }
+            _ if encoding & 0xF0 == 0xE0 => {
+                let length = usize::from(encoding & 0x0F) << 8 | usize::from(*data.first()?);
+                let value = data.get(1..1 + length)?.to_vec();
+                (ListpackEntry::String(value), 2 + length)
+            }
+            0xF0 => {
+                let length = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+                let value = data.get(4..4 + length)?.to_vec();
+                (ListpackEntry::String(value), 5 + length)
+            }
+            0xF1..=0xF4 => {
+                let size = match encoding {
+                    0xF1 => 2,
+                    0xF2 => 3,
+                    0xF3 => 4,
+                    _ => 8,
+                };
+                let mut bytes = [0u8; 8];
+                bytes[..size].copy_from_slice(data.get(..size)?);
+                let value = sign_extend(i64::from_le_bytes(bytes), size as u32 * 8);
+                (ListpackEntry::Integer(value), 1 + size)
+            }
+            _ => return None,
+        };
+        entries.push(entry);
+        position += entry_size + encode_backlen(entry_size).len();
+    }
+}
+
+/// Size of an entry, written after it so that the listpack can be walked backward.
+fn encode_backlen(size: usize) -> Vec<u8> {
+    let bytes = match size {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    };
+    (0..bytes)
+        .map(|i| {
+            let shifted = (size >> (7 * (bytes - 1 - i))) as u8;
+            if i == 0 {
+                shifted
+            } else {
+                (shifted & 127) | 128
+            }
+        })
+        .collect()
+}
+
+fn sign_extend(value: i64, bits: u32) -> i64 {
+    if bits >= 64 {
+        return value;
+    }
+    let shift = 64 - bits;
+    (value << shift) >> shift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_listpack, encode_backlen, encode_listpack, ListpackEntry};
+
+    #[test]
+    fn listpack_round_trip() {
+        let entries = vec![
+            ListpackEntry::Integer(0),
+            ListpackEntry::Integer(127),
+            ListpackEntry::Integer(-1),
+            ListpackEntry::Integer(1_700_000_000_000),
+            ListpackEntry::String(b"field".to_vec()),
+            ListpackEntry::String(vec![b'a'; 200]),
+            ListpackEntry::String(vec![b'b'; 5000]),
+        ];
+        let listpack = encode_listpack(&entries);
+
+        assert_eq!(
+            u32::from_le_bytes(listpack[..4].try_into().unwrap()) as usize,
+            listpack.len()
+        );
+        assert_eq!(decode_listpack(&listpack), Some(entries));
+    }
+
+    #[test]
+    fn decode_redis_integer_encodings() {
+        // 13 bits -2, 16 bits 1000, 24 bits -100000, 32 bits 100000000
+        let listpack = [
+            0, 0, 0, 0, 4, 0, 0xDF, 0xFE, 2, 0xF1, 0xE8, 0x03, 3, 0xF2, 0x60, 0x79, 0xFE, 4, 0xF3,
+            0x00, 0xE1, 0xF5, 0x05, 5, 0xFF,
+        ];
+        assert_eq!(
+            decode_listpack(&listpack),
+            Some(vec![
+                ListpackEntry::Integer(-2),
+                ListpackEntry::Integer(1000),
+                ListpackEntry::Integer(-100000),
+                ListpackEntry::Integer(100000000),
+            ])
+        );
+    }
+
+    #[test]
+    fn backlen() {
+        assert_eq!(encode_backlen(5), vec![5]);
+        assert_eq!(encode_backlen(500), vec![3, 128 | 116]);
+    }
+}
@@ -1,30 +1,166 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, error::Error, fmt};
 
 use chrono::{DateTime, TimeDelta, Utc};
-use stream::Stream;
+use stream::{Stream, StreamEntry};
 
+pub mod bitmap;
 pub mod dbfile;
+mod listpack;
 pub mod stream;
 
+#[derive(Clone)]
 struct Item {
     value: ValueType,
+    encoding: Encoding,
     expiry: Option<DateTime<Utc>>,
+    last_access: DateTime<Utc>,
+    access_frequency: u8,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl Item {
+    fn new(value: ValueType, expiry: Option<DateTime<Utc>>) -> Item {
+        Item {
+            encoding: Encoding::of(&value),
+            value,
+            expiry,
+            last_access: Utc::now(),
+            access_frequency: 0,
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expiry.is_some_and(|expiry| expiry < Utc::now())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 enum ValueType {
-    String(String),
+    String(Vec<u8>),
     Stream(Stream),
 }
 
+/// Internal representation of a value, as reported by `OBJECT ENCODING`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Encoding {
+    Int,
+    Embstr,
+    Raw,
+    Stream,
+}
+
+/// Estimated memory used by a key besides its name and value: hash table entry, item metadata.
+const ITEM_OVERHEAD: usize = 64;
+/// Estimated memory used by a stream entry besides its fields and values.
+const STREAM_ENTRY_OVERHEAD: usize = 32;
+
+impl ValueType {
+    /// Rough estimate of the memory used by the value, in bytes.
+    fn memory_usage(&self) -> usize {
+        match self {
+            ValueType::String(value) => value.len(),
+            ValueType::Stream(stream) => stream.entries.iter().map(entry_memory_usage).sum(),
+        }
+    }
+}
+
+fn entry_memory_usage(entry: &StreamEntry) -> usize {
+    let fields: usize = entry.values.iter().map(|(k, v)| k.len() + v.len()).sum();
+    STREAM_ENTRY_OVERHEAD + fields
+}
+
+/// Rough estimate of the memory used by a key, in bytes.
+fn item_memory_usage(key: &str, item: &Item) -> usize {
+    ITEM_OVERHEAD + key.len() + item.value.memory_usage()
+}
+
+/// Longest string stored as `embstr` by Redis.
+const EMBSTR_SIZE_LIMIT: usize = 44;
+
+impl Encoding {
+    fn of(value: &ValueType) -> Encoding {
+        match value {
+            ValueType::String(value) if is_canonical_integer(value) => Encoding::Int,
+            ValueType::String(value) if value.len() <= EMBSTR_SIZE_LIMIT => Encoding::Embstr,
+            ValueType::String(_) => Encoding::Raw,
+            ValueType::Stream(_) => Encoding::Stream,
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Encoding::Int => "int",
+            Encoding::Embstr => "embstr",
+            Encoding::Raw => "raw",
+            Encoding::Stream => "stream",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Whether `value` is an `i64` that serializes back to the exact same bytes (no `+` sign, leading
+/// zeros or whitespace).
+fn is_canonical_integer(value: &[u8]) -> bool {
+    value.len() <= 20
+        && std::str::from_utf8(value)
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .is_some_and(|parsed| parsed.to_string().as_bytes() == value)
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ItemType {
     String,
     Stream,
 }
 
+#[derive(Debug, PartialEq)]
+pub struct WrongTypeError;
+impl Error for WrongTypeError {}
+
+impl fmt::Display for WrongTypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "WRONGTYPE Operation against a key holding the wrong kind of value"
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum IncrError {
+    NotAnInteger,
+    Overflow,
+    WrongType,
+}
+impl Error for IncrError {}
+
+impl fmt::Display for IncrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            IncrError::NotAnInteger => "ERR value is not an integer or out of range",
+            IncrError::Overflow => "ERR increment or decrement would overflow",
+            IncrError::WrongType => {
+                "WRONGTYPE Operation against a key holding the wrong kind of value"
+            }
+        };
+        write!(f, "{message}")
+    }
+}
+
+/// Number of databases when not configured with `databases`.
+pub const DEFAULT_DATABASES: usize = 16;
+
 pub struct Store {
-    store: HashMap<String, Item>,
+    /// Keyspace of each database, commands operating on the `selected` one.
+    databases: Vec<HashMap<String, Item>>,
+    selected: usize,
+    /// Whether reads leave the access time and frequency of keys untouched, for clients in
+    /// `CLIENT NO-TOUCH` mode.
+    no_touch: bool,
+    /// Estimated memory used by the keys of all databases, kept up to date on every write.
+    used_memory: usize,
     pub n_replicas: u64,
 }
 
@@ -36,80 +172,369 @@ impl Default for Store {
 
 impl Store {
     pub fn new() -> Store {
+        Store::with_databases(DEFAULT_DATABASES)
+    }
+
+    /// Empty store with `count` databases, at least one.
+    pub fn with_databases(count: usize) -> Store {
         Store {
-            store: HashMap::new(),
+            databases: vec![HashMap::new(); count.max(1)],
+            selected: 0,
+            no_touch: false,
+            used_memory: 0,
             n_replicas: 0,
         }
     }
 
-    pub fn set_string(&mut self, key: &str, value: &str, ttl: Option<usize>) {
+    pub fn databases(&self) -> usize {
+        self.databases.len()
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Make the database at `index` the one commands operate on. Returns false when out of range.
+    pub fn select(&mut self, index: usize) -> bool {
+        if index >= self.databases.len() {
+            return false;
+        }
+        self.selected = index;
+        true
+    }
+
+    pub fn set_no_touch(&mut self, no_touch: bool) {
+        self.no_touch = no_touch;
+    }
+
+    /// Number of keys and of keys with an expiry for each non empty database.
+    pub fn keyspace(&self) -> Vec<(usize, usize, usize)> {
+        self.databases
+            .iter()
+            .enumerate()
+            .filter(|(_, db)| !db.is_empty())
+            .map(|(index, db)| {
+                let expires = db.values().filter(|item| item.expiry.is_some()).count();
+                (index, db.len(), expires)
+            })
+            .collect()
+    }
+
+    fn db(&self) -> &HashMap<String, Item> {
+        &self.databases[self.selected]
+    }
+
+    fn db_mut(&mut self) -> &mut HashMap<String, Item> {
+        &mut self.databases[self.selected]
+    }
+
+    /// Insert `item` at `key` of the database at `db`, replacing any previous value.
+    fn insert_item(&mut self, db: usize, key: String, item: Item) {
+        self.used_memory += item_memory_usage(&key, &item);
+        if let Some(previous) = self.databases[db].get(&key) {
+            self.used_memory -= item_memory_usage(&key, previous);
+        }
+        self.databases[db].insert(key, item);
+    }
+
+    /// Remove `key` from the database at `db`, returning its item even when expired.
+    fn remove_item(&mut self, db: usize, key: &str) -> Option<Item> {
+        let item = self.databases[db].remove(key)?;
+        self.used_memory -= item_memory_usage(key, &item);
+        Some(item)
+    }
+
+    pub fn set_string(&mut self, key: &str, value: &[u8], ttl: Option<usize>) {
         let expiry = ttl.and_then(|s| {
             Utc::now().checked_add_signed(TimeDelta::milliseconds(i64::try_from(s).ok()?))
         });
-        let item = Item {
-            value: ValueType::String(String::from(value)),
-            expiry,
-        };
-        self.store.insert(String::from(key), item);
+        let item = Item::new(ValueType::String(value.to_vec()), expiry);
+        self.insert_item(self.selected, String::from(key), item);
     }
 
-    pub fn get_string(&self, key: &str) -> Option<String> {
-        let item = self.store.get(key)?;
-
-        if let Some(expiry) = item.expiry {
-            if expiry < Utc::now() {
-                return None;
-            }
-        }
+    pub fn get_string(&mut self, key: &str) -> Result<Option<Vec<u8>>, WrongTypeError> {
+        self.touch(key);
+        let Some(item) = self.get_item(key) else {
+            return Ok(None);
+        };
 
         let Item {
             value: ValueType::String(value),
-            expiry: _,
+            ..
         } = item
         else {
-            return None;
+            return Err(WrongTypeError);
         };
 
-        Some(value.clone())
+        Ok(Some(value.clone()))
+    }
+
+    pub fn incr(&mut self, key: &str) -> Result<i64, IncrError> {
+        self.incr_by(key, 1)
     }
 
-    pub fn incr(&mut self, key: &str) -> Option<usize> {
-        match self.store.get(key) {
+    /// Add `increment` to the integer stored at `key`, a missing key counting as 0.
+    pub fn incr_by(&mut self, key: &str, increment: i64) -> Result<i64, IncrError> {
+        let (value, expiry, access_frequency) = match self.db().get(key) {
             Some(Item {
                 value: ValueType::String(val),
                 expiry,
+                access_frequency,
+                ..
             }) => {
-                let vaaaalue = val.clone();
-                let mut new_val = vaaaalue.parse::<usize>().ok()?;
-                new_val += 1;
-                self.store.insert(
-                    key.to_owned(),
-                    Item {
-                        value: ValueType::String(new_val.to_string()),
-                        expiry: *expiry,
-                    },
-                );
-                Some(new_val)
+                if !is_canonical_integer(val) {
+                    return Err(IncrError::NotAnInteger);
+                }
+                let value = std::str::from_utf8(val)
+                    .ok()
+                    .and_then(|val| val.parse::<i64>().ok())
+                    .ok_or(IncrError::NotAnInteger)?;
+                (value, *expiry, access_frequency.saturating_add(1))
+            }
+            Some(_) => return Err(IncrError::WrongType),
+            None => (0, None, 0),
+        };
+
+        let new_val = value.checked_add(increment).ok_or(IncrError::Overflow)?;
+        let mut item = Item::new(ValueType::String(new_val.to_string().into_bytes()), expiry);
+        item.access_frequency = access_frequency;
+        self.insert_item(self.selected, key.to_owned(), item);
+        Ok(new_val)
+    }
+
+    /// Append `value` to the string at `key`, creating it when missing. Returns the new length.
+    pub fn append(&mut self, key: &str, value: &[u8]) -> Result<usize, WrongTypeError> {
+        let Some(mut current) = self.get_string(key)? else {
+            self.set_string(key, value, None);
+            return Ok(value.len());
+        };
+        current.extend_from_slice(value);
+        let len = current.len();
+        self.update_string(key, current);
+        Ok(len)
+    }
+
+    /// Overwrite the string at `key` starting at `offset`, padding it with zero bytes when it is
+    /// too short. Returns the new length.
+    pub fn set_range(
+        &mut self,
+        key: &str,
+        offset: usize,
+        value: &[u8],
+    ) -> Result<usize, WrongTypeError> {
+        let current = self.get_string(key)?;
+        if value.is_empty() {
+            return Ok(current.map_or(0, |current| current.len()));
+        }
+        let mut current = current.unwrap_or_default();
+        let end = offset + value.len();
+        if current.len() < end {
+            current.resize(end, 0);
+        }
+        current[offset..end].copy_from_slice(value);
+        let len = current.len();
+        self.update_string(key, current);
+        Ok(len)
+    }
+
+    /// Substring of the string at `key` between `start` and `end` (inclusive), negative offsets
+    /// counting from the end.
+    pub fn get_range(
+        &mut self,
+        key: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<u8>, WrongTypeError> {
+        let value = self.get_string(key)?.unwrap_or_default();
+        let len = value.len() as i64;
+        let resolve = |index: i64| {
+            if index < 0 {
+                (len + index).max(0)
+            } else {
+                index
+            }
+        };
+        let (start, end) = (resolve(start), resolve(end).min(len - 1));
+        if start > end {
+            return Ok(Vec::new());
+        }
+        Ok(value[start as usize..=end as usize].to_vec())
+    }
+
+    /// Replace the string at `key`, keeping its expiry and access statistics. Strings modified in
+    /// place are always `raw` encoded, whatever their content.
+    fn update_string(&mut self, key: &str, value: Vec<u8>) {
+        match self.db_mut().get_mut(key).filter(|item| !item.is_expired()) {
+            Some(item) => {
+                let previous_len = item.value.memory_usage();
+                let len = value.len();
+                item.value = ValueType::String(value);
+                item.encoding = Encoding::Raw;
+                self.used_memory = self.used_memory + len - previous_len;
             }
-            _ => {
-                self.store.insert(
-                    key.to_owned(),
-                    Item {
-                        value: ValueType::String(1.to_string()),
-                        expiry: None,
-                    },
-                );
-                Some(1)
+            None => {
+                let mut item = Item::new(ValueType::String(value), None);
+                item.encoding = Encoding::Raw;
+                self.insert_item(self.selected, key.to_owned(), item);
+            }
+        }
+    }
+
+    /// Rough estimate of the memory used by the dataset, in bytes, as compared with `maxmemory`.
+    pub fn used_memory(&self) -> usize {
+        self.used_memory
+    }
+
+    /// Remove `key` if it has expired. Returns whether it was removed.
+    pub fn remove_if_expired(&mut self, key: &str) -> bool {
+        if !self.db().get(key).is_some_and(Item::is_expired) {
+            return false;
+        }
+        self.remove_item(self.selected, key);
+        true
+    }
+
+    /// Remove every expired key, in all databases. Returns the number of keys removed.
+    pub fn flush_expired(&mut self) -> usize {
+        let mut removed = 0;
+        for db in &mut self.databases {
+            db.retain(|key, item| {
+                if !item.is_expired() {
+                    return true;
+                }
+                self.used_memory -= item_memory_usage(key, item);
+                removed += 1;
+                false
+            });
+        }
+        removed
+    }
+
+    /// Number of keys with an expiry across all databases, and the nearest expiry among them.
+    /// Keys that expired but have not been removed yet are included.
+    pub fn expiry_stats(&self) -> (usize, Option<DateTime<Utc>>) {
+        let expiries = self
+            .databases
+            .iter()
+            .flat_map(|db| db.values())
+            .filter_map(|item| item.expiry);
+        expiries.fold((0, None), |(count, nearest), expiry| {
+            (
+                count + 1,
+                Some(nearest.map_or(expiry, |n: DateTime<Utc>| n.min(expiry))),
+            )
+        })
+    }
+
+    /// Remove `key` from the store. Returns whether a (non expired) value was removed.
+    pub fn delete(&mut self, key: &str) -> bool {
+        self.remove_item(self.selected, key)
+            .is_some_and(|item| !item.is_expired())
+    }
+
+    /// Move `source` to `destination`, overwriting it along with its expiry. Returns false when
+    /// `source` does not exist.
+    pub fn rename(&mut self, source: &str, destination: &str) -> bool {
+        if self.get_item(source).is_none() {
+            return false;
+        }
+        if let Some(item) = self.remove_item(self.selected, source) {
+            self.insert_item(self.selected, destination.to_owned(), item);
+        }
+        true
+    }
+
+    /// Copy `source` to `destination` in the database at `destination_db`, keeping the same
+    /// absolute expiry. Returns false when `source` does not exist, or when `destination` exists
+    /// and `replace` is not set.
+    pub fn copy(
+        &mut self,
+        source: &str,
+        destination: &str,
+        destination_db: usize,
+        replace: bool,
+    ) -> bool {
+        let Some(item) = self.get_item(source) else {
+            return false;
+        };
+        let mut item = item.clone();
+        let db = &self.databases[destination_db];
+        if !replace && db.get(destination).is_some_and(|item| !item.is_expired()) {
+            return false;
+        }
+        item.last_access = Utc::now();
+        item.access_frequency = 0;
+        self.insert_item(destination_db, destination.to_owned(), item);
+        true
+    }
+
+    /// Move `key` from the database at `from` to the one at `to`, along with its expiry. Returns
+    /// false when `key` does not exist in `from`, or already exists in `to`.
+    pub fn move_key(&mut self, key: &str, from: usize, to: usize) -> bool {
+        let exists =
+            |db: &HashMap<String, Item>| db.get(key).is_some_and(|item| !item.is_expired());
+        if !exists(&self.databases[from]) || exists(&self.databases[to]) {
+            return false;
+        }
+        if let Some(item) = self.remove_item(from, key) {
+            self.insert_item(to, key.to_owned(), item);
+        }
+        true
+    }
+
+    pub fn get_expiry(&self, key: &str) -> Option<DateTime<Utc>> {
+        self.get_item(key)?.expiry
+    }
+
+    /// Set, or clear with `None`, the expiry of `key`. Returns false when the key does not exist.
+    pub fn set_expiry(&mut self, key: &str, expiry: Option<DateTime<Utc>>) -> bool {
+        match self.db_mut().get_mut(key).filter(|item| !item.is_expired()) {
+            Some(item) => {
+                item.expiry = expiry;
+                true
             }
+            None => false,
         }
     }
 
     pub fn get_keys(&self) -> Vec<String> {
-        self.store.keys().map(|key| key.to_string()).collect()
+        self.db().keys().map(|key| key.to_string()).collect()
+    }
+
+    /// Number of seconds elapsed since the last access to `key`.
+    pub fn get_idle_time(&self, key: &str) -> Option<i64> {
+        let item = self.get_item(key)?;
+        Some((Utc::now() - item.last_access).num_seconds())
+    }
+
+    /// Access frequency counter of `key`, saturating at `u8::MAX`.
+    pub fn get_access_frequency(&self, key: &str) -> Option<u8> {
+        let item = self.get_item(key)?;
+        Some(item.access_frequency)
+    }
+
+    pub fn get_encoding(&self, key: &str) -> Option<Encoding> {
+        let item = self.get_item(key)?;
+        Some(item.encoding)
+    }
+
+    /// Record an access to `key`, as reported by `OBJECT IDLETIME` and `OBJECT FREQ`.
+    fn touch(&mut self, key: &str) {
+        if self.no_touch {
+            return;
+        }
+        if let Some(item) = self.db_mut().get_mut(key).filter(|item| !item.is_expired()) {
+            item.last_access = Utc::now();
+            item.access_frequency = item.access_frequency.saturating_add(1);
+        }
+    }
+
+    fn get_item(&self, key: &str) -> Option<&Item> {
+        self.db().get(key).filter(|item| !item.is_expired())
     }
 
     pub fn get_item_type(&self, key: &str) -> Option<ItemType> {
-        let item = self.store.get(key)?;
+        let item = self.get_item(key)?;
         Some(match item.value {
             ValueType::Stream(_) => ItemType::Stream,
             ValueType::String(_) => ItemType::String,
@@ -122,39 +547,50 @@ mod tests {
     use core::time;
     use std::thread;
 
+    use chrono::{TimeDelta, Utc};
     use indexmap::IndexMap;
 
     use crate::store::{
-        stream::{RequestedStreamEntryId, StreamEntryId},
-        ItemType,
+        stream::{RequestedStreamEntryId, StreamEntryId, StreamTrim},
+        Encoding, IncrError, ItemType, WrongTypeError,
     };
 
-    use super::Store;
+    use super::{item_memory_usage, Store};
 
     #[test]
     fn set_and_get_string_value() {
         let mut store = Store::new();
         let key = String::from("toto");
-        let value = String::from("tutu");
+        let value = b"tutu".to_vec();
 
         store.set_string(&key, &value, None);
 
-        assert_eq!(store.get_string(&key), Some(value));
+        assert_eq!(store.get_string(&key), Ok(Some(value)));
+    }
+
+    #[test]
+    fn set_and_get_binary_value() {
+        let mut store = Store::new();
+        let value = vec![0xff, 0x00, 0xfe];
+
+        store.set_string("toto", &value, None);
+
+        assert_eq!(store.get_string("toto"), Ok(Some(value)));
     }
 
     #[test]
     fn set_with_ttl() {
         let mut store = Store::new();
         let key = String::from("toto");
-        let value = String::from("tutu");
+        let value = b"tutu".to_vec();
 
         store.set_string(&key, &value, Some(100));
 
-        assert_eq!(store.get_string(&key), Some(value));
+        assert_eq!(store.get_string(&key), Ok(Some(value)));
 
         thread::sleep(time::Duration::from_millis(100));
 
-        assert_eq!(store.get_string(&key), None);
+        assert_eq!(store.get_string(&key), Ok(None));
     }
 
     #[test]
@@ -166,7 +602,7 @@ mod tests {
 
         // String value
         let key = String::from("my-string");
-        let value = String::from("tutu");
+        let value = b"tutu".to_vec();
         store.set_string(&key, &value, Some(100));
 
         if let Some(item_type) = store.get_item_type(&String::from("my-string")) {
@@ -198,4 +634,201 @@ mod tests {
             panic!("Should not be None but Some(StoreType::Stream)")
         }
     }
+
+    #[test]
+    fn get_string_on_stream_is_wrong_type() {
+        let mut store = Store::new();
+
+        let value = IndexMap::from([(String::from("temperature"), String::from("10"))]);
+        let _ = store.add_stream_entry(
+            "my-stream",
+            &RequestedStreamEntryId::AutoGenerate,
+            &value,
+            None,
+        );
+
+        assert_eq!(store.get_string("my-stream"), Err(WrongTypeError));
+    }
+
+    #[test]
+    fn string_encodings() {
+        let mut store = Store::new();
+
+        store.set_string("int", b"-123", None);
+        store.set_string("padded", b"0123", None);
+        store.set_string("embstr", &[b'a'; 44], None);
+        store.set_string("raw", &[b'a'; 45], None);
+
+        assert_eq!(store.get_encoding("int"), Some(Encoding::Int));
+        assert_eq!(store.get_encoding("padded"), Some(Encoding::Embstr));
+        assert_eq!(store.get_encoding("embstr"), Some(Encoding::Embstr));
+        assert_eq!(store.get_encoding("raw"), Some(Encoding::Raw));
+        assert_eq!(store.get_encoding("missing"), None);
+
+        assert_eq!(store.incr("int"), Ok(-122));
+        assert_eq!(store.get_encoding("int"), Some(Encoding::Int));
+        assert_eq!(store.incr("padded"), Err(IncrError::NotAnInteger));
+    }
+
+    #[test]
+    fn in_place_string_mutations() {
+        let mut store = Store::new();
+        store.set_string("foo", b"123", Some(10_000));
+
+        assert_eq!(store.append("foo", b"4"), Ok(4));
+        assert_eq!(store.get_encoding("foo"), Some(Encoding::Raw));
+        assert!(store.get_expiry("foo").is_some());
+        assert_eq!(store.incr("foo"), Ok(1235));
+        assert_eq!(store.get_encoding("foo"), Some(Encoding::Int));
+
+        assert_eq!(store.set_range("bar", 2, b"ab"), Ok(4));
+        assert_eq!(store.get_string("bar"), Ok(Some(b"\0\0ab".to_vec())));
+        assert_eq!(store.get_encoding("bar"), Some(Encoding::Raw));
+        assert_eq!(store.set_range("missing", 5, b""), Ok(0));
+        assert_eq!(store.get_encoding("missing"), None);
+
+        assert_eq!(store.append("new", b"42"), Ok(2));
+        assert_eq!(store.get_encoding("new"), Some(Encoding::Int));
+
+        store.set_string("hello", b"Hello World", None);
+        assert_eq!(store.get_range("hello", 0, 4), Ok(b"Hello".to_vec()));
+        assert_eq!(store.get_range("hello", -5, -1), Ok(b"World".to_vec()));
+        assert_eq!(store.get_range("hello", 5, 100), Ok(b" World".to_vec()));
+        assert_eq!(store.get_range("hello", -1, -5), Ok(Vec::new()));
+        assert_eq!(store.get_range("missing", 0, -1), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn used_memory_grows_with_values() {
+        let mut store = Store::new();
+        assert_eq!(store.used_memory(), 0);
+
+        store.set_string("foo", b"bar", None);
+        let small = store.used_memory();
+        assert!(small > 0);
+        store.set_string("foo", &[b'a'; 1000], None);
+        assert!(store.used_memory() >= small + 997);
+        store.delete("foo");
+        assert_eq!(store.used_memory(), 0);
+    }
+
+    #[test]
+    fn used_memory_is_kept_up_to_date() {
+        let mut store = Store::new();
+        let recomputed = |store: &Store| -> usize {
+            store
+                .databases
+                .iter()
+                .flatten()
+                .map(|(key, item)| item_memory_usage(key, item))
+                .sum()
+        };
+        let value = IndexMap::from([(String::from("f"), String::from("v"))]);
+
+        store.set_string("foo", b"bar", None);
+        let _ = store.append("foo", b"baz");
+        let _ = store.set_range("foo", 10, b"x");
+        let _ = store.incr_by("counter", 10);
+        store.rename("counter", "renamed");
+        store.copy("foo", "foo", 1, false);
+        for _ in 0..3 {
+            let _ =
+                store.add_stream_entry("s", &RequestedStreamEntryId::AutoGenerate, &value, None);
+        }
+        assert_eq!(store.used_memory(), recomputed(&store));
+
+        let first = store.get_raw_stream("s").unwrap()[0].id;
+        let _ = store.delete_stream_entries("s", &[first]);
+        let _ = store.trim_stream("s", StreamTrim::MaxLen(1), None);
+        store.set_string("foo", b"short", None);
+        store.set_string("expired", b"value", None);
+        store.set_expiry("expired", Some(Utc::now() - TimeDelta::seconds(1)));
+        store.flush_expired();
+        assert_eq!(store.used_memory(), recomputed(&store));
+
+        for key in ["foo", "renamed", "s"] {
+            store.delete(key);
+        }
+        store.select(1);
+        store.delete("foo");
+        assert_eq!(store.used_memory(), 0);
+    }
+
+    #[test]
+    fn incr_overflow() {
+        let mut store = Store::new();
+        store.set_string("max", i64::MAX.to_string().as_bytes(), None);
+        store.set_string("min", i64::MIN.to_string().as_bytes(), None);
+
+        assert_eq!(store.incr("max"), Err(IncrError::Overflow));
+        assert_eq!(store.incr_by("min", -1), Err(IncrError::Overflow));
+        assert_eq!(store.incr_by("max", i64::MIN), Ok(-1));
+        assert_eq!(
+            store.get_string("min"),
+            Ok(Some(i64::MIN.to_string().into_bytes()))
+        );
+        assert_eq!(store.incr_by("missing", -5), Ok(-5));
+    }
+
+    #[test]
+    fn delete_and_expiry() {
+        let mut store = Store::new();
+        store.set_string("foo", b"bar", Some(10_000));
+
+        assert!(store.get_expiry("foo").is_some());
+        assert!(store.set_expiry("foo", None));
+        assert_eq!(store.get_expiry("foo"), None);
+        assert!(!store.set_expiry("missing", None));
+
+        assert!(store.delete("foo"));
+        assert!(!store.delete("foo"));
+        assert_eq!(store.get_string("foo"), Ok(None));
+    }
+
+    #[test]
+    fn flush_expired_keys() {
+        let mut store = Store::new();
+        store.set_string("short", b"v", Some(1));
+        store.set_string("long", b"v", Some(60_000));
+        store.set_string("persistent", b"v", None);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+
+        let (keys_with_expiry, nearest) = store.expiry_stats();
+        assert_eq!(keys_with_expiry, 2);
+        assert!(nearest.is_some_and(|nearest| nearest <= chrono::Utc::now()));
+
+        assert!(!store.remove_if_expired("long"));
+        assert_eq!(store.flush_expired(), 1);
+        assert_eq!(store.flush_expired(), 0);
+        assert_eq!(store.get_keys().len(), 2);
+        assert_eq!(store.expiry_stats(), (1, store.get_expiry("long")));
+    }
+
+    #[test]
+    fn test_access_tracking() {
+        let mut store = Store::new();
+
+        assert_eq!(store.get_idle_time("counter"), None);
+        assert_eq!(store.get_access_frequency("counter"), None);
+
+        assert_eq!(store.incr("counter"), Ok(1));
+        assert_eq!(store.get_idle_time("counter"), Some(0));
+        assert_eq!(store.get_access_frequency("counter"), Some(0));
+
+        assert_eq!(store.incr("counter"), Ok(2));
+        assert_eq!(store.get_access_frequency("counter"), Some(1));
+    }
+
+    #[test]
+    fn reads_update_access_time() {
+        let mut store = Store::new();
+        store.set_string("foo", b"bar", None);
+        store.db_mut().get_mut("foo").unwrap().last_access -= chrono::TimeDelta::seconds(100);
+        assert_eq!(store.get_idle_time("foo"), Some(100));
+        assert_eq!(store.get_access_frequency("foo"), Some(0));
+
+        assert_eq!(store.get_string("foo"), Ok(Some(b"bar".to_vec())));
+        assert_eq!(store.get_idle_time("foo"), Some(0));
+        assert_eq!(store.get_access_frequency("foo"), Some(1));
+    }
 }
@@ -1,9 +1,15 @@
-use std::{cmp::Ordering, error::Error, fmt};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt,
+    ops::Bound,
+};
 
 use chrono::{DateTime, TimeDelta, Utc};
 use indexmap::IndexMap;
 
-use super::{Item, Store, ValueType};
+use super::{entry_memory_usage, Item, ItemType, Store, ValueType, WrongTypeError};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct StreamEntry {
@@ -11,7 +17,7 @@ pub struct StreamEntry {
     pub values: IndexMap<String, String>,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct StreamEntryId {
     pub timestamp: usize,
     pub sequence_number: usize,
@@ -59,12 +65,104 @@ pub enum RequestedStreamEntryId {
     AutoGenerate,
 }
 
-pub type Stream = Vec<StreamEntry>;
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Stream {
+    pub entries: Vec<StreamEntry>,
+    /// ID of the last entry added to the stream, new entries must have a greater ID.
+    pub last_id: StreamEntryId,
+    pub groups: HashMap<String, ConsumerGroup>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct ConsumerGroup {
+    /// ID of the last entry delivered to a consumer of the group.
+    pub last_delivered_id: StreamEntryId,
+    /// Pending entries list (PEL): entries delivered to consumers but not acknowledged yet.
+    pub pending: BTreeMap<StreamEntryId, PendingEntry>,
+}
+
+impl ConsumerGroup {
+    /// Remove `ids` from the pending entries list. Returns how many of them were pending.
+    pub fn ack(&mut self, ids: &[StreamEntryId]) -> usize {
+        ids.iter()
+            .filter(|id| self.pending.remove(id).is_some())
+            .count()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PendingEntry {
+    pub consumer: String,
+    /// Last time the entry was delivered.
+    pub delivery_time: DateTime<Utc>,
+    pub delivery_count: usize,
+}
+
+/// Claim of pending entries on behalf of `consumer`.
+#[derive(Debug, Clone, Copy)]
+pub struct ClaimRequest<'a> {
+    pub consumer: &'a str,
+    /// Only entries idle for at least this number of milliseconds are claimed.
+    pub min_idle: i64,
+    /// Leave the delivery count of claimed entries unchanged.
+    pub justid: bool,
+}
+
+/// How a stream is trimmed by XTRIM.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamTrim {
+    /// Keep at most this number of entries, the oldest ones being removed.
+    MaxLen(usize),
+    /// Remove the entries with an ID lower than this one.
+    MinId(StreamEntryId),
+}
+
+/// Outcome of claiming pending entries.
+#[derive(Debug, PartialEq, Default)]
+pub struct ClaimedEntries {
+    pub claimed: Vec<StreamEntry>,
+    /// Pending entries no longer in the stream, removed from the pending entries list.
+    pub deleted: Vec<StreamEntryId>,
+}
+
+enum Claim {
+    Claimed(StreamEntry),
+    Deleted,
+    Skipped,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConsumerGroupError {
+    NoSuchKey,
+    BusyGroup,
+    NoGroup,
+    WrongType,
+}
+impl Error for ConsumerGroupError {}
+
+impl fmt::Display for ConsumerGroupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            ConsumerGroupError::NoSuchKey => {
+                "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you \
+                 may want to use the MKSTREAM option to create an empty stream automatically."
+            }
+            ConsumerGroupError::BusyGroup => "BUSYGROUP Consumer Group name already exists",
+            ConsumerGroupError::NoGroup => "NOGROUP No such key or consumer group",
+            ConsumerGroupError::WrongType => {
+                "WRONGTYPE Operation against a key holding the wrong kind of value"
+            }
+        };
+        write!(f, "{message}")
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub enum AddStreamEntryError {
     EqualOrSmallerID,
     GreaterThanZeroZero,
+    ExhaustedID,
+    WrongType,
 }
 impl Error for AddStreamEntryError {}
 
@@ -77,6 +175,12 @@ impl fmt::Display for AddStreamEntryError {
             AddStreamEntryError::GreaterThanZeroZero => {
                 "ERR The ID specified in XADD must be greater than 0-0"
             }
+            AddStreamEntryError::ExhaustedID => {
+                "ERR The stream has exhausted the last possible ID, unable to add more items"
+            }
+            AddStreamEntryError::WrongType => {
+                "WRONGTYPE Operation against a key holding the wrong kind of value"
+            }
         };
         write!(f, "{message}")
     }
@@ -94,11 +198,19 @@ impl Store {
             Utc::now().checked_add_signed(TimeDelta::milliseconds(i64::try_from(s).ok()?))
         });
 
-        match self.store.get_mut(key) {
+        if self.get_item_type(key) == Some(ItemType::String) {
+            return Err(AddStreamEntryError::WrongType);
+        }
+
+        match self.db_mut().get_mut(key) {
             Some(Item {
                 value: ValueType::Stream(existing_stream),
-                expiry: _,
-            }) => append_to_existing_stream(existing_stream, id_request, entry),
+                ..
+            }) => {
+                let id = append_to_existing_stream(existing_stream, id_request, entry)?;
+                self.used_memory += existing_stream.entries.last().map_or(0, entry_memory_usage);
+                Ok(id)
+            }
             _ => self.create_new_stream(key, id_request, entry, expiry),
         }
     }
@@ -134,48 +246,324 @@ impl Store {
                 }
             }
         };
-        let item = Item {
-            value: ValueType::Stream(vec![StreamEntry {
-                id: *id,
-                values: entry.clone(),
-            }]),
+        let item = Item::new(
+            ValueType::Stream(Stream {
+                entries: vec![StreamEntry {
+                    id: *id,
+                    values: entry.clone(),
+                }],
+                last_id: *id,
+                groups: HashMap::new(),
+            }),
             expiry,
-        };
-        self.store.insert(String::from(key), item);
+        );
+        self.insert_item(self.selected, String::from(key), item);
         Ok(id.to_owned())
     }
 
     pub fn get_stream_range(
-        &self,
+        &mut self,
         key: &str,
         start: Option<&StreamEntryId>,
         end: Option<&StreamEntryId>,
-    ) -> Vec<StreamEntry> {
+    ) -> &[StreamEntry] {
+        self.touch(key);
         let Some(Item {
             value: ValueType::Stream(stream),
-            expiry: _,
-        }) = self.store.get(key)
+            ..
+        }) = self.db().get(key)
         else {
-            return Vec::new();
+            return &[];
         };
-        let matching_entries: Vec<StreamEntry> = stream
-            .iter()
-            .filter(|entry| {
-                let start_condition = start.map(|start_id| entry.id >= *start_id).unwrap_or(true);
-                let end_condition = end.map(|end_id| entry.id <= *end_id).unwrap_or(true);
-                start_condition && end_condition
-            })
-            .map(|entry| StreamEntry {
-                id: entry.id,
-                values: entry.values.clone(),
-            })
-            .collect();
-        matching_entries
+        // Entries are appended in increasing ID order, so both bounds can be binary searched
+        let from = start.map_or(0, |start_id| {
+            stream.entries.partition_point(|entry| entry.id < *start_id)
+        });
+        let to = end.map_or(stream.entries.len(), |end_id| {
+            stream.entries.partition_point(|entry| entry.id <= *end_id)
+        });
+        if from >= to {
+            return &[];
+        }
+        &stream.entries[from..to]
+    }
+
+    pub fn get_last_stream_entry(&self, key: &str) -> Option<StreamEntry> {
+        let Some(Item {
+            value: ValueType::Stream(stream),
+            ..
+        }) = self.db().get(key)
+        else {
+            return None;
+        };
+        stream.entries.last().cloned()
+    }
+
+    /// Create the consumer group `group` on the stream at `key`, starting after `id`, or after the
+    /// last entry of the stream when `None`. With `mkstream`, a missing key gets an empty stream.
+    pub fn create_consumer_group(
+        &mut self,
+        key: &str,
+        group: &str,
+        id: Option<StreamEntryId>,
+        mkstream: bool,
+    ) -> Result<(), ConsumerGroupError> {
+        if self.get_stream_mut(key)?.is_none() {
+            if !mkstream {
+                return Err(ConsumerGroupError::NoSuchKey);
+            }
+            let item = Item::new(ValueType::Stream(Stream::default()), None);
+            self.insert_item(self.selected, String::from(key), item);
+        }
+        let Some(stream) = self.get_stream_mut(key)? else {
+            return Err(ConsumerGroupError::NoSuchKey);
+        };
+        if stream.groups.contains_key(group) {
+            return Err(ConsumerGroupError::BusyGroup);
+        }
+
+        let group_info = ConsumerGroup {
+            last_delivered_id: id.unwrap_or(stream.last_id),
+            pending: BTreeMap::new(),
+        };
+        stream.groups.insert(String::from(group), group_info);
+        Ok(())
+    }
+
+    pub fn get_consumer_group(
+        &self,
+        key: &str,
+        group: &str,
+    ) -> Result<&ConsumerGroup, ConsumerGroupError> {
+        match self.get_item(key) {
+            Some(Item {
+                value: ValueType::Stream(stream),
+                ..
+            }) => stream.groups.get(group).ok_or(ConsumerGroupError::NoGroup),
+            Some(_) => Err(ConsumerGroupError::WrongType),
+            None => Err(ConsumerGroupError::NoGroup),
+        }
+    }
+
+    pub fn get_consumer_group_mut(
+        &mut self,
+        key: &str,
+        group: &str,
+    ) -> Result<&mut ConsumerGroup, ConsumerGroupError> {
+        self.get_stream_mut(key)?
+            .and_then(|stream| stream.groups.get_mut(group))
+            .ok_or(ConsumerGroupError::NoGroup)
+    }
+
+    /// Read entries from `key` on behalf of `consumer` of `group`.
+    ///
+    /// When `start` is `None` (the special `>` ID), entries never delivered to the group are
+    /// returned and added to the pending entries list, unless `noack` is set. Otherwise the
+    /// consumer's pending entries with an ID greater than `start` are delivered again.
+    pub fn read_group(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        start: Option<StreamEntryId>,
+        count: Option<usize>,
+        noack: bool,
+    ) -> Result<Vec<StreamEntry>, ConsumerGroupError> {
+        self.touch(key);
+        let Some(Stream {
+            entries, groups, ..
+        }) = self.get_stream_mut(key)?
+        else {
+            return Err(ConsumerGroupError::NoGroup);
+        };
+        let group = groups.get_mut(group).ok_or(ConsumerGroupError::NoGroup)?;
+        let count = count.unwrap_or(usize::MAX);
+        let now = Utc::now();
+
+        let Some(start) = start else {
+            let delivered: Vec<StreamEntry> = entries
+                .iter()
+                .filter(|entry| entry.id > group.last_delivered_id)
+                .take(count)
+                .cloned()
+                .collect();
+            for entry in &delivered {
+                group.last_delivered_id = entry.id;
+                if !noack {
+                    group.pending.insert(
+                        entry.id,
+                        PendingEntry {
+                            consumer: String::from(consumer),
+                            delivery_time: now,
+                            delivery_count: 1,
+                        },
+                    );
+                }
+            }
+            return Ok(delivered);
+        };
+
+        let mut delivered = Vec::new();
+        for (id, pending) in group
+            .pending
+            .range_mut((Bound::Excluded(start), Bound::Unbounded))
+            .filter(|(_, pending)| pending.consumer == consumer)
+            .take(count)
+        {
+            pending.delivery_time = now;
+            pending.delivery_count += 1;
+            if let Ok(index) = entries.binary_search_by_key(id, |entry| entry.id) {
+                delivered.push(entries[index].clone());
+            }
+        }
+        Ok(delivered)
+    }
+
+    /// Transfer the pending entries among `ids` idle for long enough to the claiming consumer.
+    /// Their idle time is reset and, unless `justid`, their delivery count increased.
+    pub fn claim(
+        &mut self,
+        key: &str,
+        group: &str,
+        request: ClaimRequest,
+        ids: &[StreamEntryId],
+    ) -> Result<ClaimedEntries, ConsumerGroupError> {
+        let Some(Stream {
+            entries, groups, ..
+        }) = self.get_stream_mut(key)?
+        else {
+            return Err(ConsumerGroupError::NoGroup);
+        };
+        let group = groups.get_mut(group).ok_or(ConsumerGroupError::NoGroup)?;
+        let now = Utc::now();
+
+        let mut result = ClaimedEntries::default();
+        for id in ids {
+            match claim_pending(entries, group, id, request, now) {
+                Claim::Claimed(entry) => result.claimed.push(entry),
+                Claim::Deleted => result.deleted.push(*id),
+                Claim::Skipped => {}
+            }
+        }
+        Ok(result)
+    }
+
+    /// Scan the pending entries list from `start` and claim up to `count` entries, like `claim`.
+    /// Also returns the ID to resume the scan from, `0-0` once the whole list has been scanned.
+    pub fn auto_claim(
+        &mut self,
+        key: &str,
+        group: &str,
+        request: ClaimRequest,
+        start: StreamEntryId,
+        count: usize,
+    ) -> Result<(StreamEntryId, ClaimedEntries), ConsumerGroupError> {
+        let Some(Stream {
+            entries, groups, ..
+        }) = self.get_stream_mut(key)?
+        else {
+            return Err(ConsumerGroupError::NoGroup);
+        };
+        let group = groups.get_mut(group).ok_or(ConsumerGroupError::NoGroup)?;
+        let now = Utc::now();
+
+        // Bound the work done by a single call, like Redis does
+        let mut attempts = count.saturating_mul(10);
+        let mut cursor = Bound::Included(start);
+        let mut result = ClaimedEntries::default();
+        let next = loop {
+            let Some(id) = group
+                .pending
+                .range((cursor, Bound::Unbounded))
+                .next()
+                .map(|(id, _)| *id)
+            else {
+                break StreamEntryId::default();
+            };
+            if attempts == 0 || result.claimed.len() == count {
+                break id;
+            }
+            attempts -= 1;
+            match claim_pending(entries, group, &id, request, now) {
+                Claim::Claimed(entry) => result.claimed.push(entry),
+                Claim::Deleted => result.deleted.push(id),
+                Claim::Skipped => {}
+            }
+            cursor = Bound::Excluded(id);
+        };
+        Ok((next, result))
+    }
+
+    /// Remove the entries with the given IDs, returning how many were found. The stream is kept
+    /// even once empty, like Redis does for streams (unlike collections such as lists or sets,
+    /// which are deleted with their last element).
+    pub fn delete_stream_entries(
+        &mut self,
+        key: &str,
+        ids: &[StreamEntryId],
+    ) -> Result<usize, WrongTypeError> {
+        let Some(stream) = self.get_stream_mut(key).map_err(|_| WrongTypeError)? else {
+            return Ok(0);
+        };
+        let len = stream.entries.len();
+        let mut freed = 0;
+        stream.entries.retain(|entry| {
+            if !ids.contains(&entry.id) {
+                return true;
+            }
+            freed += entry_memory_usage(entry);
+            false
+        });
+        let removed = len - stream.entries.len();
+        self.used_memory -= freed;
+        Ok(removed)
+    }
+
+    /// Remove the oldest entries of the stream at `key` according to `trim`, at most `limit` of
+    /// them when set. Returns the number of entries removed.
+    pub fn trim_stream(
+        &mut self,
+        key: &str,
+        trim: StreamTrim,
+        limit: Option<usize>,
+    ) -> Result<usize, WrongTypeError> {
+        let Some(stream) = self.get_stream_mut(key).map_err(|_| WrongTypeError)? else {
+            return Ok(0);
+        };
+        let removed = match trim {
+            StreamTrim::MaxLen(max_len) => stream.entries.len().saturating_sub(max_len),
+            StreamTrim::MinId(min_id) => stream.entries.partition_point(|entry| entry.id < min_id),
+        };
+        let removed = limit.map_or(removed, |limit| removed.min(limit));
+        let freed: usize = stream
+            .entries
+            .drain(..removed)
+            .map(|entry| entry_memory_usage(&entry))
+            .sum();
+        self.used_memory -= freed;
+        Ok(removed)
+    }
+
+    /// Number of entries in the stream at `key`, 0 when the key does not exist.
+    pub fn stream_len(&mut self, key: &str) -> Result<usize, WrongTypeError> {
+        let stream = self.get_stream_mut(key).map_err(|_| WrongTypeError)?;
+        Ok(stream.map_or(0, |stream| stream.entries.len()))
+    }
+
+    fn get_stream_mut(&mut self, key: &str) -> Result<Option<&mut Stream>, ConsumerGroupError> {
+        match self.db_mut().get_mut(key).filter(|item| !item.is_expired()) {
+            Some(Item {
+                value: ValueType::Stream(stream),
+                ..
+            }) => Ok(Some(stream)),
+            Some(_) => Err(ConsumerGroupError::WrongType),
+            None => Ok(None),
+        }
     }
 
     #[cfg(test)]
-    pub fn get_raw_stream(&self, key: &str) -> Option<&Stream> {
-        let item = self.store.get(key)?;
+    pub fn get_raw_stream(&self, key: &str) -> Option<&Vec<StreamEntry>> {
+        let item = self.db().get(key)?;
 
         if let Some(expiry) = item.expiry {
             if expiry < Utc::now() {
@@ -185,25 +573,48 @@ impl Store {
 
         let Item {
             value: ValueType::Stream(stream),
-            expiry: _,
+            ..
         } = item
         else {
             return None;
         };
 
-        Some(stream)
+        Some(&stream.entries)
+    }
+}
+
+fn claim_pending(
+    entries: &[StreamEntry],
+    group: &mut ConsumerGroup,
+    id: &StreamEntryId,
+    request: ClaimRequest,
+    now: DateTime<Utc>,
+) -> Claim {
+    let Some(pending) = group.pending.get_mut(id) else {
+        return Claim::Skipped;
+    };
+    if (now - pending.delivery_time).num_milliseconds() < request.min_idle {
+        return Claim::Skipped;
+    }
+    let Ok(index) = entries.binary_search_by_key(id, |entry| entry.id) else {
+        group.pending.remove(id);
+        return Claim::Deleted;
+    };
+
+    pending.consumer = String::from(request.consumer);
+    pending.delivery_time = now;
+    if !request.justid {
+        pending.delivery_count += 1;
     }
+    Claim::Claimed(entries[index].clone())
 }
 
 fn append_to_existing_stream(
-    existing_stream: &mut Vec<StreamEntry>,
+    existing_stream: &mut Stream,
     id_request: &RequestedStreamEntryId,
     entry: &IndexMap<String, String>,
 ) -> Result<StreamEntryId, AddStreamEntryError> {
-    let last_id = existing_stream
-        .last()
-        .map(|entry| &entry.id)
-        .expect("Cannot be empty");
+    let last_id = &existing_stream.last_id;
 
     let id = match id_request {
         RequestedStreamEntryId::Explicit(id) => {
@@ -221,52 +632,68 @@ fn append_to_existing_stream(
             *id
         }
         RequestedStreamEntryId::AutoGenerateSequence(timestamp) => {
-            let last_entry = existing_stream.last().expect("Cannot be empty");
-            match timestamp.cmp(&last_entry.id.timestamp) {
+            match timestamp.cmp(&last_id.timestamp) {
                 Ordering::Greater => StreamEntryId {
                     timestamp: *timestamp,
                     sequence_number: 0,
                 },
                 Ordering::Equal => StreamEntryId {
                     timestamp: *timestamp,
-                    sequence_number: last_entry.id.sequence_number + 1,
+                    sequence_number: last_id
+                        .sequence_number
+                        .checked_add(1)
+                        .ok_or(AddStreamEntryError::ExhaustedID)?,
                 },
                 Ordering::Less => return Err(AddStreamEntryError::EqualOrSmallerID),
             }
         }
         RequestedStreamEntryId::AutoGenerate => {
             let now = usize::try_from(chrono::Utc::now().timestamp_millis()).unwrap_or(0);
-            let last_entry = existing_stream.last().expect("Cannot be empty");
-            match now.cmp(&last_entry.id.timestamp) {
+            match now.cmp(&last_id.timestamp) {
                 Ordering::Greater => StreamEntryId {
                     timestamp: now,
                     sequence_number: 0,
                 },
-                Ordering::Equal => StreamEntryId {
-                    timestamp: now,
-                    sequence_number: last_entry.id.sequence_number + 1,
-                },
-                Ordering::Less => StreamEntryId {
-                    timestamp: last_entry.id.timestamp,
-                    sequence_number: last_entry.id.sequence_number + 1,
-                },
+                Ordering::Equal | Ordering::Less => next_stream_entry_id(last_id)?,
             }
         }
     };
 
-    existing_stream.push(StreamEntry {
+    existing_stream.entries.push(StreamEntry {
         id,
         values: entry.clone(),
     });
+    existing_stream.last_id = id;
     Ok(id)
 }
 
+/// Smallest ID greater than `id`, moving to the next timestamp once the sequence is exhausted.
+fn next_stream_entry_id(id: &StreamEntryId) -> Result<StreamEntryId, AddStreamEntryError> {
+    if let Some(sequence_number) = id.sequence_number.checked_add(1) {
+        return Ok(StreamEntryId {
+            timestamp: id.timestamp,
+            sequence_number,
+        });
+    }
+    let timestamp = id
+        .timestamp
+        .checked_add(1)
+        .ok_or(AddStreamEntryError::ExhaustedID)?;
+    Ok(StreamEntryId {
+        timestamp,
+        sequence_number: 0,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use indexmap::IndexMap;
 
     use crate::store::{
-        stream::{AddStreamEntryError, RequestedStreamEntryId, StreamEntry, StreamEntryId},
+        stream::{
+            AddStreamEntryError, ClaimRequest, ConsumerGroupError, RequestedStreamEntryId,
+            StreamEntry, StreamEntryId,
+        },
         Store,
     };
 
@@ -400,8 +827,237 @@ mod tests {
 
     #[test]
     fn get_empty_range() {
-        let store = Store::new();
+        let mut store = Store::new();
+
+        assert_eq!(store.get_stream_range("my-key", None, None), &[]);
+    }
+
+    #[test]
+    fn range_bounds_on_large_stream() {
+        let mut store = Store::new();
+        let values = IndexMap::from([(String::from("foo"), String::from("bar"))]);
+        // Only even timestamps, to query bounds falling between entries
+        for timestamp in (0..200_000).step_by(2) {
+            let id = StreamEntryId {
+                timestamp,
+                sequence_number: 1,
+            };
+            store
+                .add_stream_entry("s", &RequestedStreamEntryId::Explicit(id), &values, None)
+                .unwrap();
+        }
+        let id = |timestamp| StreamEntryId {
+            timestamp,
+            sequence_number: 1,
+        };
+        let timestamps = |entries: &[StreamEntry]| -> Vec<usize> {
+            entries.iter().map(|entry| entry.id.timestamp).collect()
+        };
+
+        assert_eq!(store.get_stream_range("s", None, None).len(), 100_000);
+        assert_eq!(
+            timestamps(store.get_stream_range("s", Some(&id(1000)), Some(&id(1006)))),
+            vec![1000, 1002, 1004, 1006]
+        );
+        assert_eq!(
+            timestamps(store.get_stream_range("s", Some(&id(999)), Some(&id(1005)))),
+            vec![1000, 1002, 1004]
+        );
+        assert_eq!(
+            timestamps(store.get_stream_range("s", Some(&id(199_997)), None)),
+            vec![199_998]
+        );
+        assert_eq!(
+            timestamps(store.get_stream_range("s", None, Some(&id(2)))),
+            vec![0, 2]
+        );
+        assert_eq!(
+            store.get_stream_range("s", Some(&id(1001)), Some(&id(1001))),
+            &[]
+        );
+        assert_eq!(
+            store.get_stream_range("s", Some(&id(1006)), Some(&id(1000))),
+            &[]
+        );
+    }
+
+    #[test]
+    fn auto_generated_id_does_not_overflow() {
+        let mut store = Store::new();
+        let value = IndexMap::from([(String::from("f"), String::from("v"))]);
+
+        let last_id = StreamEntryId {
+            timestamp: 5,
+            sequence_number: usize::MAX,
+        };
+        let _ = store.add_stream_entry(
+            "s",
+            &RequestedStreamEntryId::Explicit(last_id),
+            &value,
+            None,
+        );
+        assert_eq!(
+            store.add_stream_entry(
+                "s",
+                &RequestedStreamEntryId::AutoGenerateSequence(5),
+                &value,
+                None
+            ),
+            Err(AddStreamEntryError::ExhaustedID)
+        );
+
+        let last_id = StreamEntryId {
+            timestamp: usize::MAX,
+            sequence_number: usize::MAX,
+        };
+        let _ = store.add_stream_entry(
+            "s",
+            &RequestedStreamEntryId::Explicit(last_id),
+            &value,
+            None,
+        );
+        assert_eq!(
+            store.add_stream_entry("s", &RequestedStreamEntryId::AutoGenerate, &value, None),
+            Err(AddStreamEntryError::ExhaustedID)
+        );
+        assert_eq!(
+            store.get_raw_stream("s").map(|stream| stream.len()),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn add_entry_to_string_key() {
+        let mut store = Store::new();
+        store.set_string("k", b"v", None);
+
+        let value = IndexMap::from([(String::from("f"), String::from("v"))]);
+        assert_eq!(
+            store.add_stream_entry("k", &RequestedStreamEntryId::AutoGenerate, &value, None),
+            Err(AddStreamEntryError::WrongType)
+        );
+        assert_eq!(store.get_string("k"), Ok(Some(b"v".to_vec())));
+    }
+
+    #[test]
+    fn consumer_group_read_and_pending() {
+        let mut store = Store::new();
+        let value = IndexMap::from([(String::from("f"), String::from("v"))]);
+        let id = |timestamp| StreamEntryId {
+            timestamp,
+            sequence_number: 0,
+        };
+
+        assert_eq!(
+            store.create_consumer_group("s", "g", None, false),
+            Err(ConsumerGroupError::NoSuchKey)
+        );
+        assert_eq!(store.create_consumer_group("s", "g", None, true), Ok(()));
+        assert_eq!(
+            store.create_consumer_group("s", "g", None, true),
+            Err(ConsumerGroupError::BusyGroup)
+        );
+        for timestamp in 1..=3 {
+            let _ = store.add_stream_entry(
+                "s",
+                &RequestedStreamEntryId::Explicit(id(timestamp)),
+                &value,
+                None,
+            );
+        }
+
+        let read = store.read_group("s", "g", "alice", None, Some(2), false);
+        assert_eq!(
+            read.map(|entries| entries.iter().map(|entry| entry.id).collect::<Vec<_>>()),
+            Ok(vec![id(1), id(2)])
+        );
+        let read = store.read_group("s", "g", "bob", None, None, true);
+        assert_eq!(read.map(|entries| entries.len()), Ok(1));
+
+        let group = store.get_consumer_group("s", "g").unwrap();
+        assert_eq!(group.last_delivered_id, id(3));
+        assert_eq!(
+            group.pending.keys().copied().collect::<Vec<_>>(),
+            [id(1), id(2)]
+        );
+
+        // History only contains the consumer's own pending entries
+        let read = store.read_group("s", "g", "alice", Some(id(1)), None, false);
+        assert_eq!(
+            read.map(|entries| entries.iter().map(|entry| entry.id).collect::<Vec<_>>()),
+            Ok(vec![id(2)])
+        );
+        let group = store.get_consumer_group("s", "g").unwrap();
+        assert_eq!(group.pending[&id(2)].delivery_count, 2);
+        assert_eq!(
+            store.read_group("s", "g", "bob", Some(id(0)), None, false),
+            Ok(vec![])
+        );
+        assert_eq!(
+            store.read_group("s", "other", "bob", None, None, false),
+            Err(ConsumerGroupError::NoGroup)
+        );
+
+        let group = store.get_consumer_group_mut("s", "g").unwrap();
+        assert_eq!(group.ack(&[id(1), id(3), id(1)]), 1);
+        assert_eq!(group.pending.keys().copied().collect::<Vec<_>>(), [id(2)]);
+    }
+
+    #[test]
+    fn claim_pending_entries() {
+        let mut store = Store::new();
+        let value = IndexMap::from([(String::from("f"), String::from("v"))]);
+        let id = |timestamp| StreamEntryId {
+            timestamp,
+            sequence_number: 0,
+        };
+        let _ = store.create_consumer_group("s", "g", None, true);
+        for timestamp in 1..=3 {
+            let _ = store.add_stream_entry(
+                "s",
+                &RequestedStreamEntryId::Explicit(id(timestamp)),
+                &value,
+                None,
+            );
+        }
+        let _ = store.read_group("s", "g", "alice", None, None, false);
+        let request = |consumer, min_idle, justid| ClaimRequest {
+            consumer,
+            min_idle,
+            justid,
+        };
+
+        // Not idle for long enough
+        let claimed = store.claim("s", "g", request("bob", 60_000, false), &[id(1)]);
+        assert_eq!(claimed.map(|claimed| claimed.claimed.len()), Ok(0));
+
+        let claimed = store
+            .claim("s", "g", request("bob", 0, false), &[id(1), id(5)])
+            .unwrap();
+        assert_eq!(claimed.claimed.len(), 1);
+        let group = store.get_consumer_group("s", "g").unwrap();
+        assert_eq!(group.pending[&id(1)].consumer, "bob");
+        assert_eq!(group.pending[&id(1)].delivery_count, 2);
+
+        let (next, claimed) = store
+            .auto_claim("s", "g", request("carol", 0, true), id(0), 2)
+            .unwrap();
+        assert_eq!(next, id(3));
+        assert_eq!(
+            claimed
+                .claimed
+                .iter()
+                .map(|entry| entry.id)
+                .collect::<Vec<_>>(),
+            [id(1), id(2)]
+        );
+        let group = store.get_consumer_group("s", "g").unwrap();
+        assert_eq!(group.pending[&id(1)].delivery_count, 2);
 
-        assert_eq!(store.get_stream_range("my-key", None, None), Vec::new());
+        let (next, claimed) = store
+            .auto_claim("s", "g", request("carol", 0, false), next, 2)
+            .unwrap();
+        assert_eq!(next, id(0));
+        assert_eq!(claimed.claimed.len(), 1);
     }
 }